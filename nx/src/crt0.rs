@@ -1,33 +1,67 @@
+#[cfg(not(feature = "mock-svc"))]
 global_asm!(include_str!("crt0.s"));
 
 use crate::svc;
+#[cfg(not(feature = "mock-svc"))]
 use crate::mem;
 use crate::dynamic;
 use crate::sync;
+#[cfg(not(feature = "mock-svc"))]
 use crate::util;
+#[cfg(not(feature = "mock-svc"))]
 use crate::hbl;
+#[cfg(not(feature = "mock-svc"))]
+use crate::env;
 use crate::thread;
+#[cfg(not(feature = "mock-svc"))]
+use crate::diag::exception;
 use crate::result::*;
 
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::option;
+#[cfg(not(feature = "mock-svc"))]
 use core::ptr;
 
+#[cfg(not(feature = "mock-svc"))]
 extern "Rust" {
-    fn main() -> Result<()>;
+    // Defined through nx_main!
+    fn __nx_main(args: env::Args) -> Result<()>;
     fn initialize_heap(hbl_heap: util::PointerAndSize) -> util::PointerAndSize;
 }
 
 pub type ExitFn = fn(ResultCode);
 
-static mut G_EXIT_FN: sync::Locked<option::Option<ExitFn>> = sync::Locked::new(false, None);
+static G_EXIT_FN: sync::Mutex<option::Option<ExitFn>> = sync::Mutex::new(None);
+
+pub type ExitHookFn = Box<dyn FnOnce() + Send>;
+
+static G_EXIT_HOOKS: sync::Mutex<Vec<ExitHookFn>> = sync::Mutex::new(Vec::new());
+#[cfg(not(feature = "mock-svc"))]
 static mut G_MAIN_THREAD: thread::Thread = thread::Thread::new();
 
+#[cfg(not(feature = "mock-svc"))]
 unsafe fn initialize_tls_main_thread_impl(thread_handle: svc::Handle) {
-    G_MAIN_THREAD = thread::Thread::existing(thread_handle, "MainThread", ptr::null_mut(), 0, false).unwrap();
-    let mut tls = thread::get_thread_local_storage();
-    (*tls).thread_ref = &mut G_MAIN_THREAD;
+    G_MAIN_THREAD.initialize_existing(thread_handle, "MainThread", ptr::null_mut(), 0, false).unwrap();
+    thread::set_current_thread(&mut G_MAIN_THREAD);
 }
 
+// Nothing is set up yet (not even relocations), so panicking is not an option: hbloader gets the result back, otherwise we break with it
+#[cfg(not(feature = "mock-svc"))]
+unsafe fn exit_early(is_hbl_nro: bool, lr_exit_fn: ExitFn, rc: ResultCode) -> ! {
+    if is_hbl_nro {
+        lr_exit_fn(rc);
+    }
+    else {
+        let _ = svc::break_(svc::BreakReason::Panic, &rc as *const ResultCode as *mut u8, core::mem::size_of::<ResultCode>());
+        svc::exit_process();
+    }
+    loop {}
+}
+
+#[cfg(not(feature = "mock-svc"))]
 #[no_mangle]
 unsafe fn __nx_crt0_entry(abi_ptr: *const hbl::AbiConfigEntry, raw_main_thread_handle: u64, aslr_base_address: *const u8, lr_exit_fn: ExitFn, bss_start: *mut u8, bss_end: *mut u8) {
     let is_hbl_nro = !abi_ptr.is_null() && (raw_main_thread_handle == u64::MAX);
@@ -37,52 +71,61 @@ unsafe fn __nx_crt0_entry(abi_ptr: *const hbl::AbiConfigEntry, raw_main_thread_h
     ptr::write_bytes(bss_start, 0, bss_size);
 
     // Relocate ourselves
-    dynamic::relocate(aslr_base_address).unwrap();
+    if let Err(rc) = dynamic::relocate(aslr_base_address) {
+        exit_early(is_hbl_nro, lr_exit_fn, rc);
+    }
 
     let mut heap = util::PointerAndSize::new(ptr::null_mut(), 0);
     let mut main_thread_handle = raw_main_thread_handle as svc::Handle;
 
     // If homebrew NRO, parse the config entries hbloader sent us
+    let mut hbl_context = None;
     if is_hbl_nro {
-        let mut abi_entry = abi_ptr;
-        loop {
-            match (*abi_entry).key {
-                hbl::AbiConfigEntryKey::EndOfList => {
-                    break;
-                },
-                hbl::AbiConfigEntryKey::OverrideHeap => {
-                    heap.address = (*abi_entry).value[0] as *mut u8;
-                    heap.size = (*abi_entry).value[1] as usize;
-                },
-                hbl::AbiConfigEntryKey::MainThreadHandle => {
-                    main_thread_handle = (*abi_entry).value[0] as svc::Handle;
-                }
-                _ => {
-                    
-                }
-            }
-            abi_entry = abi_entry.offset(1);
+        let context = match hbl::parse_abi_config(abi_ptr) {
+            Ok(hbl_context) => hbl_context,
+            Err(rc) => exit_early(is_hbl_nro, lr_exit_fn, rc),
+        };
+        if let Some(hbl_heap) = context.heap {
+            heap = hbl_heap;
         }
+        if let Some(hbl_main_thread_handle) = context.main_thread_handle {
+            main_thread_handle = hbl_main_thread_handle;
+        }
+        hbl_context = Some(context);
     }
 
     initialize_tls_main_thread_impl(main_thread_handle);
 
+    // The context is kept behind a mutex, which needs the main thread to be set up
+    if let Some(hbl_context) = hbl_context {
+        hbl::set_context(hbl_context);
+    }
+
     // Set exit function (will be null for non-hbl NROs)
     if is_hbl_nro {
-        G_EXIT_FN.set(Some(lr_exit_fn));
+        *G_EXIT_FN.lock() = Some(lr_exit_fn);
     }
     else {
-        G_EXIT_FN.set(None);
+        *G_EXIT_FN.lock() = None;
     }
     
-    // Initialize memory allocation
+    // Initialize memory allocation (if the heap comes from svc::set_heap_size, it will grow when exhausted)
     heap = initialize_heap(heap);
     mem::initialize(heap.address, heap.size);
 
-    // TODO: finish implementing CRT0
+    // Parse the launch arguments hbloader sent us, if any
+    let hbl_argv = hbl::get_context().argv;
+    if !hbl_argv.is_null() {
+        env::set_args(env::parse_args_from_pointer(hbl_argv));
+    }
+
+    // Call global constructors (after the heap is available, since they might allocate)
+    if let Err(rc) = dynamic::call_self_init_array() {
+        exit_early(is_hbl_nro, lr_exit_fn, rc);
+    }
 
-    // Call main() and get its result code
-    let rc = match main() {
+    // Call main() with the launch arguments and get its result code
+    let rc = match __nx_main(env::args()) {
         Ok(()) => ResultCode::from::<ResultSuccess>(),
         Err(rc) => rc,
     };
@@ -91,21 +134,88 @@ unsafe fn __nx_crt0_entry(abi_ptr: *const hbl::AbiConfigEntry, raw_main_thread_h
     exit(rc);
 }
 
+// Exceptions are handled on their own stack (like libnx's __nx_exception_stack), so that stack overflows can be reported too
+pub const EXCEPTION_STACK_SIZE: usize = 0x8000;
+
+#[cfg(not(feature = "mock-svc"))]
+#[repr(C, align(16))]
+struct ExceptionStack([u8; EXCEPTION_STACK_SIZE]);
+
+#[cfg(not(feature = "mock-svc"))]
 #[no_mangle]
-unsafe fn __nx_crt0_exception_entry(_error_desc: u32, _stack_top: *mut u8) {
-    svc::return_from_exception(ResultCode::from::<svc::ResultUnhandledException>());
+#[allow(non_upper_case_globals)]
+static mut __nx_exception_stack: ExceptionStack = ExceptionStack([0; EXCEPTION_STACK_SIZE]);
+
+#[cfg(not(feature = "mock-svc"))]
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static __nx_exception_stack_size: usize = EXCEPTION_STACK_SIZE;
+
+#[cfg(not(feature = "mock-svc"))]
+#[no_mangle]
+unsafe fn __nx_crt0_exception_entry(error_desc: u32, exception_frame: *mut exception::ExceptionFrame, saved_registers: *mut exception::SavedRegisters) -> u32 {
+    // crt0.s returns from the exception with this result after restoring the registers
+    exception::handle_exception(error_desc, exception_frame, saved_registers).get_value()
+}
+
+// Hooks are called on exit in the reverse order they were registered, like atexit does
+pub fn register_exit_hook<F: FnOnce() + Send + 'static>(hook: F) {
+    G_EXIT_HOOKS.lock().push(Box::new(hook));
+}
+
+fn call_exit_hooks() {
+    // Hooks might register other hooks, so keep going until none are left (the lock can't be held while calling them)
+    loop {
+        let hook = match G_EXIT_HOOKS.lock().pop() {
+            Some(hook) => hook,
+            None => break,
+        };
+        hook();
+    }
 }
 
 pub fn exit(rc: ResultCode) -> ! {
+    // Like C++ thread_local destructors, the exiting thread's TLS values (usually the main thread's) get destroyed before the exit hooks run
+    thread::run_current_thread_tls_destructors();
+    call_exit_hooks();
     unsafe {
-        match G_EXIT_FN.get() {
-            Some(exit_fn) => {
-                exit_fn(rc);
-            },
-            None => {
-                svc::exit_process();
-            }
+        let _ = dynamic::call_self_fini_array();
+    }
+
+    // Copy it out, since the lock must not be held while exiting
+    let exit_fn = *G_EXIT_FN.lock();
+    match exit_fn {
+        Some(exit_fn) => {
+            exit_fn(rc);
+        },
+        None => {
+            svc::exit_process();
         }
     }
     loop {}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+
+    // The hooks are global, so everything about them is checked from a single test
+    #[test]
+    fn exit_hooks_are_called_in_reverse_order() {
+        let calls = Arc::new(sync::Mutex::new(Vec::new()));
+        let first_calls = calls.clone();
+        register_exit_hook(move || first_calls.lock().push(1));
+        let second_calls = calls.clone();
+        register_exit_hook(move || {
+            second_calls.lock().push(2);
+            // Hooks registered while exiting are called too
+            let nested_calls = second_calls.clone();
+            register_exit_hook(move || nested_calls.lock().push(3));
+        });
+
+        call_exit_hooks();
+        assert_eq!(*calls.lock(), [2, 3, 1]);
+        assert!(G_EXIT_HOOKS.lock().is_empty());
+    }
+}
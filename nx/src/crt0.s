@@ -39,8 +39,51 @@ __normal_entry:
 	b __nx_crt0_entry
 
 __exception_entry:
-	// bio::crt0::ExceptionEntry(error_desc, stack_top)
-	b __nx_crt0_exception_entry
+	// Switch to the dedicated exception stack, so that even stack overflows can be handled (x2-x8 are free to use, since the kernel stored them in the frame)
+	mov x4, sp
+	adrp x2, __nx_exception_stack
+	add x2, x2, #:lo12:__nx_exception_stack
+	adrp x3, __nx_exception_stack_size
+	ldr x3, [x3, #:lo12:__nx_exception_stack_size]
+	add x2, x2, x3
+	mov sp, x2
+
+	// The kernel only stores x0-x8, lr, sp and pc in the exception frame (x1 points to it), so save x9-x29 too (and the original stack pointer after them)
+	sub sp, sp, #0xB0
+	stp x9, x10, [sp, #0x00]
+	stp x11, x12, [sp, #0x10]
+	stp x13, x14, [sp, #0x20]
+	stp x15, x16, [sp, #0x30]
+	stp x17, x18, [sp, #0x40]
+	stp x19, x20, [sp, #0x50]
+	stp x21, x22, [sp, #0x60]
+	stp x23, x24, [sp, #0x70]
+	stp x25, x26, [sp, #0x80]
+	stp x27, x28, [sp, #0x90]
+	str x29, [sp, #0xA0]
+	str x4, [sp, #0xA8]
+
+	// nx::crt0::ExceptionEntry(error_desc, exception_frame, saved_registers) -> result
+	mov x2, sp
+	bl __nx_crt0_exception_entry
+
+	// Restore the (maybe modified) registers and return from the exception with the result we got
+	mov w1, w0
+	ldp x9, x10, [sp, #0x00]
+	ldp x11, x12, [sp, #0x10]
+	ldp x13, x14, [sp, #0x20]
+	ldp x15, x16, [sp, #0x30]
+	ldp x17, x18, [sp, #0x40]
+	ldp x19, x20, [sp, #0x50]
+	ldp x21, x22, [sp, #0x60]
+	ldp x23, x24, [sp, #0x70]
+	ldp x25, x26, [sp, #0x80]
+	ldp x27, x28, [sp, #0x90]
+	ldr x29, [sp, #0xA0]
+	ldr x2, [sp, #0xA8]
+	mov sp, x2
+	mov w0, w1
+	svc 0x28
 
 // Actual entrypoint called
 
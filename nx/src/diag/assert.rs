@@ -1,6 +1,8 @@
 use crate::result::*;
 use crate::svc;
+use crate::ipc;
 use crate::crt0;
+use crate::diag::backtrace;
 use crate::service;
 use crate::service::fatal;
 use crate::service::fatal::IService;
@@ -31,7 +33,9 @@ pub fn assert(mode: AssertMode, rc: ResultCode) -> ! {
             AssertMode::FatalThrow => {
                 match service::new_service_object::<fatal::Service>() {
                     Ok(mut fatal) => {
-                        let _ = fatal.throw_with_policy(rc, fatal::Policy::ErrorScreen);
+                        let backtrace = backtrace::Backtrace::capture();
+                        let cpu_context = fatal::CpuContext::from_stack_trace(backtrace.get_base_address(), backtrace.get_return_addresses());
+                        let _ = fatal.throw_with_cpu_context(rc, fatal::Policy::ErrorScreen, ipc::InMapAlias::from_var(&cpu_context));
                     },
                     _ => {}
                 }
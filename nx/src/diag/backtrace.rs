@@ -0,0 +1,865 @@
+use crate::svc;
+use crate::dynamic;
+use crate::diag::log;
+use crate::diag::log::Logger;
+use crate::diag::exception;
+use core::mem;
+use core::ptr;
+
+pub const MAX_FRAME_COUNT: usize = 32;
+
+// DWARF register numbers on aarch64: x0-x30, then sp
+const REGISTER_COUNT: usize = 32;
+const FP_REGISTER: usize = 29;
+const LR_REGISTER: usize = 30;
+const SP_REGISTER: usize = 31;
+
+const INSTRUCTION_SIZE: usize = 4;
+
+const MAX_REMEMBERED_ROW_COUNT: usize = 8;
+
+// Pointer encodings (DW_EH_PE_*)
+const POINTER_ENCODING_OMIT: u8 = 0xFF;
+const POINTER_ENCODING_ABSPTR: u8 = 0x00;
+const POINTER_ENCODING_ULEB128: u8 = 0x01;
+const POINTER_ENCODING_UDATA2: u8 = 0x02;
+const POINTER_ENCODING_UDATA4: u8 = 0x03;
+const POINTER_ENCODING_UDATA8: u8 = 0x04;
+const POINTER_ENCODING_SLEB128: u8 = 0x09;
+const POINTER_ENCODING_SDATA2: u8 = 0x0A;
+const POINTER_ENCODING_SDATA4: u8 = 0x0B;
+const POINTER_ENCODING_SDATA8: u8 = 0x0C;
+const POINTER_ENCODING_PCREL: u8 = 0x10;
+const POINTER_ENCODING_DATAREL: u8 = 0x30;
+const POINTER_ENCODING_INDIRECT: u8 = 0x80;
+
+struct Reader {
+    ptr: *const u8,
+}
+
+impl Reader {
+    const fn new(ptr: *const u8) -> Self {
+        Self { ptr: ptr }
+    }
+
+    unsafe fn read<T: Copy>(&mut self) -> T {
+        let t = ptr::read_unaligned(self.ptr as *const T);
+        self.ptr = self.ptr.offset(mem::size_of::<T>() as isize);
+        t
+    }
+
+    unsafe fn read_uleb128(&mut self) -> u64 {
+        let mut value: u64 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let byte: u8 = self.read();
+            if shift < 64 {
+                value |= ((byte & 0x7F) as u64) << shift;
+            }
+            shift += 7;
+            if (byte & 0x80) == 0 {
+                break;
+            }
+        }
+        value
+    }
+
+    unsafe fn read_sleb128(&mut self) -> i64 {
+        let mut value: i64 = 0;
+        let mut shift: u32 = 0;
+        let mut byte: u8;
+        loop {
+            byte = self.read();
+            if shift < 64 {
+                value |= ((byte & 0x7F) as i64) << shift;
+            }
+            shift += 7;
+            if (byte & 0x80) == 0 {
+                break;
+            }
+        }
+        // Sign-extend
+        if (shift < 64) && ((byte & 0x40) != 0) {
+            value |= -1i64 << shift;
+        }
+        value
+    }
+
+    // The value is always consumed, even if its encoding can't be applied
+    unsafe fn read_encoded(&mut self, encoding: u8, data_base: usize) -> Option<usize> {
+        if encoding == POINTER_ENCODING_OMIT {
+            return None;
+        }
+
+        let position = self.ptr as usize;
+        let value = match encoding & 0x0F {
+            POINTER_ENCODING_ABSPTR => self.read::<usize>(),
+            POINTER_ENCODING_ULEB128 => self.read_uleb128() as usize,
+            POINTER_ENCODING_UDATA2 => self.read::<u16>() as usize,
+            POINTER_ENCODING_UDATA4 => self.read::<u32>() as usize,
+            POINTER_ENCODING_UDATA8 => self.read::<u64>() as usize,
+            POINTER_ENCODING_SLEB128 => self.read_sleb128() as usize,
+            POINTER_ENCODING_SDATA2 => self.read::<i16>() as usize,
+            POINTER_ENCODING_SDATA4 => self.read::<i32>() as usize,
+            POINTER_ENCODING_SDATA8 => self.read::<i64>() as usize,
+            _ => return None,
+        };
+
+        let address = match encoding & 0x70 {
+            POINTER_ENCODING_ABSPTR => value,
+            POINTER_ENCODING_PCREL => position.wrapping_add(value),
+            POINTER_ENCODING_DATAREL => data_base.wrapping_add(value),
+            _ => return None,
+        };
+
+        if (encoding & POINTER_ENCODING_INDIRECT) != 0 {
+            return Some(*(address as *const usize));
+        }
+        Some(address)
+    }
+}
+
+struct CommonInfo {
+    code_alignment: u64,
+    data_alignment: i64,
+    return_address_register: usize,
+    fde_encoding: u8,
+    has_augmentation_data: bool,
+    instructions: *const u8,
+    instructions_end: *const u8,
+}
+
+struct FrameInfo {
+    cie: CommonInfo,
+    pc_begin: usize,
+    instructions: *const u8,
+    instructions_end: *const u8,
+}
+
+// Returns where the entry ends, 64-bit DWARF entries aren't supported (and aren't generated for our modules)
+unsafe fn read_entry_bounds(reader: &mut Reader) -> Option<*const u8> {
+    let length: u32 = reader.read();
+    if (length == 0) || (length == u32::MAX) {
+        return None;
+    }
+    Some(reader.ptr.offset(length as isize))
+}
+
+unsafe fn parse_cie(cie: *const u8, data_base: usize) -> Option<CommonInfo> {
+    let mut reader = Reader::new(cie);
+    let end = read_entry_bounds(&mut reader)?;
+    let id: u32 = reader.read();
+    if id != 0 {
+        return None;
+    }
+    let version: u8 = reader.read();
+    if (version != 1) && (version != 3) {
+        return None;
+    }
+
+    let augmentation = reader.ptr;
+    while reader.read::<u8>() != 0 {}
+    if (*augmentation == b'e') && (*augmentation.offset(1) == b'h') {
+        reader.read::<usize>();
+    }
+
+    let code_alignment = reader.read_uleb128();
+    let data_alignment = reader.read_sleb128();
+    let return_address_register = match version {
+        1 => reader.read::<u8>() as usize,
+        _ => reader.read_uleb128() as usize,
+    };
+
+    let mut fde_encoding = POINTER_ENCODING_ABSPTR;
+    let has_augmentation_data = *augmentation == b'z';
+    if has_augmentation_data {
+        let augmentation_data_size = reader.read_uleb128();
+        let augmentation_data_end = reader.ptr.offset(augmentation_data_size as isize);
+        let mut augmentation_char = augmentation.offset(1);
+        while *augmentation_char != 0 {
+            match *augmentation_char {
+                b'L' => {
+                    reader.read::<u8>();
+                },
+                b'P' => {
+                    let personality_encoding: u8 = reader.read();
+                    let _ = reader.read_encoded(personality_encoding, data_base);
+                },
+                b'R' => {
+                    fde_encoding = reader.read();
+                },
+                b'S' | b'B' => {},
+                // Anything after an unknown character can't be parsed, but the data size lets us skip it
+                _ => break,
+            }
+            augmentation_char = augmentation_char.offset(1);
+        }
+        reader.ptr = augmentation_data_end;
+    }
+
+    Some(CommonInfo { code_alignment: code_alignment, data_alignment: data_alignment, return_address_register: return_address_register, fde_encoding: fde_encoding, has_augmentation_data: has_augmentation_data, instructions: reader.ptr, instructions_end: end })
+}
+
+unsafe fn parse_fde(fde: *const u8, pc: usize, data_base: usize) -> Option<FrameInfo> {
+    let mut reader = Reader::new(fde);
+    let end = read_entry_bounds(&mut reader)?;
+    let cie_pointer = reader.ptr;
+    let cie_offset: u32 = reader.read();
+    if cie_offset == 0 {
+        return None;
+    }
+    let cie = parse_cie(cie_pointer.offset(-(cie_offset as isize)), data_base)?;
+
+    let pc_begin = reader.read_encoded(cie.fde_encoding, data_base)?;
+    // The range is never relative to anything
+    let pc_range = reader.read_encoded(cie.fde_encoding & 0x0F, data_base)?;
+    if (pc < pc_begin) || (pc >= pc_begin.wrapping_add(pc_range)) {
+        return None;
+    }
+
+    if cie.has_augmentation_data {
+        let augmentation_data_size = reader.read_uleb128();
+        reader.ptr = reader.ptr.offset(augmentation_data_size as isize);
+    }
+
+    Some(FrameInfo { cie: cie, pc_begin: pc_begin, instructions: reader.ptr, instructions_end: end })
+}
+
+unsafe fn find_fde(eh_frame_hdr: *const u8, pc: usize) -> Option<*const u8> {
+    let mut reader = Reader::new(eh_frame_hdr);
+    let version: u8 = reader.read();
+    let eh_frame_ptr_encoding: u8 = reader.read();
+    let fde_count_encoding: u8 = reader.read();
+    let table_encoding: u8 = reader.read();
+    if version != 1 {
+        return None;
+    }
+
+    let data_base = eh_frame_hdr as usize;
+    let _ = reader.read_encoded(eh_frame_ptr_encoding, data_base);
+    let fde_count = reader.read_encoded(fde_count_encoding, data_base)?;
+    // This is the only (sorted, and thus searchable) table encoding linkers generate
+    if table_encoding != (POINTER_ENCODING_DATAREL | POINTER_ENCODING_SDATA4) {
+        return None;
+    }
+
+    // Entries are (initial location, FDE address) pairs sorted by location, find the last one starting before the PC
+    let table = reader.ptr as *const [i32; 2];
+    let mut low: usize = 0;
+    let mut high = fde_count;
+    while low < high {
+        let mid = (low + high) / 2;
+        let location = data_base.wrapping_add((*table.offset(mid as isize))[0] as usize);
+        if location <= pc {
+            low = mid + 1;
+        }
+        else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        return None;
+    }
+    Some(data_base.wrapping_add((*table.offset((low - 1) as isize))[1] as usize) as *const u8)
+}
+
+#[derive(Copy, Clone)]
+enum RegisterRule {
+    Undefined,
+    SameValue,
+    Offset(i64),
+    ValueOffset(i64),
+    Register(usize),
+}
+
+#[derive(Copy, Clone)]
+struct UnwindRow {
+    cfa_register: usize,
+    cfa_offset: i64,
+    rules: [RegisterRule; REGISTER_COUNT],
+}
+
+impl UnwindRow {
+    const fn new() -> Self {
+        Self { cfa_register: SP_REGISTER, cfa_offset: 0, rules: [RegisterRule::SameValue; REGISTER_COUNT] }
+    }
+
+    fn set_rule(&mut self, register: u64, rule: RegisterRule) {
+        if (register as usize) < REGISTER_COUNT {
+            self.rules[register as usize] = rule;
+        }
+    }
+}
+
+// Runs the CFA instructions until the row for the given PC is reached, returns false on unsupported instructions
+unsafe fn execute_instructions(instructions: *const u8, instructions_end: *const u8, frame: &FrameInfo, initial_row: &UnwindRow, row: &mut UnwindRow, pc: usize) -> bool {
+    let cie = &frame.cie;
+    let mut reader = Reader::new(instructions);
+    let mut location = frame.pc_begin;
+    let mut remembered_rows = [UnwindRow::new(); MAX_REMEMBERED_ROW_COUNT];
+    let mut remembered_row_count: usize = 0;
+    while reader.ptr < instructions_end {
+        let instruction: u8 = reader.read();
+        let operand = (instruction & 0x3F) as u64;
+        let advance = match instruction >> 6 {
+            // DW_CFA_advance_loc
+            1 => operand,
+            // DW_CFA_offset
+            2 => {
+                let offset = reader.read_uleb128() as i64 * cie.data_alignment;
+                row.set_rule(operand, RegisterRule::Offset(offset));
+                0
+            },
+            // DW_CFA_restore
+            3 => {
+                if (operand as usize) < REGISTER_COUNT {
+                    row.rules[operand as usize] = initial_row.rules[operand as usize];
+                }
+                0
+            },
+            _ => match instruction {
+                // DW_CFA_nop
+                0x00 => 0,
+                // DW_CFA_set_loc
+                0x01 => {
+                    match reader.read_encoded(cie.fde_encoding, 0) {
+                        Some(new_location) => location = new_location,
+                        None => return false,
+                    };
+                    if location > pc {
+                        return true;
+                    }
+                    0
+                },
+                // DW_CFA_advance_loc1/2/4
+                0x02 => reader.read::<u8>() as u64,
+                0x03 => reader.read::<u16>() as u64,
+                0x04 => reader.read::<u32>() as u64,
+                // DW_CFA_offset_extended
+                0x05 => {
+                    let register = reader.read_uleb128();
+                    let offset = reader.read_uleb128() as i64 * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::Offset(offset));
+                    0
+                },
+                // DW_CFA_restore_extended
+                0x06 => {
+                    let register = reader.read_uleb128() as usize;
+                    if register < REGISTER_COUNT {
+                        row.rules[register] = initial_row.rules[register];
+                    }
+                    0
+                },
+                // DW_CFA_undefined
+                0x07 => {
+                    let register = reader.read_uleb128();
+                    row.set_rule(register, RegisterRule::Undefined);
+                    0
+                },
+                // DW_CFA_same_value
+                0x08 => {
+                    let register = reader.read_uleb128();
+                    row.set_rule(register, RegisterRule::SameValue);
+                    0
+                },
+                // DW_CFA_register
+                0x09 => {
+                    let register = reader.read_uleb128();
+                    let other_register = reader.read_uleb128() as usize;
+                    if other_register >= REGISTER_COUNT {
+                        return false;
+                    }
+                    row.set_rule(register, RegisterRule::Register(other_register));
+                    0
+                },
+                // DW_CFA_remember_state
+                0x0A => {
+                    if remembered_row_count >= MAX_REMEMBERED_ROW_COUNT {
+                        return false;
+                    }
+                    remembered_rows[remembered_row_count] = *row;
+                    remembered_row_count += 1;
+                    0
+                },
+                // DW_CFA_restore_state
+                0x0B => {
+                    if remembered_row_count == 0 {
+                        return false;
+                    }
+                    remembered_row_count -= 1;
+                    *row = remembered_rows[remembered_row_count];
+                    0
+                },
+                // DW_CFA_def_cfa
+                0x0C => {
+                    row.cfa_register = reader.read_uleb128() as usize;
+                    row.cfa_offset = reader.read_uleb128() as i64;
+                    0
+                },
+                // DW_CFA_def_cfa_register
+                0x0D => {
+                    row.cfa_register = reader.read_uleb128() as usize;
+                    0
+                },
+                // DW_CFA_def_cfa_offset
+                0x0E => {
+                    row.cfa_offset = reader.read_uleb128() as i64;
+                    0
+                },
+                // DW_CFA_expression, DW_CFA_val_expression (DWARF expressions aren't evaluated, so the register is lost)
+                0x10 | 0x16 => {
+                    let register = reader.read_uleb128();
+                    let expression_size = reader.read_uleb128();
+                    reader.ptr = reader.ptr.offset(expression_size as isize);
+                    row.set_rule(register, RegisterRule::Undefined);
+                    0
+                },
+                // DW_CFA_offset_extended_sf
+                0x11 => {
+                    let register = reader.read_uleb128();
+                    let offset = reader.read_sleb128() * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::Offset(offset));
+                    0
+                },
+                // DW_CFA_def_cfa_sf
+                0x12 => {
+                    row.cfa_register = reader.read_uleb128() as usize;
+                    row.cfa_offset = reader.read_sleb128() * cie.data_alignment;
+                    0
+                },
+                // DW_CFA_def_cfa_offset_sf
+                0x13 => {
+                    row.cfa_offset = reader.read_sleb128() * cie.data_alignment;
+                    0
+                },
+                // DW_CFA_val_offset
+                0x14 => {
+                    let register = reader.read_uleb128();
+                    let offset = reader.read_uleb128() as i64 * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::ValueOffset(offset));
+                    0
+                },
+                // DW_CFA_val_offset_sf
+                0x15 => {
+                    let register = reader.read_uleb128();
+                    let offset = reader.read_sleb128() * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::ValueOffset(offset));
+                    0
+                },
+                // DW_CFA_AARCH64_negate_ra_state (pointer authentication isn't used here)
+                0x2D => 0,
+                // DW_CFA_GNU_args_size
+                0x2E => {
+                    reader.read_uleb128();
+                    0
+                },
+                // DW_CFA_GNU_negative_offset_extended
+                0x2F => {
+                    let register = reader.read_uleb128();
+                    let offset = -(reader.read_uleb128() as i64) * cie.data_alignment;
+                    row.set_rule(register, RegisterRule::Offset(offset));
+                    0
+                },
+                // Includes DW_CFA_def_cfa_expression
+                _ => return false,
+            },
+        };
+
+        if advance != 0 {
+            location = location.wrapping_add((advance * cie.code_alignment) as usize);
+            if location > pc {
+                return true;
+            }
+        }
+    }
+    true
+}
+
+#[derive(Copy, Clone)]
+struct RegisterState {
+    registers: [usize; REGISTER_COUNT],
+    pc: usize,
+}
+
+struct StackBounds {
+    start: usize,
+    end: usize,
+}
+
+impl StackBounds {
+    // Every stack read is checked against the memory the stack pointer was in, so that a corrupted stack can't make us fault
+    fn from_stack_pointer(sp: usize) -> Self {
+        let mut info = mem::MaybeUninit::<svc::MemoryInfo>::uninit();
+        if unsafe { svc::query_memory(info.as_mut_ptr(), sp as *const u8) }.is_err() {
+            return Self { start: 0, end: 0 };
+        }
+        let (base_address, size, memory_state) = unsafe { ((*info.as_ptr()).base_address as usize, (*info.as_ptr()).size as usize, (*info.as_ptr()).memory_state) };
+        match memory_state {
+            svc::MemoryState::Free => Self { start: 0, end: 0 },
+            _ => Self { start: base_address, end: base_address + size },
+        }
+    }
+
+    fn contains(&self, address: usize, size: usize) -> bool {
+        (address >= self.start) && (address.wrapping_add(size) <= self.end) && ((address % mem::align_of::<usize>()) == 0)
+    }
+}
+
+struct ModuleInfo {
+    base_address: usize,
+    end_address: usize,
+    eh_frame_hdr: *const u8,
+}
+
+impl ModuleInfo {
+    fn get_self() -> Self {
+        let base_address = dynamic::get_self_base_address();
+        if base_address.is_null() {
+            return Self { base_address: 0, end_address: 0, eh_frame_hdr: ptr::null() };
+        }
+
+        unsafe {
+            match dynamic::get_module_header(base_address) {
+                Ok(module) => {
+                    let module_address = module as *const u8;
+                    let eh_frame_hdr = match (*module).eh_frame_hdr_start != (*module).eh_frame_hdr_end {
+                        true => module_address.offset((*module).eh_frame_hdr_start as isize),
+                        false => ptr::null(),
+                    };
+                    Self { base_address: base_address as usize, end_address: module_address.offset((*module).bss_end as isize) as usize, eh_frame_hdr: eh_frame_hdr }
+                },
+                Err(_) => Self { base_address: base_address as usize, end_address: base_address as usize, eh_frame_hdr: ptr::null() },
+            }
+        }
+    }
+
+    fn contains(&self, address: usize) -> bool {
+        (address >= self.base_address) && (address < self.end_address)
+    }
+}
+
+unsafe fn step_with_cfi(state: &mut RegisterState, module: &ModuleInfo, bounds: &StackBounds, pc: usize) -> Option<()> {
+    if module.eh_frame_hdr.is_null() || !module.contains(pc) {
+        return None;
+    }
+
+    let data_base = module.eh_frame_hdr as usize;
+    let fde = find_fde(module.eh_frame_hdr, pc)?;
+    let frame = parse_fde(fde, pc, data_base)?;
+    let return_address_register = frame.cie.return_address_register;
+    if return_address_register >= REGISTER_COUNT {
+        return None;
+    }
+
+    // The CIE instructions set up the initial row, which DW_CFA_restore goes back to
+    let mut row = UnwindRow::new();
+    let default_row = UnwindRow::new();
+    if !execute_instructions(frame.cie.instructions, frame.cie.instructions_end, &frame, &default_row, &mut row, usize::MAX) {
+        return None;
+    }
+    let initial_row = row;
+    if !execute_instructions(frame.instructions, frame.instructions_end, &frame, &initial_row, &mut row, pc) {
+        return None;
+    }
+
+    if row.cfa_register >= REGISTER_COUNT {
+        return None;
+    }
+    let cfa = (state.registers[row.cfa_register] as i64).wrapping_add(row.cfa_offset) as usize;
+
+    let mut registers = state.registers;
+    for (i, rule) in row.rules.iter().enumerate() {
+        match *rule {
+            RegisterRule::Undefined => {
+                // An undefined return address marks the outermost frame
+                if i == return_address_register {
+                    return None;
+                }
+            },
+            RegisterRule::SameValue => {},
+            RegisterRule::Offset(offset) => {
+                let address = (cfa as i64).wrapping_add(offset) as usize;
+                if !bounds.contains(address, mem::size_of::<usize>()) {
+                    return None;
+                }
+                registers[i] = *(address as *const usize);
+            },
+            RegisterRule::ValueOffset(offset) => registers[i] = (cfa as i64).wrapping_add(offset) as usize,
+            RegisterRule::Register(other_register) => registers[i] = state.registers[other_register],
+        };
+    }
+
+    state.pc = registers[return_address_register];
+    registers[SP_REGISTER] = cfa;
+    state.registers = registers;
+    Some(())
+}
+
+// Fallback for code without unwind info, which relies on frame pointers being kept (-C force-frame-pointers=yes)
+unsafe fn step_with_frame_pointer(state: &mut RegisterState, bounds: &StackBounds) -> Option<()> {
+    // A null frame pointer ends the frame record chain
+    let fp = state.registers[FP_REGISTER];
+    if (fp == 0) || ((fp % 0x10) != 0) || !bounds.contains(fp, 2 * mem::size_of::<usize>()) {
+        return None;
+    }
+
+    let frame_record = fp as *const usize;
+    state.registers[FP_REGISTER] = *frame_record;
+    state.registers[LR_REGISTER] = *frame_record.offset(1);
+    state.registers[SP_REGISTER] = fp + 2 * mem::size_of::<usize>();
+    state.pc = state.registers[LR_REGISTER];
+    Some(())
+}
+
+unsafe fn unwind(initial_state: RegisterState, module: &ModuleInfo, backtrace: &mut Backtrace) {
+    let bounds = StackBounds::from_stack_pointer(initial_state.registers[SP_REGISTER]);
+    let mut state = initial_state;
+    let mut is_first_frame = true;
+    while backtrace.return_address_count < MAX_FRAME_COUNT {
+        // Return addresses point after the call instruction, which might be the start of another function's unwind info
+        let pc = match is_first_frame {
+            true => state.pc,
+            false => state.pc.wrapping_sub(INSTRUCTION_SIZE),
+        };
+        let prev_sp = state.registers[SP_REGISTER];
+        let prev_pc = state.pc;
+        if step_with_cfi(&mut state, module, &bounds, pc).is_none() && step_with_frame_pointer(&mut state, &bounds).is_none() {
+            break;
+        }
+
+        // Stacks grow downwards, anything else means the chain is broken
+        let sp = state.registers[SP_REGISTER];
+        if (state.pc == 0) || (sp < prev_sp) || ((sp == prev_sp) && (state.pc == prev_pc)) {
+            break;
+        }
+        backtrace.push(state.pc);
+        is_first_frame = false;
+    }
+}
+
+#[cfg(not(feature = "mock-svc"))]
+#[inline(always)]
+fn get_current_register_state() -> RegisterState {
+    let fp: usize;
+    let lr: usize;
+    let sp: usize;
+    let pc: usize;
+    unsafe {
+        llvm_asm!("mov $0, x29\n mov $1, x30\n mov $2, sp\n adr $3, ." : "=r"(fp), "=r"(lr), "=r"(sp), "=r"(pc) ::: "volatile");
+    }
+    let mut registers = [0; REGISTER_COUNT];
+    registers[FP_REGISTER] = fp;
+    registers[LR_REGISTER] = lr;
+    registers[SP_REGISTER] = sp;
+    RegisterState { registers: registers, pc: pc }
+}
+
+#[cfg(feature = "mock-svc")]
+#[inline(always)]
+fn get_current_register_state() -> RegisterState {
+    RegisterState { registers: [0; REGISTER_COUNT], pc: 0 }
+}
+
+// Addresses are stored inline, so that backtraces can be captured without the heap (like from the exception handler)
+pub struct Backtrace {
+    base_address: usize,
+    end_address: usize,
+    return_addresses: [usize; MAX_FRAME_COUNT],
+    return_address_count: usize,
+}
+
+impl Backtrace {
+    fn new(module: &ModuleInfo) -> Self {
+        Self { base_address: module.base_address, end_address: module.end_address, return_addresses: [0; MAX_FRAME_COUNT], return_address_count: 0 }
+    }
+
+    fn push(&mut self, address: usize) {
+        self.return_addresses[self.return_address_count] = address;
+        self.return_address_count += 1;
+    }
+
+    #[inline(never)]
+    pub fn capture() -> Self {
+        let state = get_current_register_state();
+        let module = ModuleInfo::get_self();
+        let mut backtrace = Self::new(&module);
+        unsafe {
+            unwind(state, &module, &mut backtrace);
+        }
+        backtrace
+    }
+
+    // The faulting PC is the first address here, followed by the return addresses
+    pub fn from_exception_context(context: &exception::ExceptionContext) -> Self {
+        let mut registers = [0; REGISTER_COUNT];
+        for (register, gpr) in registers.iter_mut().zip(context.gprs.iter()) {
+            *register = *gpr as usize;
+        }
+        registers[FP_REGISTER] = context.fp as usize;
+        registers[LR_REGISTER] = context.lr as usize;
+        registers[SP_REGISTER] = context.sp as usize;
+        let state = RegisterState { registers: registers, pc: context.pc as usize };
+
+        let module = ModuleInfo::get_self();
+        let mut backtrace = Self::new(&module);
+        backtrace.push(state.pc);
+        unsafe {
+            unwind(state, &module, &mut backtrace);
+        }
+        backtrace
+    }
+
+    pub fn get_base_address(&self) -> usize {
+        self.base_address
+    }
+
+    pub fn get_return_addresses(&self) -> &[usize] {
+        &self.return_addresses[..self.return_address_count]
+    }
+
+    // Relative addresses are what addr2line expects, addresses outside our own module don't have one
+    pub fn get_relative_address(&self, address: usize) -> Option<usize> {
+        match (address >= self.base_address) && (address < self.end_address) {
+            true => Some(address - self.base_address),
+            false => None,
+        }
+    }
+}
+
+pub fn dump_backtrace<L: Logger>(backtrace: &Backtrace, severity: log::LogSeverity) {
+    diag_log!(L { severity, false } => "Backtrace (base address: {:#X}):", backtrace.get_base_address());
+    for (i, address) in backtrace.get_return_addresses().iter().enumerate() {
+        match backtrace.get_relative_address(*address) {
+            Some(relative_address) => diag_log!(L { severity, false } => "#{}: {:#X} (base + {:#X})", i, address, relative_address),
+            None => diag_log!(L { severity, false } => "#{}: {:#X} (outside the module)", i, address),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C, align(16))]
+    struct UnwindImage([u8; 0x100]);
+
+    const FUNCTION_ADDRESS: usize = 0x1000;
+    const FUNCTION_SIZE: usize = 0x20;
+    const OUTERMOST_FUNCTION_ADDRESS: usize = 0x2000;
+    const OUTERMOST_FUNCTION_SIZE: usize = 0x10;
+    const EH_FRAME_OFFSET: usize = 0x40;
+    const FDE_OFFSET: usize = 0x80;
+    const OUTERMOST_FDE_OFFSET: usize = 0xC0;
+
+    fn put_u32(image: &mut UnwindImage, offset: usize, value: u32) {
+        image.0[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_bytes(image: &mut UnwindImage, offset: usize, bytes: &[u8]) {
+        image.0[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // What a linker would emit for two functions, with addresses relative to .eh_frame_hdr (which starts the image):
+    // - a function storing fp and lr on the stack after its first instruction (stp x29, x30, [sp, #-0x10]!)
+    // - an outermost function, whose return address is undefined
+    fn make_unwind_image() -> UnwindImage {
+        let mut image = UnwindImage([0; 0x100]);
+
+        // .eh_frame_hdr: version, eh_frame_ptr (pcrel sdata4), fde_count (udata4), table (datarel sdata4)
+        put_bytes(&mut image, 0, &[1, 0x1B, 0x03, 0x3B]);
+        put_u32(&mut image, 4, (EH_FRAME_OFFSET - 4) as u32);
+        put_u32(&mut image, 8, 2);
+        put_u32(&mut image, 12, FUNCTION_ADDRESS as u32);
+        put_u32(&mut image, 16, FDE_OFFSET as u32);
+        put_u32(&mut image, 20, OUTERMOST_FUNCTION_ADDRESS as u32);
+        put_u32(&mut image, 24, OUTERMOST_FDE_OFFSET as u32);
+
+        // CIE: "zR" with pcrel sdata4 FDE pointers, code alignment 4, data alignment -8, lr as return address, CFA = sp
+        put_u32(&mut image, EH_FRAME_OFFSET, 16);
+        put_u32(&mut image, EH_FRAME_OFFSET + 4, 0);
+        put_bytes(&mut image, EH_FRAME_OFFSET + 8, &[1, b'z', b'R', 0, 4, 0x78, 30, 1, 0x1B, 0x0C, 31, 0]);
+
+        // FDE: advance_loc 1, def_cfa_offset 16, offset x29 at CFA-16, offset x30 at CFA-8
+        put_u32(&mut image, FDE_OFFSET, 0x14);
+        put_u32(&mut image, FDE_OFFSET + 4, (FDE_OFFSET + 4 - EH_FRAME_OFFSET) as u32);
+        put_u32(&mut image, FDE_OFFSET + 8, (FUNCTION_ADDRESS - (FDE_OFFSET + 8)) as u32);
+        put_u32(&mut image, FDE_OFFSET + 12, FUNCTION_SIZE as u32);
+        put_bytes(&mut image, FDE_OFFSET + 16, &[0, 0x41, 0x0E, 16, 0x80 | 29, 2, 0x80 | 30, 1]);
+
+        // FDE: undefined x30
+        put_u32(&mut image, OUTERMOST_FDE_OFFSET, 0xF);
+        put_u32(&mut image, OUTERMOST_FDE_OFFSET + 4, (OUTERMOST_FDE_OFFSET + 4 - EH_FRAME_OFFSET) as u32);
+        put_u32(&mut image, OUTERMOST_FDE_OFFSET + 8, (OUTERMOST_FUNCTION_ADDRESS - (OUTERMOST_FDE_OFFSET + 8)) as u32);
+        put_u32(&mut image, OUTERMOST_FDE_OFFSET + 12, OUTERMOST_FUNCTION_SIZE as u32);
+        put_bytes(&mut image, OUTERMOST_FDE_OFFSET + 16, &[0, 0x07, 30]);
+        image
+    }
+
+    fn get_module(image: &UnwindImage) -> ModuleInfo {
+        let base_address = image.0.as_ptr() as usize;
+        ModuleInfo { base_address: base_address, end_address: base_address + 0x3000, eh_frame_hdr: image.0.as_ptr() }
+    }
+
+    #[test]
+    fn fdes_are_found_by_pc() {
+        let image = make_unwind_image();
+        let base_address = image.0.as_ptr() as usize;
+        unsafe {
+            assert!(find_fde(image.0.as_ptr(), base_address + FUNCTION_ADDRESS - 4).is_none());
+            assert_eq!(find_fde(image.0.as_ptr(), base_address + FUNCTION_ADDRESS).unwrap() as usize, base_address + FDE_OFFSET);
+            assert_eq!(find_fde(image.0.as_ptr(), base_address + FUNCTION_ADDRESS + FUNCTION_SIZE - 4).unwrap() as usize, base_address + FDE_OFFSET);
+            assert_eq!(find_fde(image.0.as_ptr(), base_address + OUTERMOST_FUNCTION_ADDRESS + 4).unwrap() as usize, base_address + OUTERMOST_FDE_OFFSET);
+
+            // The table only tells where the last function starting before the PC is, the FDE tells whether the PC is inside it
+            let fde = find_fde(image.0.as_ptr(), base_address + FUNCTION_ADDRESS + FUNCTION_SIZE).unwrap();
+            assert!(parse_fde(fde, base_address + FUNCTION_ADDRESS + FUNCTION_SIZE, base_address).is_none());
+        }
+    }
+
+    #[test]
+    fn frames_are_stepped_with_cfi() {
+        let image = make_unwind_image();
+        let module = get_module(&image);
+        let stack: [usize; 2] = [0x5000, module.base_address + OUTERMOST_FUNCTION_ADDRESS + 8];
+        let sp = stack.as_ptr() as usize;
+        let bounds = StackBounds { start: sp, end: sp + mem::size_of_val(&stack) };
+        let mut registers = [0; REGISTER_COUNT];
+        registers[FP_REGISTER] = 0x6000;
+        registers[LR_REGISTER] = module.base_address + OUTERMOST_FUNCTION_ADDRESS + 4;
+        registers[SP_REGISTER] = sp;
+
+        unsafe {
+            // Before the prologue ran, the return address is still in lr
+            let pc = module.base_address + FUNCTION_ADDRESS;
+            let mut state = RegisterState { registers: registers, pc: pc };
+            assert!(step_with_cfi(&mut state, &module, &bounds, pc).is_some());
+            assert_eq!(state.pc, module.base_address + OUTERMOST_FUNCTION_ADDRESS + 4);
+            assert_eq!(state.registers[SP_REGISTER], sp);
+            assert_eq!(state.registers[FP_REGISTER], 0x6000);
+
+            // After it, fp and lr are read back from the stack
+            let pc = module.base_address + FUNCTION_ADDRESS + 8;
+            let mut state = RegisterState { registers: registers, pc: pc };
+            assert!(step_with_cfi(&mut state, &module, &bounds, pc).is_some());
+            assert_eq!(state.pc, stack[1]);
+            assert_eq!(state.registers[SP_REGISTER], sp + 16);
+            assert_eq!(state.registers[FP_REGISTER], 0x5000);
+
+            // Unwinding stops at the outermost frame
+            let outer_pc = state.pc;
+            assert!(step_with_cfi(&mut state, &module, &bounds, outer_pc).is_none());
+
+            // Saved registers outside of the stack aren't read
+            let empty_bounds = StackBounds { start: 0, end: 0 };
+            let mut state = RegisterState { registers: registers, pc: pc };
+            assert!(step_with_cfi(&mut state, &module, &empty_bounds, pc).is_none());
+        }
+    }
+
+    #[test]
+    fn backtraces_include_the_faulting_pc() {
+        let context = exception::ExceptionContext::from_frame(0x104, &exception::ExceptionFrame { x: [0; 9], lr: 0, sp: 0, pc: 0x3000, pstate: 0, afsr0: 0, afsr1: 0, esr: 0, far: 0 }, &[0; exception::SAVED_REGISTER_COUNT]);
+        let backtrace = Backtrace::from_exception_context(&context);
+        assert_eq!(backtrace.get_return_addresses(), &[0x3000]);
+    }
+}
\ No newline at end of file
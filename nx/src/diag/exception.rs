@@ -0,0 +1,349 @@
+extern crate alloc;
+
+use crate::result::*;
+use crate::svc;
+use crate::thread;
+use crate::dynamic;
+use crate::diag::log;
+use crate::diag::backtrace;
+use crate::diag::log::Logger;
+use core::fmt;
+use core::fmt::Write;
+use core::mem;
+use core::str;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(u32)]
+pub enum ErrorDescription {
+    InstructionAbort = 0x100,
+    Other = 0x101,
+    MisalignedPC = 0x102,
+    MisalignedSP = 0x103,
+    Trap = 0x104,
+    SError = 0x106,
+    BadSVC = 0x301,
+}
+
+// Layout of the frame the kernel stores on the stack before jumping to the exception entry
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ExceptionFrame {
+    pub x: [u64; 9],
+    pub lr: u64,
+    pub sp: u64,
+    pub pc: u64,
+    pub pstate: u32,
+    pub afsr0: u32,
+    pub afsr1: u32,
+    pub esr: u32,
+    pub far: u64,
+}
+
+// x9-x29, which crt0 saves itself since the kernel doesn't store them in the frame
+pub const SAVED_REGISTER_COUNT: usize = 21;
+
+pub type SavedRegisters = [u64; SAVED_REGISTER_COUNT];
+
+pub const GPR_COUNT: usize = 29;
+
+pub const CRASH_REPORT_SIZE: usize = 0x1000;
+
+// Crash reports are formatted on the (exception) stack, since the heap might be what got corrupted
+pub struct CrashReport {
+    buf: [u8; CRASH_REPORT_SIZE],
+    len: usize,
+}
+
+impl CrashReport {
+    pub const fn new() -> Self {
+        Self { buf: [0; CRASH_REPORT_SIZE], len: 0 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are ever copied in
+        unsafe {
+            str::from_utf8_unchecked(&self.buf[..self.len])
+        }
+    }
+}
+
+impl fmt::Write for CrashReport {
+    // Whatever doesn't fit is cut off
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut copy_len = core::cmp::min(s.len(), CRASH_REPORT_SIZE - self.len);
+        while !s.is_char_boundary(copy_len) {
+            copy_len -= 1;
+        }
+        self.buf[self.len..self.len + copy_len].copy_from_slice(&s.as_bytes()[..copy_len]);
+        self.len += copy_len;
+        match copy_len == s.len() {
+            true => Ok(()),
+            false => Err(fmt::Error),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct ExceptionContext {
+    pub error_desc: u32,
+    pub gprs: [u64; GPR_COUNT],
+    pub fp: u64,
+    pub lr: u64,
+    pub sp: u64,
+    pub pc: u64,
+    pub pstate: u32,
+    pub afsr0: u32,
+    pub afsr1: u32,
+    pub esr: u32,
+    pub far: u64,
+}
+
+impl ExceptionContext {
+    pub fn from_frame(error_desc: u32, frame: &ExceptionFrame, saved_registers: &SavedRegisters) -> Self {
+        let mut gprs: [u64; GPR_COUNT] = [0; GPR_COUNT];
+        gprs[..frame.x.len()].copy_from_slice(&frame.x);
+        gprs[frame.x.len()..].copy_from_slice(&saved_registers[..GPR_COUNT - frame.x.len()]);
+        Self { error_desc: error_desc, gprs: gprs, fp: saved_registers[SAVED_REGISTER_COUNT - 1], lr: frame.lr, sp: frame.sp, pc: frame.pc, pstate: frame.pstate, afsr0: frame.afsr0, afsr1: frame.afsr1, esr: frame.esr, far: frame.far }
+    }
+
+    // Only the registers the kernel (or crt0) restores are written back: the syndrome registers are read-only
+    pub fn write_to_frame(&self, frame: &mut ExceptionFrame, saved_registers: &mut SavedRegisters) {
+        let frame_gpr_count = frame.x.len();
+        frame.x.copy_from_slice(&self.gprs[..frame_gpr_count]);
+        saved_registers[..GPR_COUNT - frame_gpr_count].copy_from_slice(&self.gprs[frame_gpr_count..]);
+        saved_registers[SAVED_REGISTER_COUNT - 1] = self.fp;
+        frame.lr = self.lr;
+        frame.sp = self.sp;
+        frame.pc = self.pc;
+        frame.pstate = self.pstate;
+    }
+
+    pub fn get_error_description(&self) -> Option<ErrorDescription> {
+        match self.error_desc {
+            0x100 => Some(ErrorDescription::InstructionAbort),
+            0x101 => Some(ErrorDescription::Other),
+            0x102 => Some(ErrorDescription::MisalignedPC),
+            0x103 => Some(ErrorDescription::MisalignedSP),
+            0x104 => Some(ErrorDescription::Trap),
+            0x106 => Some(ErrorDescription::SError),
+            0x301 => Some(ErrorDescription::BadSVC),
+            _ => None,
+        }
+    }
+
+    pub fn format_crash_report(&self) -> CrashReport {
+        let mut report = CrashReport::new();
+        // Threads which weren't created through nx::thread have no thread object to get the name from
+        let thread_name = match thread::get_current_thread().map(|thread| thread.get_name()) {
+            Some(Ok(name)) => name,
+            _ => "<unknown>",
+        };
+        let base_address = dynamic::get_self_base_address() as u64;
+
+        // A report which doesn't fit is still worth showing, so errors are ignored
+        let _ = writeln!(report, "Exception in thread {}: {:?} ({:#X})", thread_name, self.get_error_description(), self.error_desc);
+        let _ = writeln!(report, "pc: {:#018X} (base + {:#X})", self.pc, self.pc.wrapping_sub(base_address));
+        let _ = writeln!(report, "lr: {:#018X} (base + {:#X})", self.lr, self.lr.wrapping_sub(base_address));
+        let _ = writeln!(report, "sp: {:#018X}, fp: {:#018X}", self.sp, self.fp);
+        let _ = writeln!(report, "far: {:#018X}, esr: {:#010X}, pstate: {:#010X}, afsr0: {:#010X}, afsr1: {:#010X}", self.far, self.esr, self.pstate, self.afsr0, self.afsr1);
+        for (i, gpr) in self.gprs.iter().enumerate() {
+            let _ = writeln!(report, "x{}: {:#018X}", i, gpr);
+        }
+
+        let backtrace = backtrace::Backtrace::from_exception_context(self);
+        let _ = writeln!(report, "Backtrace:");
+        for (i, address) in backtrace.get_return_addresses().iter().enumerate() {
+            let _ = match backtrace.get_relative_address(*address) {
+                Some(relative_address) => writeln!(report, "#{}: {:#X} (base + {:#X})", i, address, relative_address),
+                None => writeln!(report, "#{}: {:#X} (outside the module)", i, address),
+            };
+        }
+        report
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ExceptionAction {
+    // Continue execution with the (maybe modified) context
+    Resume,
+    // Let the kernel handle the exception, which will usually terminate the process
+    Abort,
+}
+
+pub type ExceptionHandlerFn = fn(&mut ExceptionContext) -> ExceptionAction;
+
+// The handler's address (0 meaning none): it's read while handling the exception, when taking a lock could deadlock
+static G_EXCEPTION_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static G_HANDLING_EXCEPTION: AtomicBool = AtomicBool::new(false);
+
+pub fn set_exception_handler(handler: Option<ExceptionHandlerFn>) {
+    G_EXCEPTION_HANDLER.store(handler.map_or(0, |handler| handler as usize), Ordering::SeqCst);
+}
+
+pub fn get_exception_handler() -> Option<ExceptionHandlerFn> {
+    match G_EXCEPTION_HANDLER.load(Ordering::SeqCst) {
+        0 => None,
+        handler_address => Some(unsafe { mem::transmute::<usize, ExceptionHandlerFn>(handler_address) }),
+    }
+}
+
+pub fn dump_context<L: Logger>(context: &ExceptionContext) {
+    for line in context.format_crash_report().as_str().lines() {
+        diag_log!(L { log::LogSeverity::Fatal, false } => "{}", line);
+    }
+}
+
+// Ready-made handler, meant to be used like set_exception_handler(Some(log_and_abort))
+// Loggers allocate (and the faulting thread might be holding the heap lock), so the report goes straight to svc::output_debug_string
+pub fn log_and_abort(context: &mut ExceptionContext) -> ExceptionAction {
+    let report = context.format_crash_report();
+    for line in report.as_str().lines() {
+        unsafe {
+            let _ = svc::output_debug_string(line.as_ptr(), line.len());
+        }
+    }
+    ExceptionAction::Abort
+}
+
+// Called by crt0 with the frame the kernel stored and the registers crt0 saved, returns the result to return from the exception with
+pub unsafe fn handle_exception(error_desc: u32, frame: *mut ExceptionFrame, saved_registers: *mut SavedRegisters) -> ResultCode {
+    let handler = match get_exception_handler() {
+        Some(handler) => handler,
+        None => return ResultCode::from::<svc::ResultUnhandledException>(),
+    };
+
+    // An exception inside the handler itself can't be handled, so leave it to the kernel
+    if G_HANDLING_EXCEPTION.swap(true, Ordering::SeqCst) {
+        return ResultCode::from::<svc::ResultUnhandledException>();
+    }
+
+    let mut context = ExceptionContext::from_frame(error_desc, &*frame, &*saved_registers);
+    let rc = match handler(&mut context) {
+        ExceptionAction::Resume => {
+            context.write_to_frame(&mut *frame, &mut *saved_registers);
+            ResultCode::from::<ResultSuccess>()
+        },
+        ExceptionAction::Abort => ResultCode::from::<svc::ResultUnhandledException>(),
+    };
+
+    G_HANDLING_EXCEPTION.store(false, Ordering::SeqCst);
+    rc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service;
+
+    fn make_frame() -> ExceptionFrame {
+        ExceptionFrame { x: [0, 1, 2, 3, 4, 5, 6, 7, 8], lr: 0x1000, sp: 0x2000, pc: 0x3000, pstate: 0x60000000, afsr0: 1, afsr1: 2, esr: 0x92000046, far: 0x4000 }
+    }
+
+    fn make_saved_registers() -> SavedRegisters {
+        let mut saved_registers: SavedRegisters = [0; SAVED_REGISTER_COUNT];
+        for (i, register) in saved_registers.iter_mut().enumerate() {
+            *register = 9 + i as u64;
+        }
+        saved_registers
+    }
+
+    #[test]
+    fn contexts_are_made_from_frames() {
+        let context = ExceptionContext::from_frame(0x104, &make_frame(), &make_saved_registers());
+        for (i, gpr) in context.gprs.iter().enumerate() {
+            assert_eq!(*gpr, i as u64);
+        }
+        assert_eq!(context.fp, 29);
+        assert_eq!((context.lr, context.sp, context.pc, context.far), (0x1000, 0x2000, 0x3000, 0x4000));
+        assert_eq!(context.esr, 0x92000046);
+        assert_eq!(context.get_error_description(), Some(ErrorDescription::Trap));
+    }
+
+    #[test]
+    fn contexts_are_written_back_to_frames() {
+        let mut frame = make_frame();
+        let mut saved_registers = make_saved_registers();
+        let mut context = ExceptionContext::from_frame(0x100, &frame, &saved_registers);
+        context.gprs[0] = 0x10;
+        context.gprs[28] = 0x20;
+        context.fp = 0x30;
+        context.pc += 4;
+        context.esr = 0;
+        context.write_to_frame(&mut frame, &mut saved_registers);
+
+        assert_eq!(frame.x[0], 0x10);
+        assert_eq!(saved_registers[19], 0x20);
+        assert_eq!(saved_registers[20], 0x30);
+        assert_eq!(frame.pc, 0x3004);
+        // Syndrome registers are left alone
+        assert_eq!(frame.esr, 0x92000046);
+    }
+
+    fn skip_instruction(context: &mut ExceptionContext) -> ExceptionAction {
+        context.pc += 4;
+        ExceptionAction::Resume
+    }
+
+    fn abort(context: &mut ExceptionContext) -> ExceptionAction {
+        context.pc = 0;
+        ExceptionAction::Abort
+    }
+
+    #[test]
+    fn exceptions_are_handled() {
+        let _guard = service::mock::initialize();
+        let mut frame = make_frame();
+        let mut saved_registers = make_saved_registers();
+
+        set_exception_handler(None);
+        let rc = unsafe { handle_exception(0x104, &mut frame, &mut saved_registers) };
+        assert!(rc.matches::<svc::ResultUnhandledException>());
+
+        set_exception_handler(Some(skip_instruction));
+        let rc = unsafe { handle_exception(0x104, &mut frame, &mut saved_registers) };
+        assert!(rc.is_success());
+        assert_eq!(frame.pc, 0x3004);
+
+        // Aborting leaves the frame as it was
+        set_exception_handler(Some(abort));
+        let rc = unsafe { handle_exception(0x104, &mut frame, &mut saved_registers) };
+        assert!(rc.matches::<svc::ResultUnhandledException>());
+        assert_eq!(frame.pc, 0x3004);
+
+        // Exceptions inside the handler are left to the kernel
+        set_exception_handler(Some(skip_instruction));
+        G_HANDLING_EXCEPTION.store(true, Ordering::SeqCst);
+        let rc = unsafe { handle_exception(0x104, &mut frame, &mut saved_registers) };
+        assert!(rc.matches::<svc::ResultUnhandledException>());
+        assert_eq!(frame.pc, 0x3004);
+        G_HANDLING_EXCEPTION.store(false, Ordering::SeqCst);
+        set_exception_handler(None);
+    }
+
+    #[test]
+    fn crash_reports_are_logged_without_allocating() {
+        svc::mock::reset();
+        let mut context = ExceptionContext::from_frame(0x104, &make_frame(), &make_saved_registers());
+        assert_eq!(log_and_abort(&mut context), ExceptionAction::Abort);
+
+        let debug_output = svc::mock::take_debug_output();
+        assert_eq!(debug_output[0], "Exception in thread MainThread: Some(Trap) (0x104)");
+        assert!(debug_output.iter().any(|line| line == "pc: 0x0000000000003000 (base + 0x3000)"));
+        assert_eq!(debug_output.len(), context.format_crash_report().as_str().lines().count());
+    }
+
+    #[test]
+    fn crash_reports_are_cut_off_when_full() {
+        let context = ExceptionContext::from_frame(0x104, &make_frame(), &make_saved_registers());
+        let report = context.format_crash_report();
+        assert!(report.as_str().contains("pc: 0x0000000000003000"));
+        assert!(report.as_str().contains("x28: 0x000000000000001C"));
+        assert!(report.as_str().contains("#0: 0x3000 (outside the module)"));
+
+        let mut report = CrashReport::new();
+        let line = "\u{e9}".repeat(CRASH_REPORT_SIZE);
+        assert!(write!(report, "a{}", line).is_err());
+        assert_eq!(report.as_str().len(), CRASH_REPORT_SIZE - 1);
+    }
+}
\ No newline at end of file
@@ -63,12 +63,14 @@ impl Logger for SvcOutputLogger {
             LogSeverity::Error => "Error",
             LogSeverity::Fatal => "Fatal",
         };
-        let thread_name = match thread::get_current_thread().get_name() {
-            Ok(name) => name,
+        let thread_name = match thread::get_current_thread().map(|thread| thread.get_name()) {
+            Some(Ok(name)) => name,
             _ => "<unknown>",
         };
         let msg = format!("[ SvcOutputLog (severity: {}, verbosity: {}) from {} in thread {}, at {}:{} ] {}", severity_str, metadata.verbosity, metadata.fn_name, thread_name, metadata.file_name, metadata.line_no, metadata.msg);
-        let _ = svc::output_debug_string(msg.as_ptr(), msg.len());
+        unsafe {
+            let _ = svc::output_debug_string(msg.as_ptr(), msg.len());
+        }
     }
 }
 
@@ -93,14 +95,14 @@ impl Logger for FsAccessLogLogger {
             LogSeverity::Error => "Error",
             LogSeverity::Fatal => "Fatal",
         };
-        let thread_name = match thread::get_current_thread().get_name() {
-            Ok(name) => name,
+        let thread_name = match thread::get_current_thread().map(|thread| thread.get_name()) {
+            Some(Ok(name)) => name,
             _ => "<unknown>",
         };
         let msg = format!("[ FsAccessLog (severity: {}, verbosity: {}) from {} in thread {}, at {}:{} ] {}", severity_str, metadata.verbosity, metadata.fn_name, thread_name, metadata.file_name, metadata.line_no, metadata.msg);
         match self.service {
             Ok(ref mut fspsrv) => {
-                let _ = fspsrv.output_access_log_to_sd_card(msg.as_ptr(), msg.len());
+                let _ = fspsrv.output_access_log_to_sd_card(ipc::InMapAlias::from_str(&msg));
             },
             _ => {}
         }
@@ -108,6 +110,7 @@ impl Logger for FsAccessLogLogger {
 }
 
 
+use crate::ipc;
 use crate::service::lm;
 use crate::service::lm::ILogService;
 use crate::service::lm::ILogger;
@@ -182,7 +185,7 @@ trait LogDataChunkBase {
     }
 }
 
-#[allow(safe_packed_borrows)]
+#[allow(unaligned_references)]
 #[derive(Copy, Clone)]
 #[repr(C)]
 #[repr(packed)]
@@ -192,7 +195,7 @@ struct LogDataChunk<T> {
 }
 
 impl<T: Default> LogDataChunk<T> {
-    pub const fn from(key: LogDataChunkKey, value: T) -> Self {
+    pub fn from(key: LogDataChunkKey, value: T) -> Self {
         Self { header: LogDataChunkHeader::new(key, mem::size_of::<T>() as u8), value: value }
     }
 }
@@ -343,8 +346,8 @@ impl Logger for LmLogger {
                         // TODO: module name
                         head_packet.payload.module_name = LogDataStringChunk::from(LogDataChunkKey::ModuleName, String::from("aarch64-switch-rs"));
                         
-                        let thread_name = match thread::get_current_thread().get_name() {
-                            Ok(name) => name,
+                        let thread_name = match thread::get_current_thread().map(|thread| thread.get_name()) {
+                            Some(Ok(name)) => name,
                             _ => "<unknown>",
                         };
                         head_packet.payload.thread_name = LogDataStringChunk::from(LogDataChunkKey::ThreadName, String::from(thread_name));
@@ -397,7 +400,7 @@ impl Logger for LmLogger {
 
                                 match &mut self.logger {
                                     Ok(ref mut logger) => {
-                                        let _ = logger.log(log_buf, log_buf_size);
+                                        let _ = logger.log(ipc::InBuffer::from_raw(log_buf, log_buf_size));
                                     },
                                     _ => {}
                                 }
@@ -1,3 +1,7 @@
 pub mod assert;
 
-pub mod log;
\ No newline at end of file
+pub mod log;
+
+pub mod exception;
+
+pub mod backtrace;
\ No newline at end of file
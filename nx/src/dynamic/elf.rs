@@ -29,47 +29,60 @@ pub enum Tag {
     FiniArray = 26,
     InitArraySize = 27,
     FiniArraySize = 28,
-    RelaCount = 0x6FFFFFF9
+    RelaCount = 0x6FFFFFF9,
+    RelCount = 0x6FFFFFFA
 }
 
 #[derive(Copy, Clone, PartialEq)]
 #[repr(u32)]
 pub enum RelocationType {
+    AArch64None = 0,
     AArch64Abs64 = 257,
     AArch64GlobDat = 1025,
     AArch64JumpSlot = 1026,
     AArch64Relative = 1027
 }
 
+// Tags and relocation types are kept raw, since binaries can contain values not present in the enums above
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct Dyn {
-    pub tag: Tag,
+    pub tag: i64,
     pub val_ptr: u64,
 }
 
 use core::ptr;
 
 impl Dyn {
-    pub unsafe fn find_value(&self, tag: Tag) -> Result<u64> {
+    pub unsafe fn find_optional_value(&self, tag: Tag) -> Result<Option<u64>> {
         let mut found: *const u64 = ptr::null();
         let mut self_ptr = self as *const Self;
-        while (*self_ptr).tag != Tag::Invalid {
-            if (*self_ptr).tag == tag {
+        while (*self_ptr).tag != Tag::Invalid as i64 {
+            if (*self_ptr).tag == tag as i64 {
                 result_return_unless!(found.is_null(), ResultDuplicatedDtEntry);
                 found = &(*self_ptr).val_ptr;
             }
             self_ptr = self_ptr.offset(1);
         }
-        result_return_if!(found.is_null(), ResultMissingDtEntry);
-        Ok(*found)
+        match found.is_null() {
+            true => Ok(None),
+            false => Ok(Some(*found)),
+        }
+    }
+
+    pub unsafe fn find_value(&self, tag: Tag) -> Result<u64> {
+        match self.find_optional_value(tag)? {
+            Some(value) => Ok(value),
+            None => Err(ResultCode::from::<ResultMissingDtEntry>()),
+        }
     }
 }
 
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct InfoSymbol {
-    pub relocation_type: RelocationType,
+    pub relocation_type: u32,
     pub symbol: u32,
 }
 
@@ -86,4 +99,60 @@ pub struct Rela {
     pub offset: u64,
     pub info: Info,
     pub addend: i64,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Rel {
+    pub offset: u64,
+    pub info: Info,
+}
+
+pub const SYMBOL_BINDING_WEAK: u8 = 2;
+
+pub const SECTION_INDEX_UNDEFINED: u16 = 0;
+
+// Relocations referencing no symbol at all (STN_UNDEF)
+pub const SYMBOL_INDEX_UNDEFINED: u32 = 0;
+
+// Symbols whose value is an absolute one, not relative to the module's base
+pub const SECTION_INDEX_ABSOLUTE: u16 = 0xFFF1;
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Sym {
+    pub name: u32,
+    pub info: u8,
+    pub other: u8,
+    pub section_index: u16,
+    pub value: u64,
+    pub size: u64,
+}
+
+impl Sym {
+    pub fn get_binding(&self) -> u8 {
+        self.info >> 4
+    }
+
+    pub fn is_defined(&self) -> bool {
+        self.section_index != SECTION_INDEX_UNDEFINED
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        self.section_index == SECTION_INDEX_ABSOLUTE
+    }
+}
+
+// Standard SysV hash function, used by DT_HASH tables
+pub fn hash_symbol_name(name: &[u8]) -> u32 {
+    let mut hash: u32 = 0;
+    for byte in name {
+        hash = (hash << 4).wrapping_add(*byte as u32);
+        let high = hash & 0xF0000000;
+        if high != 0 {
+            hash ^= high >> 24;
+        }
+        hash &= !high;
+    }
+    hash
 }
\ No newline at end of file
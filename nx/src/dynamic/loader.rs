@@ -0,0 +1,469 @@
+extern crate alloc;
+
+use crate::result::*;
+use crate::svc;
+use crate::dynamic;
+use crate::dynamic::elf;
+use crate::dynamic::nro;
+use crate::dynamic::nso;
+use alloc::alloc::Layout;
+use enumflags2::BitFlags;
+use core::mem;
+use core::ptr;
+
+pub const RESULT_SUBMODULE: u32 = 12;
+
+result_lib_define_group!(RESULT_SUBMODULE => {
+    ResultInvalidImageSize: 1,
+    ResultInvalidImageMagic: 2,
+    ResultInvalidSegment: 3,
+    ResultImageAllocationFailed: 4,
+    ResultNoFreeAddressSpace: 5
+});
+
+const PAGE_SIZE: usize = 0x1000;
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+fn is_page_aligned(value: usize) -> bool {
+    (value & (PAGE_SIZE - 1)) == 0
+}
+
+#[derive(Copy, Clone)]
+struct Segment {
+    memory_offset: usize,
+    size: usize,
+    file_offset: usize,
+    file_size: usize,
+    is_compressed: bool,
+}
+
+struct ImageLayout {
+    text: Segment,
+    ro: Segment,
+    data: Segment,
+    bss_size: usize,
+}
+
+unsafe fn read_header<T: Copy>(image: &[u8], offset: usize) -> Result<T> {
+    result_return_if!((offset + mem::size_of::<T>()) > image.len(), ResultInvalidImageSize);
+    Ok(ptr::read_unaligned(image.as_ptr().offset(offset as isize) as *const T))
+}
+
+unsafe fn parse_nro(image: &[u8]) -> Result<ImageLayout> {
+    let header: nro::Header = read_header(image, nro::HEADER_OFFSET)?;
+    result_return_unless!(header.magic == nro::MAGIC, ResultInvalidImageMagic);
+    result_return_if!(header.size as usize > image.len(), ResultInvalidImageSize);
+
+    // NROs are already laid out as they are in memory
+    let make_segment = |segment: nro::SegmentHeader| Segment { memory_offset: segment.file_offset as usize, size: segment.size as usize, file_offset: segment.file_offset as usize, file_size: segment.size as usize, is_compressed: false };
+    Ok(ImageLayout { text: make_segment(header.text_segment), ro: make_segment(header.ro_segment), data: make_segment(header.data_segment), bss_size: header.bss_size as usize })
+}
+
+unsafe fn parse_nso(image: &[u8]) -> Result<ImageLayout> {
+    let header: nso::Header = read_header(image, 0)?;
+    result_return_unless!(header.magic == nso::MAGIC, ResultInvalidImageMagic);
+
+    let make_segment = |segment: nso::SegmentHeader, file_size: u32, is_compressed: bool| Segment { memory_offset: segment.memory_offset as usize, size: segment.size as usize, file_offset: segment.file_offset as usize, file_size: file_size as usize, is_compressed: is_compressed };
+    Ok(ImageLayout {
+        text: make_segment(header.text_segment, header.text_file_size, header.flags.contains(nso::HeaderFlags::TextCompressed)),
+        ro: make_segment(header.ro_segment, header.ro_file_size, header.flags.contains(nso::HeaderFlags::RoCompressed)),
+        data: make_segment(header.data_segment, header.data_file_size, header.flags.contains(nso::HeaderFlags::DataCompressed)),
+        bss_size: header.bss_size as usize,
+    })
+}
+
+unsafe fn parse_image(image: &[u8]) -> Result<ImageLayout> {
+    let layout = match read_header::<u32>(image, 0)? {
+        nso::MAGIC => parse_nso(image)?,
+        _ => parse_nro(image)?,
+    };
+
+    // Segments must be page-aligned, in order and not overlapping, and their contents must be inside the image
+    for segment in [layout.text, layout.ro, layout.data].iter() {
+        result_return_unless!(is_page_aligned(segment.memory_offset), ResultInvalidSegment);
+        result_return_if!((segment.file_offset + segment.file_size) > image.len(), ResultInvalidSegment);
+        result_return_if!(!segment.is_compressed && (segment.file_size != segment.size), ResultInvalidSegment);
+    }
+    result_return_unless!(layout.text.memory_offset == 0, ResultInvalidSegment);
+    result_return_if!((layout.text.memory_offset + layout.text.size) > layout.ro.memory_offset, ResultInvalidSegment);
+    result_return_if!((layout.ro.memory_offset + layout.ro.size) > layout.data.memory_offset, ResultInvalidSegment);
+    result_return_if!(layout.text.size < mem::size_of::<dynamic::ModuleStart>(), ResultInvalidSegment);
+    Ok(layout)
+}
+
+unsafe fn load_segment(image: &[u8], segment: &Segment, image_address: *mut u8) -> Result<()> {
+    let src = &image[segment.file_offset..segment.file_offset + segment.file_size];
+    let dst = core::slice::from_raw_parts_mut(image_address.offset(segment.memory_offset as isize), segment.size);
+    match segment.is_compressed {
+        true => nso::decompress_segment(src, dst),
+        false => {
+            dst.copy_from_slice(src);
+            Ok(())
+        }
+    }
+}
+
+fn find_free_address(size: usize) -> Result<*mut u8> {
+    let region_address = svc::get_info(svc::InfoId::AslrRegionAddress, svc::CURRENT_PROCESS_PSEUDO_HANDLE, 0)? as usize;
+    let region_size = svc::get_info(svc::InfoId::AslrRegionSize, svc::CURRENT_PROCESS_PSEUDO_HANDLE, 0)? as usize;
+    let region_end = region_address + region_size;
+
+    let mut address = region_address;
+    while (address + size) <= region_end {
+        let mut info = mem::MaybeUninit::<svc::MemoryInfo>::uninit();
+        let (base_address, info_size, memory_state) = unsafe {
+            svc::query_memory(info.as_mut_ptr(), address as *const u8)?;
+            ((*info.as_ptr()).base_address as usize, (*info.as_ptr()).size as usize, (*info.as_ptr()).memory_state)
+        };
+        let info_end = base_address + info_size;
+        if (memory_state == svc::MemoryState::Free) && (info_end >= (address + size)) {
+            return Ok(address as *mut u8);
+        }
+        result_return_if!(info_end <= address, ResultNoFreeAddressSpace);
+        address = info_end;
+    }
+    Err(ResultCode::from::<ResultNoFreeAddressSpace>())
+}
+
+// Dropping a module doesn't unload it: its fini array wouldn't be called and its memory would stay mapped, so unload() must always be called
+#[must_use = "modules stay loaded until they're unloaded with unload()"]
+pub struct Module {
+    base_address: *mut u8,
+    size: usize,
+    source_address: *mut u8,
+    process_handle: svc::Handle,
+    dynamic: *const elf::Dyn,
+}
+
+impl Module {
+    pub fn get_base_address(&self) -> *const u8 {
+        self.base_address
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    fn get_image(&self) -> dynamic::Image {
+        dynamic::Image::new(self.base_address, self.size)
+    }
+
+    pub fn find_symbol(&self, name: &str) -> Result<Option<*const u8>> {
+        unsafe {
+            dynamic::find_image_symbol(&self.get_image(), self.dynamic, name)
+        }
+    }
+
+    pub fn unload(self) -> Result<()> {
+        unsafe {
+            dynamic::call_image_fini_array(&self.get_image(), self.dynamic)?;
+        }
+        self.unmap()
+    }
+
+    fn unmap(&self) -> Result<()> {
+        svc::unmap_process_code_memory(self.process_handle, self.base_address, self.source_address, self.size)?;
+        // Only freed once unmapped, the source memory is still in use otherwise
+        unsafe {
+            alloc::alloc::dealloc(self.source_address, Layout::from_size_align_unchecked(self.size, PAGE_SIZE));
+        }
+        Ok(())
+    }
+}
+
+unsafe fn map_module(process_handle: svc::Handle, layout: &ImageLayout, base_address: *mut u8, source_address: *mut u8, size: usize) -> Result<()> {
+    svc::map_process_code_memory(process_handle, base_address, source_address, size)?;
+
+    let set_permissions = |start: usize, end: usize, permissions: BitFlags<svc::MemoryPermission>| match end > start {
+        true => svc::set_process_memory_permission(process_handle, base_address.offset(start as isize), end - start, permissions),
+        false => Ok(()),
+    };
+    let rc = set_permissions(layout.text.memory_offset, layout.ro.memory_offset, svc::MemoryPermission::Read | svc::MemoryPermission::Execute)
+        .and_then(|_| set_permissions(layout.ro.memory_offset, layout.data.memory_offset, BitFlags::from(svc::MemoryPermission::Read)))
+        .and_then(|_| set_permissions(layout.data.memory_offset, size, svc::MemoryPermission::Read | svc::MemoryPermission::Write));
+    if let Err(rc) = rc {
+        let _ = svc::unmap_process_code_memory(process_handle, base_address, source_address, size);
+        return Err(rc);
+    }
+    Ok(())
+}
+
+// The source memory becomes inaccessible once mapped, so relocations are done before (another thread mapping memory at the same address meanwhile would just make mapping fail)
+unsafe fn relocate_source(layout: &ImageLayout, source_address: *mut u8, size: usize, base_address: *const u8) -> Result<*const elf::Dyn> {
+    let source_dynamic = dynamic::get_image_module_dynamic(source_address, layout.text.size, size)?;
+    dynamic::relocate_image_with_resolver(source_address, size, base_address, source_dynamic, |name| match dynamic::find_self_symbol(name) {
+        Ok(address) => address,
+        Err(_) => None,
+    })?;
+    Ok(source_dynamic)
+}
+
+// The process handle must have rights over our own process (for instance, the one hbloader provides)
+// Symbols not found in the module itself are resolved against the running executable
+pub unsafe fn load(image: &[u8], process_handle: svc::Handle) -> Result<Module> {
+    let layout = parse_image(image)?;
+    let size = align_up(layout.data.memory_offset + layout.data.size + layout.bss_size, PAGE_SIZE);
+
+    let source_layout = Layout::from_size_align_unchecked(size, PAGE_SIZE);
+    let source_address = alloc::alloc::alloc_zeroed(source_layout);
+    result_return_if!(source_address.is_null(), ResultImageAllocationFailed);
+
+    let prepare = || -> Result<(*mut u8, usize)> {
+        for segment in [layout.text, layout.ro, layout.data].iter() {
+            load_segment(image, segment, source_address)?;
+        }
+
+        let base_address = find_free_address(size)?;
+        let source_dynamic = relocate_source(&layout, source_address, size, base_address)?;
+
+        map_module(process_handle, &layout, base_address, source_address, size)?;
+        Ok((base_address, source_dynamic as usize - source_address as usize))
+    };
+
+    let (base_address, dynamic_offset) = match prepare() {
+        Ok(mapped) => mapped,
+        Err(rc) => {
+            alloc::alloc::dealloc(source_address, source_layout);
+            return Err(rc);
+        }
+    };
+
+    let module = Module { base_address: base_address, size: size, source_address: source_address, process_handle: process_handle, dynamic: base_address.offset(dynamic_offset as isize) as *const elf::Dyn };
+    if let Err(rc) = dynamic::call_image_init_array(&module.get_image(), module.dynamic) {
+        let _ = module.unmap();
+        return Err(rc);
+    }
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    const SEGMENT_SIZE: u32 = PAGE_SIZE as u32;
+
+    fn write_header<T: Copy>(image: &mut Vec<u8>, offset: usize, header: T) {
+        unsafe {
+            ptr::write_unaligned(image.as_mut_ptr().offset(offset as isize) as *mut T, header);
+        }
+    }
+
+    fn make_nro_header() -> nro::Header {
+        let mut header: nro::Header = unsafe { mem::zeroed() };
+        header.magic = nro::MAGIC;
+        header.size = SEGMENT_SIZE * 3;
+        header.text_segment = nro::SegmentHeader { file_offset: 0, size: SEGMENT_SIZE };
+        header.ro_segment = nro::SegmentHeader { file_offset: SEGMENT_SIZE, size: SEGMENT_SIZE };
+        header.data_segment = nro::SegmentHeader { file_offset: SEGMENT_SIZE * 2, size: SEGMENT_SIZE };
+        header.bss_size = 0x100;
+        header
+    }
+
+    fn make_nro(header: nro::Header) -> Vec<u8> {
+        let mut image = vec![0; (SEGMENT_SIZE * 3) as usize];
+        write_header(&mut image, nro::HEADER_OFFSET, header);
+        image
+    }
+
+    // Compressed segments take less space in the file than in memory
+    fn make_nso_header() -> nso::Header {
+        let mut header: nso::Header = unsafe { mem::zeroed() };
+        header.magic = nso::MAGIC;
+        header.flags = BitFlags::from(nso::HeaderFlags::TextCompressed);
+        header.text_segment = nso::SegmentHeader { file_offset: 0x100, memory_offset: 0, size: SEGMENT_SIZE };
+        header.text_file_size = 0x100;
+        header.ro_segment = nso::SegmentHeader { file_offset: 0x200, memory_offset: SEGMENT_SIZE, size: SEGMENT_SIZE };
+        header.ro_file_size = SEGMENT_SIZE;
+        header.data_segment = nso::SegmentHeader { file_offset: 0x200 + SEGMENT_SIZE, memory_offset: SEGMENT_SIZE * 2, size: SEGMENT_SIZE };
+        header.data_file_size = SEGMENT_SIZE;
+        header
+    }
+
+    fn make_nso(header: nso::Header) -> Vec<u8> {
+        let mut image = vec![0; 0x200 + (SEGMENT_SIZE * 2) as usize];
+        write_header(&mut image, 0, header);
+        image
+    }
+
+    fn parse(image: &[u8]) -> Result<ImageLayout> {
+        unsafe {
+            parse_image(image)
+        }
+    }
+
+    fn parse_error(image: &[u8]) -> ResultCode {
+        match parse(image) {
+            Ok(_) => panic!("The image was parsed"),
+            Err(rc) => rc,
+        }
+    }
+
+    #[test]
+    fn valid_images_are_parsed() {
+        let layout = parse(&make_nro(make_nro_header())).unwrap();
+        assert_eq!(layout.data.memory_offset, (SEGMENT_SIZE * 2) as usize);
+        assert_eq!(layout.bss_size, 0x100);
+        assert!(!layout.text.is_compressed);
+
+        let layout = parse(&make_nso(make_nso_header())).unwrap();
+        assert!(layout.text.is_compressed);
+        assert_eq!(layout.text.file_size, 0x100);
+        assert_eq!(layout.text.size, SEGMENT_SIZE as usize);
+    }
+
+    #[test]
+    fn truncated_images_are_rejected() {
+        assert!(parse_error(&[0; 2]).matches::<ResultInvalidImageSize>());
+
+        let image = make_nro(make_nro_header());
+        assert!(parse_error(&image[..nro::HEADER_OFFSET + 8]).matches::<ResultInvalidImageSize>());
+        let image = make_nso(make_nso_header());
+        assert!(parse_error(&image[..0x20]).matches::<ResultInvalidImageSize>());
+
+        let mut header = make_nro_header();
+        header.size += 1;
+        assert!(parse_error(&make_nro(header)).matches::<ResultInvalidImageSize>());
+    }
+
+    #[test]
+    fn invalid_magics_are_rejected() {
+        let mut header = make_nro_header();
+        header.magic = nso::MAGIC + 1;
+        assert!(parse_error(&make_nro(header)).matches::<ResultInvalidImageMagic>());
+        assert!(parse_error(&vec![0; 0x100]).matches::<ResultInvalidImageMagic>());
+    }
+
+    #[test]
+    fn invalid_nro_segments_are_rejected() {
+        let mut header = make_nro_header();
+        header.ro_segment.file_offset += 0x10;
+        assert!(parse_error(&make_nro(header)).matches::<ResultInvalidSegment>());
+
+        let mut header = make_nro_header();
+        header.text_segment.size += SEGMENT_SIZE;
+        assert!(parse_error(&make_nro(header)).matches::<ResultInvalidSegment>());
+
+        let mut header = make_nro_header();
+        header.data_segment.size += SEGMENT_SIZE;
+        assert!(parse_error(&make_nro(header)).matches::<ResultInvalidSegment>());
+
+        let mut header = make_nro_header();
+        header.text_segment.size = 0;
+        header.text_segment.file_offset = SEGMENT_SIZE;
+        assert!(parse_error(&make_nro(header)).matches::<ResultInvalidSegment>());
+    }
+
+    #[test]
+    fn invalid_nso_segments_are_rejected() {
+        // Only compressed segments may have a different size in the file
+        let mut header = make_nso_header();
+        header.flags = BitFlags::empty();
+        assert!(parse_error(&make_nso(header)).matches::<ResultInvalidSegment>());
+
+        let mut header = make_nso_header();
+        header.data_file_size += 1;
+        header.flags |= nso::HeaderFlags::DataCompressed;
+        assert!(parse_error(&make_nso(header)).matches::<ResultInvalidSegment>());
+
+        let mut header = make_nso_header();
+        header.ro_segment.memory_offset = SEGMENT_SIZE * 3;
+        assert!(parse_error(&make_nso(header)).matches::<ResultInvalidSegment>());
+
+        let mut header = make_nso_header();
+        header.text_segment.memory_offset = SEGMENT_SIZE;
+        header.ro_segment.memory_offset = SEGMENT_SIZE * 2;
+        header.data_segment.memory_offset = SEGMENT_SIZE * 3;
+        assert!(parse_error(&make_nso(header)).matches::<ResultInvalidSegment>());
+
+        // The text segment must at least hold the module start
+        let mut header = make_nso_header();
+        header.text_segment.size = 4;
+        assert!(parse_error(&make_nso(header)).matches::<ResultInvalidSegment>());
+    }
+
+    const MODULE_OFFSET: usize = 0x10;
+    const DYNAMIC_OFFSET: usize = 0x30;
+    const RELA_TABLE_OFFSET: usize = 0x100;
+    const SOURCE_SIZE: usize = (SEGMENT_SIZE * 3) as usize;
+    const BASE_ADDRESS: u64 = 0x8000_0000;
+
+    // Loaded segments, as relocate_source gets them (kept in u64s so that it's properly aligned)
+    struct TestSource {
+        data: Vec<u64>,
+    }
+
+    impl TestSource {
+        fn new(relocation_offset: u64) -> Self {
+            let mut source = Self { data: vec![0; SOURCE_SIZE / mem::size_of::<u64>()] };
+            source.write(0, dynamic::ModuleStart { reserved: 0, magic_offset: MODULE_OFFSET as u32 });
+            source.write(MODULE_OFFSET, dynamic::mod0::Header { magic: dynamic::mod0::MAGIC, dynamic: (DYNAMIC_OFFSET - MODULE_OFFSET) as u32, bss_start: 0, bss_end: 0, eh_frame_hdr_start: 0, eh_frame_hdr_end: 0, module_object: 0 });
+            let dynamic = [
+                (elf::Tag::RelaOffset, RELA_TABLE_OFFSET as u64),
+                (elf::Tag::RelaSize, mem::size_of::<elf::Rela>() as u64),
+                (elf::Tag::RelaEntrySize, mem::size_of::<elf::Rela>() as u64),
+            ];
+            for (i, (tag, value)) in dynamic.iter().enumerate() {
+                source.write(DYNAMIC_OFFSET + i * mem::size_of::<elf::Dyn>(), elf::Dyn { tag: *tag as i64, val_ptr: *value });
+            }
+            let info = elf::Info { symbol: elf::InfoSymbol { relocation_type: elf::RelocationType::AArch64Relative as u32, symbol: 0 } };
+            source.write(RELA_TABLE_OFFSET, elf::Rela { offset: relocation_offset, info: info, addend: 0x10 });
+            source
+        }
+
+        fn write<T: Copy>(&mut self, offset: usize, value: T) {
+            unsafe {
+                ptr::write_unaligned((self.data.as_mut_ptr() as *mut u8).offset(offset as isize) as *mut T, value);
+            }
+        }
+
+        fn relocate(&mut self) -> Result<*const elf::Dyn> {
+            let layout = parse(&make_nro(make_nro_header())).unwrap();
+            unsafe {
+                relocate_source(&layout, self.data.as_mut_ptr() as *mut u8, SOURCE_SIZE, BASE_ADDRESS as *const u8)
+            }
+        }
+    }
+
+    #[test]
+    fn sources_are_relocated() {
+        let mut source = TestSource::new(SEGMENT_SIZE as u64 * 2);
+        let dynamic = source.relocate().unwrap();
+        assert_eq!(dynamic as usize - source.data.as_ptr() as usize, DYNAMIC_OFFSET);
+        assert_eq!(source.data[(SEGMENT_SIZE * 2) as usize / mem::size_of::<u64>()], BASE_ADDRESS + 0x10);
+    }
+
+    #[test]
+    fn invalid_mod0_offsets_are_rejected() {
+        // The MOD0 header has to be inside the text segment
+        let mut source = TestSource::new(0);
+        source.write(0, dynamic::ModuleStart { reserved: 0, magic_offset: SEGMENT_SIZE - 8 });
+        assert!(source.relocate().unwrap_err().matches::<dynamic::ResultOutOfImageBounds>());
+
+        let mut source = TestSource::new(0);
+        source.write(0, dynamic::ModuleStart { reserved: 0, magic_offset: u32::MAX });
+        assert!(source.relocate().unwrap_err().matches::<dynamic::ResultOutOfImageBounds>());
+
+        let mut source = TestSource::new(0);
+        source.write(0, dynamic::ModuleStart { reserved: 0, magic_offset: 0x20 });
+        assert!(source.relocate().unwrap_err().matches::<dynamic::ResultInvalidMod0Magic>());
+
+        // So does the whole dynamic section
+        let mut source = TestSource::new(0);
+        source.write(MODULE_OFFSET + 4, (SOURCE_SIZE - MODULE_OFFSET - 8) as u32);
+        assert!(source.relocate().unwrap_err().matches::<dynamic::ResultOutOfImageBounds>());
+    }
+
+    #[test]
+    fn out_of_range_relocations_are_rejected() {
+        let mut source = TestSource::new((SOURCE_SIZE - 4) as u64);
+        assert!(source.relocate().unwrap_err().matches::<dynamic::ResultOutOfImageBounds>());
+
+        let mut source = TestSource::new(0);
+        source.write(DYNAMIC_OFFSET + 8, SOURCE_SIZE as u64);
+        assert!(source.relocate().unwrap_err().matches::<dynamic::ResultOutOfImageBounds>());
+    }
+}
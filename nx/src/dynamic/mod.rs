@@ -1,7 +1,11 @@
 pub mod elf;
 pub mod mod0;
+pub mod nro;
+pub mod nso;
+pub mod loader;
 
 use crate::result::*;
+use core::ptr;
 
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -13,39 +17,599 @@ pub struct ModuleStart {
 pub const RESULT_SUBMODULE: u32 = 1;
 
 result_lib_define_group!(RESULT_SUBMODULE => {
-    ResultRelaSizeMismatch: 1
+    ResultRelaSizeMismatch: 1,
+    ResultRelSizeMismatch: 2,
+    ResultInvalidPltRelType: 3,
+    ResultUnsupportedRelocationType: 4,
+    ResultUndefinedSymbol: 5,
+    ResultInvalidMod0Magic: 6,
+    ResultOutOfImageBounds: 7
 });
 
-pub unsafe fn relocate_with_dyn(base_address: *const u8, dynamic: *const elf::Dyn) -> Result<()> {
-    let rela_offset = (*dynamic).find_value(elf::Tag::RelaOffset)?;
-    let rela_size = (*dynamic).find_value(elf::Tag::RelaSize)?;
-    let rela_entry_size = (*dynamic).find_value(elf::Tag::RelaEntrySize)?;
-    let rela_count = (*dynamic).find_value(elf::Tag::RelaCount)?;
-    result_return_unless!(rela_size == rela_entry_size * rela_count, ResultRelaSizeMismatch);
+// Foreign images (like the modules the loader loads) might be malformed, so everything read from them is checked to be inside the image
+// Our own image is trusted instead, and its size isn't even known
+#[derive(Copy, Clone)]
+struct Image {
+    address: *mut u8,
+    size: usize,
+}
+
+impl Image {
+    const fn new(address: *mut u8, size: usize) -> Self {
+        Self { address: address, size: size }
+    }
+
+    const fn new_unbounded(address: *const u8) -> Self {
+        Self::new(address as *mut u8, usize::MAX)
+    }
+
+    fn check_range(&self, offset: u64, size: u64) -> Result<()> {
+        let is_inside = match offset.checked_add(size) {
+            Some(end) => end <= self.size as u64,
+            None => false,
+        };
+        result_return_unless!(is_inside, ResultOutOfImageBounds);
+        Ok(())
+    }
+
+    unsafe fn get<T>(&self, offset: u64) -> Result<*mut T> {
+        self.check_range(offset, core::mem::size_of::<T>() as u64)?;
+        Ok(self.address.offset(offset as isize) as *mut T)
+    }
+
+    unsafe fn get_c_str_bytes(&self, offset: u64) -> Result<&'static [u8]> {
+        let c_str = self.get::<u8>(offset)?;
+        let mut len: usize = 0;
+        while *c_str.offset(len as isize) != 0 {
+            len += 1;
+            self.check_range(offset, len as u64 + 1)?;
+        }
+        Ok(core::slice::from_raw_parts(c_str, len))
+    }
+}
+
+struct SymbolTables {
+    symbol_table_offset: u64,
+    string_table_offset: u64,
+}
+
+impl SymbolTables {
+    unsafe fn get_symbol(&self, image: &Image, symbol_index: u32) -> Result<&'static elf::Sym> {
+        let symbol_offset = self.symbol_table_offset.checked_add(symbol_index as u64 * core::mem::size_of::<elf::Sym>() as u64);
+        match symbol_offset {
+            Some(symbol_offset) => Ok(&*image.get::<elf::Sym>(symbol_offset)?),
+            None => Err(ResultCode::from::<ResultOutOfImageBounds>()),
+        }
+    }
 
-    let rela_base = base_address.offset(rela_offset as isize) as *const elf::Rela;
+    unsafe fn get_symbol_name(&self, image: &Image, symbol: &elf::Sym) -> Result<&'static [u8]> {
+        image.get_c_str_bytes(self.string_table_offset.wrapping_add(symbol.name as u64))
+    }
+}
+
+unsafe fn load_symbol_tables(dynamic: *const elf::Dyn) -> Result<Option<SymbolTables>> {
+    let symbol_table = (*dynamic).find_optional_value(elf::Tag::SymTab)?;
+    let string_table = (*dynamic).find_optional_value(elf::Tag::StrTab)?;
+    match (symbol_table, string_table) {
+        (Some(symbol_table_offset), Some(string_table_offset)) => Ok(Some(SymbolTables {
+            symbol_table_offset: symbol_table_offset,
+            string_table_offset: string_table_offset,
+        })),
+        _ => Ok(None),
+    }
+}
+
+unsafe fn resolve_symbol<F: Fn(&str) -> Option<*const u8>>(image: &Image, load_address: *const u8, symbol_tables: &Option<SymbolTables>, symbol_index: u32, resolver: &F) -> Result<u64> {
+    // Their value is just zero, so only the addend is left
+    if symbol_index == elf::SYMBOL_INDEX_UNDEFINED {
+        return Ok(0);
+    }
+
+    let symbol_tables = match symbol_tables {
+        Some(symbol_tables) => symbol_tables,
+        None => return Err(ResultCode::from::<elf::ResultMissingDtEntry>()),
+    };
+
+    let symbol = symbol_tables.get_symbol(image, symbol_index)?;
+    if symbol.is_absolute() {
+        return Ok(symbol.value);
+    }
+    if symbol.is_defined() {
+        return Ok(load_address as u64 + symbol.value);
+    }
+
+    let name = symbol_tables.get_symbol_name(image, symbol)?;
+    let resolved = match core::str::from_utf8(name) {
+        Ok(name) => resolver(name),
+        Err(_) => None,
+    };
+    match resolved {
+        Some(address) => Ok(address as u64),
+        // Unresolved weak symbols are just null
+        None if symbol.get_binding() == elf::SYMBOL_BINDING_WEAK => Ok(0),
+        None => Err(ResultCode::from::<ResultUndefinedSymbol>()),
+    }
+}
+
+unsafe fn apply_relocation<F: Fn(&str) -> Option<*const u8>>(image: &Image, load_address: *const u8, symbol_tables: &Option<SymbolTables>, offset: u64, info: elf::Info, addend: Option<i64>, resolver: &F) -> Result<()> {
+    let target = image.get::<u64>(offset)?;
+    // REL entries have their addend already stored in the target
+    let addend = match addend {
+        Some(addend) => addend,
+        None => *target as i64,
+    };
+
+    match info.symbol.relocation_type {
+        relocation_type if relocation_type == elf::RelocationType::AArch64None as u32 => {},
+        relocation_type if relocation_type == elf::RelocationType::AArch64Relative as u32 => {
+            *target = (load_address as u64).wrapping_add(addend as u64);
+        },
+        relocation_type if (relocation_type == elf::RelocationType::AArch64Abs64 as u32) || (relocation_type == elf::RelocationType::AArch64GlobDat as u32) || (relocation_type == elf::RelocationType::AArch64JumpSlot as u32) => {
+            let symbol_value = resolve_symbol(image, load_address, symbol_tables, info.symbol.symbol, resolver)?;
+            *target = symbol_value.wrapping_add(addend as u64);
+        },
+        _ => return Err(ResultCode::from::<ResultUnsupportedRelocationType>()),
+    };
+    Ok(())
+}
+
+unsafe fn apply_rela_table<F: Fn(&str) -> Option<*const u8>>(image: &Image, load_address: *const u8, symbol_tables: &Option<SymbolTables>, table_offset: u64, table_size: u64, resolver: &F) -> Result<()> {
+    image.check_range(table_offset, table_size)?;
+    let rela_base = image.address.offset(table_offset as isize) as *const elf::Rela;
+    let rela_count = table_size as usize / core::mem::size_of::<elf::Rela>();
     for i in 0..rela_count {
         let rela = rela_base.offset(i as isize);
-        match (*rela).info.symbol.relocation_type {
-            elf::RelocationType::AArch64Relative => {
-                if (*rela).info.symbol.symbol == 0 {
-                    let relocation_offset = base_address.offset((*rela).offset as isize) as *mut *const u8;
-                    *relocation_offset = base_address.offset((*rela).addend as isize);
-                }
-            },
-            _ => {}
+        apply_relocation(image, load_address, symbol_tables, (*rela).offset, (*rela).info, Some((*rela).addend), resolver)?;
+    }
+    Ok(())
+}
+
+unsafe fn apply_rel_table<F: Fn(&str) -> Option<*const u8>>(image: &Image, load_address: *const u8, symbol_tables: &Option<SymbolTables>, table_offset: u64, table_size: u64, resolver: &F) -> Result<()> {
+    image.check_range(table_offset, table_size)?;
+    let rel_base = image.address.offset(table_offset as isize) as *const elf::Rel;
+    let rel_count = table_size as usize / core::mem::size_of::<elf::Rel>();
+    for i in 0..rel_count {
+        let rel = rel_base.offset(i as isize);
+        apply_relocation(image, load_address, symbol_tables, (*rel).offset, (*rel).info, None, resolver)?;
+    }
+    Ok(())
+}
+
+unsafe fn relocate_image<F: Fn(&str) -> Option<*const u8>>(image: &Image, load_address: *const u8, dynamic: *const elf::Dyn, resolver: &F) -> Result<()> {
+    let symbol_tables = load_symbol_tables(dynamic)?;
+
+    if let Some(rela_offset) = (*dynamic).find_optional_value(elf::Tag::RelaOffset)? {
+        let rela_size = (*dynamic).find_value(elf::Tag::RelaSize)?;
+        let rela_entry_size = (*dynamic).find_value(elf::Tag::RelaEntrySize)?;
+        result_return_unless!(rela_entry_size as usize == core::mem::size_of::<elf::Rela>(), ResultRelaSizeMismatch);
+        result_return_unless!(rela_size % rela_entry_size == 0, ResultRelaSizeMismatch);
+        apply_rela_table(image, load_address, &symbol_tables, rela_offset, rela_size, resolver)?;
+    }
+
+    if let Some(rel_offset) = (*dynamic).find_optional_value(elf::Tag::RelOffset)? {
+        let rel_size = (*dynamic).find_value(elf::Tag::RelSize)?;
+        let rel_entry_size = (*dynamic).find_value(elf::Tag::RelEntrySize)?;
+        result_return_unless!(rel_entry_size as usize == core::mem::size_of::<elf::Rel>(), ResultRelSizeMismatch);
+        result_return_unless!(rel_size % rel_entry_size == 0, ResultRelSizeMismatch);
+        apply_rel_table(image, load_address, &symbol_tables, rel_offset, rel_size, resolver)?;
+    }
+
+    if let Some(jmp_rel_offset) = (*dynamic).find_optional_value(elf::Tag::JmpRel)? {
+        let plt_rel_size = (*dynamic).find_value(elf::Tag::PltRelSize)?;
+        let plt_rel_type = (*dynamic).find_value(elf::Tag::PltRel)?;
+        match plt_rel_type {
+            plt_rel_type if plt_rel_type == elf::Tag::RelaOffset as u64 => apply_rela_table(image, load_address, &symbol_tables, jmp_rel_offset, plt_rel_size, resolver)?,
+            plt_rel_type if plt_rel_type == elf::Tag::RelOffset as u64 => apply_rel_table(image, load_address, &symbol_tables, jmp_rel_offset, plt_rel_size, resolver)?,
+            _ => return Err(ResultCode::from::<ResultInvalidPltRelType>()),
+        };
+    }
+
+    Ok(())
+}
+
+// The image is relocated where it currently is, but as if it was located at the load address (the dynamic section must be the one inside the image, see get_image_module_dynamic)
+// Symbols not defined in the module itself are looked up through the resolver (for instance, with find_symbol on other modules)
+pub unsafe fn relocate_image_with_resolver<F: Fn(&str) -> Option<*const u8>>(image_address: *mut u8, image_size: usize, load_address: *const u8, dynamic: *const elf::Dyn, resolver: F) -> Result<()> {
+    relocate_image(&Image::new(image_address, image_size), load_address, dynamic, &resolver)
+}
+
+pub unsafe fn relocate_with_dyn_and_resolver<F: Fn(&str) -> Option<*const u8>>(base_address: *const u8, dynamic: *const elf::Dyn, resolver: F) -> Result<()> {
+    relocate_image(&Image::new_unbounded(base_address), base_address, dynamic, &resolver)
+}
+
+pub unsafe fn relocate_with_dyn(base_address: *const u8, dynamic: *const elf::Dyn) -> Result<()> {
+    relocate_with_dyn_and_resolver(base_address, dynamic, |_| None)
+}
+
+unsafe fn find_image_symbol(image: &Image, dynamic: *const elf::Dyn, name: &str) -> Result<Option<*const u8>> {
+    let symbol_tables = match load_symbol_tables(dynamic)? {
+        Some(symbol_tables) => symbol_tables,
+        None => return Ok(None),
+    };
+    let hash_table_offset = (*dynamic).find_value(elf::Tag::Hash)?;
+    let hash_table = image.get::<[u32; 2]>(hash_table_offset)? as *const u32;
+
+    let bucket_count = *hash_table;
+    if bucket_count == 0 {
+        return Ok(None);
+    }
+    let chain_count = *hash_table.offset(1);
+    image.check_range(hash_table_offset, (2 + bucket_count as u64 + chain_count as u64) * core::mem::size_of::<u32>() as u64)?;
+    let buckets = hash_table.offset(2);
+    let chains = buckets.offset(bucket_count as isize);
+
+    let name_bytes = name.as_bytes();
+    let mut symbol_index = *buckets.offset((elf::hash_symbol_name(name_bytes) % bucket_count) as isize);
+    while symbol_index != 0 {
+        result_return_unless!(symbol_index < chain_count, ResultOutOfImageBounds);
+        let symbol = symbol_tables.get_symbol(image, symbol_index)?;
+        if symbol.is_defined() && (symbol_tables.get_symbol_name(image, symbol)? == name_bytes) {
+            return match symbol.is_absolute() {
+                true => Ok(Some(symbol.value as *const u8)),
+                false => Ok(Some(image.address.offset(symbol.value as isize) as *const u8)),
+            };
         }
+        symbol_index = *chains.offset(symbol_index as isize);
+    }
+    Ok(None)
+}
+
+// Looks up a symbol defined by the module through its DT_HASH table
+pub unsafe fn find_symbol(base_address: *const u8, dynamic: *const elf::Dyn, name: &str) -> Result<Option<*const u8>> {
+    find_image_symbol(&Image::new_unbounded(base_address), dynamic, name)
+}
+
+pub type InitFiniFn = extern "C" fn();
+
+unsafe fn call_function_array(image: &Image, dynamic: *const elf::Dyn, array_tag: elf::Tag, array_size_tag: elf::Tag, reverse: bool) -> Result<()> {
+    let array_offset = match (*dynamic).find_optional_value(array_tag)? {
+        Some(array_offset) => array_offset,
+        None => return Ok(()),
+    };
+    let array_size = (*dynamic).find_value(array_size_tag)?;
+    image.check_range(array_offset, array_size)?;
+
+    let array = image.address.offset(array_offset as isize) as *const InitFiniFn;
+    let fn_count = array_size as usize / core::mem::size_of::<InitFiniFn>();
+    for i in 0..fn_count {
+        let index = match reverse {
+            true => fn_count - 1 - i,
+            false => i,
+        };
+        (*array.offset(index as isize))();
     }
     Ok(())
 }
 
-pub unsafe fn relocate(base_address: *const u8) -> Result<()> {
+unsafe fn call_image_init_array(image: &Image, dynamic: *const elf::Dyn) -> Result<()> {
+    call_function_array(image, dynamic, elf::Tag::InitArray, elf::Tag::InitArraySize, false)
+}
+
+unsafe fn call_image_fini_array(image: &Image, dynamic: *const elf::Dyn) -> Result<()> {
+    call_function_array(image, dynamic, elf::Tag::FiniArray, elf::Tag::FiniArraySize, true)
+}
+
+// The module must be already relocated, since the arrays contain absolute addresses
+pub unsafe fn call_init_array(base_address: *const u8, dynamic: *const elf::Dyn) -> Result<()> {
+    call_image_init_array(&Image::new_unbounded(base_address), dynamic)
+}
+
+pub unsafe fn call_fini_array(base_address: *const u8, dynamic: *const elf::Dyn) -> Result<()> {
+    call_image_fini_array(&Image::new_unbounded(base_address), dynamic)
+}
+
+pub unsafe fn get_module_header(base_address: *const u8) -> Result<*const mod0::Header> {
     let module_start = base_address as *const ModuleStart;
-    let mod_offset = (*module_start).magic_offset as isize;
-    let module = base_address.offset(mod_offset) as *const mod0::Header;
-    assert!((*module).magic == mod0::MAGIC);
+    let module = base_address.offset((*module_start).magic_offset as isize) as *const mod0::Header;
+    result_return_unless!((*module).magic == mod0::MAGIC, ResultInvalidMod0Magic);
+
+    Ok(module)
+}
+
+pub unsafe fn get_module_dynamic(base_address: *const u8) -> Result<*const elf::Dyn> {
+    let module = get_module_header(base_address)?;
+    Ok((module as *const u8).offset((*module).dynamic as isize) as *const elf::Dyn)
+}
+
+// Like get_module_dynamic, but for foreign images: the MOD0 header must be inside the text segment, and the whole dynamic section (terminator included) inside the image
+pub unsafe fn get_image_module_dynamic(image_address: *const u8, text_size: usize, image_size: usize) -> Result<*const elf::Dyn> {
+    let text = Image::new(image_address as *mut u8, text_size);
+    let module_start = text.get::<ModuleStart>(0)?;
+    let module_offset = (*module_start).magic_offset as u64;
+    let module = text.get::<mod0::Header>(module_offset)?;
+    result_return_unless!((*module).magic == mod0::MAGIC, ResultInvalidMod0Magic);
+
+    let image = Image::new(image_address as *mut u8, image_size);
+    let dynamic_offset = module_offset + (*module).dynamic as u64;
+    let mut entry_offset = dynamic_offset;
+    while (*image.get::<elf::Dyn>(entry_offset)?).tag != elf::Tag::Invalid as i64 {
+        entry_offset += core::mem::size_of::<elf::Dyn>() as u64;
+    }
+    Ok(image.address.offset(dynamic_offset as isize) as *const elf::Dyn)
+}
+
+static mut G_SELF_BASE_ADDRESS: *const u8 = ptr::null();
+
+pub unsafe fn relocate(base_address: *const u8) -> Result<()> {
+    let dynamic = get_module_dynamic(base_address)?;
+    relocate_with_dyn(base_address, dynamic)?;
+    G_SELF_BASE_ADDRESS = base_address;
+    Ok(())
+}
+
+pub fn get_self_base_address() -> *const u8 {
+    unsafe {
+        G_SELF_BASE_ADDRESS
+    }
+}
+
+pub unsafe fn find_self_symbol(name: &str) -> Result<Option<*const u8>> {
+    let base_address = get_self_base_address();
+    if base_address.is_null() {
+        return Ok(None);
+    }
+
+    let dynamic = get_module_dynamic(base_address)?;
+    find_symbol(base_address, dynamic, name)
+}
+
+pub unsafe fn call_self_init_array() -> Result<()> {
+    let base_address = get_self_base_address();
+    if base_address.is_null() {
+        return Ok(());
+    }
+
+    call_init_array(base_address, get_module_dynamic(base_address)?)
+}
+
+pub unsafe fn call_self_fini_array() -> Result<()> {
+    let base_address = get_self_base_address();
+    if base_address.is_null() {
+        return Ok(());
+    }
+
+    call_fini_array(base_address, get_module_dynamic(base_address)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use core::mem;
+
+    const IMAGE_SIZE: usize = 0x800;
+    const SYMBOL_TABLE_OFFSET: usize = 0x100;
+    const STRING_TABLE_OFFSET: usize = 0x200;
+    const TABLE_OFFSET: usize = 0x300;
+    const JMP_REL_TABLE_OFFSET: usize = 0x400;
+    const TARGETS_OFFSET: usize = 0x600;
+
+    const LOAD_ADDRESS: u64 = 0x8000_0000;
+    const EXTERNAL_ADDRESS: u64 = 0xDEAD_0000;
+
+    // Symbol indices of the test image
+    const LOCAL_SYMBOL: u32 = 1;
+    const EXTERNAL_SYMBOL: u32 = 2;
+    const ABSOLUTE_SYMBOL: u32 = 3;
+    const WEAK_SYMBOL: u32 = 4;
+
+    const STRING_TABLE: &[u8] = b"\0local\0external\0absolute\0weak\0";
+
+    // A fake module image (kept in u64s so that it's properly aligned)
+    struct TestImage {
+        data: Vec<u64>,
+    }
+
+    impl TestImage {
+        fn new(dynamic: &[(elf::Tag, u64)]) -> Self {
+            let mut image = Self { data: vec![0; IMAGE_SIZE / mem::size_of::<u64>()] };
+            let mut dyn_offset = 0;
+            for (tag, value) in [(elf::Tag::SymTab, SYMBOL_TABLE_OFFSET as u64), (elf::Tag::StrTab, STRING_TABLE_OFFSET as u64)].iter().chain(dynamic.iter()) {
+                image.write(dyn_offset, elf::Dyn { tag: *tag as i64, val_ptr: *value });
+                dyn_offset += mem::size_of::<elf::Dyn>();
+            }
 
-    let dyn_offset = mod_offset + (*module).dynamic as isize;
-    let dynamic = base_address.offset(dyn_offset) as *const elf::Dyn;
-    relocate_with_dyn(base_address, dynamic)
+            image.write_symbol(LOCAL_SYMBOL, elf::Sym { name: 1, info: 0x12, other: 0, section_index: 1, value: 0x40, size: 0 });
+            image.write_symbol(EXTERNAL_SYMBOL, elf::Sym { name: 7, info: 0x12, other: 0, section_index: elf::SECTION_INDEX_UNDEFINED, value: 0, size: 0 });
+            image.write_symbol(ABSOLUTE_SYMBOL, elf::Sym { name: 16, info: 0x10, other: 0, section_index: elf::SECTION_INDEX_ABSOLUTE, value: 0x1234, size: 0 });
+            image.write_symbol(WEAK_SYMBOL, elf::Sym { name: 25, info: (elf::SYMBOL_BINDING_WEAK << 4) | 0x2, other: 0, section_index: elf::SECTION_INDEX_UNDEFINED, value: 0, size: 0 });
+            for (i, byte) in STRING_TABLE.iter().enumerate() {
+                image.write(STRING_TABLE_OFFSET + i, *byte);
+            }
+            image
+        }
+
+        fn get_address(&mut self) -> *mut u8 {
+            self.data.as_mut_ptr() as *mut u8
+        }
+
+        fn write<T: Copy>(&mut self, offset: usize, value: T) {
+            assert!(offset + mem::size_of::<T>() <= IMAGE_SIZE);
+            unsafe {
+                ptr::write_unaligned(self.get_address().offset(offset as isize) as *mut T, value);
+            }
+        }
+
+        fn read(&self, offset: usize) -> u64 {
+            self.data[offset / mem::size_of::<u64>()]
+        }
+
+        fn write_symbol(&mut self, index: u32, symbol: elf::Sym) {
+            self.write(SYMBOL_TABLE_OFFSET + index as usize * mem::size_of::<elf::Sym>(), symbol);
+        }
+
+        fn write_rela_table(&mut self, table_offset: usize, relas: &[(u64, elf::RelocationType, u32, i64)]) {
+            for (i, (offset, relocation_type, symbol, addend)) in relas.iter().enumerate() {
+                self.write(table_offset + i * mem::size_of::<elf::Rela>(), elf::Rela { offset: *offset, info: make_info(*relocation_type as u32, *symbol), addend: *addend });
+            }
+        }
+
+        fn write_rel_table(&mut self, table_offset: usize, rels: &[(u64, elf::RelocationType, u32)]) {
+            for (i, (offset, relocation_type, symbol)) in rels.iter().enumerate() {
+                self.write(table_offset + i * mem::size_of::<elf::Rel>(), elf::Rel { offset: *offset, info: make_info(*relocation_type as u32, *symbol) });
+            }
+        }
+
+        fn relocate(&mut self) -> Result<()> {
+            let image_address = self.get_address();
+            unsafe {
+                relocate_image_with_resolver(image_address, IMAGE_SIZE, LOAD_ADDRESS as *const u8, image_address as *const elf::Dyn, |name| match name {
+                    "external" => Some(EXTERNAL_ADDRESS as *const u8),
+                    _ => None,
+                })
+            }
+        }
+    }
+
+    fn make_info(relocation_type: u32, symbol: u32) -> elf::Info {
+        elf::Info { symbol: elf::InfoSymbol { relocation_type: relocation_type, symbol: symbol } }
+    }
+
+    fn target(index: usize) -> usize {
+        TARGETS_OFFSET + index * mem::size_of::<u64>()
+    }
+
+    #[test]
+    fn rela_and_jmp_rela_tables_are_applied() {
+        let relas = [
+            (target(0) as u64, elf::RelocationType::AArch64Relative, 0, 0x10),
+            (target(1) as u64, elf::RelocationType::AArch64Abs64, LOCAL_SYMBOL, 8),
+            (target(2) as u64, elf::RelocationType::AArch64GlobDat, EXTERNAL_SYMBOL, 0),
+            (target(3) as u64, elf::RelocationType::AArch64Abs64, ABSOLUTE_SYMBOL, 4),
+            (target(4) as u64, elf::RelocationType::AArch64GlobDat, WEAK_SYMBOL, 0),
+            (target(5) as u64, elf::RelocationType::AArch64None, 0, 0),
+        ];
+        let jmp_relas = [
+            (target(6) as u64, elf::RelocationType::AArch64JumpSlot, EXTERNAL_SYMBOL, 0),
+        ];
+        let mut image = TestImage::new(&[
+            (elf::Tag::RelaOffset, TABLE_OFFSET as u64),
+            (elf::Tag::RelaSize, (relas.len() * mem::size_of::<elf::Rela>()) as u64),
+            (elf::Tag::RelaEntrySize, mem::size_of::<elf::Rela>() as u64),
+            (elf::Tag::JmpRel, JMP_REL_TABLE_OFFSET as u64),
+            (elf::Tag::PltRelSize, (jmp_relas.len() * mem::size_of::<elf::Rela>()) as u64),
+            (elf::Tag::PltRel, elf::Tag::RelaOffset as u64),
+        ]);
+        image.write_rela_table(TABLE_OFFSET, &relas);
+        image.write_rela_table(JMP_REL_TABLE_OFFSET, &jmp_relas);
+        image.write(target(5), 0x5555u64);
+        image.relocate().unwrap();
+
+        assert_eq!(image.read(target(0)), LOAD_ADDRESS + 0x10);
+        assert_eq!(image.read(target(1)), LOAD_ADDRESS + 0x48);
+        assert_eq!(image.read(target(2)), EXTERNAL_ADDRESS);
+        // Absolute symbols don't get rebased
+        assert_eq!(image.read(target(3)), 0x1238);
+        assert_eq!(image.read(target(4)), 0);
+        assert_eq!(image.read(target(5)), 0x5555);
+        assert_eq!(image.read(target(6)), EXTERNAL_ADDRESS);
+    }
+
+    #[test]
+    fn rel_and_jmp_rel_tables_use_stored_addends() {
+        let rels = [
+            (target(0) as u64, elf::RelocationType::AArch64Relative, 0),
+            (target(1) as u64, elf::RelocationType::AArch64Abs64, LOCAL_SYMBOL),
+            (target(2) as u64, elf::RelocationType::AArch64Abs64, ABSOLUTE_SYMBOL),
+        ];
+        let jmp_rels = [
+            (target(3) as u64, elf::RelocationType::AArch64JumpSlot, EXTERNAL_SYMBOL),
+        ];
+        let mut image = TestImage::new(&[
+            (elf::Tag::RelOffset, TABLE_OFFSET as u64),
+            (elf::Tag::RelSize, (rels.len() * mem::size_of::<elf::Rel>()) as u64),
+            (elf::Tag::RelEntrySize, mem::size_of::<elf::Rel>() as u64),
+            (elf::Tag::JmpRel, JMP_REL_TABLE_OFFSET as u64),
+            (elf::Tag::PltRelSize, (jmp_rels.len() * mem::size_of::<elf::Rel>()) as u64),
+            (elf::Tag::PltRel, elf::Tag::RelOffset as u64),
+        ]);
+        image.write_rel_table(TABLE_OFFSET, &rels);
+        image.write_rel_table(JMP_REL_TABLE_OFFSET, &jmp_rels);
+        image.write(target(0), 0x20u64);
+        image.write(target(1), 4u64);
+        image.write(target(2), 0u64);
+        image.write(target(3), 0x100u64);
+        image.relocate().unwrap();
+
+        assert_eq!(image.read(target(0)), LOAD_ADDRESS + 0x20);
+        assert_eq!(image.read(target(1)), LOAD_ADDRESS + 0x44);
+        assert_eq!(image.read(target(2)), 0x1234);
+        assert_eq!(image.read(target(3)), EXTERNAL_ADDRESS + 0x100);
+    }
+
+    #[test]
+    fn relocations_without_a_symbol_use_the_addend() {
+        let relas = [
+            (target(0) as u64, elf::RelocationType::AArch64Abs64, elf::SYMBOL_INDEX_UNDEFINED, 0x30),
+            (target(1) as u64, elf::RelocationType::AArch64GlobDat, elf::SYMBOL_INDEX_UNDEFINED, 0x40),
+        ];
+        let mut image = TestImage::new(&[
+            (elf::Tag::RelaOffset, TABLE_OFFSET as u64),
+            (elf::Tag::RelaSize, (relas.len() * mem::size_of::<elf::Rela>()) as u64),
+            (elf::Tag::RelaEntrySize, mem::size_of::<elf::Rela>() as u64),
+        ]);
+        image.write_rela_table(TABLE_OFFSET, &relas);
+        image.relocate().unwrap();
+
+        assert_eq!(image.read(target(0)), 0x30);
+        assert_eq!(image.read(target(1)), 0x40);
+    }
+
+    #[test]
+    fn invalid_relocations_fail() {
+        let rela_dynamic = [
+            (elf::Tag::RelaOffset, TABLE_OFFSET as u64),
+            (elf::Tag::RelaSize, mem::size_of::<elf::Rela>() as u64),
+            (elf::Tag::RelaEntrySize, mem::size_of::<elf::Rela>() as u64),
+        ];
+
+        // R_AARCH64_COPY, which modules shouldn't need
+        let mut image = TestImage::new(&rela_dynamic);
+        image.write(TABLE_OFFSET, elf::Rela { offset: target(0) as u64, info: make_info(1024, EXTERNAL_SYMBOL), addend: 0 });
+        assert!(image.relocate().unwrap_err().matches::<ResultUnsupportedRelocationType>());
+
+        // Neither defined nor resolved, and not weak either
+        let mut image = TestImage::new(&rela_dynamic);
+        image.write_symbol(EXTERNAL_SYMBOL + 1, elf::Sym { name: 16, info: 0x12, other: 0, section_index: elf::SECTION_INDEX_UNDEFINED, value: 0, size: 0 });
+        image.write_rela_table(TABLE_OFFSET, &[(target(0) as u64, elf::RelocationType::AArch64GlobDat, EXTERNAL_SYMBOL + 1, 0)]);
+        assert!(image.relocate().unwrap_err().matches::<ResultUndefinedSymbol>());
+
+        let mut image = TestImage::new(&[
+            (elf::Tag::JmpRel, JMP_REL_TABLE_OFFSET as u64),
+            (elf::Tag::PltRelSize, 0),
+            (elf::Tag::PltRel, elf::Tag::Hash as u64),
+        ]);
+        assert!(image.relocate().unwrap_err().matches::<ResultInvalidPltRelType>());
+
+        let mut image = TestImage::new(&[
+            (elf::Tag::RelaOffset, TABLE_OFFSET as u64),
+            (elf::Tag::RelaSize, mem::size_of::<elf::Rela>() as u64),
+            (elf::Tag::RelaEntrySize, mem::size_of::<elf::Rel>() as u64),
+        ]);
+        assert!(image.relocate().unwrap_err().matches::<ResultRelaSizeMismatch>());
+    }
+
+    #[test]
+    fn absolute_symbols_are_found_as_is() {
+        let mut image = TestImage::new(&[(elf::Tag::Hash, TABLE_OFFSET as u64)]);
+        // A single bucket holding every symbol
+        image.write(TABLE_OFFSET, 1u32);
+        image.write(TABLE_OFFSET + 4, 5u32);
+        image.write(TABLE_OFFSET + 8, WEAK_SYMBOL);
+        let chains = TABLE_OFFSET + 12;
+        image.write(chains + WEAK_SYMBOL as usize * 4, ABSOLUTE_SYMBOL);
+        image.write(chains + ABSOLUTE_SYMBOL as usize * 4, EXTERNAL_SYMBOL);
+        image.write(chains + EXTERNAL_SYMBOL as usize * 4, LOCAL_SYMBOL);
+        image.write(chains + LOCAL_SYMBOL as usize * 4, 0u32);
+
+        let base_address = image.get_address();
+        let dynamic = base_address as *const elf::Dyn;
+        unsafe {
+            assert_eq!(find_symbol(base_address, dynamic, "local").unwrap(), Some(base_address.offset(0x40) as *const u8));
+            assert_eq!(find_symbol(base_address, dynamic, "absolute").unwrap(), Some(0x1234 as *const u8));
+            // Undefined symbols aren't the module's to give
+            assert_eq!(find_symbol(base_address, dynamic, "external").unwrap(), None);
+            assert_eq!(find_symbol(base_address, dynamic, "missing").unwrap(), None);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,31 @@
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct SegmentHeader {
+    pub file_offset: u32,
+    pub size: u32,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Header {
+    pub magic: u32,
+    pub version: u32,
+    pub size: u32,
+    pub flags: u32,
+    pub text_segment: SegmentHeader,
+    pub ro_segment: SegmentHeader,
+    pub data_segment: SegmentHeader,
+    pub bss_size: u32,
+    pub reserved: u32,
+    pub module_id: [u8; 0x20],
+    pub dso_handle_offset: u32,
+    pub reserved_2: u32,
+    pub api_info_segment: SegmentHeader,
+    pub dynstr_segment: SegmentHeader,
+    pub dynsym_segment: SegmentHeader,
+}
+
+// The header is located after the module start
+pub const HEADER_OFFSET: usize = 0x10;
+
+pub const MAGIC: u32 = 0x304F524E;
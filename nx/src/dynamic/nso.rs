@@ -0,0 +1,183 @@
+use crate::result::*;
+use enumflags2::BitFlags;
+
+#[derive(BitFlags, Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum HeaderFlags {
+    TextCompressed = 0b1,
+    RoCompressed = 0b10,
+    DataCompressed = 0b100,
+    TextCheckHash = 0b1000,
+    RoCheckHash = 0b10000,
+    DataCheckHash = 0b100000,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct SegmentHeader {
+    pub file_offset: u32,
+    pub memory_offset: u32,
+    pub size: u32,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct RoRelativeSegmentHeader {
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Header {
+    pub magic: u32,
+    pub version: u32,
+    pub reserved: u32,
+    pub flags: BitFlags<HeaderFlags>,
+    pub text_segment: SegmentHeader,
+    pub module_name_offset: u32,
+    pub ro_segment: SegmentHeader,
+    pub module_name_size: u32,
+    pub data_segment: SegmentHeader,
+    pub bss_size: u32,
+    pub module_id: [u8; 0x20],
+    pub text_file_size: u32,
+    pub ro_file_size: u32,
+    pub data_file_size: u32,
+    pub reserved_2: [u8; 0x1C],
+    pub api_info_segment: RoRelativeSegmentHeader,
+    pub dynstr_segment: RoRelativeSegmentHeader,
+    pub dynsym_segment: RoRelativeSegmentHeader,
+    pub text_hash: [u8; 0x20],
+    pub ro_hash: [u8; 0x20],
+    pub data_hash: [u8; 0x20],
+}
+
+pub const MAGIC: u32 = 0x304F534E;
+
+pub const RESULT_SUBMODULE: u32 = 13;
+
+result_lib_define_group!(RESULT_SUBMODULE => {
+    ResultInvalidCompressedData: 1,
+    ResultDecompressedSizeMismatch: 2
+});
+
+fn read_extended_length(src: &[u8], src_offset: &mut usize, length: &mut usize) -> Result<()> {
+    loop {
+        result_return_unless!(*src_offset < src.len(), ResultInvalidCompressedData);
+        let byte = src[*src_offset];
+        *src_offset += 1;
+        *length += byte as usize;
+        if byte != 0xFF {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Segments are compressed as raw LZ4 blocks
+pub fn decompress_segment(src: &[u8], dst: &mut [u8]) -> Result<()> {
+    let mut src_offset: usize = 0;
+    let mut dst_offset: usize = 0;
+    while src_offset < src.len() {
+        let token = src[src_offset];
+        src_offset += 1;
+
+        let mut literal_length = (token >> 4) as usize;
+        if literal_length == 0xF {
+            read_extended_length(src, &mut src_offset, &mut literal_length)?;
+        }
+        result_return_if!((src_offset + literal_length) > src.len(), ResultInvalidCompressedData);
+        result_return_if!((dst_offset + literal_length) > dst.len(), ResultDecompressedSizeMismatch);
+        dst[dst_offset..dst_offset + literal_length].copy_from_slice(&src[src_offset..src_offset + literal_length]);
+        src_offset += literal_length;
+        dst_offset += literal_length;
+
+        // The last sequence only has literals
+        if src_offset == src.len() {
+            break;
+        }
+
+        result_return_if!((src_offset + 2) > src.len(), ResultInvalidCompressedData);
+        let match_offset = (src[src_offset] as usize) | ((src[src_offset + 1] as usize) << 8);
+        src_offset += 2;
+        result_return_if!((match_offset == 0) || (match_offset > dst_offset), ResultInvalidCompressedData);
+
+        let mut match_length = (token & 0xF) as usize;
+        if match_length == 0xF {
+            read_extended_length(src, &mut src_offset, &mut match_length)?;
+        }
+        match_length += 4;
+        result_return_if!((dst_offset + match_length) > dst.len(), ResultDecompressedSizeMismatch);
+
+        // Matches can overlap with the data they produce, so copy them byte by byte
+        for _ in 0..match_length {
+            dst[dst_offset] = dst[dst_offset - match_offset];
+            dst_offset += 1;
+        }
+    }
+    result_return_unless!(dst_offset == dst.len(), ResultDecompressedSizeMismatch);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn decompress(src: &[u8], size: usize) -> Result<Vec<u8>> {
+        let mut dst = vec![0; size];
+        decompress_segment(src, &mut dst)?;
+        Ok(dst)
+    }
+
+    #[test]
+    fn literals_and_matches_are_decompressed() {
+        assert_eq!(decompress(&[0x30, b'a', b'b', b'c'], 3).unwrap(), b"abc");
+        assert_eq!(decompress(&[0x20, b'a', b'b', 0x02, 0x00, 0x10, b'c'], 7).unwrap(), b"abababc");
+        assert!(decompress(&[], 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn overlapping_matches_repeat_their_output() {
+        assert_eq!(decompress(&[0x12, b'a', 0x01, 0x00], 7).unwrap(), b"aaaaaaa");
+        assert_eq!(decompress(&[0x21, b'a', b'b', 0x02, 0x00], 7).unwrap(), b"abababa");
+    }
+
+    #[test]
+    fn extended_lengths_are_added_up() {
+        let mut src = vec![0xF0, 0x05];
+        src.extend_from_slice(&[b'x'; 20]);
+        assert_eq!(decompress(&src, 20).unwrap(), vec![b'x'; 20]);
+
+        // 15 + 255 + 1, plus the minimum match length of 4
+        let dst = decompress(&[0x1F, b'y', 0x01, 0x00, 0xFF, 0x01], 1 + 275).unwrap();
+        assert!(dst.iter().all(|byte| *byte == b'y'));
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        // Literals
+        assert!(decompress(&[0x50, b'a', b'b'], 5).unwrap_err().matches::<ResultInvalidCompressedData>());
+        // Extended literal length
+        assert!(decompress(&[0xF0], 15).unwrap_err().matches::<ResultInvalidCompressedData>());
+        assert!(decompress(&[0xF0, 0xFF], 270).unwrap_err().matches::<ResultInvalidCompressedData>());
+        // Match offset
+        assert!(decompress(&[0x10, b'a', 0x01], 5).unwrap_err().matches::<ResultInvalidCompressedData>());
+        // Extended match length
+        assert!(decompress(&[0x1F, b'a', 0x01, 0x00], 20).unwrap_err().matches::<ResultInvalidCompressedData>());
+    }
+
+    #[test]
+    fn invalid_match_offsets_are_rejected() {
+        assert!(decompress(&[0x10, b'a', 0x00, 0x00], 5).unwrap_err().matches::<ResultInvalidCompressedData>());
+        assert!(decompress(&[0x10, b'a', 0x02, 0x00], 5).unwrap_err().matches::<ResultInvalidCompressedData>());
+    }
+
+    #[test]
+    fn size_mismatches_are_rejected() {
+        assert!(decompress(&[0x30, b'a', b'b', b'c'], 4).unwrap_err().matches::<ResultDecompressedSizeMismatch>());
+        assert!(decompress(&[0x30, b'a', b'b', b'c'], 2).unwrap_err().matches::<ResultDecompressedSizeMismatch>());
+        assert!(decompress(&[0x12, b'a', 0x01, 0x00], 6).unwrap_err().matches::<ResultDecompressedSizeMismatch>());
+    }
+}
@@ -0,0 +1,137 @@
+extern crate alloc;
+
+use crate::result::*;
+use crate::sync;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub type Args = Vec<String>;
+
+static G_ARGS: sync::Mutex<Args> = sync::Mutex::new(Vec::new());
+
+fn is_arg_separator(c: char) -> bool {
+    c.is_ascii_whitespace()
+}
+
+// Same rules as libnx: arguments are separated by whitespace, and an argument starting with a quote lasts until the next quote
+pub fn parse_args(args_str: &str) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut chars = args_str.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| is_arg_separator(*c)) {
+            chars.next();
+        }
+
+        let first = match chars.next() {
+            Some(c) => c,
+            None => break,
+        };
+
+        let mut arg = String::new();
+        if first == '"' {
+            while let Some(c) = chars.next() {
+                if c == '"' {
+                    break;
+                }
+                arg.push(c);
+            }
+        }
+        else {
+            arg.push(first);
+            while let Some(c) = chars.peek() {
+                if is_arg_separator(*c) {
+                    break;
+                }
+                arg.push(*c);
+                chars.next();
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
+
+pub unsafe fn parse_args_from_pointer(args_ptr: *const u8) -> Vec<String> {
+    let mut args_len: usize = 0;
+    while *args_ptr.offset(args_len as isize) != 0 {
+        args_len += 1;
+    }
+    let args_bytes = core::slice::from_raw_parts(args_ptr, args_len);
+    parse_args(&String::from_utf8_lossy(args_bytes))
+}
+
+pub fn set_args(args: Args) {
+    *G_ARGS.lock() = args;
+}
+
+pub fn args() -> Args {
+    G_ARGS.lock().clone()
+}
+
+// Implemented for main functions with or without the launch arguments, see nx_main!
+// The type parameter only tells both implementations apart
+pub trait MainFn<A> {
+    fn call_main(self, args: Args) -> Result<()>;
+}
+
+impl<F: FnOnce() -> Result<()>> MainFn<()> for F {
+    fn call_main(self, _args: Args) -> Result<()> {
+        self()
+    }
+}
+
+impl<F: FnOnce(Args) -> Result<()>> MainFn<Args> for F {
+    fn call_main(self, args: Args) -> Result<()> {
+        self(args)
+    }
+}
+
+pub fn call_main<A, F: MainFn<A>>(main_fn: F, args: Args) -> Result<()> {
+    main_fn.call_main(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn args_are_split_by_whitespace() {
+        assert_eq!(parse_args("sdmc:/switch/app.nro -v  --level 3"), strings(&["sdmc:/switch/app.nro", "-v", "--level", "3"]));
+        assert_eq!(parse_args("  \tleading and trailing \r\n "), strings(&["leading", "and", "trailing"]));
+        assert!(parse_args("").is_empty());
+        assert!(parse_args(" \t ").is_empty());
+    }
+
+    #[test]
+    fn quoted_args_keep_whitespace() {
+        assert_eq!(parse_args("app \"sdmc:/my file.txt\" next"), strings(&["app", "sdmc:/my file.txt", "next"]));
+        assert_eq!(parse_args("\"\" empty"), strings(&["", "empty"]));
+        // Quotes only matter at the start of an argument
+        assert_eq!(parse_args("a\"b c\""), strings(&["a\"b", "c\""]));
+    }
+
+    #[test]
+    fn unterminated_quotes_last_until_the_end() {
+        assert_eq!(parse_args("app \"unterminated arg  "), strings(&["app", "unterminated arg  "]));
+    }
+
+    #[test]
+    fn args_are_parsed_from_c_strings() {
+        let args = unsafe { parse_args_from_pointer(b"app \"x y\"\0ignored\0".as_ptr()) };
+        assert_eq!(args, strings(&["app", "x y"]));
+    }
+
+    #[test]
+    fn main_functions_may_take_args() {
+        assert!(call_main(|| Ok(()), strings(&["app"])).is_ok());
+        assert!(call_main(|args: Args| {
+            assert_eq!(args, strings(&["app", "-v"]));
+            Ok(())
+        }, strings(&["app", "-v"])).is_ok());
+    }
+}
\ No newline at end of file
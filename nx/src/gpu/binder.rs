@@ -1,5 +1,6 @@
 use crate::result::*;
 use crate::svc;
+use crate::ipc;
 use crate::gpu::parcel;
 use crate::service::dispdrv;
 use crate::service::dispdrv::IHOSBinderDriver;
@@ -98,8 +99,8 @@ impl Binder {
     }
 
     fn transact_parcel_impl(&mut self, transaction_id: dispdrv::ParcelTransactionId, payload: parcel::ParcelPayload, payload_size: usize) -> Result<parcel::Parcel> {
-        let response_payload = parcel::ParcelPayload::new();
-        self.hos_binder_driver.borrow_mut().transact_parcel(self.handle, transaction_id, 0, &payload as *const _ as *const u8, payload_size, &response_payload as *const _ as *const u8, cmem::size_of::<parcel::ParcelPayload>())?;
+        let mut response_payload = parcel::ParcelPayload::new();
+        self.hos_binder_driver.borrow_mut().transact_parcel(self.handle, transaction_id, 0, ipc::InMapAlias::from_var(&payload).into_bytes().with_size(payload_size), ipc::OutMapAlias::from_mut_var(&mut response_payload).into_bytes())?;
         
         let mut parcel = parcel::Parcel::new();
         parcel.load_from(response_payload);
@@ -230,7 +231,7 @@ impl Binder {
         Ok(qbo)
     }
 
-    pub fn get_native_handle(&mut self, unk: u32) -> Result<svc::Handle> {
+    pub fn get_native_handle(&mut self, unk: u32) -> Result<svc::handle::ReadableEvent> {
         self.hos_binder_driver.borrow_mut().get_native_handle(self.handle, unk)
     }
 }
\ No newline at end of file
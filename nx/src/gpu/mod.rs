@@ -4,13 +4,13 @@ use crate::result::*;
 use crate::service;
 use crate::mem;
 use crate::svc;
+use crate::ipc;
 use crate::service::nv;
 use crate::service::nv::INvDrvService;
 use crate::service::vi;
 use crate::service::vi::IRootService;
 use crate::service::vi::IApplicationDisplayService;
 use crate::service::dispdrv;
-use core::mem as cmem;
 use enumflags2::BitFlags;
 
 pub mod parcel;
@@ -714,7 +714,7 @@ pub struct GpuContext<VS: IRootService + service::Service + service::SessionObje
     hos_binder_driver: mem::SharedObject<dispdrv::HOSBinderDriver>,
     transfer_mem: *mut u8,
     transfer_mem_alloc_layout: alloc::alloc::Layout,
-    transfer_mem_handle: svc::Handle,
+    transfer_mem_handle: svc::handle::TransferMemory,
     nvhost_fd: u32,
     nvmap_fd: u32,
     nvhostctrl_fd: u32,
@@ -728,14 +728,14 @@ impl<VS: IRootService + service::Service + service::SessionObject + service::Sha
         let transfer_mem_alloc_layout = unsafe { alloc::alloc::Layout::from_size_align_unchecked(transfer_mem_size, 0x1000) };
         
         let transfer_mem = unsafe { alloc::alloc::alloc(transfer_mem_alloc_layout) };
-        let transfer_mem_handle = svc::create_transfer_memory(transfer_mem, transfer_mem_size, BitFlags::empty())?;
-        nvdrv_srv.borrow_mut().initialize(transfer_mem_handle, transfer_mem_size as u32)?;
+        let transfer_mem_handle = svc::handle::TransferMemory::create(transfer_mem, transfer_mem_size, BitFlags::empty())?;
+        nvdrv_srv.borrow_mut().initialize(&transfer_mem_handle, transfer_mem_size as u32)?;
 
-        let (nvhost_fd, nvhost_err) = nvdrv_srv.borrow_mut().open_fd(NVHOST_PATH.as_ptr(), NVHOST_PATH.len())?;
+        let (nvhost_fd, nvhost_err) = nvdrv_srv.borrow_mut().open_fd(ipc::InMapAlias::from_str(NVHOST_PATH))?;
         nv::convert_error_code(nvhost_err)?;
-        let (nvmap_fd, nvmap_err) = nvdrv_srv.borrow_mut().open_fd(NVMAP_PATH.as_ptr(), NVMAP_PATH.len())?;
+        let (nvmap_fd, nvmap_err) = nvdrv_srv.borrow_mut().open_fd(ipc::InMapAlias::from_str(NVMAP_PATH))?;
         nv::convert_error_code(nvmap_err)?;
-        let (nvhostctrl_fd, nvhostctrl_err) = nvdrv_srv.borrow_mut().open_fd(NVHOSTCTRL_PATH.as_ptr(), NVHOSTCTRL_PATH.len())?;
+        let (nvhostctrl_fd, nvhostctrl_err) = nvdrv_srv.borrow_mut().open_fd(ipc::InMapAlias::from_str(NVHOSTCTRL_PATH))?;
         nv::convert_error_code(nvhostctrl_err)?;
 
         let application_display_srv: mem::SharedObject<vi::ApplicationDisplayService> = vi_srv.borrow_mut().get_display_service(false)?;
@@ -773,8 +773,8 @@ impl<VS: IRootService + service::Service + service::SessionObject + service::Sha
 
     pub fn create_stray_layer_surface(&mut self, display_name: &str, width: u32, height: u32, buffer_count: u32, color_fmt: ColorFormat, pixel_fmt: PixelFormat, layout: Layout) -> Result<surface::Surface<NS>> {
         let display_id = self.application_display_service.borrow_mut().open_display(vi::DisplayName::from(display_name)?)?;
-        let native_window = parcel::ParcelPayload::new();
-        let (layer_id, _) = self.application_display_service.borrow_mut().create_stray_layer(BitFlags::from(vi::LayerFlags::Default), display_id, &native_window as *const _ as *const u8, cmem::size_of::<parcel::ParcelPayload>())?;
+        let mut native_window = parcel::ParcelPayload::new();
+        let (layer_id, _) = self.application_display_service.borrow_mut().create_stray_layer(BitFlags::from(vi::LayerFlags::Default), display_id, ipc::OutMapAlias::from_mut_var(&mut native_window).into_bytes())?;
 
         self.create_surface_impl(buffer_count, display_id, layer_id, width, height, color_fmt, pixel_fmt, layout, Self::stray_layer_destroy, native_window)
     }
@@ -786,7 +786,8 @@ impl<VS: IRootService + service::Service + service::SessionObject + service::Sha
         let _ = self.nvdrv_service.borrow_mut().close_fd(self.nvmap_fd);
         let _ = self.nvdrv_service.borrow_mut().close_fd(self.nvhostctrl_fd);
 
+        // The memory is only ours again once the transfer memory is gone
+        self.transfer_mem_handle.close();
         unsafe { alloc::alloc::dealloc(self.transfer_mem, self.transfer_mem_alloc_layout); }
-        let _ = svc::close_handle(self.transfer_mem_handle);
     }
 }
\ No newline at end of file
@@ -3,6 +3,7 @@ extern crate alloc;
 use crate::gpu::binder;
 use crate::gpu::ioctl;
 use crate::svc;
+use crate::ipc;
 use crate::service::nv;
 use crate::service::vi;
 use crate::service::dispdrv;
@@ -33,6 +34,7 @@ pub struct Surface<NS: nv::INvDrvService> {
     display_id: vi::DisplayId,
     layer_id: vi::LayerId,
     layer_destroy_fn: LayerDestroyFn,
+    vsync_event: svc::handle::ReadableEvent,
     nvhost_fd: u32,
     nvmap_fd: u32,
     nvhostctrl_fd: u32,
@@ -43,28 +45,31 @@ impl<NS: nv::INvDrvService> Surface<NS> {
         let mut binder = binder::Binder::new(binder_handle, hos_binder_driver);
         binder.increase_refcounts()?;
         let _ = binder.connect(ConnectionApi::Cpu, false)?;
-        let mut surface = Self { binder: binder, nvdrv_srv: nvdrv_srv, application_display_service: application_display_service, width: width, height: height, buffer_data: ptr::null_mut(), buffer_alloc_layout: alloc::alloc::Layout::new::<u8>(), single_buffer_size: 0, buffer_count: buffer_count, slot_has_requested: [false; MAX_BUFFERS], graphic_buf: unsafe { cmem::zeroed() }, color_fmt: color_fmt, pixel_fmt: pixel_fmt, layout: layout, display_id: display_id, layer_id: layer_id, layer_destroy_fn: layer_destroy_fn, nvhost_fd: nvhost_fd, nvmap_fd: nvmap_fd, nvhostctrl_fd: nvhostctrl_fd };
+        let vsync_event = application_display_service.borrow_mut().get_display_vsync_event(display_id)?;
+        let mut surface = Self { binder: binder, nvdrv_srv: nvdrv_srv, application_display_service: application_display_service, width: width, height: height, buffer_data: ptr::null_mut(), buffer_alloc_layout: alloc::alloc::Layout::new::<u8>(), single_buffer_size: 0, buffer_count: buffer_count, slot_has_requested: [false; MAX_BUFFERS], graphic_buf: unsafe { cmem::zeroed() }, color_fmt: color_fmt, pixel_fmt: pixel_fmt, layout: layout, display_id: display_id, layer_id: layer_id, layer_destroy_fn: layer_destroy_fn, vsync_event: vsync_event, nvhost_fd: nvhost_fd, nvmap_fd: nvmap_fd, nvhostctrl_fd: nvhostctrl_fd };
         surface.initialize()?;
         Ok(surface)
     }
 
-    fn do_ioctl<I: ioctl::Ioctl>(&mut self, i: &mut I) -> Result<()> {
+    fn do_ioctl<I: ioctl::Ioctl + Copy>(&mut self, i: &mut I) -> Result<()> {
         let fd = match I::get_fd() {
             ioctl::IoctlFd::NvHost => self.nvhost_fd,
             ioctl::IoctlFd::NvMap => self.nvmap_fd,
             ioctl::IoctlFd::NvHostCtrl => self.nvhostctrl_fd,
         };
 
-        let (in_buf, in_size) = match I::get_mode().contains(ioctl::IoctlMode::In) {
-            true => (i as *mut I as *const u8, cmem::size_of::<I>()),
-            false => (ptr::null::<u8>(), 0usize)
+        // In-out ioctls send and receive the same struct, so the input is sent from a copy of it
+        let in_data = *i;
+        let in_buf = match I::get_mode().contains(ioctl::IoctlMode::In) {
+            true => ipc::InBuffer::from_var(&in_data).into_bytes(),
+            false => ipc::InBuffer::empty()
         };
-        let (out_buf, out_size) = match I::get_mode().contains(ioctl::IoctlMode::Out) {
-            true => (i as *mut I as *const u8, cmem::size_of::<I>()),
-            false => (ptr::null::<u8>(), 0usize)
+        let out_buf = match I::get_mode().contains(ioctl::IoctlMode::Out) {
+            true => ipc::OutBuffer::from_mut_var(i).into_bytes(),
+            false => ipc::OutBuffer::empty()
         };
 
-        let err = self.nvdrv_srv.borrow_mut().ioctl(fd, I::get_id(), in_buf, in_size, out_buf, out_size)?;
+        let err = self.nvdrv_srv.borrow_mut().ioctl(fd, I::get_id(), in_buf, out_buf)?;
         nv::convert_error_code(err)
     }
 
@@ -176,6 +181,15 @@ impl<NS: nv::INvDrvService> Surface<NS> {
         self.binder.queue_buffer(slot, qbi)?;
         Ok(())
     }
+
+    pub fn get_vsync_event(&self) -> &svc::handle::ReadableEvent {
+        &self.vsync_event
+    }
+
+    // Blocks until the next vsync (timeout in nanoseconds), instead of spinning on dequeue_buffer
+    pub fn wait_vsync(&self, timeout: i64) -> Result<()> {
+        self.vsync_event.wait_and_reset(timeout)
+    }
 }
 
 impl<NS: nv::INvDrvService> Drop for Surface<NS> {
@@ -1,4 +1,9 @@
+use crate::result::*;
+use crate::svc;
+use crate::sync;
+use crate::util;
 use enumflags2::BitFlags;
+use core::ptr;
 
 #[derive(Copy, Clone, PartialEq)]
 #[repr(u32)]
@@ -32,10 +37,304 @@ pub enum AbiConfigAppletFlags {
     ApplicationOverride = 0b1,
 }
 
+// The key is kept raw, since unknown keys must be checked for the mandatory flag
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct AbiConfigEntry {
-    pub key: AbiConfigEntryKey,
+    pub key: u32,
     pub flags: BitFlags<AbiConfigEntryFlags>,
     pub value: [u64; 2],
-}
\ No newline at end of file
+}
+
+pub const RESULT_SUBMODULE: u32 = 14;
+
+result_lib_define_group!(RESULT_SUBMODULE => {
+    ResultTooManyServiceOverrides: 2,
+    ResultNextLoadUnavailable: 3,
+    ResultNextLoadPathTooLong: 4,
+    ResultNextLoadArgvTooLong: 5
+});
+
+// hbloader expects unknown mandatory keys to be reported with results from this module, as 100 + key
+pub const HOMEBREW_ABI_MODULE: u32 = 346;
+
+pub fn make_unknown_key_result(key: u32) -> ResultCode {
+    ResultCode::new(pack_value(HOMEBREW_ABI_MODULE, 100 + key))
+}
+
+pub const MAX_SERVICE_OVERRIDE_COUNT: usize = 32;
+
+// Sizes of the buffers hbloader provides for the next load path and arguments
+pub const NEXT_LOAD_PATH_SIZE: usize = 0x200;
+pub const NEXT_LOAD_ARGV_SIZE: usize = 0x800;
+
+// Value sent along with the HOS version when running under Atmosphere
+pub const ATMOSPHERE_MAGIC: u64 = 0x41544D4F53504852;
+
+#[derive(Copy, Clone)]
+pub struct ServiceOverride {
+    pub name: u64,
+    pub handle: svc::Handle,
+}
+
+#[derive(Copy, Clone)]
+pub struct HblContext {
+    pub is_valid: bool,
+    pub loader_info: util::PointerAndSize,
+    pub main_thread_handle: Option<svc::Handle>,
+    pub next_load_path: *mut u8,
+    pub next_load_argv: *mut u8,
+    pub heap: Option<util::PointerAndSize>,
+    pub service_overrides: [ServiceOverride; MAX_SERVICE_OVERRIDE_COUNT],
+    pub service_override_count: usize,
+    pub argv: *const u8,
+    pub syscall_available_hint: Option<[u64; 2]>,
+    pub applet_type: Option<u32>,
+    // Raw bits, since BitFlags can't be built in const fns (see get_applet_flags)
+    pub applet_flags: u32,
+    pub applet_workaround_aruid: Option<u64>,
+    pub process_handle: Option<svc::Handle>,
+    pub last_load_result: Option<ResultCode>,
+    pub random_seed: Option<[u64; 2]>,
+    pub user_id_storage: *mut u8,
+    pub hos_version: Option<u32>,
+    pub is_atmosphere: bool,
+}
+
+impl HblContext {
+    pub const fn new() -> Self {
+        Self {
+            is_valid: false,
+            loader_info: util::PointerAndSize::new(ptr::null_mut(), 0),
+            main_thread_handle: None,
+            next_load_path: ptr::null_mut(),
+            next_load_argv: ptr::null_mut(),
+            heap: None,
+            service_overrides: [ServiceOverride { name: 0, handle: 0 }; MAX_SERVICE_OVERRIDE_COUNT],
+            service_override_count: 0,
+            argv: ptr::null(),
+            syscall_available_hint: None,
+            applet_type: None,
+            applet_flags: 0,
+            applet_workaround_aruid: None,
+            process_handle: None,
+            last_load_result: None,
+            random_seed: None,
+            user_id_storage: ptr::null_mut(),
+            hos_version: None,
+            is_atmosphere: false,
+        }
+    }
+
+    pub fn get_applet_flags(&self) -> BitFlags<AbiConfigAppletFlags> {
+        BitFlags::from_bits_truncate(self.applet_flags)
+    }
+
+    pub fn find_service_override(&self, name: u64) -> Option<svc::Handle> {
+        self.service_overrides[..self.service_override_count].iter().find(|service_override| service_override.name == name).map(|service_override| service_override.handle)
+    }
+
+    pub fn is_syscall_available(&self, svc_id: u32) -> Option<bool> {
+        match self.syscall_available_hint {
+            Some(hint) if svc_id < 0x80 => Some((hint[(svc_id / 64) as usize] & (1 << (svc_id % 64))) != 0),
+            _ => None,
+        }
+    }
+}
+
+pub unsafe fn parse_abi_config(abi_ptr: *const AbiConfigEntry) -> Result<HblContext> {
+    let mut context = HblContext::new();
+    context.is_valid = true;
+
+    let mut abi_entry = abi_ptr;
+    loop {
+        let value = (*abi_entry).value;
+        match (*abi_entry).key {
+            key if key == AbiConfigEntryKey::EndOfList as u32 => {
+                context.loader_info = util::PointerAndSize::new(value[0] as *mut u8, value[1] as usize);
+                break;
+            },
+            key if key == AbiConfigEntryKey::MainThreadHandle as u32 => context.main_thread_handle = Some(value[0] as svc::Handle),
+            key if key == AbiConfigEntryKey::NextLoadPath as u32 => {
+                context.next_load_path = value[0] as *mut u8;
+                context.next_load_argv = value[1] as *mut u8;
+            },
+            key if key == AbiConfigEntryKey::OverrideHeap as u32 => context.heap = Some(util::PointerAndSize::new(value[0] as *mut u8, value[1] as usize)),
+            key if key == AbiConfigEntryKey::OverrideService as u32 => {
+                result_return_if!(context.service_override_count >= MAX_SERVICE_OVERRIDE_COUNT, ResultTooManyServiceOverrides);
+                context.service_overrides[context.service_override_count] = ServiceOverride { name: value[0], handle: value[1] as svc::Handle };
+                context.service_override_count += 1;
+            },
+            key if key == AbiConfigEntryKey::Argv as u32 => context.argv = value[1] as *const u8,
+            key if key == AbiConfigEntryKey::SyscallAvailableHint as u32 => context.syscall_available_hint = Some(value),
+            key if key == AbiConfigEntryKey::AppletType as u32 => {
+                context.applet_type = Some(value[0] as u32);
+                context.applet_flags = value[1] as u32;
+            },
+            key if key == AbiConfigEntryKey::AppletWorkaround as u32 => context.applet_workaround_aruid = Some(value[0]),
+            key if key == AbiConfigEntryKey::ProcessHandle as u32 => context.process_handle = Some(value[0] as svc::Handle),
+            key if key == AbiConfigEntryKey::LastLoadResult as u32 => context.last_load_result = Some(ResultCode::new(value[0] as u32)),
+            key if key == AbiConfigEntryKey::RandomSeed as u32 => context.random_seed = Some(value),
+            key if key == AbiConfigEntryKey::UserIdStorage as u32 => context.user_id_storage = value[0] as *mut u8,
+            key if key == AbiConfigEntryKey::HosVersion as u32 => {
+                context.hos_version = Some(value[0] as u32);
+                context.is_atmosphere = value[1] == ATMOSPHERE_MAGIC;
+            },
+            key => {
+                if (*abi_entry).flags.contains(AbiConfigEntryFlags::Mandatory) {
+                    return Err(make_unknown_key_result(key));
+                }
+            },
+        };
+        abi_entry = abi_entry.offset(1);
+    }
+    Ok(context)
+}
+
+// The pointers refer to hbloader's memory, which stays valid for the whole process
+unsafe impl Send for HblContext {}
+
+static G_CONTEXT: sync::Mutex<HblContext> = sync::Mutex::new(HblContext::new());
+
+pub fn set_context(context: HblContext) {
+    *G_CONTEXT.lock() = context;
+}
+
+// Only valid when running as a homebrew NRO (check is_valid)
+pub fn get_context() -> HblContext {
+    *G_CONTEXT.lock()
+}
+
+unsafe fn write_c_str(buffer: *mut u8, string: &str) {
+    ptr::copy(string.as_ptr(), buffer, string.len());
+    *buffer.offset(string.len() as isize) = 0;
+}
+
+// hbloader launches the given NRO once we exit, instead of returning to the menu
+pub fn set_next_load(path: &str, argv: &str) -> Result<()> {
+    let context = get_context();
+    result_return_if!(context.next_load_path.is_null() || context.next_load_argv.is_null(), ResultNextLoadUnavailable);
+    // Room is needed for the NUL terminators
+    result_return_unless!(path.len() < NEXT_LOAD_PATH_SIZE, ResultNextLoadPathTooLong);
+    result_return_unless!(argv.len() < NEXT_LOAD_ARGV_SIZE, ResultNextLoadArgvTooLong);
+
+    unsafe {
+        write_c_str(context.next_load_path, path);
+        write_c_str(context.next_load_argv, argv);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: AbiConfigEntryKey, value: [u64; 2]) -> AbiConfigEntry {
+        AbiConfigEntry { key: key as u32, flags: BitFlags::empty(), value: value }
+    }
+
+    fn parse(entries: &[AbiConfigEntry]) -> Result<HblContext> {
+        unsafe {
+            parse_abi_config(entries.as_ptr())
+        }
+    }
+
+    #[test]
+    fn end_of_list_holds_the_loader_info() {
+        let context = parse(&[entry(AbiConfigEntryKey::EndOfList, [0x1000, 0x20])]).unwrap();
+
+        assert!(context.is_valid);
+        assert_eq!(context.loader_info.address as usize, 0x1000);
+        assert_eq!(context.loader_info.size, 0x20);
+        // Nothing after the end is parsed
+        assert!(context.argv.is_null());
+    }
+
+    #[test]
+    fn unknown_mandatory_keys_fail_with_the_key() {
+        let mut unknown = entry(AbiConfigEntryKey::EndOfList, [0; 2]);
+        unknown.key = 12;
+        let context = parse(&[unknown, entry(AbiConfigEntryKey::EndOfList, [0; 2])]);
+        assert!(context.is_ok());
+
+        unknown.flags = AbiConfigEntryFlags::Mandatory.into();
+        let rc = match parse(&[unknown, entry(AbiConfigEntryKey::EndOfList, [0; 2])]) {
+            Ok(_) => panic!("unknown mandatory key was accepted"),
+            Err(rc) => rc,
+        };
+        assert_eq!(rc.get_module(), HOMEBREW_ABI_MODULE);
+        assert_eq!(rc.get_description(), 112);
+    }
+
+    #[test]
+    fn service_overrides_are_collected() {
+        let context = parse(&[
+            entry(AbiConfigEntryKey::OverrideService, [0x1111, 0xA]),
+            entry(AbiConfigEntryKey::OverrideService, [0x2222, 0xB]),
+            entry(AbiConfigEntryKey::EndOfList, [0; 2]),
+        ]).unwrap();
+
+        assert_eq!(context.service_override_count, 2);
+        assert_eq!(context.find_service_override(0x1111), Some(0xA));
+        assert_eq!(context.find_service_override(0x2222), Some(0xB));
+        assert_eq!(context.find_service_override(0x3333), None);
+
+        let mut entries = [entry(AbiConfigEntryKey::OverrideService, [0x1111, 0xA]); MAX_SERVICE_OVERRIDE_COUNT + 2];
+        entries[MAX_SERVICE_OVERRIDE_COUNT + 1] = entry(AbiConfigEntryKey::EndOfList, [0; 2]);
+        let rc = match parse(&entries) {
+            Ok(_) => panic!("too many service overrides were accepted"),
+            Err(rc) => rc,
+        };
+        assert!(rc.matches::<ResultTooManyServiceOverrides>());
+    }
+
+    #[test]
+    fn argv_and_next_load_buffers_are_read() {
+        let context = parse(&[
+            entry(AbiConfigEntryKey::Argv, [0, 0x3000]),
+            entry(AbiConfigEntryKey::NextLoadPath, [0x4000, 0x5000]),
+            entry(AbiConfigEntryKey::EndOfList, [0; 2]),
+        ]).unwrap();
+
+        assert_eq!(context.argv as usize, 0x3000);
+        assert_eq!(context.next_load_path as usize, 0x4000);
+        assert_eq!(context.next_load_argv as usize, 0x5000);
+    }
+
+    fn read_c_str(buffer: &[u8]) -> &str {
+        let len = buffer.iter().position(|&byte| byte == 0).unwrap();
+        core::str::from_utf8(&buffer[..len]).unwrap()
+    }
+
+    // The context is global, so everything about it is checked from a single test
+    #[test]
+    fn next_load_is_written_to_the_loader_buffers() {
+        set_context(HblContext::new());
+        let rc = set_next_load("sdmc:/test.nro", "test").unwrap_err();
+        assert!(rc.matches::<ResultNextLoadUnavailable>());
+
+        let mut path = vec![0xFFu8; NEXT_LOAD_PATH_SIZE];
+        let mut argv = vec![0xFFu8; NEXT_LOAD_ARGV_SIZE];
+        let mut context = HblContext::new();
+        context.next_load_path = path.as_mut_ptr();
+        context.next_load_argv = argv.as_mut_ptr();
+        set_context(context);
+
+        // The NUL terminator has to fit too
+        let long_path = "a".repeat(NEXT_LOAD_PATH_SIZE);
+        let long_argv = "a".repeat(NEXT_LOAD_ARGV_SIZE);
+        assert!(set_next_load(&long_path, "").unwrap_err().matches::<ResultNextLoadPathTooLong>());
+        assert!(set_next_load("", &long_argv).unwrap_err().matches::<ResultNextLoadArgvTooLong>());
+
+        set_next_load(&long_path[1..], &long_argv[1..]).unwrap();
+        assert_eq!(read_c_str(&path), &long_path[1..]);
+        assert_eq!(read_c_str(&argv), &long_argv[1..]);
+
+        // Shorter strings are terminated, not mixed with what was there before
+        set_next_load("sdmc:/test.nro", "test arg").unwrap();
+        assert_eq!(read_c_str(&path), "sdmc:/test.nro");
+        assert_eq!(read_c_str(&argv), "test arg");
+
+        set_context(HblContext::new());
+    }
+}
@@ -1,5 +1,13 @@
 use crate::ipc;
+use crate::svc;
+use crate::service;
+use crate::svc::handle;
+use crate::svc::handle::CopyHandle;
+use crate::svc::handle::MoveHandle;
+use crate::svc::handle::FromHandle;
 use crate::result::*;
+use enumflags2::BitFlags;
+use enumflags2::RawBitFlags;
 use core::mem;
 
 pub const RESULT_SUBMODULE: u32 = 5;
@@ -103,7 +111,11 @@ pub fn write_request_command_on_ipc_buffer(ctx: &mut ipc::CommandContext, reques
         let mut data_header = data_offset as *mut ipc::DataHeader;
         if ctx.session.is_domain() {
             let domain_header = data_offset as *mut ipc::DomainInDataHeader;
-            let left_data_size = mem::size_of::<ipc::DataHeader>() as u32 + ctx.in_params.data_size;
+            // Close requests don't have a data header, so it isn't part of their data either
+            let left_data_size = match has_data_header {
+                true => mem::size_of::<ipc::DataHeader>() as u32 + ctx.in_params.data_size,
+                false => ctx.in_params.data_size,
+            };
             *domain_header = ipc::DomainInDataHeader::new(domain_command_type, ctx.in_params.object_count as u8, left_data_size as u16, ctx.session.object_id, 0);
             data_offset = data_offset.offset(mem::size_of::<ipc::DomainInDataHeader>() as isize);
             ctx.in_params.objects_offset = data_offset.offset(left_data_size as isize);
@@ -195,4 +207,207 @@ pub fn read_control_command_response_from_ipc_buffer(ctx: &mut ipc::CommandConte
 #[inline(always)]
 pub fn write_close_command_on_ipc_buffer(ctx: &mut ipc::CommandContext) {
     write_command_on_ipc_buffer(ctx, ipc::CommandType::Close, 0);
-}
\ No newline at end of file
+}
+
+// Types sent as raw data, copied as they are: pointers, slices and references mean nothing to the other process, so they aren't plain data
+// svc::Handle being a u32 alias, a raw handle is sent as plain data too: handles are sent through the svc::handle types instead
+pub trait PlainData: Copy {}
+
+pub trait RequestCommandParameter {
+    // Buffers, handles and the raw data size must be known before the request is written
+    fn before_request_write(param: &Self, walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<()>;
+    fn before_send_sync_request(param: &Self, walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<()>;
+
+    // Once the request is ready to be sent, parameters the request took over (like move handles, which the kernel closes) are given away
+    fn after_request_write(_param: Self) where Self: Sized {}
+}
+
+pub trait ResponseCommandParameter: Sized {
+    // The raw data size must be known before the response is read
+    fn before_response_read(walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<()>;
+    fn after_response_read(walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<Self>;
+}
+
+pub fn before_plain_data_request_write<T: PlainData>(walker: &mut ipc::DataWalker) -> Result<()> {
+    walker.advance::<T>();
+    Ok(())
+}
+
+pub fn before_plain_data_send_sync_request<T: PlainData>(param: &T, walker: &mut ipc::DataWalker) -> Result<()> {
+    walker.advance_set(*param);
+    Ok(())
+}
+
+pub fn before_plain_data_response_read<T: PlainData>(walker: &mut ipc::DataWalker) -> Result<()> {
+    walker.advance::<T>();
+    Ok(())
+}
+
+pub fn after_plain_data_response_read<T: PlainData>(walker: &mut ipc::DataWalker) -> Result<T> {
+    Ok(walker.advance_get())
+}
+
+ipc_client_plain_data_impl!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, bool, f32, f64, ResultCode);
+
+impl<T: PlainData, const N: usize> PlainData for [T; N] {}
+
+impl<T: RawBitFlags> PlainData for BitFlags<T> {}
+
+// Generic types can't go through ipc_client_plain_data_impl!
+
+impl<T: PlainData, const N: usize> RequestCommandParameter for [T; N] {
+    fn before_request_write(_param: &Self, walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        before_plain_data_request_write::<Self>(walker)
+    }
+
+    fn before_send_sync_request(param: &Self, walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        before_plain_data_send_sync_request(param, walker)
+    }
+}
+
+impl<T: PlainData, const N: usize> ResponseCommandParameter for [T; N] {
+    fn before_response_read(walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        before_plain_data_response_read::<Self>(walker)
+    }
+
+    fn after_response_read(walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<Self> {
+        after_plain_data_response_read(walker)
+    }
+}
+
+impl<T: RawBitFlags> RequestCommandParameter for BitFlags<T> {
+    fn before_request_write(_param: &Self, walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        before_plain_data_request_write::<Self>(walker)
+    }
+
+    fn before_send_sync_request(param: &Self, walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        before_plain_data_send_sync_request(param, walker)
+    }
+}
+
+impl<T: RawBitFlags> ResponseCommandParameter for BitFlags<T> {
+    fn before_response_read(walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        before_plain_data_response_read::<Self>(walker)
+    }
+
+    fn after_response_read(walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<Self> {
+        after_plain_data_response_read(walker)
+    }
+}
+
+impl<'a, A: ipc::BufferAttributeSet, T> RequestCommandParameter for ipc::Buffer<'a, A, T> {
+    fn before_request_write(param: &Self, _walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<()> {
+        ctx.add_buffer(param.get_address(), param.get_size(), param.get_attributes())
+    }
+
+    fn before_send_sync_request(_param: &Self, _walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Borrowed handles are copied, so they stay ours
+impl<'a, H: CopyHandle> RequestCommandParameter for &'a H {
+    fn before_request_write(param: &Self, _walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<()> {
+        ctx.in_params.add_copy_handle(param.copy_handle());
+        Ok(())
+    }
+
+    fn before_send_sync_request(_param: &Self, _walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> RequestCommandParameter for handle::BorrowedHandle<'a> {
+    fn before_request_write(param: &Self, _walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<()> {
+        ctx.in_params.add_copy_handle(param.copy_handle());
+        Ok(())
+    }
+
+    fn before_send_sync_request(_param: &Self, _walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Out handles don't say whether they were copied or moved, so copy handles are taken first (commands returning both must declare the copied ones first)
+fn pop_out_handle(ctx: &mut ipc::CommandContext) -> Result<svc::Handle> {
+    match ctx.out_params.pop_copy_handle() {
+        Ok(handle) => Ok(handle),
+        Err(_) => ctx.out_params.pop_move_handle(),
+    }
+}
+
+// Owned handles are moved when passed by value (the kernel closes them once sent), and closed when dropped once received
+macro_rules! handle_parameter_impl {
+    ($( $t:ty ),*) => {
+        $(
+            impl RequestCommandParameter for $t {
+                fn before_request_write(param: &Self, _walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<()> {
+                    ctx.in_params.add_move_handle(param.get_handle());
+                    Ok(())
+                }
+
+                fn before_send_sync_request(_param: &Self, _walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+                    Ok(())
+                }
+
+                fn after_request_write(param: Self) {
+                    param.move_handle();
+                }
+            }
+
+            impl ResponseCommandParameter for $t {
+                fn before_response_read(_walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+                    Ok(())
+                }
+
+                fn after_response_read(_walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<Self> {
+                    Ok(Self::from_handle(pop_out_handle(ctx)?))
+                }
+            }
+        )*
+    };
+}
+
+handle_parameter_impl!(handle::OwnedHandle, handle::Event, handle::ReadableEvent, handle::TransferMemory, handle::SharedMemory, handle::Thread, handle::Process);
+
+impl<'a> RequestCommandParameter for ipc::InSession<'a> {
+    fn before_request_write(param: &Self, _walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<()> {
+        if param.session.is_domain() {
+            // Object IDs only mean something within their own domain
+            result_return_unless!(ctx.session.is_domain() && (ctx.session.handle == param.session.handle), ipc::ResultObjectNotInDomain);
+            ctx.in_params.add_object(param.session.object_id);
+        }
+        else {
+            ctx.in_params.add_copy_handle(param.session.handle);
+        }
+        Ok(())
+    }
+
+    fn before_send_sync_request(_param: &Self, _walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Lets interfaces defined with ipc_client_interface_define! return session objects
+impl<S: service::SessionObject> ResponseCommandParameter for S {
+    fn before_response_read(_walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        Ok(())
+    }
+
+    fn after_response_read(_walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<Self> {
+        Ok(S::new(ctx.pop_session()?))
+    }
+}
+
+impl RequestCommandParameter for ipc::ProcessId {
+    fn before_request_write(_param: &Self, walker: &mut ipc::DataWalker, ctx: &mut ipc::CommandContext) -> Result<()> {
+        ctx.in_params.send_process_id = true;
+        walker.advance::<u64>();
+        Ok(())
+    }
+
+    fn before_send_sync_request(param: &Self, walker: &mut ipc::DataWalker, _ctx: &mut ipc::CommandContext) -> Result<()> {
+        walker.advance_set(param.process_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+extern crate alloc;
+
+use crate::ipc;
+use crate::ipc::server;
+use crate::svc;
+use crate::result::*;
+use alloc::vec::Vec;
+use core::mem;
+use core::ptr;
+
+pub struct Request {
+    pub command_type: ipc::CommandType,
+    pub request_id: u32,
+    pub domain_command_type: ipc::DomainCommandType,
+    pub object_id: u32,
+    pub process_id: Option<u64>,
+    pub copy_handles: Vec<svc::Handle>,
+    pub move_handles: Vec<svc::Handle>,
+    pub objects: Vec<u32>,
+    pub send_statics: Vec<ipc::SendStaticDescriptor>,
+    pub send_buffers: Vec<ipc::BufferDescriptor>,
+    pub receive_buffers: Vec<ipc::BufferDescriptor>,
+    pub exchange_buffers: Vec<ipc::BufferDescriptor>,
+    pub receive_statics: Vec<ipc::ReceiveStaticDescriptor>,
+    pub data: Vec<u8>,
+}
+
+impl Request {
+    pub fn read_data_at<T: Copy>(&self, offset: usize) -> T {
+        let mut t: T = unsafe { mem::zeroed() };
+        let available = self.data.len().saturating_sub(offset);
+        let size = core::cmp::min(available, mem::size_of::<T>());
+        unsafe {
+            ptr::copy(self.data.as_ptr().offset(offset as isize), &mut t as *mut T as *mut u8, size);
+        }
+        t
+    }
+
+    pub fn read_data<T: Copy>(&self) -> T {
+        self.read_data_at(0)
+    }
+}
+
+enum OutMoveItem {
+    Handle(svc::Handle),
+    Session(usize),
+}
+
+pub struct Response<S> {
+    pub rc: ResultCode,
+    data: Vec<u8>,
+    copy_handles: Vec<svc::Handle>,
+    move_items: Vec<OutMoveItem>,
+    objects: Vec<u32>,
+    sessions: Vec<S>,
+}
+
+impl<S> Response<S> {
+    pub fn new() -> Self {
+        Self::from_result(ResultCode::from::<ResultSuccess>())
+    }
+
+    pub fn from_result(rc: ResultCode) -> Self {
+        Self { rc: rc, data: Vec::new(), copy_handles: Vec::new(), move_items: Vec::new(), objects: Vec::new(), sessions: Vec::new() }
+    }
+
+    pub fn push_data<T: Copy>(&mut self, t: T) -> &mut Self {
+        server::push_out_data(&mut self.data, t);
+        self
+    }
+
+    pub fn push_copy_handle(&mut self, handle: svc::Handle) -> &mut Self {
+        self.copy_handles.push(handle);
+        self
+    }
+
+    pub fn push_move_handle(&mut self, handle: svc::Handle) -> &mut Self {
+        self.move_items.push(OutMoveItem::Handle(handle));
+        self
+    }
+
+    pub fn push_object(&mut self, object_id: u32) -> &mut Self {
+        self.objects.push(object_id);
+        self
+    }
+
+    pub fn push_session(&mut self, session: S) -> &mut Self {
+        self.move_items.push(OutMoveItem::Session(self.sessions.len()));
+        self.sessions.push(session);
+        self
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Sessions are resolved by the caller, either into new handles or into domain object IDs
+    pub fn resolve_sessions<F: FnMut(S) -> svc::Handle, G: FnMut(S) -> u32>(self, is_domain: bool, mut make_handle: F, mut make_object: G) -> ResolvedResponse {
+        let mut sessions: Vec<Option<S>> = self.sessions.into_iter().map(Some).collect();
+        let mut move_handles: Vec<svc::Handle> = Vec::new();
+        let mut objects = self.objects;
+        for item in self.move_items {
+            match item {
+                OutMoveItem::Handle(handle) => move_handles.push(handle),
+                OutMoveItem::Session(index) => {
+                    let session = sessions[index].take().unwrap();
+                    if is_domain {
+                        objects.push(make_object(session));
+                    }
+                    else {
+                        move_handles.push(make_handle(session));
+                    }
+                }
+            }
+        }
+        ResolvedResponse { rc: self.rc, data: self.data, copy_handles: self.copy_handles, move_handles: move_handles, objects: objects }
+    }
+}
+
+pub struct ResolvedResponse {
+    pub rc: ResultCode,
+    pub data: Vec<u8>,
+    pub copy_handles: Vec<svc::Handle>,
+    pub move_handles: Vec<svc::Handle>,
+    pub objects: Vec<u32>,
+}
+
+// Requests are decoded just like ipc::server does, then copied out so that handlers can keep them around
+pub fn read_request_from_ipc_buffer(is_domain: bool) -> Request {
+    let mut request = Request { command_type: ipc::CommandType::Invalid, request_id: 0, domain_command_type: ipc::DomainCommandType::Invalid, object_id: 0, process_id: None, copy_handles: Vec::new(), move_handles: Vec::new(), objects: Vec::new(), send_statics: Vec::new(), send_buffers: Vec::new(), receive_buffers: Vec::new(), exchange_buffers: Vec::new(), receive_statics: Vec::new(), data: Vec::new() };
+    let mut ctx = ipc::CommandContext::new(ipc::Session::new());
+    request.command_type = server::read_command_from_ipc_buffer(&mut ctx);
+    if ctx.in_params.send_process_id {
+        request.process_id = Some(ctx.in_params.process_id);
+    }
+    request.copy_handles.extend_from_slice(&ctx.in_params.copy_handles[..ctx.in_params.copy_handle_count]);
+    request.move_handles.extend_from_slice(&ctx.in_params.move_handles[..ctx.in_params.move_handle_count]);
+    request.send_statics.extend_from_slice(&ctx.send_statics[..ctx.send_static_count]);
+    request.send_buffers.extend_from_slice(&ctx.send_buffers[..ctx.send_buffer_count]);
+    request.receive_buffers.extend_from_slice(&ctx.receive_buffers[..ctx.receive_buffer_count]);
+    request.exchange_buffers.extend_from_slice(&ctx.exchange_buffers[..ctx.exchange_buffer_count]);
+    request.receive_statics.extend_from_slice(&ctx.receive_statics[..ctx.receive_static_count]);
+
+    if (request.command_type == ipc::CommandType::Close) || (request.command_type == ipc::CommandType::Invalid) {
+        return request;
+    }
+
+    let is_domain_request = is_domain && (request.command_type == ipc::CommandType::Request);
+    match server::read_request_command_from_ipc_buffer(&mut ctx, is_domain_request) {
+        Ok((domain_command_type, request_id)) => {
+            request.domain_command_type = domain_command_type;
+            request.request_id = request_id;
+            request.object_id = ctx.session.object_id;
+            request.objects.extend_from_slice(&ctx.in_params.objects[..ctx.in_params.object_count]);
+            if ctx.in_params.data_size > 0 {
+                request.data.extend_from_slice(unsafe { core::slice::from_raw_parts(ctx.in_params.data_offset, ctx.in_params.data_size as usize) });
+            }
+        },
+        // Malformed requests are left for the caller to reject
+        Err(_) => request.command_type = ipc::CommandType::Invalid,
+    };
+    request
+}
+
+pub fn write_response_on_ipc_buffer(response: &ResolvedResponse, is_domain: bool) {
+    let mut ctx = ipc::CommandContext::new(ipc::Session::new());
+    for handle in response.copy_handles.iter() {
+        ctx.out_params.add_copy_handle(*handle);
+    }
+    for handle in response.move_handles.iter() {
+        ctx.out_params.add_move_handle(*handle);
+    }
+    for object_id in response.objects.iter() {
+        ctx.out_params.add_object(*object_id);
+    }
+    server::write_request_command_response_with_data_on_ipc_buffer(&mut ctx, response.rc, is_domain, &response.data);
+}
@@ -1,19 +1,95 @@
+extern crate alloc;
+
 use crate::svc;
 use crate::result::*;
 use crate::thread;
+use crate::sync;
+use crate::service;
+use alloc::vec::Vec;
 use core::ptr;
 use core::fmt;
+use core::mem;
+use core::marker;
 use enumflags2::BitFlags;
 
 #[macro_use]
 pub mod client;
 
+pub mod server;
+
+#[cfg(feature = "mock-svc")]
+pub mod mock;
+
 pub const RESULT_SUBMODULE: u32 = 4;
 
 result_lib_define_group!(RESULT_SUBMODULE => {
-    ResultNoItemsLeft: 1
+    ResultNoItemsLeft: 1,
+    ResultPointerBufferTooBig: 2,
+    ResultObjectNotInDomain: 3,
+    ResultInvalidBufferAttributes: 4
 });
 
+// Domain objects share the handle of their parent session, which must stay open until the last of them is closed
+static G_DOMAIN_REFERENCES: sync::Mutex<Vec<(svc::Handle, u32)>> = sync::Mutex::new(Vec::new());
+
+pub fn add_domain_reference(handle: svc::Handle) {
+    let mut references = G_DOMAIN_REFERENCES.lock();
+    match references.iter_mut().find(|reference| reference.0 == handle) {
+        Some(reference) => reference.1 += 1,
+        None => references.push((handle, 1)),
+    }
+}
+
+// Returns whether the handle isn't referenced anymore (untracked handles never were)
+pub fn release_domain_reference(handle: svc::Handle) -> bool {
+    let mut references = G_DOMAIN_REFERENCES.lock();
+    match references.iter().position(|reference| reference.0 == handle) {
+        Some(index) => {
+            references[index].1 -= 1;
+            if references[index].1 == 0 {
+                references.remove(index);
+                return true;
+            }
+            false
+        },
+        None => true,
+    }
+}
+
+pub fn get_domain_reference_count(handle: svc::Handle) -> u32 {
+    match G_DOMAIN_REFERENCES.lock().iter().find(|reference| reference.0 == handle) {
+        Some(reference) => reference.1,
+        None => 0,
+    }
+}
+
+// Servers don't change their pointer buffer size, so it's queried once per handle instead of once per Session copy
+static G_POINTER_BUFFER_SIZES: sync::Mutex<Vec<(svc::Handle, u16)>> = sync::Mutex::new(Vec::new());
+
+fn find_cached_pointer_buffer_size(handle: svc::Handle) -> Option<u16> {
+    G_POINTER_BUFFER_SIZES.lock().iter().find(|entry| entry.0 == handle).map(|entry| entry.1)
+}
+
+fn cache_pointer_buffer_size(handle: svc::Handle, pointer_buffer_size: u16) {
+    let mut sizes = G_POINTER_BUFFER_SIZES.lock();
+    match sizes.iter_mut().find(|entry| entry.0 == handle) {
+        Some(entry) => entry.1 = pointer_buffer_size,
+        None => sizes.push((handle, pointer_buffer_size)),
+    }
+}
+
+// Closed handle values get reused, so their cached sizes must go away with them
+fn forget_pointer_buffer_size(handle: svc::Handle) {
+    G_POINTER_BUFFER_SIZES.lock().retain(|entry| entry.0 != handle);
+}
+
+// Only meant for when every handle went away at once, like when the mock kernel is reset
+#[cfg(feature = "mock-svc")]
+pub fn reset_handle_tracking() {
+    G_DOMAIN_REFERENCES.lock().clear();
+    G_POINTER_BUFFER_SIZES.lock().clear();
+}
+
 #[derive(Copy, Clone)]
 pub struct Session {
     pub handle: svc::Handle,
@@ -34,6 +110,12 @@ impl Session {
         Self { handle: parent_handle, object_id: object_id, owns_handle: false }
     }
 
+    // Unlike from_object_id, the object keeps the parent handle alive until it's closed
+    pub fn from_domain_object(parent_handle: svc::Handle, object_id: u32) -> Self {
+        add_domain_reference(parent_handle);
+        Self { handle: parent_handle, object_id: object_id, owns_handle: true }
+    }
+
     pub const fn is_valid(&self) -> bool {
         self.handle != 0
     }
@@ -56,9 +138,38 @@ impl Session {
             OutObjects {};
             OutSessions {};
         });
+        if self.owns_handle {
+            add_domain_reference(self.handle);
+        }
         Ok(())
     }
 
+    pub fn copy_from_current_domain(&mut self, object_id: u32) -> Result<Session> {
+        let handle: svc::Handle;
+        ipc_client_session_send_control_command!([*self; client::ControlRequestId::CopyFromCurrentDomain; false] => {
+            In {
+                object_id: u32 = object_id
+            };
+            InHandles {};
+            InObjects {};
+            InSessions {};
+            Buffers {};
+            Out {};
+            OutHandles {
+                handle => HandleMode::Move
+            };
+            OutObjects {};
+            OutSessions {};
+        });
+        Ok(Session::from_handle(handle))
+    }
+
+    // The copy is a standalone session for the object, outside the domain
+    pub fn copy_object_from_current_domain(&mut self) -> Result<Session> {
+        let object_id = self.object_id;
+        self.copy_from_current_domain(object_id)
+    }
+
     pub fn query_pointer_buffer_size(&mut self) -> Result<u16> {
         let pointer_buf_size: u16;
         ipc_client_session_send_control_command!([*self; client::ControlRequestId::QueryPointerBufferSize; false] => {
@@ -74,26 +185,100 @@ impl Session {
             OutObjects {};
             OutSessions {};
         });
+        cache_pointer_buffer_size(self.handle, pointer_buf_size);
         Ok(pointer_buf_size)
     }
 
+    pub fn get_pointer_buffer_size(&mut self) -> Result<u16> {
+        match find_cached_pointer_buffer_size(self.handle) {
+            Some(pointer_buf_size) => Ok(pointer_buf_size),
+            None => self.query_pointer_buffer_size(),
+        }
+    }
+
+    // Clones of domain sessions are new handles to the same domain, so they keep the object ID
+    fn make_clone(&self, handle: svc::Handle) -> Session {
+        let mut session = Session::from_handle(handle);
+        if self.is_domain() {
+            session.object_id = self.object_id;
+            add_domain_reference(handle);
+        }
+        session
+    }
+
+    pub fn clone_current_object(&mut self) -> Result<Session> {
+        let handle: svc::Handle;
+        ipc_client_session_send_control_command!([*self; client::ControlRequestId::CloneCurrentObject; false] => {
+            In {};
+            InHandles {};
+            InObjects {};
+            InSessions {};
+            Buffers {};
+            Out {};
+            OutHandles {
+                handle => HandleMode::Move
+            };
+            OutObjects {};
+            OutSessions {};
+        });
+        Ok(self.make_clone(handle))
+    }
+
+    pub fn clone_current_object_ex(&mut self, tag: u32) -> Result<Session> {
+        let handle: svc::Handle;
+        ipc_client_session_send_control_command!([*self; client::ControlRequestId::CloneCurrentObjectEx; false] => {
+            In {
+                tag: u32 = tag
+            };
+            InHandles {};
+            InObjects {};
+            InSessions {};
+            Buffers {};
+            Out {};
+            OutHandles {
+                handle => HandleMode::Move
+            };
+            OutObjects {};
+            OutSessions {};
+        });
+        Ok(self.make_clone(handle))
+    }
+
     pub fn close(&mut self) {
         if self.is_valid() {
             if self.is_domain() {
+                // The object is closed first, and the handle only once no other object of the domain uses it
                 let mut ctx = CommandContext::new(*self);
                 client::write_request_command_on_ipc_buffer(&mut ctx, None, DomainCommandType::Close);
                 let _ = svc::send_sync_request(self.handle);
+                if self.owns_handle && release_domain_reference(self.handle) {
+                    forget_pointer_buffer_size(self.handle);
+                    let _ = svc::close_handle(self.handle);
+                }
             }
             else if self.owns_handle {
                 let mut ctx = CommandContext::new(*self);
                 client::write_close_command_on_ipc_buffer(&mut ctx);
                 let _ = svc::send_sync_request(self.handle);
+                forget_pointer_buffer_size(self.handle);
+                let _ = svc::close_handle(self.handle);
             }
-            if self.owns_handle {
+            *self = Self::new();
+        }
+    }
+
+    // Clones of domain sessions are just other handles to the same domain, so closing one must leave the (shared) object alone
+    pub fn close_clone(&mut self) {
+        if self.is_valid() && self.is_domain() {
+            if self.owns_handle && release_domain_reference(self.handle) {
+                forget_pointer_buffer_size(self.handle);
                 let _ = svc::close_handle(self.handle);
             }
             *self = Self::new();
         }
+        else {
+            self.close();
+        }
     }
 }
 
@@ -132,22 +317,35 @@ impl BufferDescriptor {
         Self { size_low: 0, address_low: 0, bits: 0 }
     }
 
-    pub const fn new(buffer: *const u8, buffer_size: usize, flags: BufferFlags) -> Self {
-        unsafe {
-            let address_low = buffer as usize as u32;
-            let address_mid = ((buffer as usize) >> 32) as u32;
-            let address_high = ((buffer as usize) >> 36) as u32;
-            let size_low = buffer_size as u32;
-            let size_high = (buffer_size >> 32) as u32;
+    pub fn new(buffer: *const u8, buffer_size: usize, flags: BufferFlags) -> Self {
+        let address_low = buffer as usize as u32;
+        let address_mid = ((buffer as usize) >> 32) as u32;
+        let address_high = ((buffer as usize) >> 36) as u32;
+        let size_low = buffer_size as u32;
+        let size_high = (buffer_size >> 32) as u32;
 
-            let mut bits: u32 = 0;
-            write_bits!(0, 1, bits, flags as u32);
-            write_bits!(2, 23, bits, address_high);
-            write_bits!(24, 27, bits, size_high);
-            write_bits!(28, 31, bits, address_mid);
+        let mut bits: u32 = 0;
+        write_bits!(0, 1, bits, flags as u32);
+        write_bits!(2, 23, bits, address_high);
+        write_bits!(24, 27, bits, size_high);
+        write_bits!(28, 31, bits, address_mid);
 
-            Self { size_low: size_low, address_low: address_low, bits: bits }
-        }
+        Self { size_low: size_low, address_low: address_low, bits: bits }
+    }
+
+    pub fn get_address(&self) -> *mut u8 {
+        let address_mid = read_bits!(28, 31, self.bits) as usize;
+        let address_high = read_bits!(2, 23, self.bits) as usize;
+        (self.address_low as usize | (address_mid << 32) | (address_high << 36)) as *mut u8
+    }
+
+    pub const fn get_size(&self) -> usize {
+        let size_high = read_bits!(24, 27, self.bits) as usize;
+        self.size_low as usize | (size_high << 32)
+    }
+
+    pub const fn get_flags(&self) -> u32 {
+        read_bits!(0, 1, self.bits)
     }
 }
 
@@ -163,20 +361,32 @@ impl SendStaticDescriptor {
         Self { bits: 0, address_low: 0 }
     }
 
-    pub const fn new(buffer: *const u8, buffer_size: usize, index: u32) -> Self {
-        unsafe {
-            let address_low = buffer as usize as u32;
-            let address_mid = ((buffer as usize) >> 32) as u32;
-            let address_high = ((buffer as usize) >> 36) as u32;
+    pub fn new(buffer: *const u8, buffer_size: usize, index: u32) -> Self {
+        let address_low = buffer as usize as u32;
+        let address_mid = ((buffer as usize) >> 32) as u32;
+        let address_high = ((buffer as usize) >> 36) as u32;
 
-            let mut bits: u32 = 0;
-            write_bits!(0, 5, bits, index);
-            write_bits!(6, 11, bits, address_high);
-            write_bits!(12, 15, bits, address_mid);
-            write_bits!(16, 31, bits, buffer_size as u32);
+        let mut bits: u32 = 0;
+        write_bits!(0, 5, bits, index);
+        write_bits!(6, 11, bits, address_high);
+        write_bits!(12, 15, bits, address_mid);
+        write_bits!(16, 31, bits, buffer_size as u32);
 
-            Self { bits: bits, address_low: address_low }
-        }
+        Self { bits: bits, address_low: address_low }
+    }
+
+    pub const fn get_index(&self) -> u32 {
+        read_bits!(0, 5, self.bits)
+    }
+
+    pub fn get_address(&self) -> *mut u8 {
+        let address_high = read_bits!(6, 11, self.bits) as usize;
+        let address_mid = read_bits!(12, 15, self.bits) as usize;
+        (self.address_low as usize | (address_mid << 32) | (address_high << 36)) as *mut u8
+    }
+
+    pub const fn get_size(&self) -> usize {
+        read_bits!(16, 31, self.bits) as usize
     }
 }
 
@@ -192,17 +402,24 @@ impl ReceiveStaticDescriptor {
         Self { address_low: 0, bits: 0 }
     }
 
-    pub const fn new(buffer: *const u8, buffer_size: usize) -> Self {
-        unsafe {
-            let address_low = buffer as usize as u32;
-            let address_high = ((buffer as usize) >> 32) as u32;
+    pub fn new(buffer: *const u8, buffer_size: usize) -> Self {
+        let address_low = buffer as usize as u32;
+        let address_high = ((buffer as usize) >> 32) as u32;
 
-            let mut bits: u32 = 0;
-            write_bits!(0, 15, bits, address_high);
-            write_bits!(16, 31, bits, buffer_size as u32);
+        let mut bits: u32 = 0;
+        write_bits!(0, 15, bits, address_high);
+        write_bits!(16, 31, bits, buffer_size as u32);
 
-            Self { address_low: address_low, bits: bits }
-        }
+        Self { address_low: address_low, bits: bits }
+    }
+
+    pub fn get_address(&self) -> *mut u8 {
+        let address_high = read_bits!(0, 15, self.bits) as usize;
+        (self.address_low as usize | (address_high << 32)) as *mut u8
+    }
+
+    pub const fn get_size(&self) -> usize {
+        read_bits!(16, 31, self.bits) as usize
     }
 }
 
@@ -258,10 +475,40 @@ impl CommandHeader {
         Self { bits_1: bits_1, bits_2: bits_2 }
     }
 
+    pub const fn get_command_type(&self) -> u32 {
+        read_bits!(0, 15, self.bits_1)
+    }
+
     pub const fn get_send_static_count(&self) -> u32 {
         read_bits!(16, 19, self.bits_1)
     }
 
+    pub const fn get_send_buffer_count(&self) -> u32 {
+        read_bits!(20, 23, self.bits_1)
+    }
+
+    pub const fn get_receive_buffer_count(&self) -> u32 {
+        read_bits!(24, 27, self.bits_1)
+    }
+
+    pub const fn get_exchange_buffer_count(&self) -> u32 {
+        read_bits!(28, 31, self.bits_1)
+    }
+
+    pub const fn get_data_word_count(&self) -> u32 {
+        read_bits!(0, 9, self.bits_2)
+    }
+
+    pub const fn get_receive_static_count(&self) -> u32 {
+        let static_type = read_bits!(10, 13, self.bits_2);
+        match static_type {
+            0 => 0,
+            // A single descriptor shared by all the receive statics
+            2 => 1,
+            _ => static_type - 2,
+        }
+    }
+
     pub const fn get_has_special_header(&self) -> bool {
         read_bits!(31, 31, self.bits_2) != 0
     }
@@ -383,6 +630,225 @@ pub enum BufferAttribute {
     MapTransferAllowsNonDevice = 0b10000000,
 }
 
+pub trait BufferAttributeSet {
+    fn get_attributes() -> BitFlags<BufferAttribute>;
+}
+
+// Buffers we send data in can be built from shared borrows, while ones we get data back in need mutable ones
+pub trait InBufferAttributeSet: BufferAttributeSet {}
+pub trait OutBufferAttributeSet: BufferAttributeSet {}
+
+macro_rules! buffer_attribute_set_define {
+    ($name:ident, $direction:ident, $kind:ident) => {
+        pub struct $name;
+
+        impl BufferAttributeSet for $name {
+            fn get_attributes() -> BitFlags<BufferAttribute> {
+                BufferAttribute::$direction | BufferAttribute::$kind
+            }
+        }
+    };
+}
+
+buffer_attribute_set_define!(InAutoSelectAttributes, In, AutoSelect);
+buffer_attribute_set_define!(OutAutoSelectAttributes, Out, AutoSelect);
+buffer_attribute_set_define!(InPointerAttributes, In, Pointer);
+buffer_attribute_set_define!(OutPointerAttributes, Out, Pointer);
+buffer_attribute_set_define!(InMapAliasAttributes, In, MapAlias);
+buffer_attribute_set_define!(OutMapAliasAttributes, Out, MapAlias);
+
+impl InBufferAttributeSet for InAutoSelectAttributes {}
+impl OutBufferAttributeSet for OutAutoSelectAttributes {}
+impl InBufferAttributeSet for InPointerAttributes {}
+impl OutBufferAttributeSet for OutPointerAttributes {}
+impl InBufferAttributeSet for InMapAliasAttributes {}
+impl OutBufferAttributeSet for OutMapAliasAttributes {}
+
+// The attributes are part of the type, so that command definitions can't be called with the wrong kind of buffer
+// The borrowed slice must outlive the buffer, which is only sent while the command is being processed
+pub struct Buffer<'a, A: BufferAttributeSet, T> {
+    address: *const u8,
+    size: usize,
+    attributes: marker::PhantomData<A>,
+    data: marker::PhantomData<&'a mut [T]>,
+}
+
+impl<'a, A: BufferAttributeSet, T> Buffer<'a, A, T> {
+    // For buffers whose memory isn't owned by Rust (received from other processes, provided by the homebrew loader...)
+    pub unsafe fn from_raw(address: *const u8, size: usize) -> Self {
+        Self { address: address, size: size, attributes: marker::PhantomData, data: marker::PhantomData }
+    }
+
+    pub fn empty() -> Self {
+        Self { address: ptr::null(), size: 0, attributes: marker::PhantomData, data: marker::PhantomData }
+    }
+
+    pub fn get_address(&self) -> *const u8 {
+        self.address
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get_count(&self) -> usize {
+        match mem::size_of::<T>() {
+            0 => 0,
+            t_size => self.size / t_size,
+        }
+    }
+
+    pub fn get_attributes(&self) -> BitFlags<BufferAttribute> {
+        A::get_attributes()
+    }
+
+    // Only ever shrinks the buffer, for sending just the used part of it
+    pub fn with_size(self, size: usize) -> Self {
+        let new_size = match size < self.size {
+            true => size,
+            false => self.size,
+        };
+        Self { address: self.address, size: new_size, attributes: marker::PhantomData, data: marker::PhantomData }
+    }
+}
+
+impl<'a, A: BufferAttributeSet, T: Copy> Buffer<'a, A, T> {
+    // Plain data can be sent/received as raw bytes, for commands which take any kind of data
+    pub fn into_bytes(self) -> Buffer<'a, A, u8> {
+        Buffer { address: self.address, size: self.size, attributes: marker::PhantomData, data: marker::PhantomData }
+    }
+}
+
+impl<'a, A: InBufferAttributeSet, T> Buffer<'a, A, T> {
+    pub fn from_slice(slice: &'a [T]) -> Self {
+        unsafe {
+            Self::from_raw(slice.as_ptr() as *const u8, slice.len() * mem::size_of::<T>())
+        }
+    }
+
+    pub fn from_var(var: &'a T) -> Self {
+        Self::from_slice(core::slice::from_ref(var))
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        match self.address.is_null() {
+            true => &[],
+            false => unsafe { core::slice::from_raw_parts(self.address as *const T, self.get_count()) },
+        }
+    }
+}
+
+impl<'a, A: InBufferAttributeSet> Buffer<'a, A, u8> {
+    pub fn from_str(string: &'a str) -> Self {
+        Self::from_slice(string.as_bytes())
+    }
+}
+
+impl<'a, A: OutBufferAttributeSet, T> Buffer<'a, A, T> {
+    pub fn from_mut_slice(slice: &'a mut [T]) -> Self {
+        unsafe {
+            Self::from_raw(slice.as_mut_ptr() as *const u8, slice.len() * mem::size_of::<T>())
+        }
+    }
+
+    pub fn from_mut_var(var: &'a mut T) -> Self {
+        Self::from_mut_slice(core::slice::from_mut(var))
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        match self.address.is_null() {
+            true => &mut [],
+            false => unsafe { core::slice::from_raw_parts_mut(self.address as *mut T, self.get_count()) },
+        }
+    }
+}
+
+// "Buffers" let the client pick between pointer and map-alias transfers depending on their size
+pub type InBuffer<'a, T> = Buffer<'a, InAutoSelectAttributes, T>;
+pub type OutBuffer<'a, T> = Buffer<'a, OutAutoSelectAttributes, T>;
+pub type InPointer<'a, T> = Buffer<'a, InPointerAttributes, T>;
+pub type OutPointer<'a, T> = Buffer<'a, OutPointerAttributes, T>;
+pub type InMapAlias<'a, T> = Buffer<'a, InMapAliasAttributes, T>;
+pub type OutMapAlias<'a, T> = Buffer<'a, OutMapAliasAttributes, T>;
+
+// Sends a session object: an object ID when it's in the same domain as the session the command is sent to, a copy of its handle otherwise
+pub struct InSession<'a> {
+    pub session: Session,
+    object: marker::PhantomData<&'a Session>,
+}
+
+impl<'a> InSession<'a> {
+    pub fn from<S: service::SessionObject>(object: &'a S) -> Self {
+        Self { session: object.get_session(), object: marker::PhantomData }
+    }
+}
+
+// Makes the command send our process ID (the kernel fills it in), the value is just a placeholder in the raw data
+pub struct ProcessId {
+    pub process_id: u64,
+}
+
+impl ProcessId {
+    pub const fn new() -> Self {
+        Self { process_id: 0 }
+    }
+}
+
+// Walks raw data with the same layout a #[repr(C)] struct with the same fields would have (a null pointer just computes the size)
+pub struct DataWalker {
+    ptr: *mut u8,
+    offset: usize,
+    max_align: usize,
+}
+
+impl DataWalker {
+    pub const fn new(ptr: *mut u8) -> Self {
+        Self { ptr: ptr, offset: 0, max_align: 1 }
+    }
+
+    fn align_for<T>(&mut self) {
+        let align = mem::align_of::<T>();
+        self.offset = (self.offset + align - 1) & !(align - 1);
+        if align > self.max_align {
+            self.max_align = align;
+        }
+    }
+
+    pub fn advance<T>(&mut self) {
+        self.align_for::<T>();
+        self.offset += mem::size_of::<T>();
+    }
+
+    pub fn advance_get<T: Copy>(&mut self) -> T {
+        self.align_for::<T>();
+        let t = unsafe { ptr::read_unaligned(self.ptr.offset(self.offset as isize) as *const T) };
+        self.offset += mem::size_of::<T>();
+        t
+    }
+
+    // Like advance_get, but nothing ending past the limit is read
+    pub fn advance_get_within<T: Copy>(&mut self, limit: usize) -> Option<T> {
+        self.align_for::<T>();
+        if (self.offset + mem::size_of::<T>()) > limit {
+            return None;
+        }
+        Some(self.advance_get())
+    }
+
+    pub fn advance_set<T: Copy>(&mut self, t: T) {
+        self.align_for::<T>();
+        unsafe {
+            ptr::write_unaligned(self.ptr.offset(self.offset as isize) as *mut T, t);
+        }
+        self.offset += mem::size_of::<T>();
+    }
+
+    // Trailing padding included, like size_of would
+    pub fn get_size(&self) -> usize {
+        (self.offset + self.max_align - 1) & !(self.max_align - 1)
+    }
+}
+
 const MAX_COUNT: usize = 8;
 
 pub struct CommandIn {
@@ -462,6 +928,27 @@ impl CommandOut {
         Self { send_process_id: false, process_id: 0, data_size: 0, data_offset: ptr::null_mut(), data_words_offset: ptr::null_mut(), copy_handles: [0; MAX_COUNT], copy_handle_count: 0, move_handles: [0; MAX_COUNT], move_handle_count: 0, objects: [0; MAX_COUNT], object_count: 0 }
     }
     
+    pub fn add_copy_handle(&mut self, handle: svc::Handle) {
+        if self.copy_handle_count < MAX_COUNT {
+            self.copy_handles[self.copy_handle_count] = handle;
+            self.copy_handle_count += 1;
+        }
+    }
+
+    pub fn add_move_handle(&mut self, handle: svc::Handle) {
+        if self.move_handle_count < MAX_COUNT {
+            self.move_handles[self.move_handle_count] = handle;
+            self.move_handle_count += 1;
+        }
+    }
+
+    pub fn add_object(&mut self, object_id: u32) {
+        if self.object_count < MAX_COUNT {
+            self.objects[self.object_count] = object_id;
+            self.object_count += 1;
+        }
+    }
+
     pub fn pop_copy_handle(&mut self) -> Result<svc::Handle> {
         if self.copy_handle_count > 0 {
             self.copy_handle_count -= 1;
@@ -560,33 +1047,47 @@ impl CommandContext {
         let is_in = buffer_attribute.contains(BufferAttribute::In);
         let is_out = buffer_attribute.contains(BufferAttribute::Out);
 
+        let mut flags = BufferFlags::Normal;
+        if buffer_attribute.contains(BufferAttribute::MapTransferAllowsNonSecure) {
+            flags = BufferFlags::NonSecure;
+        }
+        else if buffer_attribute.contains(BufferAttribute::MapTransferAllowsNonDevice){
+            flags = BufferFlags::NonDevice;
+        }
+
         if buffer_attribute.contains(BufferAttribute::AutoSelect) {
-            let pointer_buf_size = self.session.query_pointer_buffer_size()?;
+            // There's no way to send a single buffer both ways as statics
+            result_return_if!(is_in && is_out, ResultInvalidBufferAttributes);
+
+            // Both descriptor kinds are always sent (the server expects them in pairs), with the unused one left empty
+            let pointer_buf_size = self.session.get_pointer_buffer_size()?;
             let buffer_in_static = (pointer_buf_size > 0) && (buffer_size <= pointer_buf_size as usize);
             if is_in {
                 if buffer_in_static {
-                    self.add_send_buffer(BufferDescriptor::new(ptr::null(), 0, BufferFlags::Normal));
+                    self.add_send_buffer(BufferDescriptor::new(ptr::null(), 0, flags));
                     self.add_send_static(SendStaticDescriptor::new(buffer, buffer_size, self.send_static_count as u32));
                 }
                 else {
-                    self.add_send_buffer(BufferDescriptor::new(buffer, buffer_size, BufferFlags::Normal));
+                    self.add_send_buffer(BufferDescriptor::new(buffer, buffer_size, flags));
                     self.add_send_static(SendStaticDescriptor::new(ptr::null(), 0, self.send_static_count as u32));
                 }
             }
             else if is_out {
                 if buffer_in_static {
-                    self.add_receive_buffer(BufferDescriptor::new(ptr::null(), 0, BufferFlags::Normal));
+                    self.add_receive_buffer(BufferDescriptor::new(ptr::null(), 0, flags));
                     self.add_receive_static(ReceiveStaticDescriptor::new(buffer, buffer_size));
                     self.in_params.add_out_pointer_size(buffer_size as u16);
                 }
                 else {
-                    self.add_receive_buffer(BufferDescriptor::new(buffer, buffer_size, BufferFlags::Normal));
+                    self.add_receive_buffer(BufferDescriptor::new(buffer, buffer_size, flags));
                     self.add_receive_static(ReceiveStaticDescriptor::new(ptr::null(), 0));
                     self.in_params.add_out_pointer_size(0);
                 }
             }
         }
         else if buffer_attribute.contains(BufferAttribute::Pointer) {
+            // Static descriptors (and the out pointer sizes) only have 16 bits for the size
+            result_return_if!(buffer_size > u16::max_value() as usize, ResultPointerBufferTooBig);
             if is_in {
                 self.add_send_static(SendStaticDescriptor::new(buffer, buffer_size, self.send_static_count as u32));
             }
@@ -598,13 +1099,6 @@ impl CommandContext {
             }
         }
         else if buffer_attribute.contains(BufferAttribute::MapAlias) {
-            let mut flags = BufferFlags::Normal;
-            if buffer_attribute.contains(BufferAttribute::MapTransferAllowsNonSecure) {
-                flags = BufferFlags::NonSecure;
-            }
-            else if buffer_attribute.contains(BufferAttribute::MapTransferAllowsNonDevice){
-                flags = BufferFlags::NonDevice;
-            }
             let buf_desc = BufferDescriptor::new(buffer, buffer_size, flags);
             if is_in && is_out {
                 self.add_exchange_buffer(buf_desc);
@@ -624,7 +1118,7 @@ impl CommandContext {
         let session: Session;
         if self.session.is_domain() {
             let object = self.out_params.pop_object()?;
-            session = Session::from_object_id(self.session.handle, object);
+            session = Session::from_domain_object(self.session.handle, object);
         }
         else {
             let handle = self.out_params.pop_handle(HandleMode::Move)?;
@@ -666,9 +1160,194 @@ pub fn write_array_to_buffer<T: Copy>(buffer: *mut u8, count: u32, array: &[T; M
     }
 }
 
-pub const fn get_aligned_data_offset(data_words_offset: *mut u8, base_offset: *mut u8) -> *mut u8 {
-    unsafe {
-        let data_offset = (data_words_offset as usize - base_offset as usize + 15) & !15;
-        (data_offset + base_offset as usize) as *mut u8
+pub fn get_aligned_data_offset(data_words_offset: *mut u8, base_offset: *mut u8) -> *mut u8 {
+    let data_offset = (data_words_offset as usize - base_offset as usize + 15) & !15;
+    (data_offset + base_offset as usize) as *mut u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service;
+
+    session_object_define!(TestObject);
+
+    ipc_client_interface_define! {
+        pub trait ITestObject {
+            [0] take_object(object: InSession) => ();
+            [1] take_handles(copied: &svc::handle::ReadableEvent, moved: svc::handle::OwnedHandle) => ();
+            [2] get_event() => (event: svc::handle::ReadableEvent);
+            [3; true] set_value(value: u32) => ();
+        }
+    }
+
+    impl ITestObject for TestObject {}
+
+    fn connect_with_pointer_buffer_size(pointer_buffer_size: u16) -> Session {
+        let mut server = service::mock::Server::new();
+        server.set_pointer_buffer_size(pointer_buffer_size);
+        service::mock::register_named_port(nul!("ipc:test"), server);
+        Session::from_handle(unsafe { svc::connect_to_named_port(nul!("ipc:test").as_ptr()) }.unwrap())
+    }
+
+    #[test]
+    fn auto_select_buffers_fitting_the_pointer_buffer_are_statics() {
+        let _process = service::mock::initialize();
+        let mut ctx = CommandContext::new(connect_with_pointer_buffer_size(0x100));
+        let in_data = [1u8; 0x100];
+        let mut out_data = [0u8; 0x80];
+        ctx.add_buffer(in_data.as_ptr(), in_data.len(), BufferAttribute::In | BufferAttribute::AutoSelect).unwrap();
+        ctx.add_buffer(out_data.as_mut_ptr(), out_data.len(), BufferAttribute::Out | BufferAttribute::AutoSelect).unwrap();
+
+        // X and C descriptors, with the A/B ones sent empty
+        assert_eq!((ctx.send_buffer_count, ctx.receive_buffer_count, ctx.exchange_buffer_count), (1, 1, 0));
+        assert_eq!(ctx.send_buffers[0].get_size(), 0);
+        assert_eq!(ctx.receive_buffers[0].get_size(), 0);
+        // Static descriptors can't hold a whole host address, so only the sizes are checked
+        assert_eq!((ctx.send_statics[0].get_size(), ctx.send_statics[0].get_index()), (0x100, 0));
+        assert_eq!(ctx.receive_statics[0].get_size(), 0x80);
+        assert_eq!(&ctx.in_params.out_pointer_sizes[..ctx.in_params.out_pointer_size_count], &[0x80]);
+    }
+
+    #[test]
+    fn auto_select_buffers_too_big_for_the_pointer_buffer_are_mapped() {
+        let _process = service::mock::initialize();
+        let mut ctx = CommandContext::new(connect_with_pointer_buffer_size(0x80));
+        let in_data = [1u8; 0x100];
+        let mut out_data = [0u8; 0x100];
+        ctx.add_buffer(in_data.as_ptr(), in_data.len(), BufferAttribute::In | BufferAttribute::AutoSelect).unwrap();
+        ctx.add_buffer(out_data.as_mut_ptr(), out_data.len(), BufferAttribute::Out | BufferAttribute::AutoSelect).unwrap();
+
+        // A and B descriptors, with the X/C ones sent empty
+        assert_eq!(ctx.send_buffers[0].get_address() as *const u8, in_data.as_ptr());
+        assert_eq!(ctx.send_buffers[0].get_size(), 0x100);
+        assert_eq!(ctx.receive_buffers[0].get_address(), out_data.as_mut_ptr());
+        assert_eq!(ctx.receive_buffers[0].get_size(), 0x100);
+        assert_eq!((ctx.send_static_count, ctx.receive_static_count), (1, 1));
+        assert_eq!(ctx.send_statics[0].get_size(), 0);
+        assert_eq!(ctx.receive_statics[0].get_size(), 0);
+        assert_eq!(&ctx.in_params.out_pointer_sizes[..ctx.in_params.out_pointer_size_count], &[0]);
+
+        // Servers without a pointer buffer always get mapped buffers
+        let mut ctx = CommandContext::new(connect_with_pointer_buffer_size(0));
+        ctx.add_buffer(in_data.as_ptr(), 0x10, BufferAttribute::In | BufferAttribute::AutoSelect).unwrap();
+        assert_eq!(ctx.send_buffers[0].get_size(), 0x10);
+        assert_eq!(ctx.send_statics[0].get_size(), 0);
+    }
+
+    #[test]
+    fn in_out_auto_select_buffers_are_rejected() {
+        let _process = service::mock::initialize();
+        let mut ctx = CommandContext::new(connect_with_pointer_buffer_size(0x100));
+        let mut data = [0u8; 0x10];
+        assert!(ctx.add_buffer(data.as_mut_ptr(), data.len(), BufferAttribute::In | BufferAttribute::Out | BufferAttribute::AutoSelect).unwrap_err().matches::<ResultInvalidBufferAttributes>());
+        assert_eq!((ctx.send_static_count, ctx.receive_static_count, ctx.exchange_buffer_count), (0, 0, 0));
+    }
+
+    #[test]
+    fn pointer_buffers_bigger_than_statics_are_rejected() {
+        let _process = service::mock::initialize();
+        let mut ctx = CommandContext::new(connect_with_pointer_buffer_size(0x100));
+        let data = [0u8; 0x10000];
+        assert!(ctx.add_buffer(data.as_ptr(), data.len(), BufferAttribute::In | BufferAttribute::Pointer).unwrap_err().matches::<ResultPointerBufferTooBig>());
+        assert!(ctx.add_buffer(data.as_ptr(), data.len(), BufferAttribute::Out | BufferAttribute::Pointer).unwrap_err().matches::<ResultPointerBufferTooBig>());
+        assert_eq!((ctx.send_static_count, ctx.receive_static_count), (0, 0));
+        assert!(ctx.add_buffer(data.as_ptr(), 0xFFFF, BufferAttribute::In | BufferAttribute::Pointer).is_ok());
+    }
+    #[test]
+    fn sessions_are_sent_as_copy_handles() {
+        let _process = service::mock::initialize();
+        let sent_handles = crate::mem::make_shared(Vec::new());
+        let mut server = service::mock::Server::new();
+        let server_sent_handles = sent_handles.clone();
+        server.register_command(0, move |request| {
+            server_sent_handles.borrow_mut().extend_from_slice(&request.copy_handles);
+            assert!(request.objects.is_empty());
+            service::mock::Response::new()
+        });
+        service::mock::register_named_port(nul!("ipc:test"), server);
+        let mut object = <TestObject as service::SessionObject>::new(Session::from_handle(unsafe { svc::connect_to_named_port(nul!("ipc:test").as_ptr()) }.unwrap()));
+        let sent_object = <TestObject as service::SessionObject>::new(Session::from_handle(unsafe { svc::connect_to_named_port(nul!("ipc:test").as_ptr()) }.unwrap()));
+
+        object.take_object(InSession::from(&sent_object)).unwrap();
+        assert_eq!(*sent_handles.borrow(), [sent_object.session.handle]);
+    }
+
+    #[test]
+    fn handles_are_sent_and_received_by_type() {
+        let _process = service::mock::initialize();
+        let sent_handles = crate::mem::make_shared(Vec::new());
+        let (writable_handle, readable_handle) = svc::create_event().unwrap();
+        let mut server = service::mock::Server::new();
+        let server_sent_handles = sent_handles.clone();
+        server.register_command(1, move |request| {
+            server_sent_handles.borrow_mut().push((request.copy_handles.clone(), request.move_handles.clone()));
+            service::mock::Response::new()
+        });
+        server.register_command(2, move |_| {
+            let mut response = service::mock::Response::new();
+            response.push_copy_handle(readable_handle);
+            response
+        });
+        service::mock::register_named_port(nul!("ipc:test"), server);
+        let mut object = <TestObject as service::SessionObject>::new(Session::from_handle(unsafe { svc::connect_to_named_port(nul!("ipc:test").as_ptr()) }.unwrap()));
+
+        // Borrowed handles are copied and stay ours, owned ones are moved and given away (so they're not closed by us)
+        let (copied_handle, moved_handle) = svc::create_event().unwrap();
+        let copied = svc::handle::ReadableEvent::from_raw(copied_handle);
+        object.take_handles(&copied, svc::handle::OwnedHandle::new(moved_handle)).unwrap();
+        assert_eq!(*sent_handles.borrow(), [(vec![copied_handle], vec![moved_handle])]);
+        assert!(svc::mock::is_handle_open(copied_handle));
+        assert!(svc::mock::is_handle_open(moved_handle));
+        svc::close_handle(moved_handle).unwrap();
+        drop(copied);
+        assert!(!svc::mock::is_handle_open(copied_handle));
+
+        // Received handles are closed once dropped
+        let event = object.get_event().unwrap();
+        assert_eq!(event.get_handle(), readable_handle);
+        drop(event);
+        assert!(!svc::mock::is_handle_open(readable_handle));
+        svc::close_handle(writable_handle).unwrap();
+    }
+
+    #[test]
+    fn process_id_placeholders_go_after_the_parameters() {
+        let _process = service::mock::initialize();
+        let sent_data = crate::mem::make_shared(Vec::new());
+        let mut server = service::mock::Server::new();
+        let server_sent_data = sent_data.clone();
+        server.register_command(3, move |request| {
+            assert!(request.process_id.is_some());
+            // The u64 placeholder is aligned, so it starts right after the value's padding
+            assert!(request.data.len() >= 0x10);
+            server_sent_data.borrow_mut().push((request.read_data::<u32>(), request.read_data_at::<u64>(8)));
+            service::mock::Response::new()
+        });
+        service::mock::register_named_port(nul!("ipc:test"), server);
+        let mut object = <TestObject as service::SessionObject>::new(Session::from_handle(unsafe { svc::connect_to_named_port(nul!("ipc:test").as_ptr()) }.unwrap()));
+
+        object.set_value(0x1234).unwrap();
+        assert_eq!(*sent_data.borrow(), [(0x1234, 0)]);
+    }
+
+    #[test]
+    fn domain_sessions_are_sent_as_object_ids() {
+        let _process = service::mock::initialize();
+        let domain_object = <TestObject as service::SessionObject>::new(Session::from_object_id(0x10, 2));
+        let mut ctx = CommandContext::new(Session::from_object_id(0x10, 1));
+        let mut walker = DataWalker::new(ptr::null_mut());
+        client::RequestCommandParameter::before_request_write(&InSession::from(&domain_object), &mut walker, &mut ctx).unwrap();
+        assert_eq!(&ctx.in_params.objects[..ctx.in_params.object_count], &[2]);
+        assert_eq!(ctx.in_params.copy_handle_count, 0);
+
+        // Neither a session outside of the domain nor one in a different domain can refer to the object by its ID
+        let mut ctx = CommandContext::new(Session::from_handle(0x10));
+        assert!(client::RequestCommandParameter::before_request_write(&InSession::from(&domain_object), &mut walker, &mut ctx).unwrap_err().matches::<ResultObjectNotInDomain>());
+        let mut ctx = CommandContext::new(Session::from_object_id(0x20, 1));
+        assert!(client::RequestCommandParameter::before_request_write(&InSession::from(&domain_object), &mut walker, &mut ctx).unwrap_err().matches::<ResultObjectNotInDomain>());
+
+        // It isn't a real session, so it mustn't be closed
+        mem::forget(domain_object);
     }
 }
\ No newline at end of file
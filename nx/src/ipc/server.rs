@@ -0,0 +1,1171 @@
+extern crate alloc;
+
+use crate::result::*;
+use crate::ipc;
+use crate::ipc::client;
+use crate::svc;
+use crate::thread;
+use crate::svc::handle::CopyHandle;
+use crate::svc::handle::MoveHandle;
+use crate::mem;
+use crate::service;
+use crate::service::sm;
+use crate::service::sm::IUserInterface;
+use alloc::vec::Vec;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::ptr;
+use core::mem as cmem;
+
+pub const RESULT_SUBMODULE: u32 = 15;
+
+result_lib_define_group!(RESULT_SUBMODULE => {
+    ResultInvalidInDataHeaderMagic: 1,
+    ResultInvalidCommandType: 2,
+    ResultInvalidDomainCommandType: 3,
+    ResultCommandNotFound: 4,
+    ResultObjectIdNotFound: 5,
+    ResultAlreadyDomain: 6,
+    ResultInvalidControlCommand: 7,
+    ResultPointerBufferTooSmall: 8,
+    ResultInvalidPortName: 10,
+    ResultTooManyItems: 11,
+    ResultInvalidDataSize: 12
+});
+
+// Kernel limit for named port names, NUL terminator included
+pub const MAX_PORT_NAME_LENGTH: usize = 0xC;
+
+fn convert_command_type(command_type: u32) -> ipc::CommandType {
+    match command_type {
+        1 => ipc::CommandType::LegacyRequest,
+        2 => ipc::CommandType::Close,
+        3 => ipc::CommandType::LegacyControl,
+        4 => ipc::CommandType::Request,
+        5 => ipc::CommandType::Control,
+        6 => ipc::CommandType::RequestWithContext,
+        7 => ipc::CommandType::ControlWithContext,
+        _ => ipc::CommandType::Invalid,
+    }
+}
+
+fn convert_domain_command_type(domain_command_type: u8) -> ipc::DomainCommandType {
+    match domain_command_type {
+        1 => ipc::DomainCommandType::SendMessage,
+        2 => ipc::DomainCommandType::Close,
+        _ => ipc::DomainCommandType::Invalid,
+    }
+}
+
+#[inline(always)]
+pub fn read_command_from_ipc_buffer(ctx: &mut ipc::CommandContext) -> ipc::CommandType {
+    unsafe {
+        let mut ipc_buf = ipc::get_ipc_buffer();
+
+        let command_header = ipc_buf as *mut ipc::CommandHeader;
+        ipc_buf = command_header.offset(1) as *mut u8;
+
+        let mut copy_handle_count: u32 = 0;
+        let mut move_handle_count: u32 = 0;
+        if (*command_header).get_has_special_header() {
+            let special_header = ipc_buf as *mut ipc::CommandSpecialHeader;
+            copy_handle_count = (*special_header).get_copy_handle_count();
+            move_handle_count = (*special_header).get_move_handle_count();
+            ipc_buf = special_header.offset(1) as *mut u8;
+            if (*special_header).get_send_process_id() {
+                // Only 4-byte aligned right after the special header
+                ctx.in_params.send_process_id = true;
+                ctx.in_params.process_id = ptr::read_unaligned(ipc_buf as *const u64);
+                ipc_buf = ipc_buf.offset(cmem::size_of::<u64>() as isize);
+            }
+        }
+
+        let send_static_count = (*command_header).get_send_static_count();
+        let send_buffer_count = (*command_header).get_send_buffer_count();
+        let receive_buffer_count = (*command_header).get_receive_buffer_count();
+        let exchange_buffer_count = (*command_header).get_exchange_buffer_count();
+        let receive_static_count = (*command_header).get_receive_static_count();
+        // Anything we can't hold makes the whole request invalid
+        let item_counts = [copy_handle_count, move_handle_count, send_static_count, send_buffer_count, receive_buffer_count, exchange_buffer_count, receive_static_count];
+        if item_counts.iter().any(|count| *count as usize > ipc::MAX_COUNT) {
+            return ipc::CommandType::Invalid;
+        }
+        // So does anything past the end of the IPC buffer
+        let data_words_size = cmem::size_of::<u32>() * (*command_header).get_data_word_count() as usize;
+        let items_size = cmem::size_of::<svc::Handle>() * (copy_handle_count + move_handle_count) as usize
+            + cmem::size_of::<ipc::SendStaticDescriptor>() * send_static_count as usize
+            + cmem::size_of::<ipc::BufferDescriptor>() * (send_buffer_count + receive_buffer_count + exchange_buffer_count) as usize
+            + data_words_size
+            + cmem::size_of::<ipc::ReceiveStaticDescriptor>() * receive_static_count as usize;
+        if (ipc_buf as usize + items_size) > (command_header as usize + thread::IPC_BUFFER_SIZE) {
+            return ipc::CommandType::Invalid;
+        }
+
+        ipc_buf = ipc::read_array_from_buffer(ipc_buf, copy_handle_count, &mut ctx.in_params.copy_handles);
+        ctx.in_params.copy_handle_count = copy_handle_count as usize;
+        ipc_buf = ipc::read_array_from_buffer(ipc_buf, move_handle_count, &mut ctx.in_params.move_handles);
+        ctx.in_params.move_handle_count = move_handle_count as usize;
+
+        ipc_buf = ipc::read_array_from_buffer(ipc_buf, send_static_count, &mut ctx.send_statics);
+        ctx.send_static_count = send_static_count as usize;
+        ipc_buf = ipc::read_array_from_buffer(ipc_buf, send_buffer_count, &mut ctx.send_buffers);
+        ctx.send_buffer_count = send_buffer_count as usize;
+        ipc_buf = ipc::read_array_from_buffer(ipc_buf, receive_buffer_count, &mut ctx.receive_buffers);
+        ctx.receive_buffer_count = receive_buffer_count as usize;
+        ipc_buf = ipc::read_array_from_buffer(ipc_buf, exchange_buffer_count, &mut ctx.exchange_buffers);
+        ctx.exchange_buffer_count = exchange_buffer_count as usize;
+
+        // The data size is narrowed down to the actual data once the headers inside the data words are read
+        ctx.in_params.data_words_offset = ipc_buf;
+        ctx.in_params.data_size = data_words_size as u32;
+        ipc_buf = ipc_buf.offset(data_words_size as isize);
+        /* ipc_buf = */ ipc::read_array_from_buffer(ipc_buf, receive_static_count, &mut ctx.receive_statics);
+        ctx.receive_static_count = receive_static_count as usize;
+
+        convert_command_type((*command_header).get_command_type())
+    }
+}
+
+// Returns the domain command type (always SendMessage outside domains) and the request ID
+#[inline(always)]
+pub fn read_request_command_from_ipc_buffer(ctx: &mut ipc::CommandContext, is_domain: bool) -> Result<(ipc::DomainCommandType, u32)> {
+    unsafe {
+        let ipc_buf = ipc::get_ipc_buffer();
+        let data_words_end = ctx.in_params.data_words_offset.offset(ctx.in_params.data_size as isize);
+
+        // Every size here comes from the client, so nothing is read past the data words
+        let mut domain_command_type = ipc::DomainCommandType::SendMessage;
+        let mut data_offset = ipc::get_aligned_data_offset(ctx.in_params.data_words_offset, ipc_buf);
+        let mut data_end = data_words_end;
+        if is_domain {
+            result_return_if!((data_offset as usize + cmem::size_of::<ipc::DomainInDataHeader>()) > data_words_end as usize, ResultInvalidDataSize);
+            let domain_header = data_offset as *mut ipc::DomainInDataHeader;
+            // Read the raw value, the client might have sent anything there
+            domain_command_type = convert_domain_command_type(*(domain_header as *mut u8));
+            result_return_if!(domain_command_type == ipc::DomainCommandType::Invalid, ResultInvalidDomainCommandType);
+
+            data_offset = data_offset.offset(cmem::size_of::<ipc::DomainInDataHeader>() as isize);
+            let data_size = (*domain_header).data_size as usize;
+            let object_count = (*domain_header).in_object_count as u32;
+            result_return_if!(object_count as usize > ipc::MAX_COUNT, ResultTooManyItems);
+            // The object IDs come right after the data
+            result_return_if!((data_offset as usize + data_size + cmem::size_of::<u32>() * object_count as usize) > data_words_end as usize, ResultInvalidDataSize);
+            data_end = data_offset.offset(data_size as isize);
+            ctx.in_params.objects_offset = data_end;
+            ipc::read_array_from_buffer(ctx.in_params.objects_offset, object_count, &mut ctx.in_params.objects);
+            ctx.in_params.object_count = object_count as usize;
+            ctx.session = ipc::Session::from_object_id(ctx.session.handle, (*domain_header).object_id);
+
+            // Closing a domain object doesn't come with a data header
+            if domain_command_type == ipc::DomainCommandType::Close {
+                ctx.in_params.data_offset = data_offset;
+                ctx.in_params.data_size = data_size as u32;
+                return Ok((domain_command_type, 0));
+            }
+        }
+
+        result_return_if!((data_offset as usize + cmem::size_of::<ipc::DataHeader>()) > data_end as usize, ResultInvalidDataSize);
+        let data_header = data_offset as *mut ipc::DataHeader;
+        result_return_unless!((*data_header).magic == ipc::IN_DATA_HEADER_MAGIC, ResultInvalidInDataHeaderMagic);
+        data_offset = data_offset.offset(cmem::size_of::<ipc::DataHeader>() as isize);
+
+        ctx.in_params.data_size = (data_end as usize - data_offset as usize) as u32;
+        ctx.in_params.data_offset = data_offset;
+        Ok((domain_command_type, (*data_header).value))
+    }
+}
+
+#[inline(always)]
+pub fn read_control_command_from_ipc_buffer(ctx: &mut ipc::CommandContext) -> Result<u32> {
+    let (_, request_id) = read_request_command_from_ipc_buffer(ctx, false)?;
+    Ok(request_id)
+}
+
+#[inline(always)]
+pub fn write_command_response_on_ipc_buffer(ctx: &mut ipc::CommandContext, data_size: u32) {
+    unsafe {
+        let mut ipc_buf = ipc::get_ipc_buffer();
+
+        let has_special_header = ctx.out_params.send_process_id || ctx.out_params.copy_handle_count > 0 || ctx.out_params.move_handle_count > 0;
+        let data_word_count = (data_size + 3) / 4;
+        // Responses have no command type, and only send statics (for the client's receive statics) are sent back
+        let command_header = ipc_buf as *mut ipc::CommandHeader;
+        *command_header = ipc::CommandHeader::new(ipc::CommandType::Invalid, ctx.send_static_count as u32, 0, 0, 0, data_word_count, 0, has_special_header);
+        ipc_buf = command_header.offset(1) as *mut u8;
+
+        if has_special_header {
+            let special_header = ipc_buf as *mut ipc::CommandSpecialHeader;
+            *special_header = ipc::CommandSpecialHeader::new(ctx.out_params.send_process_id, ctx.out_params.copy_handle_count as u32, ctx.out_params.move_handle_count as u32);
+            ipc_buf = special_header.offset(1) as *mut u8;
+
+            if ctx.out_params.send_process_id {
+                ipc_buf = ipc_buf.offset(cmem::size_of::<u64>() as isize);
+            }
+
+            ipc_buf = ipc::write_array_to_buffer(ipc_buf, ctx.out_params.copy_handle_count as u32, &ctx.out_params.copy_handles);
+            ipc_buf = ipc::write_array_to_buffer(ipc_buf, ctx.out_params.move_handle_count as u32, &ctx.out_params.move_handles);
+        }
+
+        ipc_buf = ipc::write_array_to_buffer(ipc_buf, ctx.send_static_count as u32, &ctx.send_statics);
+        ctx.out_params.data_words_offset = ipc_buf;
+    }
+}
+
+const PADDING: u32 = 16;
+
+#[inline(always)]
+pub fn write_request_command_response_on_ipc_buffer(ctx: &mut ipc::CommandContext, rc: ResultCode, is_domain: bool) {
+    unsafe {
+        let ipc_buf = ipc::get_ipc_buffer();
+
+        let mut data_size = PADDING + cmem::size_of::<ipc::DataHeader>() as u32 + ctx.out_params.data_size;
+        if is_domain {
+            data_size += (cmem::size_of::<ipc::DomainOutDataHeader>() + cmem::size_of::<u32>() * ctx.out_params.object_count) as u32;
+        }
+
+        write_command_response_on_ipc_buffer(ctx, data_size);
+        let mut data_offset = ipc::get_aligned_data_offset(ctx.out_params.data_words_offset, ipc_buf);
+
+        if is_domain {
+            let domain_header = data_offset as *mut ipc::DomainOutDataHeader;
+            *domain_header = ipc::DomainOutDataHeader::new(ctx.out_params.object_count as u32);
+            data_offset = data_offset.offset(cmem::size_of::<ipc::DomainOutDataHeader>() as isize);
+            let objects_offset = data_offset.offset((cmem::size_of::<ipc::DataHeader>() + ctx.out_params.data_size as usize) as isize);
+            ipc::write_array_to_buffer(objects_offset, ctx.out_params.object_count as u32, &ctx.out_params.objects);
+        }
+
+        let data_header = data_offset as *mut ipc::DataHeader;
+        *data_header = ipc::DataHeader::new(ipc::OUT_DATA_HEADER_MAGIC, 0, rc.get_value(), 0);
+        data_offset = data_offset.offset(cmem::size_of::<ipc::DataHeader>() as isize);
+
+        ctx.out_params.data_offset = data_offset;
+    }
+}
+
+// Writes the response header and copies the output data right after it
+pub fn write_request_command_response_with_data_on_ipc_buffer(ctx: &mut ipc::CommandContext, rc: ResultCode, is_domain: bool, out_data: &[u8]) {
+    ctx.out_params.data_size = out_data.len() as u32;
+    write_request_command_response_on_ipc_buffer(ctx, rc, is_domain);
+    unsafe {
+        ptr::copy(out_data.as_ptr(), ctx.out_params.data_offset, out_data.len());
+    }
+}
+
+// Output data keeps the same layout a #[repr(C)] output struct would have
+pub fn push_out_data<T: Copy>(out_data: &mut Vec<u8>, t: T) {
+    let align = cmem::align_of::<T>();
+    while out_data.len() % align != 0 {
+        out_data.push(0);
+    }
+    let t_bytes = unsafe { core::slice::from_raw_parts(&t as *const T as *const u8, cmem::size_of::<T>()) };
+    out_data.extend_from_slice(t_bytes);
+}
+
+#[inline(always)]
+pub fn write_control_command_response_on_ipc_buffer(ctx: &mut ipc::CommandContext, rc: ResultCode) {
+    write_request_command_response_on_ipc_buffer(ctx, rc, false);
+}
+
+pub trait ServerObject {
+    // Dispatches by ctx.get_request_id(), unknown commands should fail with ResultCommandNotFound
+    fn handle_request(&mut self, ctx: &mut ServerContext) -> Result<()>;
+}
+
+pub type ObjectFactory = Box<dyn FnMut() -> mem::SharedObject<dyn ServerObject>>;
+
+struct Domain {
+    objects: BTreeMap<u32, mem::SharedObject<dyn ServerObject>>,
+    next_object_id: u32,
+}
+
+impl Domain {
+    fn new() -> Self {
+        Self { objects: BTreeMap::new(), next_object_id: 1 }
+    }
+
+    fn add_object(&mut self, object: mem::SharedObject<dyn ServerObject>) -> u32 {
+        let object_id = self.next_object_id;
+        self.next_object_id += 1;
+        self.objects.insert(object_id, object);
+        object_id
+    }
+
+    fn get_object(&self, object_id: u32) -> Result<mem::SharedObject<dyn ServerObject>> {
+        match self.objects.get(&object_id) {
+            Some(object) => Ok(object.clone()),
+            None => Err(ResultCode::from::<ResultObjectIdNotFound>()),
+        }
+    }
+}
+
+enum OutMoveItem {
+    Handle(svc::Handle),
+    Object(mem::SharedObject<dyn ServerObject>),
+}
+
+fn pop_item<T: Copy>(array: &[T; ipc::MAX_COUNT], count: usize, index: &mut usize) -> Result<T> {
+    result_return_unless!(*index < count, ipc::ResultNoItemsLeft);
+    let item = array[*index];
+    *index += 1;
+    Ok(item)
+}
+
+// Everything a handler reads and writes, where the request's items are popped in the order the client pushed them
+pub struct ServerContext {
+    pub ctx: ipc::CommandContext,
+    request_id: u32,
+    domain: Option<mem::SharedObject<Domain>>,
+    in_walker: ipc::DataWalker,
+    copy_handle_index: usize,
+    move_handle_index: usize,
+    object_index: usize,
+    send_static_index: usize,
+    receive_static_index: usize,
+    send_buffer_index: usize,
+    receive_buffer_index: usize,
+    exchange_buffer_index: usize,
+    pointer_buffer: *mut u8,
+    pointer_buffer_in_end: usize,
+    pointer_buffer_out_start: usize,
+    out_statics: Vec<ipc::SendStaticDescriptor>,
+    out_data: Vec<u8>,
+    out_copy_handles: Vec<svc::Handle>,
+    out_move_items: Vec<OutMoveItem>,
+}
+
+impl ServerContext {
+    fn new(ctx: ipc::CommandContext, request_id: u32, domain: Option<mem::SharedObject<Domain>>, pointer_buffer: *mut u8, pointer_buffer_size: usize) -> Self {
+        // The kernel copied the send statics to the start of the pointer buffer, so out pointer buffers are taken from its end
+        let pointer_buffer_start = pointer_buffer as usize;
+        let mut pointer_buffer_in_end: usize = 0;
+        for send_static in ctx.send_statics[..ctx.send_static_count].iter() {
+            let address = send_static.get_address() as usize;
+            if (address >= pointer_buffer_start) && (address < pointer_buffer_start + pointer_buffer_size) {
+                pointer_buffer_in_end = core::cmp::max(pointer_buffer_in_end, address - pointer_buffer_start + send_static.get_size());
+            }
+        }
+
+        let in_walker = ipc::DataWalker::new(ctx.in_params.data_offset);
+        Self { ctx: ctx, request_id: request_id, domain: domain, in_walker: in_walker, copy_handle_index: 0, move_handle_index: 0, object_index: 0, send_static_index: 0, receive_static_index: 0, send_buffer_index: 0, receive_buffer_index: 0, exchange_buffer_index: 0, pointer_buffer: pointer_buffer, pointer_buffer_in_end: pointer_buffer_in_end, pointer_buffer_out_start: pointer_buffer_size, out_statics: Vec::new(), out_data: Vec::new(), out_copy_handles: Vec::new(), out_move_items: Vec::new() }
+    }
+
+    pub fn get_request_id(&self) -> u32 {
+        self.request_id
+    }
+
+    pub fn get_process_id(&self) -> Option<u64> {
+        match self.ctx.in_params.send_process_id {
+            true => Some(self.ctx.in_params.process_id),
+            false => None,
+        }
+    }
+
+    pub fn is_domain(&self) -> bool {
+        self.domain.is_some()
+    }
+
+    // Only the data the client actually sent can be read
+    pub fn read_in<T: Copy>(&mut self) -> Result<T> {
+        match self.in_walker.advance_get_within(self.ctx.in_params.data_size as usize) {
+            Some(t) => Ok(t),
+            None => Err(ResultCode::from::<ResultInvalidDataSize>()),
+        }
+    }
+
+    pub fn pop_copy_handle(&mut self) -> Result<svc::Handle> {
+        pop_item(&self.ctx.in_params.copy_handles, self.ctx.in_params.copy_handle_count, &mut self.copy_handle_index)
+    }
+
+    pub fn pop_move_handle(&mut self) -> Result<svc::Handle> {
+        pop_item(&self.ctx.in_params.move_handles, self.ctx.in_params.move_handle_count, &mut self.move_handle_index)
+    }
+
+    pub fn pop_handle(&mut self, mode: ipc::HandleMode) -> Result<svc::Handle> {
+        match mode {
+            ipc::HandleMode::Copy => self.pop_copy_handle(),
+            ipc::HandleMode::Move => self.pop_move_handle(),
+        }
+    }
+
+    // Objects sent by the client are other objects of the same domain
+    pub fn pop_object(&mut self) -> Result<mem::SharedObject<dyn ServerObject>> {
+        let object_id = pop_item(&self.ctx.in_params.objects, self.ctx.in_params.object_count, &mut self.object_index)?;
+        match self.domain {
+            Some(ref domain) => domain.borrow().get_object(object_id),
+            None => Err(ResultCode::from::<ResultObjectIdNotFound>()),
+        }
+    }
+
+    fn pop_send_static(&mut self) -> Result<ipc::SendStaticDescriptor> {
+        pop_item(&self.ctx.send_statics, self.ctx.send_static_count, &mut self.send_static_index)
+    }
+
+    fn pop_receive_static(&mut self) -> Result<ipc::ReceiveStaticDescriptor> {
+        pop_item(&self.ctx.receive_statics, self.ctx.receive_static_count, &mut self.receive_static_index)
+    }
+
+    fn pop_send_buffer(&mut self) -> Result<ipc::BufferDescriptor> {
+        pop_item(&self.ctx.send_buffers, self.ctx.send_buffer_count, &mut self.send_buffer_index)
+    }
+
+    fn pop_receive_buffer(&mut self) -> Result<ipc::BufferDescriptor> {
+        pop_item(&self.ctx.receive_buffers, self.ctx.receive_buffer_count, &mut self.receive_buffer_index)
+    }
+
+    fn pop_exchange_buffer(&mut self) -> Result<ipc::BufferDescriptor> {
+        pop_item(&self.ctx.exchange_buffers, self.ctx.exchange_buffer_count, &mut self.exchange_buffer_index)
+    }
+
+    // Out pointer data is written to our pointer buffer, and the kernel copies it to the client's receive static with the response
+    fn pop_out_pointer(&mut self) -> Result<(*mut u8, usize)> {
+        let index = self.receive_static_index as u32;
+        let receive_static = self.pop_receive_static()?;
+        let size = receive_static.get_size();
+        result_return_if!(size > self.pointer_buffer_out_start, ResultPointerBufferTooSmall);
+        let start = (self.pointer_buffer_out_start - size) & !0xF;
+        result_return_if!(start < self.pointer_buffer_in_end, ResultPointerBufferTooSmall);
+
+        self.pointer_buffer_out_start = start;
+        let address = unsafe { self.pointer_buffer.offset(start as isize) };
+        self.out_statics.push(ipc::SendStaticDescriptor::new(address, size, index));
+        Ok((address, size))
+    }
+
+    pub fn pop_buffer<'a, A: ipc::BufferAttributeSet, T>(&mut self) -> Result<ipc::Buffer<'a, A, T>> {
+        let buffer_attribute = A::get_attributes();
+        let is_in = buffer_attribute.contains(ipc::BufferAttribute::In);
+        let is_out = buffer_attribute.contains(ipc::BufferAttribute::Out);
+
+        let (address, size) = if buffer_attribute.contains(ipc::BufferAttribute::AutoSelect) {
+            // The client sends both kinds of buffers, leaving the one it didn't pick empty
+            if is_in {
+                let buffer = self.pop_send_buffer()?;
+                let send_static = self.pop_send_static()?;
+                if send_static.get_size() > 0 {
+                    (send_static.get_address(), send_static.get_size())
+                }
+                else {
+                    (buffer.get_address(), buffer.get_size())
+                }
+            }
+            else {
+                let buffer = self.pop_receive_buffer()?;
+                result_return_unless!(self.receive_static_index < self.ctx.receive_static_count, ipc::ResultNoItemsLeft);
+                if self.ctx.receive_statics[self.receive_static_index].get_size() > 0 {
+                    self.pop_out_pointer()?
+                }
+                else {
+                    self.pop_receive_static()?;
+                    (buffer.get_address(), buffer.get_size())
+                }
+            }
+        }
+        else if buffer_attribute.contains(ipc::BufferAttribute::Pointer) {
+            if is_in {
+                let send_static = self.pop_send_static()?;
+                (send_static.get_address(), send_static.get_size())
+            }
+            else {
+                self.pop_out_pointer()?
+            }
+        }
+        else {
+            let buffer = if is_in && is_out {
+                self.pop_exchange_buffer()?
+            }
+            else if is_in {
+                self.pop_send_buffer()?
+            }
+            else {
+                self.pop_receive_buffer()?
+            };
+            (buffer.get_address(), buffer.get_size())
+        };
+
+        // The client's memory stays mapped until we reply
+        unsafe {
+            Ok(ipc::Buffer::from_raw(address, size))
+        }
+    }
+
+    pub fn push_out<T: Copy>(&mut self, t: T) {
+        push_out_data(&mut self.out_data, t);
+    }
+
+    // Like with the client macros, copy handles are borrowed and move handles are given away
+    pub fn push_copy_handle<H: CopyHandle>(&mut self, handle: &H) {
+        self.out_copy_handles.push(handle.copy_handle());
+    }
+
+    pub fn push_move_handle<H: MoveHandle>(&mut self, handle: H) {
+        self.out_move_items.push(OutMoveItem::Handle(handle.move_handle()));
+    }
+
+    // Sent as a new session, or as a new object ID inside domains
+    pub fn push_shared_object(&mut self, object: mem::SharedObject<dyn ServerObject>) {
+        self.out_move_items.push(OutMoveItem::Object(object));
+    }
+
+    pub fn push_object<S: ServerObject + 'static>(&mut self, object: S) {
+        self.push_shared_object(mem::make_shared(object));
+    }
+
+    fn discard_out(&mut self) {
+        for item in self.out_move_items.drain(..) {
+            if let OutMoveItem::Handle(handle) = item {
+                let _ = svc::close_handle(handle);
+            }
+        }
+        self.out_statics.clear();
+        self.out_data.clear();
+        self.out_copy_handles.clear();
+    }
+}
+
+struct Port {
+    handle: svc::Handle,
+    service_name: Option<u64>,
+    factory: ObjectFactory,
+}
+
+struct Session {
+    handle: svc::Handle,
+    object: mem::SharedObject<dyn ServerObject>,
+    domain: Option<mem::SharedObject<Domain>>,
+}
+
+pub struct ServerManager {
+    ports: Vec<Port>,
+    sessions: Vec<Session>,
+    pointer_buffer: Vec<u8>,
+}
+
+impl ServerManager {
+    pub fn new(pointer_buffer_size: u16) -> Self {
+        Self { ports: Vec::new(), sessions: Vec::new(), pointer_buffer: vec![0; pointer_buffer_size as usize] }
+    }
+
+    fn get_handle_count(&self) -> usize {
+        self.ports.len() + self.sessions.len()
+    }
+
+    fn add_port<F: FnMut() -> mem::SharedObject<dyn ServerObject> + 'static>(&mut self, handle: svc::Handle, service_name: Option<u64>, factory: F) {
+        self.ports.push(Port { handle: handle, service_name: service_name, factory: Box::new(factory) });
+    }
+
+    pub fn register_named_port<F: FnMut() -> mem::SharedObject<dyn ServerObject> + 'static>(&mut self, name: &str, max_sessions: i32, factory: F) -> Result<()> {
+        result_return_if!(self.get_handle_count() >= svc::MAX_WAIT_HANDLE_COUNT, svc::ResultOutOfRange);
+        let name = name.trim_end_matches('\0');
+        result_return_if!(name.is_empty() || (name.len() >= MAX_PORT_NAME_LENGTH), ResultInvalidPortName);
+
+        let mut port_name: [u8; MAX_PORT_NAME_LENGTH] = [0; MAX_PORT_NAME_LENGTH];
+        port_name[..name.len()].copy_from_slice(name.as_bytes());
+        // port_name always has a NUL terminator after the name, which was checked to be shorter than it
+        let handle = unsafe { svc::manage_named_port(port_name.as_ptr(), max_sessions)? };
+        self.add_port(handle, None, factory);
+        Ok(())
+    }
+
+    pub fn register_service<F: FnMut() -> mem::SharedObject<dyn ServerObject> + 'static>(&mut self, name: &str, max_sessions: i32, factory: F) -> Result<()> {
+        result_return_if!(self.get_handle_count() >= svc::MAX_WAIT_HANDLE_COUNT, svc::ResultOutOfRange);
+        let service_name = sm::ServiceName::new(name).encode();
+
+        let handle = service::with_sm_session(|sm_session| sm_session.register_service(sm::ServiceName::from(service_name), false, max_sessions))?.into_raw();
+        self.add_port(handle, Some(service_name), factory);
+        Ok(())
+    }
+
+    // For server sessions obtained by other means
+    pub fn register_session(&mut self, handle: svc::Handle, object: mem::SharedObject<dyn ServerObject>) -> Result<()> {
+        self.add_session(Session { handle: handle, object: object, domain: None })
+    }
+
+    fn add_session(&mut self, session: Session) -> Result<()> {
+        result_return_if!(self.get_handle_count() >= svc::MAX_WAIT_HANDLE_COUNT, svc::ResultOutOfRange);
+        self.sessions.push(session);
+        Ok(())
+    }
+
+    // Returns the client handle of the new session
+    fn create_session(&mut self, object: mem::SharedObject<dyn ServerObject>, domain: Option<mem::SharedObject<Domain>>) -> Result<svc::Handle> {
+        let (server_handle, client_handle) = svc::create_session(false, 0)?;
+        if let Err(rc) = self.add_session(Session { handle: server_handle, object: object, domain: domain }) {
+            let _ = svc::close_handle(server_handle);
+            let _ = svc::close_handle(client_handle);
+            return Err(rc);
+        }
+        Ok(client_handle)
+    }
+
+    fn close_session(&mut self, session_index: usize) {
+        let session = self.sessions.remove(session_index);
+        let _ = svc::close_handle(session.handle);
+    }
+
+    fn prepare_receive_buffer(&mut self) {
+        unsafe {
+            let ipc_buf = ipc::get_ipc_buffer();
+
+            // The kernel copies the send statics of incoming requests to the buffers in our receive list
+            let receive_static_count: u32 = if self.pointer_buffer.is_empty() { 0 } else { 1 };
+            let command_header = ipc_buf as *mut ipc::CommandHeader;
+            *command_header = ipc::CommandHeader::new(ipc::CommandType::Invalid, 0, 0, 0, 0, 0, receive_static_count, false);
+            if receive_static_count > 0 {
+                let receive_static = command_header.offset(1) as *mut ipc::ReceiveStaticDescriptor;
+                *receive_static = ipc::ReceiveStaticDescriptor::new(self.pointer_buffer.as_ptr(), self.pointer_buffer.len());
+            }
+        }
+    }
+
+    fn write_response(&mut self, mut server_ctx: ServerContext, rc: ResultCode, is_domain: bool) -> Result<()> {
+        if rc.is_failure() {
+            server_ctx.discard_out();
+        }
+
+        let ServerContext { mut ctx, domain, out_statics, out_data, out_copy_handles, out_move_items, .. } = server_ctx;
+        ctx.send_static_count = 0;
+        for out_static in out_statics {
+            ctx.add_send_static(out_static);
+        }
+        for handle in out_copy_handles {
+            ctx.out_params.add_copy_handle(handle);
+        }
+        for item in out_move_items {
+            match item {
+                OutMoveItem::Handle(handle) => ctx.out_params.add_move_handle(handle),
+                OutMoveItem::Object(object) => {
+                    match domain {
+                        Some(ref domain) if is_domain => {
+                            let object_id = domain.borrow_mut().add_object(object);
+                            ctx.out_params.add_object(object_id);
+                        },
+                        _ => {
+                            let client_handle = self.create_session(object, None)?;
+                            ctx.out_params.add_move_handle(client_handle);
+                        }
+                    }
+                }
+            }
+        }
+
+        write_request_command_response_with_data_on_ipc_buffer(&mut ctx, rc, is_domain, &out_data);
+        Ok(())
+    }
+
+    fn make_server_context(&mut self, ctx: ipc::CommandContext, request_id: u32, domain: Option<mem::SharedObject<Domain>>) -> ServerContext {
+        let pointer_buffer_size = self.pointer_buffer.len();
+        ServerContext::new(ctx, request_id, domain, self.pointer_buffer.as_mut_ptr(), pointer_buffer_size)
+    }
+
+    fn handle_request_command(&mut self, session_index: usize, mut ctx: ipc::CommandContext) -> Result<()> {
+        let domain = self.sessions[session_index].domain.clone();
+        let is_domain = domain.is_some();
+        let (domain_command_type, request_id) = read_request_command_from_ipc_buffer(&mut ctx, is_domain)?;
+
+        let object = match domain {
+            Some(ref domain) => {
+                let object_id = ctx.session.object_id;
+                if domain_command_type == ipc::DomainCommandType::Close {
+                    let rc = match domain.borrow_mut().objects.remove(&object_id) {
+                        Some(_) => ResultCode::from::<ResultSuccess>(),
+                        None => ResultCode::from::<ResultObjectIdNotFound>(),
+                    };
+                    let server_ctx = self.make_server_context(ctx, request_id, None);
+                    return self.write_response(server_ctx, rc, is_domain);
+                }
+                domain.borrow().get_object(object_id)
+            },
+            None => Ok(self.sessions[session_index].object.clone()),
+        };
+
+        let mut server_ctx = self.make_server_context(ctx, request_id, domain);
+        let rc = match object {
+            Ok(object) => match object.borrow_mut().handle_request(&mut server_ctx) {
+                Ok(()) => ResultCode::from::<ResultSuccess>(),
+                Err(rc) => rc,
+            },
+            Err(rc) => rc,
+        };
+        self.write_response(server_ctx, rc, is_domain)
+    }
+
+    fn handle_control_request(&mut self, session_index: usize, ctx: &mut ServerContext) -> Result<()> {
+        match ctx.get_request_id() {
+            id if id == client::ControlRequestId::ConvertCurrentObjectToDomain as u32 => {
+                let session = &mut self.sessions[session_index];
+                result_return_if!(session.domain.is_some(), ResultAlreadyDomain);
+                let mut domain = Domain::new();
+                let object_id = domain.add_object(session.object.clone());
+                session.domain = Some(mem::make_shared(domain));
+                ctx.push_out(object_id);
+            },
+            id if id == client::ControlRequestId::CopyFromCurrentDomain as u32 => {
+                let object_id: u32 = ctx.read_in()?;
+                let object = match self.sessions[session_index].domain {
+                    Some(ref domain) => domain.borrow().get_object(object_id)?,
+                    None => return Err(ResultCode::from::<ResultObjectIdNotFound>()),
+                };
+                ctx.push_shared_object(object);
+            },
+            id if (id == client::ControlRequestId::CloneCurrentObject as u32) || (id == client::ControlRequestId::CloneCurrentObjectEx as u32) => {
+                // The clone keeps working with the same object (and domain, if any)
+                let object = self.sessions[session_index].object.clone();
+                let domain = self.sessions[session_index].domain.clone();
+                let client_handle = self.create_session(object, domain)?;
+                ctx.push_move_handle(client_handle);
+            },
+            id if id == client::ControlRequestId::QueryPointerBufferSize as u32 => {
+                ctx.push_out(self.pointer_buffer.len() as u16);
+            },
+            _ => return Err(ResultCode::from::<ResultInvalidControlCommand>()),
+        }
+        Ok(())
+    }
+
+    fn handle_control_command(&mut self, session_index: usize, mut ctx: ipc::CommandContext) -> Result<()> {
+        let request_id = read_control_command_from_ipc_buffer(&mut ctx)?;
+        let mut server_ctx = self.make_server_context(ctx, request_id, None);
+        let rc = match self.handle_control_request(session_index, &mut server_ctx) {
+            Ok(()) => ResultCode::from::<ResultSuccess>(),
+            Err(rc) => rc,
+        };
+        self.write_response(server_ctx, rc, false)
+    }
+
+    // Returns whether a response was written and needs to be replied
+    fn handle_session_command(&mut self, session_index: usize) -> Result<bool> {
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(self.sessions[session_index].handle));
+        match read_command_from_ipc_buffer(&mut ctx) {
+            ipc::CommandType::Request | ipc::CommandType::RequestWithContext => self.handle_request_command(session_index, ctx)?,
+            ipc::CommandType::Control | ipc::CommandType::ControlWithContext => self.handle_control_command(session_index, ctx)?,
+            ipc::CommandType::Close => {
+                self.close_session(session_index);
+                return Ok(false);
+            },
+            _ => return Err(ResultCode::from::<ResultInvalidCommandType>()),
+        };
+        Ok(true)
+    }
+
+    fn process_port(&mut self, port_index: usize) -> Result<()> {
+        let handle = svc::accept_session(self.ports[port_index].handle)?;
+        let object = (self.ports[port_index].factory)();
+        if self.register_session(handle, object).is_err() {
+            // No room to wait on it, so refuse it
+            let _ = svc::close_handle(handle);
+        }
+        Ok(())
+    }
+
+    fn process_session(&mut self, session_index: usize) -> Result<()> {
+        let handle = self.sessions[session_index].handle;
+
+        self.prepare_receive_buffer();
+        if let Err(rc) = unsafe { svc::reply_and_receive(&handle, 1, 0, 0) } {
+            if rc.matches::<svc::ResultSessionClosed>() {
+                self.close_session(session_index);
+                return Ok(());
+            }
+            return Err(rc);
+        }
+
+        match self.handle_session_command(session_index) {
+            Ok(true) => {},
+            Ok(false) => return Ok(()),
+            Err(_) => {
+                // Malformed requests aren't worth taking the whole server down
+                self.close_session(session_index);
+                return Ok(());
+            }
+        };
+
+        // Without handles to wait on, the reply is sent and the receive times out right away
+        match unsafe { svc::reply_and_receive(ptr::null(), 0, handle, 0) } {
+            Ok(_) => Ok(()),
+            Err(rc) => {
+                if rc.matches::<svc::ResultTimedOut>() {
+                    Ok(())
+                }
+                else if rc.matches::<svc::ResultSessionClosed>() {
+                    if let Some(session_index) = self.sessions.iter().position(|session| session.handle == handle) {
+                        self.close_session(session_index);
+                    }
+                    Ok(())
+                }
+                else {
+                    Err(rc)
+                }
+            }
+        }
+    }
+
+    pub fn process(&mut self) -> Result<()> {
+        let handles: Vec<svc::Handle> = self.ports.iter().map(|port| port.handle).chain(self.sessions.iter().map(|session| session.handle)).collect();
+        let index = unsafe { svc::wait_synchronization(handles.as_ptr(), handles.len() as u32, svc::INFINITE_TIMEOUT)? } as usize;
+
+        if index < self.ports.len() {
+            self.process_port(index)
+        }
+        else {
+            self.process_session(index - self.ports.len())
+        }
+    }
+
+    pub fn loop_process(&mut self) -> Result<()> {
+        loop {
+            self.process()?;
+        }
+    }
+}
+
+impl Drop for ServerManager {
+    fn drop(&mut self) {
+        for session in self.sessions.drain(..) {
+            let _ = svc::close_handle(session.handle);
+        }
+
+        for port in self.ports.drain(..) {
+            let _ = svc::close_handle(port.handle);
+            if let Some(service_name) = port.service_name {
+                let _ = service::with_sm_session(|sm_session| sm_session.unregister_service(sm::ServiceName::from(service_name)));
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+
+    const POINTER_BUFFER_SIZE: u16 = 0x500;
+
+    #[derive(Copy, Clone)]
+    #[repr(C)]
+    struct AddIn {
+        a: u32,
+        b: u64,
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    #[repr(C)]
+    struct AddOut {
+        value: u32,
+        sum: u64,
+    }
+
+    struct TestObject {
+        value: u32,
+    }
+
+    impl ServerObject for TestObject {
+        fn handle_request(&mut self, ctx: &mut ServerContext) -> Result<()> {
+            match ctx.get_request_id() {
+                // Add
+                0 => {
+                    let a: u32 = ctx.read_in()?;
+                    let b: u64 = ctx.read_in()?;
+                    ctx.push_out(self.value);
+                    ctx.push_out(a as u64 + b);
+                },
+                // OpenObject
+                1 => ctx.push_object(TestObject { value: self.value + 1 }),
+                // FillOutPointer
+                2 => {
+                    let (address, size) = ctx.pop_out_pointer()?;
+                    unsafe {
+                        ptr::write_bytes(address, self.value as u8, size);
+                    }
+                },
+                // Fails after pushing output, which must not be sent
+                3 => {
+                    let (writable_handle, _) = svc::create_event()?;
+                    ctx.push_move_handle(writable_handle);
+                    ctx.push_out(self.value);
+                    return Err(ResultCode::from::<svc::ResultInvalidState>());
+                },
+                _ => return Err(ResultCode::from::<ResultCommandNotFound>()),
+            }
+            Ok(())
+        }
+    }
+
+    fn make_test_object(value: u32) -> mem::SharedObject<dyn ServerObject> {
+        mem::make_shared(TestObject { value: value })
+    }
+
+    fn write_request<T: Copy>(ctx: &mut ipc::CommandContext, request_id: u32, in_data: T) {
+        ctx.in_params.data_size = cmem::size_of::<T>() as u32;
+        client::write_request_command_on_ipc_buffer(ctx, Some(request_id), ipc::DomainCommandType::SendMessage);
+        unsafe {
+            ptr::write_unaligned(ctx.in_params.data_offset as *mut T, in_data);
+        }
+    }
+
+    fn write_control<T: Copy>(ctx: &mut ipc::CommandContext, request_id: client::ControlRequestId, in_data: T) {
+        ctx.in_params.data_size = cmem::size_of::<T>() as u32;
+        client::write_control_command_on_ipc_buffer(ctx, request_id);
+        unsafe {
+            ptr::write_unaligned(ctx.in_params.data_offset as *mut T, in_data);
+        }
+    }
+
+    fn read_response<T: Copy>(ctx: &mut ipc::CommandContext) -> Result<T> {
+        ctx.out_params.data_size = cmem::size_of::<T>() as u32;
+        client::read_request_command_response_from_ipc_buffer(ctx)?;
+        unsafe {
+            Ok(ptr::read_unaligned(ctx.out_params.data_offset as *const T))
+        }
+    }
+
+    // Registers a session to the object, returning its client end
+    fn make_manager(value: u32) -> (ServerManager, svc::Handle) {
+        svc::mock::reset();
+        let mut manager = ServerManager::new(POINTER_BUFFER_SIZE);
+        let (server_handle, client_handle) = svc::create_session(false, 0).unwrap();
+        manager.register_session(server_handle, make_test_object(value)).unwrap();
+        (manager, client_handle)
+    }
+
+    #[test]
+    fn requests_are_parsed_and_dispatched() {
+        let (mut manager, client_handle) = make_manager(7);
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_request(&mut ctx, 0, AddIn { a: 5, b: 0x100000000 });
+        assert!(manager.handle_session_command(0).unwrap());
+        let out: AddOut = read_response(&mut ctx).unwrap();
+        assert_eq!(out.value, 7);
+        assert_eq!(out.sum, 0x100000005);
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_request(&mut ctx, 42, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        assert!(read_response::<()>(&mut ctx).unwrap_err().matches::<ResultCommandNotFound>());
+
+        // Corrupt the data header magic
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_request(&mut ctx, 0, AddIn { a: 0, b: 0 });
+        unsafe {
+            *(ctx.in_params.data_offset.offset(-(cmem::size_of::<ipc::DataHeader>() as isize)) as *mut u32) = 0;
+        }
+        assert!(manager.handle_session_command(0).unwrap_err().matches::<ResultInvalidInDataHeaderMagic>());
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        client::write_close_command_on_ipc_buffer(&mut ctx);
+        assert!(!manager.handle_session_command(0).unwrap());
+        assert!(manager.sessions.is_empty());
+    }
+
+    #[test]
+    fn domain_requests_are_dispatched_to_their_objects() {
+        let (mut manager, client_handle) = make_manager(1);
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_control(&mut ctx, client::ControlRequestId::ConvertCurrentObjectToDomain, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        client::read_control_command_response_from_ipc_buffer(&mut ctx).unwrap();
+        let object_id: u32 = unsafe { ptr::read_unaligned(ctx.out_params.data_offset as *const u32) };
+        assert_eq!(object_id, 1);
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_object_id(client_handle, object_id));
+        write_request(&mut ctx, 1, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        read_response::<()>(&mut ctx).unwrap();
+        let new_object_id = ctx.out_params.pop_object().unwrap();
+        assert_eq!(new_object_id, 2);
+        // New objects stay in the domain instead of getting their own sessions
+        assert_eq!(manager.sessions.len(), 1);
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_object_id(client_handle, new_object_id));
+        write_request(&mut ctx, 0, AddIn { a: 1, b: 2 });
+        assert!(manager.handle_session_command(0).unwrap());
+        let out: AddOut = read_response(&mut ctx).unwrap();
+        assert_eq!(out.value, 2);
+        assert_eq!(out.sum, 3);
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_object_id(client_handle, new_object_id));
+        client::write_request_command_on_ipc_buffer(&mut ctx, None, ipc::DomainCommandType::Close);
+        assert!(manager.handle_session_command(0).unwrap());
+        read_response::<()>(&mut ctx).unwrap();
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_object_id(client_handle, new_object_id));
+        write_request(&mut ctx, 0, AddIn { a: 1, b: 2 });
+        assert!(manager.handle_session_command(0).unwrap());
+        assert!(read_response::<AddOut>(&mut ctx).unwrap_err().matches::<ResultObjectIdNotFound>());
+
+        // Closing it again fails the same way
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_object_id(client_handle, new_object_id));
+        client::write_request_command_on_ipc_buffer(&mut ctx, None, ipc::DomainCommandType::Close);
+        assert!(manager.handle_session_command(0).unwrap());
+        assert!(read_response::<()>(&mut ctx).unwrap_err().matches::<ResultObjectIdNotFound>());
+    }
+
+    #[test]
+    fn requests_are_limited_to_the_data_sent() {
+        let (mut manager, client_handle) = make_manager(1);
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_control(&mut ctx, client::ControlRequestId::ConvertCurrentObjectToDomain, ());
+        assert!(manager.handle_session_command(0).unwrap());
+
+        // The domain header claims more data than the data words hold
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_object_id(client_handle, 1));
+        write_request(&mut ctx, 0, AddIn { a: 1, b: 2 });
+        unsafe {
+            let domain_header = ctx.in_params.data_offset.offset(-((cmem::size_of::<ipc::DataHeader>() + cmem::size_of::<ipc::DomainInDataHeader>()) as isize)) as *mut ipc::DomainInDataHeader;
+            (*domain_header).data_size = u16::MAX;
+        }
+        assert!(manager.handle_session_command(0).unwrap_err().matches::<ResultInvalidDataSize>());
+
+        // Same for the object IDs after the data
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_object_id(client_handle, 1));
+        write_request(&mut ctx, 0, AddIn { a: 1, b: 2 });
+        unsafe {
+            let domain_header = ctx.in_params.data_offset.offset(-((cmem::size_of::<ipc::DataHeader>() + cmem::size_of::<ipc::DomainInDataHeader>()) as isize)) as *mut ipc::DomainInDataHeader;
+            (*domain_header).in_object_count = ipc::MAX_COUNT as u8;
+        }
+        assert!(manager.handle_session_command(0).unwrap_err().matches::<ResultInvalidDataSize>());
+
+        // Commands can't read past the data they got either
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_object_id(client_handle, 1));
+        write_request(&mut ctx, 0, 1u32);
+        assert!(manager.handle_session_command(0).unwrap());
+        assert!(read_response::<AddOut>(&mut ctx).unwrap_err().matches::<ResultInvalidDataSize>());
+    }
+
+    #[test]
+    fn control_commands_are_handled() {
+        let (mut manager, client_handle) = make_manager(1);
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_control(&mut ctx, client::ControlRequestId::QueryPointerBufferSize, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        let pointer_buffer_size: u16 = read_response(&mut ctx).unwrap();
+        assert_eq!(pointer_buffer_size, POINTER_BUFFER_SIZE);
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_control(&mut ctx, client::ControlRequestId::CopyFromCurrentDomain, 1u32);
+        assert!(manager.handle_session_command(0).unwrap());
+        assert!(read_response::<()>(&mut ctx).unwrap_err().matches::<ResultObjectIdNotFound>());
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_control(&mut ctx, client::ControlRequestId::CloneCurrentObject, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        read_response::<()>(&mut ctx).unwrap();
+        let clone_handle = ctx.out_params.pop_move_handle().unwrap();
+        assert!(svc::mock::is_handle_open(clone_handle));
+        assert_eq!(manager.sessions.len(), 2);
+        assert!(Rc::ptr_eq(&manager.sessions[0].object, &manager.sessions[1].object));
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_control(&mut ctx, client::ControlRequestId::ConvertCurrentObjectToDomain, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        assert_eq!(read_response::<u32>(&mut ctx).unwrap(), 1);
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_control(&mut ctx, client::ControlRequestId::ConvertCurrentObjectToDomain, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        assert!(read_response::<u32>(&mut ctx).unwrap_err().matches::<ResultAlreadyDomain>());
+
+        // Copied objects get their own sessions, outside of the domain
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_control(&mut ctx, client::ControlRequestId::CopyFromCurrentDomain, 1u32);
+        assert!(manager.handle_session_command(0).unwrap());
+        read_response::<()>(&mut ctx).unwrap();
+        let copy_handle = ctx.out_params.pop_move_handle().unwrap();
+        assert!(svc::mock::is_handle_open(copy_handle));
+        assert_eq!(manager.sessions.len(), 3);
+        assert!(manager.sessions[2].domain.is_none());
+        assert!(Rc::ptr_eq(&manager.sessions[0].object, &manager.sessions[2].object));
+    }
+
+    #[test]
+    fn out_pointers_are_taken_from_the_end_of_the_pointer_buffer() {
+        let (mut manager, client_handle) = make_manager(0xAB);
+        let receive_buffer = [0u8; 0x20];
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        ctx.add_receive_static(ipc::ReceiveStaticDescriptor::new(receive_buffer.as_ptr(), 0x18));
+        write_request(&mut ctx, 2, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        read_response::<()>(&mut ctx).unwrap();
+
+        // The response carries the data back as a send static for the client's receive static
+        let command_header = unsafe { *(ipc::get_ipc_buffer() as *const ipc::CommandHeader) };
+        assert_eq!(command_header.get_send_static_count(), 1);
+        let send_static = unsafe { *(ipc::get_ipc_buffer().offset(cmem::size_of::<ipc::CommandHeader>() as isize) as *const ipc::SendStaticDescriptor) };
+        assert_eq!(send_static.get_index(), 0);
+        assert_eq!(send_static.get_size(), 0x18);
+
+        let out_start = (POINTER_BUFFER_SIZE as usize - 0x18) & !0xF;
+        assert!(manager.pointer_buffer[out_start..out_start + 0x18].iter().all(|byte| *byte == 0xAB));
+        assert!(manager.pointer_buffer[..out_start].iter().all(|byte| *byte == 0));
+
+        let big_receive_buffer = vec![0u8; POINTER_BUFFER_SIZE as usize + 1];
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        ctx.add_receive_static(ipc::ReceiveStaticDescriptor::new(big_receive_buffer.as_ptr(), big_receive_buffer.len()));
+        write_request(&mut ctx, 2, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        assert!(read_response::<()>(&mut ctx).unwrap_err().matches::<ResultPointerBufferTooSmall>());
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_request(&mut ctx, 2, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        assert!(read_response::<()>(&mut ctx).unwrap_err().matches::<ipc::ResultNoItemsLeft>());
+    }
+
+    #[test]
+    fn failed_responses_discard_their_output() {
+        let (mut manager, client_handle) = make_manager(1);
+        let open_handle_count = svc::mock::get_open_handle_count();
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_request(&mut ctx, 3, ());
+        assert!(manager.handle_session_command(0).unwrap());
+        assert!(read_response::<u32>(&mut ctx).unwrap_err().matches::<svc::ResultInvalidState>());
+        assert!(ctx.out_params.pop_move_handle().is_err());
+
+        // The move handle was closed instead of being leaked (only the readable one is left)
+        assert_eq!(svc::mock::get_open_handle_count(), open_handle_count + 1);
+        let command_header = unsafe { *(ipc::get_ipc_buffer() as *const ipc::CommandHeader) };
+        assert!(!command_header.get_has_special_header());
+    }
+
+    #[test]
+    fn ports_and_sessions_are_processed() {
+        svc::mock::reset();
+        let mut manager = ServerManager::new(POINTER_BUFFER_SIZE);
+        manager.register_named_port("srv:", 1, || make_test_object(3)).unwrap();
+        assert!(manager.register_named_port("too-long-name", 1, || make_test_object(3)).unwrap_err().matches::<ResultInvalidPortName>());
+
+        let client_handle = unsafe { svc::connect_to_named_port(nul!("srv:").as_ptr()) }.unwrap();
+        manager.process().unwrap();
+        assert_eq!(manager.sessions.len(), 1);
+
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_request(&mut ctx, 0, AddIn { a: 1, b: 1 });
+        svc::mock::post_request(client_handle).unwrap();
+        manager.process().unwrap();
+        svc::mock::take_reply(client_handle).unwrap();
+        let out: AddOut = read_response(&mut ctx).unwrap();
+        assert_eq!(out.value, 3);
+        assert_eq!(out.sum, 2);
+
+        // Malformed requests get the session closed
+        let mut ctx = ipc::CommandContext::new(ipc::Session::from_handle(client_handle));
+        write_request(&mut ctx, 0, ());
+        unsafe {
+            *(ctx.in_params.data_offset.offset(-(cmem::size_of::<ipc::DataHeader>() as isize)) as *mut u32) = 0;
+        }
+        svc::mock::post_request(client_handle).unwrap();
+        manager.process().unwrap();
+        assert!(manager.sessions.is_empty());
+        assert!(svc::mock::post_request(client_handle).unwrap_err().matches::<svc::ResultSessionClosed>());
+        svc::close_handle(client_handle).unwrap();
+
+        // So do clients going away
+        let client_handle = unsafe { svc::connect_to_named_port(nul!("srv:").as_ptr()) }.unwrap();
+        manager.process().unwrap();
+        assert_eq!(manager.sessions.len(), 1);
+        svc::close_handle(client_handle).unwrap();
+        manager.process().unwrap();
+        assert!(manager.sessions.is_empty());
+
+        drop(manager);
+        assert_eq!(svc::mock::get_open_handle_count(), 0);
+    }
+}
@@ -1,14 +1,16 @@
 #![no_std]
-#![feature(llvm_asm)]
+#![cfg_attr(not(feature = "mock-svc"), feature(llvm_asm))]
 #![feature(global_asm)]
 #![feature(alloc_error_handler)]
-#![feature(const_fn)]
-#![feature(const_raw_ptr_to_usize_cast)]
 #![macro_use]
 
 #[macro_use]
 extern crate alloc;
 
+// The mock backend runs on the host, which is also where its state is kept
+#[cfg(feature = "mock-svc")]
+extern crate std;
+
 #[macro_use]
 pub mod macros;
 
@@ -26,6 +28,8 @@ pub mod thread;
 
 pub mod hbl;
 
+pub mod env;
+
 pub mod crt0;
 
 pub mod svc;
@@ -36,4 +40,6 @@ pub mod service;
 
 pub mod diag;
 
-pub mod gpu;
\ No newline at end of file
+pub mod gpu;
+
+pub mod wait;
\ No newline at end of file
@@ -0,0 +1,13 @@
+#![macro_use]
+
+// Defines the entrypoint crt0 calls, forwarding to a main function which may take the launch arguments or not:
+// fn main() -> Result<()> or fn main(args: nx::env::Args) -> Result<()>
+#[macro_export]
+macro_rules! nx_main {
+    ($main_fn:path) => {
+        #[no_mangle]
+        pub fn __nx_main(args: $crate::env::Args) -> $crate::result::Result<()> {
+            $crate::env::call_main($main_fn, args)
+        }
+    };
+}
\ No newline at end of file
@@ -1,8 +1,19 @@
 #![macro_use]
 
+// Copy handles are only borrowed, while move handles are given away, so an owned handle can't be sent as a move handle by reference (and then closed twice)
+#[macro_export]
+macro_rules! ipc_client_add_in_handle {
+    ($ctx:expr, $handle:expr, Copy) => {
+        $ctx.in_params.add_copy_handle($crate::svc::handle::CopyHandle::copy_handle(&$handle))
+    };
+    ($ctx:expr, $handle:expr, Move) => {
+        $ctx.in_params.add_move_handle($crate::svc::handle::MoveHandle::move_handle($handle))
+    };
+}
+
 #[macro_export]
 macro_rules! ipc_client_session_send_request_command {
-    ([$session:expr; $rq_id:expr; $send_pid:expr] => { In { $( $in_name:ident: $in_ty:ty = $in_val:expr ),* }; InHandles { $( $in_handle:expr => $in_handle_mode:expr ),* }; InObjects { $( $in_object:expr ),* }; InSessions { $( $in_session:expr ),* }; Buffers { $( ($buf:expr, $buf_size:expr) => $buf_attr:expr ),* }; Out { $( $out_name:ident: $out_ty:ty => $out_val:ident ),* }; OutHandles { $( $out_handle:expr => $out_handle_mode:expr ),* }; OutObjects { $( $out_object:expr ),* }; OutSessions { $( $out_session:expr ),* }; }) => {
+    ([$session:expr; $rq_id:expr; $send_pid:expr] => { In { $( $in_name:ident: $in_ty:ty = $in_val:expr ),* }; InHandles { $( $in_handle:expr => ipc::HandleMode::$in_handle_mode:ident ),* }; InObjects { $( $in_object:expr ),* }; InSessions { $( $in_session:expr ),* }; Buffers { $( $buf:expr ),* }; Out { $( $out_name:ident: $out_ty:ty => $out_val:ident ),* }; OutHandles { $( $out_handle:expr => $out_handle_mode:expr ),* }; OutObjects { $( $out_object:expr ),* }; OutSessions { $( $out_session:expr ),* }; }) => {
         {
             #[repr(C)]
             struct _In {
@@ -17,8 +28,8 @@ macro_rules! ipc_client_session_send_request_command {
             let mut ctx = $crate::ipc::CommandContext::new($session);
             ctx.in_params.send_process_id = $send_pid;
             ctx.in_params.data_size = in_size as u32;
-            $( ctx.add_buffer($buf, $buf_size, $buf_attr)?; )*
-            $( ctx.in_params.add_handle($in_handle, $in_handle_mode); )*
+            $( ctx.add_buffer($buf.get_address(), $buf.get_size(), $buf.get_attributes())?; )*
+            $( $crate::ipc_client_add_in_handle!(ctx, $in_handle, $in_handle_mode); )*
             $( ctx.in_params.add_object($in_object); )*
             $( ctx.in_params.add_object($in_session.object_id); )*
 
@@ -55,7 +66,7 @@ macro_rules! ipc_client_session_send_request_command {
             }
 
             $( $out_val = out_data.$out_name; )*
-            $( $out_handle = ctx.out_params.pop_handle($out_handle_mode)?; )*
+            $( $out_handle = $crate::svc::handle::FromHandle::from_handle(ctx.out_params.pop_handle($out_handle_mode)?); )*
             $( $out_object = ctx.out_params.pop_object()?; )*
             $( $out_session = ctx.pop_session()?; )*
         }
@@ -64,7 +75,7 @@ macro_rules! ipc_client_session_send_request_command {
 
 #[macro_export]
 macro_rules! ipc_client_session_send_control_command {
-    ([$session:expr; $control_rq_id:expr; $send_pid:expr] => { In { $( $in_name:ident: $in_ty:ty = $in_val:expr ),* }; InHandles { $( $in_handle:expr => $in_handle_mode:expr ),* }; InObjects { $( $in_object:expr ),* }; InSessions { $( $in_session:expr ),* }; Buffers { $( ($buf:expr, $buf_size:expr) => $buf_attr:expr ),* }; Out { $( $out_name:ident: $out_ty:ty => $out_val:expr ),* }; OutHandles { $( $out_handle:expr => $out_handle_mode:expr ),* }; OutObjects { $( $out_object:expr ),* }; OutSessions { $( $out_session:expr ),* }; }) => {
+    ([$session:expr; $control_rq_id:expr; $send_pid:expr] => { In { $( $in_name:ident: $in_ty:ty = $in_val:expr ),* }; InHandles { $( $in_handle:expr => ipc::HandleMode::$in_handle_mode:ident ),* }; InObjects { $( $in_object:expr ),* }; InSessions { $( $in_session:expr ),* }; Buffers { $( $buf:expr ),* }; Out { $( $out_name:ident: $out_ty:ty => $out_val:expr ),* }; OutHandles { $( $out_handle:expr => $out_handle_mode:expr ),* }; OutObjects { $( $out_object:expr ),* }; OutSessions { $( $out_session:expr ),* }; }) => {
         {
             #[repr(C)]
             struct _In {
@@ -79,8 +90,8 @@ macro_rules! ipc_client_session_send_control_command {
             let mut ctx = $crate::ipc::CommandContext::new($session);
             ctx.in_params.send_process_id = $send_pid;
             ctx.in_params.data_size = in_size as u32;
-            $( ctx.add_buffer($buf, $buf_size, $buf_attr)?; )*
-            $( ctx.in_params.add_handle($in_handle, $in_handle_mode); )*
+            $( ctx.add_buffer($buf.get_address(), $buf.get_size(), $buf.get_attributes())?; )*
+            $( $crate::ipc_client_add_in_handle!(ctx, $in_handle, $in_handle_mode); )*
             $( ctx.in_params.add_object($in_object); )*
             $( ctx.in_params.add_object($in_session.object_id); )*
 
@@ -114,9 +125,110 @@ macro_rules! ipc_client_session_send_control_command {
             }
 
             $( $out_val = out_data.$out_name; )*
-            $( $out_handle = ctx.out_params.pop_handle($out_handle_mode)?; )*
+            $( $out_handle = $crate::svc::handle::FromHandle::from_handle(ctx.out_params.pop_handle($out_handle_mode)?); )*
             $( $out_object = ctx.out_params.pop_object()?; )*
             $( $out_session = ctx.pop_session()?; )*
         }
     };
+}
+
+// Marks types as plain data (see ipc::client::PlainData), which are sent and received as raw data in interface commands
+// A blanket impl over PlainData would clash with the one for borrowed handles, hence this macro:
+// ipc_client_plain_data_impl!(Foo, Bar);
+#[macro_export]
+macro_rules! ipc_client_plain_data_impl {
+    ($( $t:ty ),*) => {
+        $(
+            impl $crate::ipc::client::PlainData for $t {}
+
+            impl $crate::ipc::client::RequestCommandParameter for $t {
+                fn before_request_write(_param: &Self, walker: &mut $crate::ipc::DataWalker, _ctx: &mut $crate::ipc::CommandContext) -> $crate::result::Result<()> {
+                    $crate::ipc::client::before_plain_data_request_write::<Self>(walker)
+                }
+
+                fn before_send_sync_request(param: &Self, walker: &mut $crate::ipc::DataWalker, _ctx: &mut $crate::ipc::CommandContext) -> $crate::result::Result<()> {
+                    $crate::ipc::client::before_plain_data_send_sync_request(param, walker)
+                }
+            }
+
+            impl $crate::ipc::client::ResponseCommandParameter for $t {
+                fn before_response_read(walker: &mut $crate::ipc::DataWalker, _ctx: &mut $crate::ipc::CommandContext) -> $crate::result::Result<()> {
+                    $crate::ipc::client::before_plain_data_response_read::<Self>(walker)
+                }
+
+                fn after_response_read(walker: &mut $crate::ipc::DataWalker, _ctx: &mut $crate::ipc::CommandContext) -> $crate::result::Result<Self> {
+                    $crate::ipc::client::after_plain_data_response_read(walker)
+                }
+            }
+        )*
+    };
+}
+
+// Parameter kinds come from their types (see ipc::client::RequestCommandParameter and ipc::client::ResponseCommandParameter), so unlike the macros above no sections are needed
+#[macro_export]
+macro_rules! ipc_client_send_request_command {
+    ([$session:expr; $rq_id:expr $(; $send_pid:expr)?] ( $( $in_param:ident ),* ) => ( $( $out_param:ident: $out_param_ty:ty ),* )) => {
+        {
+            let session: $crate::ipc::Session = $session;
+            let mut ctx = $crate::ipc::CommandContext::new(session);
+            // The placeholder the kernel fills in with our process ID goes after every other parameter
+            let send_pid = false $( || $send_pid )?;
+            let process_id = $crate::ipc::ProcessId::new();
+
+            #[allow(unused_mut)]
+            let mut walker = $crate::ipc::DataWalker::new(core::ptr::null_mut());
+            $( $crate::ipc::client::RequestCommandParameter::before_request_write(&$in_param, &mut walker, &mut ctx)?; )*
+            if send_pid {
+                $crate::ipc::client::RequestCommandParameter::before_request_write(&process_id, &mut walker, &mut ctx)?;
+            }
+            ctx.in_params.data_size = walker.get_size() as u32;
+
+            $crate::ipc::client::write_request_command_on_ipc_buffer(&mut ctx, Some($rq_id), $crate::ipc::DomainCommandType::SendMessage);
+
+            #[allow(unused_mut, unused_variables)]
+            let mut walker = $crate::ipc::DataWalker::new(ctx.in_params.data_offset);
+            $( $crate::ipc::client::RequestCommandParameter::before_send_sync_request(&$in_param, &mut walker, &mut ctx)?; )*
+            if send_pid {
+                $crate::ipc::client::RequestCommandParameter::before_send_sync_request(&process_id, &mut walker, &mut ctx)?;
+            }
+            $( $crate::ipc::client::RequestCommandParameter::after_request_write($in_param); )*
+
+            $crate::svc::send_sync_request(session.handle)?;
+
+            #[allow(unused_mut)]
+            let mut walker = $crate::ipc::DataWalker::new(core::ptr::null_mut());
+            $( <$out_param_ty as $crate::ipc::client::ResponseCommandParameter>::before_response_read(&mut walker, &mut ctx)?; )*
+            ctx.out_params.data_size = walker.get_size() as u32;
+
+            $crate::ipc::client::read_request_command_response_from_ipc_buffer(&mut ctx)?;
+
+            #[allow(unused_mut, unused_variables)]
+            let mut walker = $crate::ipc::DataWalker::new(ctx.out_params.data_offset);
+            $( let $out_param = <$out_param_ty as $crate::ipc::client::ResponseCommandParameter>::after_response_read(&mut walker, &mut ctx)?; )*
+            Ok(( $( $out_param ),* ))
+        }
+    };
+}
+
+// Defines an interface trait whose methods are all implemented, so session objects just need an empty impl block:
+// ipc_client_interface_define! {
+//     pub trait IFoo {
+//         [0] get_bar(baz: u32, buf: ipc::InMapAlias<u8>) => (bar: u64, event: svc::handle::ReadableEvent);
+//         [1; true] set_qux(qux: ipc::InSession) => ();
+//         [2] open_quux<S: service::SessionObject>() => (quux: S);
+//     }
+// }
+// A true after the request ID sends the process ID, like the section macros above do
+#[macro_export]
+macro_rules! ipc_client_interface_define {
+    (pub trait $intf:ident { $( [$rq_id:expr $(; $send_pid:expr)?] $fn_name:ident $(< $generic:ident: $generic_bound:path >)? ( $( $in_param:ident: $in_param_ty:ty ),* ) => ( $( $out_param:ident: $out_param_ty:ty ),* ); )* }) => {
+        pub trait $intf: $crate::service::SessionObject {
+            $(
+                #[allow(unused_parens)]
+                fn $fn_name $(< $generic: $generic_bound >)? (&mut self, $( $in_param: $in_param_ty ),* ) -> $crate::result::Result<( $( $out_param_ty ),* )> {
+                    $crate::ipc_client_send_request_command!([$crate::service::SessionObject::get_session(self); $rq_id $(; $send_pid)?] ( $( $in_param ),* ) => ( $( $out_param: $out_param_ty ),* ))
+                }
+            )*
+        }
+    };
 }
\ No newline at end of file
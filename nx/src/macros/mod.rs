@@ -6,4 +6,6 @@ pub mod ipc;
 
 pub mod service;
 
-pub mod diag;
\ No newline at end of file
+pub mod diag;
+
+pub mod env;
\ No newline at end of file
@@ -30,10 +30,10 @@ macro_rules! session_object_define {
 
         impl $crate::service::SharedSessionObject for $name {
             fn shared(session: $crate::ipc::Session) -> $crate::mem::SharedObject<Self> {
-                $crate::mem::make_shared(Self::new(session))
+                $crate::mem::make_shared(<Self as $crate::service::SessionObject>::new(session))
             }
         }
-        
+
         impl core::ops::Drop for $name {
             fn drop(&mut self) {
                 self.session.close();
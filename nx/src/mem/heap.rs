@@ -0,0 +1,436 @@
+use crate::svc;
+use crate::sync;
+#[cfg(feature = "leak-tracking")]
+use crate::mem::tracking;
+use core::alloc::{GlobalAlloc, Layout};
+use core::cmp;
+use core::ptr;
+
+// Every block is at least this big and aligned, so that free list nodes always fit in freed memory
+const MIN_BLOCK_SIZE: usize = 0x10;
+
+const SIZE_CLASS_COUNT: usize = 8;
+const SIZE_CLASSES: [usize; SIZE_CLASS_COUNT] = [0x10, 0x20, 0x40, 0x80, 0x100, 0x200, 0x400, 0x800];
+const SLAB_SIZE: usize = 0x4000;
+
+const HEAP_SIZE_ALIGNMENT: usize = 0x200000;
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct FreeChunk {
+    next: *mut FreeChunk,
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+fn get_size_class(layout: &Layout) -> Option<usize> {
+    let size = cmp::max(cmp::max(layout.size(), layout.align()), MIN_BLOCK_SIZE);
+    SIZE_CLASSES.iter().position(|class_size| size <= *class_size)
+}
+
+#[derive(Copy, Clone)]
+pub struct HeapStatistics {
+    pub total_size: usize,
+    pub used_size: usize,
+    pub peak_used_size: usize,
+    // Free memory any allocation can use
+    pub free_size: usize,
+    pub largest_free_block_size: usize,
+    // Free slab chunks, which are kept for allocations of their own size class (see allocate_chunk)
+    pub free_chunk_size: usize,
+}
+
+impl HeapStatistics {
+    // 0 means all the free memory is a single block, values close to 100 mean it's scattered in small blocks
+    pub fn get_fragmentation_percent(&self) -> usize {
+        match self.free_size {
+            0 => 0,
+            _ => 100 - (self.largest_free_block_size * 100 / self.free_size),
+        }
+    }
+}
+
+pub struct Heap {
+    address: *mut u8,
+    size: usize,
+    growth_enabled: bool,
+    free_blocks: *mut FreeBlock,
+    free_chunks: [*mut FreeChunk; SIZE_CLASS_COUNT],
+    used_size: usize,
+    peak_used_size: usize,
+}
+
+unsafe impl Send for Heap {}
+
+impl Heap {
+    pub const fn empty() -> Self {
+        Self { address: ptr::null_mut(), size: 0, growth_enabled: false, free_blocks: ptr::null_mut(), free_chunks: [ptr::null_mut(); SIZE_CLASS_COUNT], used_size: 0, peak_used_size: 0 }
+    }
+
+    pub unsafe fn initialize(&mut self, address: *mut u8, size: usize) {
+        *self = Self::empty();
+        let start = align_up(address as usize, MIN_BLOCK_SIZE);
+        let end = (address as usize + size) & !(MIN_BLOCK_SIZE - 1);
+        self.address = address;
+        self.size = size;
+        if end > start {
+            self.free_block(start as *mut u8, end - start);
+        }
+    }
+
+    // Only enable this if the heap was obtained through svc::set_heap_size, since it will be resized with it when exhausted
+    pub fn set_growth_enabled(&mut self, enabled: bool) {
+        self.growth_enabled = enabled;
+    }
+
+    fn grow(&mut self, min_size: usize) -> bool {
+        if !self.growth_enabled {
+            return false;
+        }
+
+        let new_size = align_up(self.size + min_size, HEAP_SIZE_ALIGNMENT);
+        match svc::set_heap_size(new_size) {
+            Ok(address) if address == self.address => {
+                unsafe {
+                    self.free_block(self.address.offset(self.size as isize), new_size - self.size);
+                }
+                self.size = new_size;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    unsafe fn allocate_block(&mut self, size: usize, align: usize) -> *mut u8 {
+        let size = align_up(size, MIN_BLOCK_SIZE);
+        let align = cmp::max(align, MIN_BLOCK_SIZE);
+
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut cur = self.free_blocks;
+        while !cur.is_null() {
+            let block_start = cur as usize;
+            let block_end = block_start + (*cur).size;
+            let alloc_start = align_up(block_start, align);
+            let alloc_end = alloc_start + size;
+            if alloc_end <= block_end {
+                // Whatever is left before and after the allocation stays in the list
+                let mut next = (*cur).next;
+                if alloc_end < block_end {
+                    let back_block = alloc_end as *mut FreeBlock;
+                    (*back_block).size = block_end - alloc_end;
+                    (*back_block).next = next;
+                    next = back_block;
+                }
+                if alloc_start > block_start {
+                    (*cur).size = alloc_start - block_start;
+                    (*cur).next = next;
+                    next = cur;
+                }
+                if prev.is_null() {
+                    self.free_blocks = next;
+                }
+                else {
+                    (*prev).next = next;
+                }
+                return alloc_start as *mut u8;
+            }
+            prev = cur;
+            cur = (*cur).next;
+        }
+        ptr::null_mut()
+    }
+
+    unsafe fn free_block(&mut self, address: *mut u8, size: usize) {
+        let size = align_up(size, MIN_BLOCK_SIZE);
+        let start = address as usize;
+
+        // The list is kept sorted by address, so that neighbour blocks can be merged
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut cur = self.free_blocks;
+        while !cur.is_null() && ((cur as usize) < start) {
+            prev = cur;
+            cur = (*cur).next;
+        }
+
+        let block = address as *mut FreeBlock;
+        (*block).size = size;
+        (*block).next = cur;
+        if !cur.is_null() && ((start + size) == (cur as usize)) {
+            (*block).size += (*cur).size;
+            (*block).next = (*cur).next;
+        }
+
+        if prev.is_null() {
+            self.free_blocks = block;
+        }
+        else if ((prev as usize) + (*prev).size) == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        }
+        else {
+            (*prev).next = block;
+        }
+    }
+
+    unsafe fn allocate_large(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut address = self.allocate_block(size, align);
+        if address.is_null() && self.grow(size + align) {
+            address = self.allocate_block(size, align);
+        }
+        address
+    }
+
+    // Slabs are never given back to the block list (even once all their chunks are free), freed chunks are just reused for the same size class
+    // Small allocations come and go all the time, so keeping the slabs around avoids carving and merging them over and over
+    unsafe fn allocate_chunk(&mut self, class: usize) -> *mut u8 {
+        if self.free_chunks[class].is_null() {
+            let slab = self.allocate_large(SLAB_SIZE, SIZE_CLASSES[SIZE_CLASS_COUNT - 1]);
+            if slab.is_null() {
+                return ptr::null_mut();
+            }
+
+            let chunk_size = SIZE_CLASSES[class];
+            for i in (0..(SLAB_SIZE / chunk_size)).rev() {
+                let chunk = slab.offset((i * chunk_size) as isize) as *mut FreeChunk;
+                (*chunk).next = self.free_chunks[class];
+                self.free_chunks[class] = chunk;
+            }
+        }
+
+        let chunk = self.free_chunks[class];
+        self.free_chunks[class] = (*chunk).next;
+        chunk as *mut u8
+    }
+
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let address = match get_size_class(&layout) {
+            Some(class) => self.allocate_chunk(class),
+            None => self.allocate_large(layout.size(), layout.align()),
+        };
+        if !address.is_null() {
+            self.used_size += layout.size();
+            self.peak_used_size = cmp::max(self.peak_used_size, self.used_size);
+        }
+        address
+    }
+
+    pub unsafe fn deallocate(&mut self, address: *mut u8, layout: Layout) {
+        match get_size_class(&layout) {
+            Some(class) => {
+                let chunk = address as *mut FreeChunk;
+                (*chunk).next = self.free_chunks[class];
+                self.free_chunks[class] = chunk;
+            },
+            None => self.free_block(address, layout.size()),
+        };
+        self.used_size -= layout.size();
+    }
+
+    pub fn get_statistics(&self) -> HeapStatistics {
+        let mut free_size: usize = 0;
+        let mut largest_free_block_size: usize = 0;
+        let mut cur = self.free_blocks;
+        while !cur.is_null() {
+            unsafe {
+                free_size += (*cur).size;
+                largest_free_block_size = cmp::max(largest_free_block_size, (*cur).size);
+                cur = (*cur).next;
+            }
+        }
+
+        let mut free_chunk_size: usize = 0;
+        for (class, chunk) in self.free_chunks.iter().enumerate() {
+            let mut cur = *chunk;
+            while !cur.is_null() {
+                unsafe {
+                    free_chunk_size += SIZE_CLASSES[class];
+                    cur = (*cur).next;
+                }
+            }
+        }
+        HeapStatistics { total_size: self.size, used_size: self.used_size, peak_used_size: self.peak_used_size, free_size: free_size, largest_free_block_size: largest_free_block_size, free_chunk_size: free_chunk_size }
+    }
+}
+
+pub struct LockedHeap {
+    heap: sync::Mutex<Heap>,
+}
+
+impl LockedHeap {
+    pub const fn empty() -> Self {
+        Self { heap: sync::Mutex::new(Heap::empty()) }
+    }
+
+    pub fn lock(&self) -> sync::MutexGuard<Heap> {
+        self.heap.lock()
+    }
+}
+
+#[cfg(not(feature = "leak-tracking"))]
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, address: *mut u8, layout: Layout) {
+        self.lock().deallocate(address, layout)
+    }
+}
+
+#[cfg(feature = "leak-tracking")]
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let tracked_address = self.lock().allocate(tracking::get_tracked_layout(layout));
+        if tracked_address.is_null() {
+            return ptr::null_mut();
+        }
+        tracking::track(tracked_address, layout)
+    }
+
+    unsafe fn dealloc(&self, address: *mut u8, layout: Layout) {
+        let tracked_address = tracking::untrack(address, layout);
+        self.lock().deallocate(tracked_address, tracking::get_tracked_layout(layout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_HEAP_SIZE: usize = 0x20000;
+
+    struct TestHeap {
+        heap: Heap,
+        memory: *mut u8,
+    }
+
+    impl TestHeap {
+        fn new() -> Self {
+            let memory = unsafe { std::alloc::alloc(Layout::from_size_align(TEST_HEAP_SIZE, 0x1000).unwrap()) };
+            assert!(!memory.is_null());
+            let mut heap = Heap::empty();
+            unsafe {
+                heap.initialize(memory, TEST_HEAP_SIZE);
+            }
+            Self { heap: heap, memory: memory }
+        }
+
+        fn allocate(&mut self, size: usize, align: usize) -> *mut u8 {
+            let address = unsafe { self.heap.allocate(Layout::from_size_align(size, align).unwrap()) };
+            assert!(!address.is_null());
+            assert!(((address as usize) >= (self.memory as usize)) && ((address as usize + size) <= (self.memory as usize + TEST_HEAP_SIZE)));
+            assert_eq!((address as usize) % align, 0);
+            address
+        }
+
+        fn deallocate(&mut self, address: *mut u8, size: usize, align: usize) {
+            unsafe {
+                self.heap.deallocate(address, Layout::from_size_align(size, align).unwrap());
+            }
+        }
+    }
+
+    impl Drop for TestHeap {
+        fn drop(&mut self) {
+            unsafe {
+                std::alloc::dealloc(self.memory, Layout::from_size_align(TEST_HEAP_SIZE, 0x1000).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn freed_blocks_are_merged() {
+        let mut heap = TestHeap::new();
+        let first = heap.allocate(0x1000, 8);
+        let second = heap.allocate(0x1000, 8);
+        let third = heap.allocate(0x1000, 8);
+        assert_eq!(heap.heap.get_statistics().free_size, TEST_HEAP_SIZE - 0x3000);
+
+        // Freeing the middle block last merges it with both neighbours
+        heap.deallocate(first, 0x1000, 8);
+        heap.deallocate(third, 0x1000, 8);
+        assert!(heap.heap.get_statistics().get_fragmentation_percent() > 0);
+        heap.deallocate(second, 0x1000, 8);
+        let stats = heap.heap.get_statistics();
+        assert_eq!(stats.free_size, TEST_HEAP_SIZE);
+        assert_eq!(stats.largest_free_block_size, TEST_HEAP_SIZE);
+        assert_eq!(stats.get_fragmentation_percent(), 0);
+        assert_eq!(heap.allocate(0x1000, 8), first);
+    }
+
+    #[test]
+    fn every_size_class_gets_chunks() {
+        let mut heap = TestHeap::new();
+        for class_size in SIZE_CLASSES.iter() {
+            let size = *class_size;
+            let first = heap.allocate(size, 8);
+            let second = heap.allocate(size, 8);
+            assert_eq!((second as usize) - (first as usize), size);
+            unsafe {
+                ptr::write_bytes(first, 0xAB, size);
+            }
+
+            // Freed chunks are reused first
+            heap.deallocate(first, size, 8);
+            assert_eq!(heap.allocate(size, 8), first);
+        }
+
+        // Alignments pick the size class too
+        let address = heap.allocate(8, 0x100);
+        assert_eq!((address as usize) % 0x100, 0);
+        // One slab per size class
+        assert_eq!(heap.heap.get_statistics().free_size, TEST_HEAP_SIZE - SIZE_CLASS_COUNT * SLAB_SIZE);
+    }
+
+    #[test]
+    fn large_alignments_are_honoured() {
+        let mut heap = TestHeap::new();
+        let small = heap.allocate(0x1000 + 0x10, 8);
+        let aligned = heap.allocate(0x1000, 0x1000);
+        assert_eq!((aligned as usize) % 0x1000, 0);
+
+        // The padding before the aligned block stays free
+        let stats = heap.heap.get_statistics();
+        assert_eq!(stats.free_size, TEST_HEAP_SIZE - 0x1010 - 0x1000);
+        heap.deallocate(aligned, 0x1000, 0x1000);
+        heap.deallocate(small, 0x1000 + 0x10, 8);
+        assert_eq!(heap.heap.get_statistics().largest_free_block_size, TEST_HEAP_SIZE);
+    }
+
+    #[test]
+    fn statistics_track_usage() {
+        let mut heap = TestHeap::new();
+        let large = heap.allocate(0x1000, 8);
+        let small = heap.allocate(0x18, 8);
+        let stats = heap.heap.get_statistics();
+        assert_eq!(stats.total_size, TEST_HEAP_SIZE);
+        assert_eq!(stats.used_size, 0x1018);
+        assert_eq!(stats.free_size, TEST_HEAP_SIZE - 0x1000 - SLAB_SIZE);
+        assert_eq!(stats.free_chunk_size, SLAB_SIZE - 0x20);
+
+        heap.deallocate(large, 0x1000, 8);
+        heap.deallocate(small, 0x18, 8);
+        let stats = heap.heap.get_statistics();
+        assert_eq!(stats.used_size, 0);
+        assert_eq!(stats.peak_used_size, 0x1018);
+        // The slab is kept for later small allocations
+        assert_eq!(stats.free_size, TEST_HEAP_SIZE - SLAB_SIZE);
+        assert_eq!(stats.free_chunk_size, SLAB_SIZE);
+    }
+
+    #[test]
+    fn exhausted_heaps_return_null() {
+        let mut heap = TestHeap::new();
+        let all = heap.allocate(TEST_HEAP_SIZE, 8);
+        let address = unsafe { heap.heap.allocate(Layout::from_size_align(0x1000, 8).unwrap()) };
+        assert!(address.is_null());
+        let address = unsafe { heap.heap.allocate(Layout::from_size_align(0x10, 8).unwrap()) };
+        assert!(address.is_null());
+        heap.deallocate(all, TEST_HEAP_SIZE, 8);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,89 @@
+extern crate alloc;
+
+use crate::sync;
+use crate::svc;
+use crate::hbl;
+use alloc::rc;
+use alloc::sync::Arc;
+use core::cell;
+
+pub mod heap;
+
+#[cfg(feature = "leak-tracking")]
+pub mod tracking;
+
+pub type SharedObject<T> = rc::Rc<cell::RefCell<T>>;
+
+pub fn make_shared<T>(t: T) -> SharedObject<T> {
+    SharedObject::new(cell::RefCell::new(t))
+}
+
+// Same as above, but can be shared between threads
+pub type SyncSharedObject<T> = Arc<sync::Mutex<T>>;
+
+pub fn make_sync_shared<T>(t: T) -> SyncSharedObject<T> {
+    SyncSharedObject::new(sync::Mutex::new(t))
+}
+
+// With the mock backend the host allocator is used instead, but the heap can still be initialized and inspected
+
+#[cfg_attr(not(feature = "mock-svc"), global_allocator)]
+static GLOBAL_ALLOCATOR: heap::LockedHeap = heap::LockedHeap::empty();
+
+// svc::set_heap_size always hands out the start of the heap region, but hbloader's heap is there too (and it's hbloader's to resize)
+fn is_set_heap_size_heap(heap_address: *mut u8) -> bool {
+    util_return_if!(heap_address.is_null(), false);
+    util_return_if!(hbl::get_context().heap.map_or(false, |hbl_heap| hbl_heap.address == heap_address), false);
+    match svc::get_info(svc::InfoId::HeapRegionAddress, svc::CURRENT_PROCESS_PSEUDO_HANDLE, 0) {
+        Ok(heap_region_address) => heap_region_address == heap_address as u64,
+        Err(_) => false,
+    }
+}
+
+// Heaps obtained through svc::set_heap_size are grown with it when exhausted
+pub unsafe fn initialize(heap_address: *mut u8, heap_size: usize) {
+    let mut heap = GLOBAL_ALLOCATOR.lock();
+    heap.initialize(heap_address, heap_size);
+    heap.set_growth_enabled(is_set_heap_size_heap(heap_address));
+}
+
+pub fn set_heap_growth_enabled(enabled: bool) {
+    GLOBAL_ALLOCATOR.lock().set_growth_enabled(enabled);
+}
+
+pub fn get_heap_statistics() -> heap::HeapStatistics {
+    GLOBAL_ALLOCATOR.lock().get_statistics()
+}
+
+#[cfg(not(feature = "mock-svc"))]
+#[alloc_error_handler]
+fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
+    panic!("Memory allocation failed - size: {}, alignment: {}", layout.size(), layout.align())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::alloc::Layout;
+
+    #[test]
+    fn set_heap_size_heaps_grow() {
+        svc::mock::reset();
+        let heap_size: usize = 0x200000;
+        let heap_address = svc::set_heap_size(heap_size).unwrap();
+        unsafe {
+            initialize(heap_address, heap_size);
+        }
+
+        let layout = Layout::from_size_align(heap_size * 2, 0x10).unwrap();
+        let address = unsafe { GLOBAL_ALLOCATOR.lock().allocate(layout) };
+        assert!(!address.is_null());
+        let stats = get_heap_statistics();
+        assert!(stats.total_size >= heap_size * 3);
+        assert_eq!(svc::get_info(svc::InfoId::HeapRegionSize, svc::INVALID_HANDLE, 0).unwrap(), stats.total_size as u64);
+
+        unsafe {
+            GLOBAL_ALLOCATOR.lock().deallocate(address, layout);
+        }
+    }
+}
\ No newline at end of file
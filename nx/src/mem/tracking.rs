@@ -0,0 +1,259 @@
+extern crate alloc;
+
+use crate::sync;
+use crate::diag::log;
+use crate::diag::log::Logger;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::cmp;
+use core::mem;
+use core::ptr;
+
+pub const RETURN_ADDRESS_COUNT: usize = 4;
+
+// The frames of capture_return_addresses, track and the global allocator itself
+const SKIPPED_FRAME_COUNT: usize = 3;
+
+#[repr(C)]
+struct AllocationHeader {
+    prev: *mut AllocationHeader,
+    next: *mut AllocationHeader,
+    id: u64,
+    size: usize,
+    align: usize,
+    return_addresses: [usize; RETURN_ADDRESS_COUNT],
+}
+
+#[derive(Copy, Clone)]
+pub struct AllocationInfo {
+    pub id: u64,
+    pub address: *mut u8,
+    pub size: usize,
+    pub align: usize,
+    pub return_addresses: [usize; RETURN_ADDRESS_COUNT],
+}
+
+struct Tracker {
+    head: *mut AllocationHeader,
+    next_id: u64,
+    live_count: usize,
+}
+
+unsafe impl Send for Tracker {}
+
+static G_TRACKER: sync::Mutex<Tracker> = sync::Mutex::new(Tracker { head: ptr::null_mut(), next_id: 0, live_count: 0 });
+
+fn get_header_offset(layout: &Layout) -> usize {
+    let header_size = mem::size_of::<AllocationHeader>();
+    let align = layout.align();
+    (header_size + align - 1) & !(align - 1)
+}
+
+// The header is placed right before the address given to the user, with enough padding to keep the requested alignment
+pub fn get_tracked_layout(layout: Layout) -> Layout {
+    let align = cmp::max(layout.align(), mem::align_of::<AllocationHeader>());
+    unsafe {
+        Layout::from_size_align_unchecked(layout.size() + get_header_offset(&layout), align)
+    }
+}
+
+#[cfg(not(feature = "mock-svc"))]
+fn get_frame_pointer() -> *const usize {
+    let fp: *const usize;
+    unsafe {
+        llvm_asm!("mov x0, x29" : "={x0}"(fp) ::: "volatile");
+    }
+    fp
+}
+
+#[cfg(feature = "mock-svc")]
+fn get_frame_pointer() -> *const usize {
+    ptr::null()
+}
+
+// This relies on frame pointers being kept (-C force-frame-pointers=yes), otherwise addresses might be missing
+#[inline(never)]
+fn capture_return_addresses() -> [usize; RETURN_ADDRESS_COUNT] {
+    let mut return_addresses = [0; RETURN_ADDRESS_COUNT];
+    let mut fp = get_frame_pointer();
+    let mut depth: usize = 0;
+    let mut count: usize = 0;
+    while !fp.is_null() && ((fp as usize) % 0x10 == 0) && (count < RETURN_ADDRESS_COUNT) {
+        let (next_fp, return_address) = unsafe { (*fp as *const usize, *fp.offset(1)) };
+        if depth >= SKIPPED_FRAME_COUNT {
+            return_addresses[count] = return_address;
+            count += 1;
+        }
+        depth += 1;
+        // Stacks grow downwards, anything else means the chain is broken
+        if (next_fp as usize) <= (fp as usize) {
+            break;
+        }
+        fp = next_fp;
+    }
+    return_addresses
+}
+
+#[inline(never)]
+pub unsafe fn track(tracked_address: *mut u8, layout: Layout) -> *mut u8 {
+    let address = tracked_address.offset(get_header_offset(&layout) as isize);
+    let header = (address as *mut AllocationHeader).offset(-1);
+    let return_addresses = capture_return_addresses();
+
+    let mut tracker = G_TRACKER.lock();
+    *header = AllocationHeader { prev: ptr::null_mut(), next: tracker.head, id: tracker.next_id, size: layout.size(), align: layout.align(), return_addresses: return_addresses };
+    if !tracker.head.is_null() {
+        (*tracker.head).prev = header;
+    }
+    tracker.head = header;
+    tracker.next_id += 1;
+    tracker.live_count += 1;
+    address
+}
+
+pub unsafe fn untrack(address: *mut u8, layout: Layout) -> *mut u8 {
+    let header = (address as *mut AllocationHeader).offset(-1);
+
+    let mut tracker = G_TRACKER.lock();
+    if (*header).prev.is_null() {
+        tracker.head = (*header).next;
+    }
+    else {
+        (*(*header).prev).next = (*header).next;
+    }
+    if !(*header).next.is_null() {
+        (*(*header).next).prev = (*header).prev;
+    }
+    tracker.live_count -= 1;
+    address.offset(-(get_header_offset(&layout) as isize))
+}
+
+pub fn get_live_allocation_count() -> usize {
+    G_TRACKER.lock().live_count
+}
+
+// Allocations made from now on will have IDs greater or equal than this one
+pub fn get_next_allocation_id() -> u64 {
+    G_TRACKER.lock().next_id
+}
+
+pub fn get_live_allocations_since(min_id: u64) -> Vec<AllocationInfo> {
+    // The tracker can't stay locked while the vector allocates, so leave some room for allocations made in between
+    let capacity = get_live_allocation_count() + 0x10;
+    let mut allocations: Vec<AllocationInfo> = Vec::with_capacity(capacity);
+
+    let tracker = G_TRACKER.lock();
+    let mut cur = tracker.head;
+    while !cur.is_null() && (allocations.len() < capacity) {
+        unsafe {
+            if (*cur).id >= min_id {
+                allocations.push(AllocationInfo { id: (*cur).id, address: cur.offset(1) as *mut u8, size: (*cur).size, align: (*cur).align, return_addresses: (*cur).return_addresses });
+            }
+            cur = (*cur).next;
+        }
+    }
+    allocations
+}
+
+pub fn get_live_allocations() -> Vec<AllocationInfo> {
+    get_live_allocations_since(0)
+}
+
+pub fn dump_live_allocations_since<L: Logger>(min_id: u64) {
+    let allocations = get_live_allocations_since(min_id);
+    diag_log!(L { log::LogSeverity::Info, false } => "Live allocations: {}", allocations.len());
+    for allocation in allocations.iter() {
+        diag_log!(L { log::LogSeverity::Info, false } => "Allocation #{} at {:p} - size: {:#X}, alignment: {:#X}, return addresses: {:#X?}", allocation.id, allocation.address, allocation.size, allocation.align, allocation.return_addresses);
+    }
+}
+
+pub fn dump_live_allocations<L: Logger>() {
+    dump_live_allocations_since::<L>(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service;
+
+    const ALIGNMENTS: [usize; 3] = [1, 0x10, 0x1000];
+
+    unsafe fn allocate_tracked(layout: Layout) -> *mut u8 {
+        let tracked_address = alloc::alloc::alloc(get_tracked_layout(layout));
+        assert!(!tracked_address.is_null());
+        track(tracked_address, layout)
+    }
+
+    unsafe fn deallocate_tracked(address: *mut u8, layout: Layout) {
+        alloc::alloc::dealloc(untrack(address, layout), get_tracked_layout(layout));
+    }
+
+    #[test]
+    fn tracked_layouts_fit_the_header_and_keep_the_alignment() {
+        for &align in ALIGNMENTS.iter() {
+            let layout = Layout::from_size_align(0x30, align).unwrap();
+            let tracked_layout = get_tracked_layout(layout);
+            let header_offset = get_header_offset(&layout);
+            assert!(header_offset >= mem::size_of::<AllocationHeader>());
+            assert_eq!(header_offset % align, 0);
+            assert_eq!(tracked_layout.size(), layout.size() + header_offset);
+            assert_eq!(tracked_layout.align(), cmp::max(align, mem::align_of::<AllocationHeader>()));
+        }
+    }
+
+    #[test]
+    fn headers_are_placed_right_before_the_address() {
+        let _process = service::mock::initialize();
+        for &align in ALIGNMENTS.iter() {
+            let layout = Layout::from_size_align(0x30, align).unwrap();
+            unsafe {
+                let tracked_address = alloc::alloc::alloc(get_tracked_layout(layout));
+                let id = get_next_allocation_id();
+                let address = track(tracked_address, layout);
+                assert_eq!(address as usize % align, 0);
+                assert_eq!(address as usize - tracked_address as usize, get_header_offset(&layout));
+                let header = (address as *mut AllocationHeader).offset(-1);
+                assert!(header as usize >= tracked_address as usize);
+                assert_eq!(header as usize % mem::align_of::<AllocationHeader>(), 0);
+                assert_eq!(((*header).id, (*header).size, (*header).align), (id, 0x30, align));
+
+                assert_eq!(untrack(address, layout), tracked_address);
+                alloc::alloc::dealloc(tracked_address, get_tracked_layout(layout));
+            }
+        }
+    }
+
+    #[test]
+    fn live_allocations_are_listed_until_untracked() {
+        let _process = service::mock::initialize();
+        let live_count = get_live_allocation_count();
+        let min_id = get_next_allocation_id();
+        let layouts: Vec<Layout> = ALIGNMENTS.iter().map(|&align| Layout::from_size_align(0x10 * align, align).unwrap()).collect();
+        let addresses: Vec<*mut u8> = layouts.iter().map(|&layout| unsafe { allocate_tracked(layout) }).collect();
+        assert_eq!(get_live_allocation_count(), live_count + 3);
+
+        // Newest allocations come first
+        let allocations = get_live_allocations_since(min_id);
+        let listed: Vec<(u64, *mut u8, usize, usize)> = allocations.iter().map(|allocation| (allocation.id, allocation.address, allocation.size, allocation.align)).collect();
+        assert_eq!(listed, [(min_id + 2, addresses[2], 0x10000, 0x1000), (min_id + 1, addresses[1], 0x100, 0x10), (min_id, addresses[0], 0x10, 1)]);
+
+        // Unlinking from the middle, the head and the tail of the list
+        unsafe {
+            deallocate_tracked(addresses[1], layouts[1]);
+        }
+        let listed: Vec<*mut u8> = get_live_allocations_since(min_id).iter().map(|allocation| allocation.address).collect();
+        assert_eq!(listed, [addresses[2], addresses[0]]);
+        unsafe {
+            deallocate_tracked(addresses[2], layouts[2]);
+        }
+        let listed: Vec<*mut u8> = get_live_allocations_since(min_id).iter().map(|allocation| allocation.address).collect();
+        assert_eq!(listed, [addresses[0]]);
+        unsafe {
+            deallocate_tracked(addresses[0], layouts[0]);
+        }
+        assert!(get_live_allocations_since(min_id).is_empty());
+        assert_eq!(get_live_allocation_count(), live_count);
+        assert_eq!(get_next_allocation_id(), min_id + 3);
+    }
+}
\ No newline at end of file
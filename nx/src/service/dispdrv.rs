@@ -2,7 +2,6 @@ use crate::result::*;
 use crate::ipc;
 use crate::svc;
 use crate::service;
-use crate::service::SessionObject;
 
 #[derive(Copy, Clone, PartialEq)]
 #[repr(u32)]
@@ -31,13 +30,13 @@ pub enum ParcelTransactionId {
 }
 
 pub trait IHOSBinderDriver {
-    fn transact_parcel(&mut self, binder_handle: i32, transaction_id: ParcelTransactionId, flags: u32, in_parcel_buf: *const u8, in_parcel_size: usize, out_parcel_buf: *const u8, out_parcel_size: usize) -> Result<()>;
+    fn transact_parcel(&mut self, binder_handle: i32, transaction_id: ParcelTransactionId, flags: u32, in_parcel: ipc::InMapAlias<u8>, out_parcel: ipc::OutMapAlias<u8>) -> Result<()>;
 
     fn adjust_refcount(&mut self, binder_handle: i32, add_value: i32, refcount_type: RefcountType) -> Result<()>;
 
-    fn get_native_handle(&mut self, binder_handle: i32, unk_type: u32) -> Result<svc::Handle>;
+    fn get_native_handle(&mut self, binder_handle: i32, unk_type: u32) -> Result<svc::handle::ReadableEvent>;
 
-    fn transact_parcel_auto(&mut self, binder_handle: i32, transaction_id: ParcelTransactionId, flags: u32, in_parcel_buf: *const u8, in_parcel_size: usize, out_parcel_buf: *const u8, out_parcel_size: usize) -> Result<()>;
+    fn transact_parcel_auto(&mut self, binder_handle: i32, transaction_id: ParcelTransactionId, flags: u32, in_parcel: ipc::InBuffer<u8>, out_parcel: ipc::OutBuffer<u8>) -> Result<()>;
 }
 
 session_object_define!(HOSBinderDriver);
@@ -57,7 +56,7 @@ impl service::Service for HOSBinderDriver {
 }
 
 impl IHOSBinderDriver for HOSBinderDriver {
-    fn transact_parcel(&mut self, binder_handle: i32, transaction_id: ParcelTransactionId, flags: u32, in_parcel_buf: *const u8, in_parcel_size: usize, out_parcel_buf: *const u8, out_parcel_size: usize) -> Result<()> {
+    fn transact_parcel(&mut self, binder_handle: i32, transaction_id: ParcelTransactionId, flags: u32, in_parcel: ipc::InMapAlias<u8>, out_parcel: ipc::OutMapAlias<u8>) -> Result<()> {
         ipc_client_session_send_request_command!([self.session; 0; false] => {
             In {
                 binder_handle: i32 = binder_handle,
@@ -68,8 +67,8 @@ impl IHOSBinderDriver for HOSBinderDriver {
             InObjects {};
             InSessions {};
             Buffers {
-                (in_parcel_buf, in_parcel_size) => ipc::BufferAttribute::In | ipc::BufferAttribute::MapAlias,
-                (out_parcel_buf, out_parcel_size) => ipc::BufferAttribute::Out | ipc::BufferAttribute::MapAlias
+                in_parcel,
+                out_parcel
             };
             Out {};
             OutHandles {};
@@ -98,8 +97,8 @@ impl IHOSBinderDriver for HOSBinderDriver {
         Ok(())
     }
 
-    fn get_native_handle(&mut self, binder_handle: i32, unk_type: u32) -> Result<svc::Handle> {
-        let handle: svc::Handle;
+    fn get_native_handle(&mut self, binder_handle: i32, unk_type: u32) -> Result<svc::handle::ReadableEvent> {
+        let handle: svc::handle::ReadableEvent;
         ipc_client_session_send_request_command!([self.session; 2; false] => {
             In {
                 binder_handle: i32 = binder_handle,
@@ -119,7 +118,7 @@ impl IHOSBinderDriver for HOSBinderDriver {
         Ok(handle)
     }
 
-    fn transact_parcel_auto(&mut self, binder_handle: i32, transaction_id: ParcelTransactionId, flags: u32, in_parcel_buf: *const u8, in_parcel_size: usize, out_parcel_buf: *const u8, out_parcel_size: usize) -> Result<()> {
+    fn transact_parcel_auto(&mut self, binder_handle: i32, transaction_id: ParcelTransactionId, flags: u32, in_parcel: ipc::InBuffer<u8>, out_parcel: ipc::OutBuffer<u8>) -> Result<()> {
         ipc_client_session_send_request_command!([self.session; 3; false] => {
             In {
                 binder_handle: i32 = binder_handle,
@@ -130,8 +129,8 @@ impl IHOSBinderDriver for HOSBinderDriver {
             InObjects {};
             InSessions {};
             Buffers {
-                (in_parcel_buf, in_parcel_size) => ipc::BufferAttribute::In | ipc::BufferAttribute::AutoSelect,
-                (out_parcel_buf, out_parcel_size) => ipc::BufferAttribute::Out | ipc::BufferAttribute::AutoSelect
+                in_parcel,
+                out_parcel
             };
             Out {};
             OutHandles {};
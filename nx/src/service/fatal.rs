@@ -1,6 +1,6 @@
 use crate::result::*;
+use crate::ipc;
 use crate::service;
-use crate::service::SessionObject;
 
 #[derive(Copy, Clone, PartialEq)]
 #[repr(u32)]
@@ -10,8 +10,61 @@ pub enum Policy {
     ErrorScreen,
 }
 
-pub trait IService {
-    fn throw_with_policy(&mut self, rc: ResultCode, policy: Policy) -> Result<()>;
+ipc_client_plain_data_impl!(Policy);
+
+// x0-x28, fp, lr, sp and pc
+pub const AARCH64_REGISTER_COUNT: usize = 33;
+pub const STACK_TRACE_SIZE: usize = 32;
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Aarch64Context {
+    pub registers: [u64; AARCH64_REGISTER_COUNT],
+    pub pstate: u64,
+    pub afsr0: u64,
+    pub afsr1: u64,
+    pub esr: u64,
+    pub far: u64,
+    pub stack_trace: [u64; STACK_TRACE_SIZE],
+    pub start_address: u64,
+    pub register_set_flags: u64,
+    pub stack_trace_size: u32,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct CpuContext {
+    pub aarch64_context: Aarch64Context,
+    pub is_aarch32: bool,
+    pub exception_type: u32,
+}
+
+impl CpuContext {
+    pub fn new() -> Self {
+        Self {
+            aarch64_context: Aarch64Context { registers: [0; AARCH64_REGISTER_COUNT], pstate: 0, afsr0: 0, afsr1: 0, esr: 0, far: 0, stack_trace: [0; STACK_TRACE_SIZE], start_address: 0, register_set_flags: 0, stack_trace_size: 0 },
+            is_aarch32: false,
+            exception_type: 0,
+        }
+    }
+
+    // fatal shows the stack trace relative to the start address, extra addresses are dropped
+    pub fn from_stack_trace(start_address: usize, stack_trace: &[usize]) -> Self {
+        let mut context = Self::new();
+        for (entry, address) in context.aarch64_context.stack_trace.iter_mut().zip(stack_trace.iter()) {
+            *entry = *address as u64;
+        }
+        context.aarch64_context.stack_trace_size = core::cmp::min(stack_trace.len(), STACK_TRACE_SIZE) as u32;
+        context.aarch64_context.start_address = start_address as u64;
+        context
+    }
+}
+
+ipc_client_interface_define! {
+    pub trait IService {
+        [1; true] throw_with_policy(rc: ResultCode, policy: Policy) => ();
+        [2; true] throw_with_cpu_context(rc: ResultCode, policy: Policy, cpu_context: ipc::InMapAlias<CpuContext>) => ();
+    }
 }
 
 session_object_define!(Service);
@@ -30,23 +83,4 @@ impl service::Service for Service {
     }
 }
 
-impl IService for Service {
-    fn throw_with_policy(&mut self, rc: ResultCode, policy: Policy) -> Result<()> {
-        ipc_client_session_send_request_command!([self.session; 1; true] => {
-            In {
-                rc: ResultCode = rc,
-                policy: Policy = policy,
-                process_id_holder: u64 = 0
-            };
-            InHandles {};
-            InObjects {};
-            InSessions {};
-            Buffers {};
-            Out {};
-            OutHandles {};
-            OutObjects {};
-            OutSessions {};
-        });
-        Ok(())
-    }
-}
\ No newline at end of file
+impl IService for Service {}
\ No newline at end of file
@@ -1,23 +1,22 @@
 use crate::result::*;
 use crate::ipc;
 use crate::service;
-use crate::service::SessionObject;
 
 pub trait IFileSystem {
-    fn create_directory(&mut self, path: *const u8, path_len: usize) -> Result<()>;
+    fn create_directory(&mut self, path: ipc::InPointer<u8>) -> Result<()>;
 }
 
 session_object_define!(FileSystem);
 
 impl IFileSystem for FileSystem {
-    fn create_directory(&mut self, path: *const u8, path_len: usize) -> Result<()> {
+    fn create_directory(&mut self, path: ipc::InPointer<u8>) -> Result<()> {
         ipc_client_session_send_request_command!([self.session; 2; false] => {
             In {};
             InHandles {};
             InObjects {};
             InSessions {};
             Buffers {
-                (path, path_len) => ipc::BufferAttribute::In | ipc::BufferAttribute::Pointer
+                path
             };
             Out {};
             OutHandles {};
@@ -33,7 +32,7 @@ pub trait IFileSystemProxy {
 
     fn open_sd_card_filesystem<S: service::SessionObject>(&mut self) -> Result<S>;
 
-    fn output_access_log_to_sd_card(&mut self, buf: *const u8, buf_size: usize) -> Result<()>;
+    fn output_access_log_to_sd_card(&mut self, buf: ipc::InMapAlias<u8>) -> Result<()>;
 }
 
 session_object_define!(FileSystemProxy);
@@ -88,14 +87,14 @@ impl IFileSystemProxy for FileSystemProxy {
         Ok(S::new(fs))
     }
 
-    fn output_access_log_to_sd_card(&mut self, buf: *const u8, buf_size: usize) -> Result<()> {
+    fn output_access_log_to_sd_card(&mut self, buf: ipc::InMapAlias<u8>) -> Result<()> {
         ipc_client_session_send_request_command!([self.session; 1006; false] => {
             In {};
             InHandles {};
             InObjects {};
             InSessions {};
             Buffers {
-                (buf, buf_size) => ipc::BufferAttribute::In | ipc::BufferAttribute::MapAlias
+                buf
             };
             Out {};
             OutHandles {};
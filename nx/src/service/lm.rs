@@ -1,7 +1,6 @@
 use crate::result::*;
 use crate::ipc;
 use crate::service;
-use crate::service::SessionObject;
 use enumflags2::BitFlags;
 
 #[derive(BitFlags, Copy, Clone, PartialEq)]
@@ -12,54 +11,23 @@ pub enum LogDestination {
     UARTSleeping = 0b100,
 }
 
-pub trait ILogger {
-    fn log(&mut self, buf: *const u8, buf_size: usize) -> Result<()>;
-
-    fn set_destination(&mut self, log_destination: BitFlags<LogDestination>) -> Result<()>;
+ipc_client_interface_define! {
+    pub trait ILogger {
+        [0] log(buf: ipc::InBuffer<u8>) => ();
+        [1] set_destination(log_destination: BitFlags<LogDestination>) => ();
+    }
 }
 
 session_object_define!(Logger);
 
-impl ILogger for Logger {
-    fn log(&mut self, buf: *const u8, buf_size: usize) -> Result<()> {
-        ipc_client_session_send_request_command!([self.session; 0; false] => {
-            In {};
-            InHandles {};
-            InObjects {};
-            InSessions {};
-            Buffers {
-                (buf, buf_size) => ipc::BufferAttribute::In | ipc::BufferAttribute::AutoSelect
-            };
-            Out {};
-            OutHandles {};
-            OutObjects {};
-            OutSessions {};
-        });
-        Ok(())
-    }
+impl ILogger for Logger {}
 
-    fn set_destination(&mut self, log_destination: BitFlags<LogDestination>) -> Result<()> {
-        ipc_client_session_send_request_command!([self.session; 1; false] => {
-            In {
-                log_dest: BitFlags<LogDestination> = log_destination
-            };
-            InHandles {};
-            InObjects {};
-            InSessions {};
-            Buffers {};
-            Out {};
-            OutHandles {};
-            OutObjects {};
-            OutSessions {};
-        });
-        Ok(())
+ipc_client_interface_define! {
+    pub trait ILogService {
+        [0; true] open_logger<S: service::SessionObject>() => (logger: S);
     }
 }
 
-pub trait ILogService {
-    fn open_logger<S: SessionObject>(&mut self) -> Result<S>;
-}
-
 session_object_define!(LogService);
 
 impl service::Service for LogService {
@@ -76,24 +44,4 @@ impl service::Service for LogService {
     }
 }
 
-impl ILogService for LogService {
-    fn open_logger<S: SessionObject>(&mut self) -> Result<S> {
-        let logger: ipc::Session;
-        ipc_client_session_send_request_command!([self.session; 0; true] => {
-            In {
-                process_id_holder: u64 = 0
-            };
-            InHandles {};
-            InObjects {};
-            InSessions {};
-            Buffers {};
-            Out {};
-            OutHandles {};
-            OutObjects {};
-            OutSessions {
-                logger
-            };
-        });
-        Ok(S::new(logger))
-    }
-}
\ No newline at end of file
+impl ILogService for LogService {}
\ No newline at end of file
@@ -0,0 +1,548 @@
+extern crate alloc;
+
+use crate::result::*;
+use crate::ipc;
+use crate::ipc::client;
+use crate::svc;
+use crate::mem;
+use crate::service;
+use crate::service::sm;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::cell::UnsafeCell;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+pub type Response = ipc::mock::Response<mem::SharedObject<Server>>;
+
+pub type CommandHandler = Box<dyn FnMut(&ipc::mock::Request) -> Response>;
+
+pub const RESULT_SUBMODULE: u32 = 10;
+
+result_lib_define_group!(RESULT_SUBMODULE => {
+    ResultCommandNotRegistered: 1,
+    ResultInvalidObjectId: 2,
+    ResultInvalidControlCommand: 3
+});
+
+// Same value sm: returns for unknown services
+result_define!(ResultNotRegistered: 21, 7);
+
+pub struct Server {
+    commands: BTreeMap<u32, CommandHandler>,
+    pointer_buffer_size: u16,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self { commands: BTreeMap::new(), pointer_buffer_size: 0 }
+    }
+
+    pub fn register_command<F: FnMut(&ipc::mock::Request) -> Response + 'static>(&mut self, request_id: u32, handler: F) {
+        self.commands.insert(request_id, Box::new(handler));
+    }
+
+    pub fn set_pointer_buffer_size(&mut self, pointer_buffer_size: u16) {
+        self.pointer_buffer_size = pointer_buffer_size;
+    }
+
+    pub fn get_pointer_buffer_size(&self) -> u16 {
+        self.pointer_buffer_size
+    }
+
+    fn handle_request(&mut self, request: &ipc::mock::Request) -> Response {
+        match self.commands.get_mut(&request.request_id) {
+            Some(handler) => handler(request),
+            None => Response::from_result(ResultCode::from::<ResultCommandNotRegistered>()),
+        }
+    }
+}
+
+struct Domain {
+    objects: BTreeMap<u32, mem::SharedObject<Server>>,
+    next_object_id: u32,
+}
+
+impl Domain {
+    fn add_object(&mut self, server: mem::SharedObject<Server>) -> u32 {
+        let object_id = self.next_object_id;
+        self.next_object_id += 1;
+        self.objects.insert(object_id, server);
+        object_id
+    }
+}
+
+struct SessionState {
+    server: mem::SharedObject<Server>,
+    domain: Option<Domain>,
+}
+
+std::thread_local! {
+    // Like the kernel in svc::mock, every host thread gets its own set of services
+    static G_SERVICES: UnsafeCell<BTreeMap<u64, mem::SharedObject<Server>>> = UnsafeCell::new(BTreeMap::new());
+}
+
+fn get_services() -> &'static mut BTreeMap<u64, mem::SharedObject<Server>> {
+    G_SERVICES.with(|services| unsafe { &mut *services.get() })
+}
+
+// IPC handle tracking, the sm: session and the service registry are process-wide though, so only one fake process can use them at a time
+static G_PROCESS_LOCKED: AtomicBool = AtomicBool::new(false);
+
+// Keeps other host threads from using the services until dropped
+pub struct ProcessGuard {
+    _private: (),
+}
+
+impl ProcessGuard {
+    fn lock() -> Self {
+        while G_PROCESS_LOCKED.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            std::thread::yield_now();
+        }
+        Self { _private: () }
+    }
+}
+
+impl Drop for ProcessGuard {
+    fn drop(&mut self) {
+        G_PROCESS_LOCKED.store(false, Ordering::Release);
+    }
+}
+
+fn open_session(state: mem::SharedObject<SessionState>) -> svc::Handle {
+    svc::mock::create_session(move |_| dispatch(&state))
+}
+
+fn open_server_session(server: mem::SharedObject<Server>) -> svc::Handle {
+    open_session(mem::make_shared(SessionState { server: server, domain: None }))
+}
+
+fn handle_control(state: &mem::SharedObject<SessionState>, request: &ipc::mock::Request) -> Response {
+    let mut response = Response::new();
+    match request.request_id {
+        id if id == client::ControlRequestId::ConvertCurrentObjectToDomain as u32 => {
+            let mut state_ref = state.borrow_mut();
+            let mut domain = Domain { objects: BTreeMap::new(), next_object_id: 1 };
+            let object_id = domain.add_object(state_ref.server.clone());
+            state_ref.domain = Some(domain);
+            response.push_data(object_id);
+        },
+        id if id == client::ControlRequestId::CopyFromCurrentDomain as u32 => {
+            let object_id: u32 = request.read_data();
+            let server = match state.borrow().domain {
+                Some(ref domain) => domain.objects.get(&object_id).cloned(),
+                None => None,
+            };
+            match server {
+                Some(server) => {
+                    response.push_move_handle(open_server_session(server));
+                },
+                None => response.rc = ResultCode::from::<ResultInvalidObjectId>(),
+            };
+        },
+        id if (id == client::ControlRequestId::CloneCurrentObject as u32) || (id == client::ControlRequestId::CloneCurrentObjectEx as u32) => {
+            response.push_move_handle(open_session(state.clone()));
+        },
+        id if id == client::ControlRequestId::QueryPointerBufferSize as u32 => {
+            let pointer_buffer_size = state.borrow().server.borrow().get_pointer_buffer_size();
+            response.push_data(pointer_buffer_size);
+        },
+        _ => response.rc = ResultCode::from::<ResultInvalidControlCommand>(),
+    }
+    response
+}
+
+fn dispatch(state: &mem::SharedObject<SessionState>) -> Result<()> {
+    let is_domain = state.borrow().domain.is_some();
+    let request = ipc::mock::read_request_from_ipc_buffer(is_domain);
+    match request.command_type {
+        ipc::CommandType::Close => Ok(()),
+        ipc::CommandType::Control => {
+            let response = handle_control(state, &request);
+            let resolved = response.resolve_sessions(false, open_server_session, |_| 0);
+            ipc::mock::write_response_on_ipc_buffer(&resolved, false);
+            Ok(())
+        },
+        ipc::CommandType::Request => {
+            if is_domain && (request.domain_command_type == ipc::DomainCommandType::Close) {
+                if let Some(ref mut domain) = state.borrow_mut().domain {
+                    domain.objects.remove(&request.object_id);
+                }
+                return Ok(());
+            }
+
+            let server = match state.borrow().domain {
+                Some(ref domain) => domain.objects.get(&request.object_id).cloned(),
+                None => Some(state.borrow().server.clone()),
+            };
+            let response = match server {
+                Some(server) => server.borrow_mut().handle_request(&request),
+                None => Response::from_result(ResultCode::from::<ResultInvalidObjectId>()),
+            };
+
+            let resolved = response.resolve_sessions(is_domain, open_server_session, |server| {
+                match state.borrow_mut().domain {
+                    Some(ref mut domain) => domain.add_object(server),
+                    None => 0,
+                }
+            });
+            ipc::mock::write_response_on_ipc_buffer(&resolved, is_domain);
+            Ok(())
+        },
+        _ => Err(ResultCode::from::<svc::ResultNotImplemented>()),
+    }
+}
+
+fn create_sm_server() -> Server {
+    let mut server = Server::new();
+    // Initialize
+    server.register_command(0, |_| Response::new());
+    // GetService
+    server.register_command(1, |request| {
+        let name: u64 = request.read_data();
+        match get_services().get(&name) {
+            Some(service) => {
+                let mut response = Response::new();
+                response.push_session(service.clone());
+                response
+            },
+            None => Response::from_result(ResultCode::from::<ResultNotRegistered>()),
+        }
+    });
+    server
+}
+
+pub fn initialize() -> ProcessGuard {
+    let guard = ProcessGuard::lock();
+    svc::mock::reset();
+    ipc::reset_handle_tracking();
+    service::reset_sm_session();
+    service::registry::reset();
+    get_services().clear();
+    register_named_port(nul!("sm:"), create_sm_server());
+    guard
+}
+
+pub fn register_service(name: &str, server: Server) -> mem::SharedObject<Server> {
+    let shared_server = mem::make_shared(server);
+    get_services().insert(sm::ServiceName::new(name).encode(), shared_server.clone());
+    shared_server
+}
+
+pub fn unregister_service(name: &str) {
+    get_services().remove(&sm::ServiceName::new(name).encode());
+}
+
+pub fn register_named_port(name: &str, server: Server) -> mem::SharedObject<Server> {
+    let shared_server = mem::make_shared(server);
+    let port_server = shared_server.clone();
+    let mut sessions: BTreeMap<svc::Handle, mem::SharedObject<SessionState>> = BTreeMap::new();
+    svc::mock::register_named_port(name, move |handle| {
+        // Every connection to the port gets its own session
+        let state = sessions.entry(handle).or_insert_with(|| mem::make_shared(SessionState { server: port_server.clone(), domain: None })).clone();
+        dispatch(&state)
+    });
+    shared_server
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::SessionObject;
+    use crate::service::psm::IPsmServer;
+    use crate::service::lm::ILogger;
+    use crate::service::lm::ILogService;
+    use crate::service::fspsrv::IFileSystem;
+    use crate::service::fspsrv::IFileSystemProxy;
+    use crate::service::vi::IApplicationDisplayService;
+    use crate::service::vi::IRootService;
+    use crate::service::nv::INvDrvService;
+    use crate::service::dispdrv::IHOSBinderDriver;
+    use crate::service::psm;
+    use crate::service::lm;
+    use crate::service::fspsrv;
+    use crate::service::vi;
+    use crate::service::nv;
+    use crate::service::dispdrv;
+    use alloc::rc::Rc;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+    use core::slice;
+    use enumflags2::BitFlags;
+
+    fn read_buffer(buffer: &ipc::BufferDescriptor) -> Vec<u8> {
+        unsafe { slice::from_raw_parts(buffer.get_address(), buffer.get_size()).to_vec() }
+    }
+
+    fn write_buffer(buffer: &ipc::BufferDescriptor, data: &[u8]) {
+        assert!(data.len() <= buffer.get_size());
+        unsafe { slice::from_raw_parts_mut(buffer.get_address(), data.len()).copy_from_slice(data) };
+    }
+
+    #[test]
+    fn psm_returns_battery_charge() {
+        let _process = initialize();
+        let mut psm_server = Server::new();
+        psm_server.register_command(0, |_| {
+            let mut response = Response::new();
+            response.push_data(87u32);
+            response
+        });
+        register_service("psm", psm_server);
+
+        let mut psm = service::new_service_object::<psm::PsmServer>().unwrap();
+        assert_eq!(psm.get_battery_charge_percentage().unwrap(), 87);
+    }
+
+    #[test]
+    fn lm_opens_logger_and_logs() {
+        let _process = initialize();
+        let logs: Rc<RefCell<Vec<Vec<u8>>>> = Rc::new(RefCell::new(Vec::new()));
+        let destination = Rc::new(RefCell::new(0u32));
+
+        let mut logger_server = Server::new();
+        let logger_logs = logs.clone();
+        logger_server.register_command(0, move |request| {
+            logger_logs.borrow_mut().push(read_buffer(&request.send_buffers[0]));
+            Response::new()
+        });
+        let logger_destination = destination.clone();
+        logger_server.register_command(1, move |request| {
+            *logger_destination.borrow_mut() = request.read_data();
+            Response::new()
+        });
+        let logger_server = mem::make_shared(logger_server);
+
+        let mut log_server = Server::new();
+        log_server.register_command(0, move |request| {
+            assert!(request.process_id.is_some());
+            let mut response = Response::new();
+            response.push_session(logger_server.clone());
+            response
+        });
+        register_service("lm", log_server);
+
+        let mut log_service = service::new_service_object::<lm::LogService>().unwrap();
+        let mut logger: lm::Logger = log_service.open_logger().unwrap();
+        logger.log(ipc::InBuffer::from_str("Hello from the host")).unwrap();
+        logger.set_destination(lm::LogDestination::UART | lm::LogDestination::TMA).unwrap();
+
+        assert_eq!(&logs.borrow()[0][..], &b"Hello from the host"[..]);
+        assert_eq!(*destination.borrow(), (lm::LogDestination::UART | lm::LogDestination::TMA).bits());
+    }
+
+    #[test]
+    fn fspsrv_opens_sd_card_filesystem_through_domain() {
+        let _process = initialize();
+        let directory_path_sizes: Rc<RefCell<Vec<usize>>> = Rc::new(RefCell::new(Vec::new()));
+        let access_logs: Rc<RefCell<Vec<Vec<u8>>>> = Rc::new(RefCell::new(Vec::new()));
+        let current_process = Rc::new(RefCell::new(None));
+
+        let mut fs_server = Server::new();
+        let fs_directory_path_sizes = directory_path_sizes.clone();
+        fs_server.register_command(2, move |request| {
+            // Static descriptors can't hold a whole host address, so only the size is checked
+            assert_eq!(request.send_statics[0].get_index(), 0);
+            fs_directory_path_sizes.borrow_mut().push(request.send_statics[0].get_size());
+            Response::new()
+        });
+        let fs_server = mem::make_shared(fs_server);
+
+        let mut fsp_server = Server::new();
+        let fsp_current_process = current_process.clone();
+        fsp_server.register_command(1, move |request| {
+            *fsp_current_process.borrow_mut() = request.process_id;
+            Response::new()
+        });
+        fsp_server.register_command(18, move |_| {
+            let mut response = Response::new();
+            response.push_session(fs_server.clone());
+            response
+        });
+        let fsp_access_logs = access_logs.clone();
+        fsp_server.register_command(1006, move |request| {
+            fsp_access_logs.borrow_mut().push(read_buffer(&request.send_buffers[0]));
+            Response::new()
+        });
+        register_service("fsp-srv", fsp_server);
+
+        let mut fsp = service::new_service_object::<fspsrv::FileSystemProxy>().unwrap();
+        assert!(fsp.get_session().is_domain());
+        assert!(current_process.borrow().is_some());
+
+        let mut sd_fs = fsp.open_sd_card_filesystem::<fspsrv::FileSystem>().unwrap();
+        assert!(sd_fs.get_session().is_domain());
+        sd_fs.create_directory(ipc::InPointer::from_str("/switch")).unwrap();
+        fsp.output_access_log_to_sd_card(ipc::InMapAlias::from_str("access")).unwrap();
+
+        assert_eq!(&directory_path_sizes.borrow()[..], &[7][..]);
+        assert_eq!(&access_logs.borrow()[0][..], &b"access"[..]);
+    }
+
+    #[test]
+    fn vi_opens_display_and_creates_stray_layer() {
+        let _process = initialize();
+        let display_names: Rc<RefCell<Vec<Vec<u8>>>> = Rc::new(RefCell::new(Vec::new()));
+
+        let mut display_server = Server::new();
+        let opened_display_names = display_names.clone();
+        display_server.register_command(1010, move |request| {
+            opened_display_names.borrow_mut().push(request.data[..0x40].to_vec());
+            let mut response = Response::new();
+            response.push_data(0x10u64);
+            response
+        });
+        display_server.register_command(2030, |request| {
+            let flags: u32 = request.read_data();
+            assert_eq!(flags, vi::LayerFlags::Default as u32);
+            let display_id: u64 = request.read_data_at(8);
+            assert_eq!(display_id, 0x10);
+            write_buffer(&request.receive_buffers[0], b"parcel");
+            let mut response = Response::new();
+            response.push_data(0x20u64);
+            response.push_data(6usize);
+            response
+        });
+        display_server.register_command(5202, |_| {
+            let (writable_handle, readable_handle) = svc::create_event().unwrap();
+            svc::signal_event(writable_handle).unwrap();
+            let mut response = Response::new();
+            response.push_copy_handle(readable_handle);
+            response
+        });
+        let display_server = mem::make_shared(display_server);
+
+        let mut root_server = Server::new();
+        root_server.register_command(1, move |request| {
+            let is_privileged: u32 = request.read_data();
+            assert_eq!(is_privileged, 1);
+            let mut response = Response::new();
+            response.push_session(display_server.clone());
+            response
+        });
+        register_service("vi:s", root_server);
+
+        let mut root = service::new_service_object::<vi::SystemRootService>().unwrap();
+        let mut display_service = root.get_display_service::<vi::ApplicationDisplayService>(true).unwrap();
+        let display_id = display_service.open_display(vi::DisplayName::from("Default").unwrap()).unwrap();
+        assert_eq!(display_id, 0x10);
+        assert_eq!(&display_names.borrow()[0][..8], &b"Default\0"[..]);
+
+        let mut native_window = [0u8; 0x100];
+        let (layer_id, native_window_size) = display_service.create_stray_layer(BitFlags::from(vi::LayerFlags::Default), display_id, ipc::OutMapAlias::from_mut_slice(&mut native_window)).unwrap();
+        assert_eq!(layer_id, 0x20);
+        assert_eq!(&native_window[..native_window_size], &b"parcel"[..]);
+
+        let vsync_event = display_service.get_display_vsync_event(display_id).unwrap();
+        assert!(vsync_event.wait(0).is_ok());
+    }
+
+    #[test]
+    fn nv_opens_fd_and_forwards_ioctls() {
+        let _process = initialize();
+        let mut nv_server = Server::new();
+        nv_server.register_command(0, |request| {
+            let mut response = Response::new();
+            match &read_buffer(&request.send_buffers[0])[..] {
+                b"/dev/nvmap" => {
+                    response.push_data(3u32);
+                    response.push_data(nv::ErrorCode::Success);
+                },
+                _ => {
+                    response.push_data(0u32);
+                    response.push_data(nv::ErrorCode::FileOperationFailed);
+                },
+            };
+            response
+        });
+        nv_server.register_command(1, |request| {
+            let fd: u32 = request.read_data();
+            let ioctl_id: u32 = request.read_data_at(4);
+            assert_eq!(fd, 3);
+            assert_eq!(ioctl_id, nv::IoctlId::NvMapCreate as u32);
+            // Echo the input back with every byte incremented
+            let data: Vec<u8> = read_buffer(&request.send_buffers[0]).iter().map(|byte| byte + 1).collect();
+            write_buffer(&request.receive_buffers[0], &data);
+            let mut response = Response::new();
+            response.push_data(nv::ErrorCode::Success);
+            response
+        });
+        register_service("nvdrv:a", nv_server);
+
+        let mut nvdrv = service::new_service_object::<nv::AppletNvDrvService>().unwrap();
+        assert_eq!(nvdrv.open_fd(ipc::InMapAlias::from_str("/dev/nvhost-ctrl")).unwrap().1, nv::ErrorCode::FileOperationFailed);
+        let (fd, err_code) = nvdrv.open_fd(ipc::InMapAlias::from_str("/dev/nvmap")).unwrap();
+        assert_eq!(fd, 3);
+        assert_eq!(err_code, nv::ErrorCode::Success);
+
+        let in_data = [1u8, 2, 3, 4];
+        let mut out_data = [0u8; 4];
+        let err_code = nvdrv.ioctl(fd, nv::IoctlId::NvMapCreate, ipc::InBuffer::from_slice(&in_data), ipc::OutBuffer::from_mut_slice(&mut out_data)).unwrap();
+        assert_eq!(err_code, nv::ErrorCode::Success);
+        assert_eq!(out_data, [2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn dispdrv_adjusts_refcount_and_returns_native_handle() {
+        let _process = initialize();
+        let refcount = Rc::new(RefCell::new(0i32));
+
+        let mut binder_server = Server::new();
+        let binder_refcount = refcount.clone();
+        binder_server.register_command(1, move |request| {
+            let binder_handle: i32 = request.read_data();
+            let add_value: i32 = request.read_data_at(4);
+            let refcount_type: u32 = request.read_data_at(8);
+            assert_eq!(binder_handle, 7);
+            assert_eq!(refcount_type, dispdrv::RefcountType::Strong as u32);
+            *binder_refcount.borrow_mut() += add_value;
+            Response::new()
+        });
+        binder_server.register_command(2, |_| {
+            let (_, readable_handle) = svc::create_event().unwrap();
+            let mut response = Response::new();
+            response.push_copy_handle(readable_handle);
+            response
+        });
+        register_service("dispdrv", binder_server);
+
+        let mut binder = service::new_service_object::<dispdrv::HOSBinderDriver>().unwrap();
+        binder.adjust_refcount(7, 1, dispdrv::RefcountType::Strong).unwrap();
+        binder.adjust_refcount(7, 1, dispdrv::RefcountType::Strong).unwrap();
+        assert_eq!(*refcount.borrow(), 2);
+
+        let native_handle = binder.get_native_handle(7, 0xF).unwrap();
+        assert!(svc::mock::is_handle_open(native_handle.get_handle()));
+        assert!(native_handle.wait(0).unwrap_err().matches::<svc::ResultTimedOut>());
+    }
+
+    #[test]
+    fn services_share_the_sm_session() {
+        let _process = initialize();
+        let mut psm_server = Server::new();
+        psm_server.register_command(0, |_| {
+            let mut response = Response::new();
+            response.push_data(50u32);
+            response
+        });
+        register_service("psm", psm_server);
+
+        let mut first_psm = service::new_service_object::<psm::PsmServer>().unwrap();
+        let mut second_psm = service::new_service_object::<psm::PsmServer>().unwrap();
+        assert_eq!(first_psm.get_battery_charge_percentage().unwrap(), 50);
+        assert_eq!(second_psm.get_battery_charge_percentage().unwrap(), 50);
+
+        let sm_handle = service::with_sm_session(|sm_session| Ok(sm_session.get_session().handle)).unwrap();
+        assert!(svc::mock::is_handle_open(sm_handle));
+        service::close_sm_session();
+        assert!(!svc::mock::is_handle_open(sm_handle));
+    }
+
+    #[test]
+    fn unregistered_services_are_not_found() {
+        let _process = initialize();
+        let rc = service::new_service_object::<psm::PsmServer>().err().unwrap();
+        assert!(rc.matches::<ResultNotRegistered>());
+    }
+}
\ No newline at end of file
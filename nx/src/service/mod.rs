@@ -1,6 +1,9 @@
 use crate::ipc;
 use crate::mem;
 use crate::svc;
+use crate::hbl;
+use crate::sync;
+use crate::crt0;
 use crate::result::*;
 
 pub mod sm;
@@ -20,6 +23,11 @@ pub mod dispdrv;
 
 pub mod fatal;
 
+pub mod registry;
+
+#[cfg(feature = "mock-svc")]
+pub mod mock;
+
 pub trait SessionObject {
     fn new(session: ipc::Session) -> Self;
     fn get_session(&self) -> ipc::Session;
@@ -95,8 +103,31 @@ impl<T: SessionObject> SessionObject for mem::SharedObject<T> {
     }
 }
 
+impl<T: SessionObject> SessionObject for mem::SyncSharedObject<T> {
+    fn new(session: ipc::Session) -> Self {
+        mem::make_sync_shared(T::new(session))
+    }
+
+    fn get_session(&self) -> ipc::Session {
+        self.lock().get_session()
+    }
+
+    fn convert_current_object_to_domain(&mut self) -> Result<()> {
+        self.lock().convert_current_object_to_domain()
+    }
+    fn query_pointer_buffer_size(&mut self) -> Result<u16> {
+        self.lock().query_pointer_buffer_size()
+    }
+
+    fn close(&mut self) {
+        self.lock().close()
+    }
+}
+
 pub fn new_named_port_object<T: SessionObject + NamedPort>() -> Result<T> {
-    let handle = svc::connect_to_named_port(T::get_name().as_ptr())?;
+    let name = T::get_name();
+    result_return_unless!(name.ends_with('\0'), ipc::server::ResultInvalidPortName);
+    let handle = unsafe { svc::connect_to_named_port(name.as_ptr())? };
     let session = ipc::Session::from_handle(handle);
     let mut object = T::new(session);
     object.post_initialize()?;
@@ -109,9 +140,46 @@ pub fn new_shared_named_port_object<T: SessionObject + SharedSessionObject + Nam
     Ok(shared_object)
 }
 
+// A single sm: session is opened on first use and kept until the process exits, instead of opening one per service
+// The object itself is cached (and not just its session), since dropping it closes the session
+static G_SM_SESSION: sync::Mutex<Option<sm::UserInterface>> = sync::Mutex::new(None);
+
+pub fn with_sm_session<R, F: FnOnce(&mut sm::UserInterface) -> Result<R>>(f: F) -> Result<R> {
+    let mut sm_session_ref = G_SM_SESSION.lock();
+    if sm_session_ref.is_none() {
+        *sm_session_ref = Some(new_named_port_object::<sm::UserInterface>()?);
+        crt0::register_exit_hook(close_sm_session);
+    }
+
+    // The lock is held during the call, so only one thread talks to sm at a time
+    f(sm_session_ref.as_mut().unwrap())
+}
+
+pub fn close_sm_session() {
+    // Dropping the object closes the session
+    let sm_session = G_SM_SESSION.lock().take();
+    drop(sm_session);
+}
+
+// For when the handle went away without being closed (like when the mock backend is reset)
+pub fn reset_sm_session() {
+    if let Some(sm_session) = G_SM_SESSION.lock().take() {
+        core::mem::forget(sm_session);
+    }
+}
+
+fn get_service_session(name: sm::ServiceName) -> Result<ipc::Session> {
+    // Services overridden by hbloader are shared, so work with a clone of them instead
+    if let Some(handle) = hbl::get_context().find_service_override(name.encode()) {
+        let mut override_session = ipc::Session { handle: handle, object_id: 0, owns_handle: false };
+        return override_session.clone_current_object();
+    }
+
+    with_sm_session(|sm_session| sm_session.get_service(name))
+}
+
 pub fn new_service_object<T: SessionObject + Service>() -> Result<T> {
-    let mut sm_session = new_named_port_object::<sm::UserInterface>()?;
-    let session = sm_session.get_service(sm::ServiceName::new(T::get_name()))?;
+    let session = get_service_session(sm::ServiceName::new(T::get_name()))?;
     let mut object = T::new(session);
     object.post_initialize()?;
     if T::as_domain() {
@@ -124,4 +192,10 @@ pub fn new_shared_service_object<T: SessionObject + SharedSessionObject + Servic
     let object = new_service_object::<T>()?;
     let shared_object = mem::make_shared(object);
     Ok(shared_object)
+}
+
+pub fn new_sync_shared_service_object<T: SessionObject + Service>() -> Result<mem::SyncSharedObject<T>> {
+    let object = new_service_object::<T>()?;
+    let shared_object = mem::make_sync_shared(object);
+    Ok(shared_object)
 }
\ No newline at end of file
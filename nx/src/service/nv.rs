@@ -92,19 +92,19 @@ pub enum IoctlId {
 } 
 
 pub trait INvDrvService {
-    fn open_fd(&mut self, path: *const u8, path_len: usize) -> Result<(u32, ErrorCode)>;
+    fn open_fd(&mut self, path: ipc::InMapAlias<u8>) -> Result<(u32, ErrorCode)>;
 
-    fn ioctl(&mut self, fd: u32, ioctl_id: IoctlId, in_buf: *const u8, in_buf_size: usize, out_buf: *const u8, out_buf_size: usize) -> Result<ErrorCode>;
+    fn ioctl(&mut self, fd: u32, ioctl_id: IoctlId, in_buf: ipc::InBuffer<u8>, out_buf: ipc::OutBuffer<u8>) -> Result<ErrorCode>;
 
     fn close_fd(&mut self, fd: u32) -> Result<ErrorCode>;
 
-    fn initialize(&mut self, transfer_mem_handle: svc::Handle, transfer_mem_size: u32) -> Result<ErrorCode>;
+    fn initialize(&mut self, transfer_mem: &svc::handle::TransferMemory, transfer_mem_size: u32) -> Result<ErrorCode>;
 }
 
 pub trait NvDrvService {}
 
 impl<T: NvDrvService + SessionObject> INvDrvService for T {
-    fn open_fd(&mut self, path: *const u8, path_len: usize) -> Result<(u32, ErrorCode)> {
+    fn open_fd(&mut self, path: ipc::InMapAlias<u8>) -> Result<(u32, ErrorCode)> {
         let fd: u32;
         let err_code: ErrorCode;
         ipc_client_session_send_request_command!([self.get_session(); 0; false] => {
@@ -113,7 +113,7 @@ impl<T: NvDrvService + SessionObject> INvDrvService for T {
             InObjects {};
             InSessions {};
             Buffers {
-                (path, path_len) => ipc::BufferAttribute::In | ipc::BufferAttribute::MapAlias
+                path
             };
             Out {
                 fd: u32 => fd,
@@ -126,7 +126,7 @@ impl<T: NvDrvService + SessionObject> INvDrvService for T {
         Ok((fd, err_code))
     }
 
-    fn ioctl(&mut self, fd: u32, ioctl_id: IoctlId, in_buf: *const u8, in_buf_size: usize, out_buf: *const u8, out_buf_size: usize) -> Result<ErrorCode> {
+    fn ioctl(&mut self, fd: u32, ioctl_id: IoctlId, in_buf: ipc::InBuffer<u8>, out_buf: ipc::OutBuffer<u8>) -> Result<ErrorCode> {
         let err_code: ErrorCode;
         ipc_client_session_send_request_command!([self.get_session(); 1; false] => {
             In {
@@ -137,8 +137,8 @@ impl<T: NvDrvService + SessionObject> INvDrvService for T {
             InObjects {};
             InSessions {};
             Buffers {
-                (in_buf, in_buf_size) => ipc::BufferAttribute::In | ipc::BufferAttribute::AutoSelect,
-                (out_buf, out_buf_size) => ipc::BufferAttribute::Out | ipc::BufferAttribute::AutoSelect
+                in_buf,
+                out_buf
             };
             Out {
                 err_code: ErrorCode => err_code
@@ -170,15 +170,15 @@ impl<T: NvDrvService + SessionObject> INvDrvService for T {
         Ok(err_code)
     }
 
-    fn initialize(&mut self, transfer_mem_handle: svc::Handle, transfer_mem_size: u32) -> Result<ErrorCode> {
+    fn initialize(&mut self, transfer_mem: &svc::handle::TransferMemory, transfer_mem_size: u32) -> Result<ErrorCode> {
         let err_code: ErrorCode;
         ipc_client_session_send_request_command!([self.get_session(); 3; false] => {
             In {
                 transfer_mem_size: u32 = transfer_mem_size
             };
             InHandles {
-                svc::CURRENT_PROCESS_PSEUDO_HANDLE => ipc::HandleMode::Copy,
-                transfer_mem_handle => ipc::HandleMode::Copy
+                svc::handle::CURRENT_PROCESS => ipc::HandleMode::Copy,
+                transfer_mem => ipc::HandleMode::Copy
             };
             InObjects {};
             InSessions {};
@@ -1,9 +1,10 @@
 use crate::result::*;
 use crate::service;
-use crate::service::SessionObject;
 
-pub trait IPsmServer {
-    fn get_battery_charge_percentage(&mut self) -> Result<u32>;
+ipc_client_interface_define! {
+    pub trait IPsmServer {
+        [0] get_battery_charge_percentage() => (charge: u32);
+    }
 }
 
 session_object_define!(PsmServer);
@@ -22,22 +23,4 @@ impl service::Service for PsmServer {
     }
 }
 
-impl IPsmServer for PsmServer {
-    fn get_battery_charge_percentage(&mut self) -> Result<u32> {
-        let charge: u32;
-        ipc_client_session_send_request_command!([self.session; 0; false] => {
-            In {};
-            InHandles {};
-            InObjects {};
-            InSessions {};
-            Buffers {};
-            Out {
-                charge: u32 => charge
-            };
-            OutHandles {};
-            OutObjects {};
-            OutSessions {};
-        });
-        Ok(charge)
-    }
-}
\ No newline at end of file
+impl IPsmServer for PsmServer {}
\ No newline at end of file
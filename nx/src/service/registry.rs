@@ -0,0 +1,295 @@
+extern crate alloc;
+
+use crate::result::*;
+use crate::ipc;
+use crate::svc;
+use crate::mem;
+use crate::sync;
+use crate::crt0;
+use crate::thread;
+use crate::service;
+use crate::service::sm;
+use crate::service::SessionObject;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::marker;
+use core::mem::ManuallyDrop;
+use core::ops::Deref;
+use core::ops::DerefMut;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+struct RegistryEntry {
+    name: u64,
+    // Always a mem::SyncSharedObject<T> of the service type it was registered with, whose drop closes the session once nobody else holds it
+    object: Box<dyn Any + Send>,
+}
+
+// Services opened through the registry are opened once and shared by every thread
+static G_REGISTRY: sync::Mutex<Vec<RegistryEntry>> = sync::Mutex::new(Vec::new());
+static G_EXIT_HOOK_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+pub fn get_service<T: SessionObject + service::Service + Send + 'static>() -> Result<mem::SyncSharedObject<T>> {
+    let name = sm::ServiceName::new(T::get_name()).encode();
+    let mut registry = G_REGISTRY.lock();
+    if let Some(entry) = registry.iter().find(|entry| entry.name == name) {
+        if let Some(object) = entry.object.downcast_ref::<mem::SyncSharedObject<T>>() {
+            return Ok(object.clone());
+        }
+    }
+
+    let object = service::new_sync_shared_service_object::<T>()?;
+    if !G_EXIT_HOOK_REGISTERED.swap(true, Ordering::AcqRel) {
+        // Hooks run in reverse order, so services get closed before the sm: session
+        crt0::register_exit_hook(close_all);
+    }
+    // A different type for an already registered service replaces the older one, which stays open for whoever still holds it
+    registry.retain(|entry| entry.name != name);
+    registry.push(RegistryEntry { name: name, object: Box::new(object.clone()) });
+    Ok(object)
+}
+
+pub fn is_service_registered(name: &str) -> bool {
+    let name = sm::ServiceName::new(name).encode();
+    G_REGISTRY.lock().iter().any(|entry| entry.name == name)
+}
+
+// The service gets closed once whoever still holds it drops it too
+pub fn close_service(name: &str) {
+    let name = sm::ServiceName::new(name).encode();
+    let mut registry = G_REGISTRY.lock();
+    if let Some(index) = registry.iter().position(|entry| entry.name == name) {
+        registry.remove(index);
+    }
+}
+
+pub fn close_all() {
+    G_REGISTRY.lock().clear();
+}
+
+// For when the handles went away without being closed (like when the mock backend is reset)
+#[cfg(feature = "mock-svc")]
+pub fn reset() {
+    for entry in G_REGISTRY.lock().drain(..) {
+        core::mem::forget(entry.object);
+    }
+}
+
+// IPC requests on a session are handled one after the other, so threads sharing one end up waiting on each other
+// A pool gives each thread its own clone of the session (CloneCurrentObject), so their requests can be processed concurrently
+pub struct ServicePool<T: SessionObject> {
+    // The original object, used by the thread which created the pool
+    object: T,
+    owner_thread_handle: svc::Handle,
+    clones: sync::Mutex<Vec<(svc::Handle, ipc::Session)>>,
+    max_sessions: usize,
+}
+
+// A view of a pooled session, which is never closed through it: the pool takes care of that
+pub struct PooledObject<'a, T: SessionObject> {
+    object: ManuallyDrop<T>,
+    pool: marker::PhantomData<&'a ServicePool<T>>,
+}
+
+impl<'a, T: SessionObject> Deref for PooledObject<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.object
+    }
+}
+
+impl<'a, T: SessionObject> DerefMut for PooledObject<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.object
+    }
+}
+
+impl<T: SessionObject> ServicePool<T> {
+    // Threads beyond max_sessions share the original session instead of cloning it
+    pub fn from(object: T, max_sessions: usize) -> Self {
+        let thread_handle = thread::get_current_thread_handle().unwrap_or(svc::INVALID_HANDLE);
+        Self { object: object, owner_thread_handle: thread_handle, clones: sync::Mutex::new(Vec::new()), max_sessions: max_sessions }
+    }
+
+    pub fn get_session(&self) -> Result<ipc::Session> {
+        let mut base_session = self.object.get_session();
+        result_return_unless!(base_session.is_valid(), svc::ResultInvalidHandle);
+        // Threads without a thread object can't be told apart, so they share the original session too
+        let thread_handle = match thread::get_current_thread_handle() {
+            Ok(handle) => handle,
+            Err(_) => return Ok(base_session),
+        };
+        if thread_handle == self.owner_thread_handle {
+            return Ok(base_session);
+        }
+
+        let mut clones = self.clones.lock();
+        if let Some(session) = clones.iter().find(|session| session.0 == thread_handle) {
+            return Ok(session.1);
+        }
+        if (clones.len() + 1) >= self.max_sessions {
+            return Ok(base_session);
+        }
+
+        let session = base_session.clone_current_object()?;
+        clones.push((thread_handle, session));
+        Ok(session)
+    }
+
+    pub fn get(&self) -> Result<PooledObject<'_, T>> {
+        Ok(PooledObject { object: ManuallyDrop::new(T::new(self.get_session()?)), pool: marker::PhantomData })
+    }
+
+    pub fn get_session_count(&self) -> usize {
+        match self.object.is_valid() {
+            true => self.clones.lock().len() + 1,
+            false => 0,
+        }
+    }
+
+    pub fn close(&mut self) {
+        for (_, mut session) in self.clones.lock().drain(..) {
+            session.close_clone();
+        }
+        self.object.close();
+    }
+}
+
+impl<T: SessionObject + service::Service> ServicePool<T> {
+    pub fn new(max_sessions: usize) -> Result<Self> {
+        let object = service::new_service_object::<T>()?;
+        Ok(Self::from(object, max_sessions))
+    }
+}
+
+impl<T: SessionObject> Drop for ServicePool<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::mock;
+    use crate::service::psm;
+    use crate::service::psm::IPsmServer;
+
+    fn register_psm() {
+        let mut psm_server = mock::Server::new();
+        psm_server.register_command(0, |_| {
+            let mut response = mock::Response::new();
+            response.push_data(87u32);
+            response
+        });
+        mock::register_service("psm", psm_server);
+    }
+
+    // Pools tell threads apart by their handle, so other threads are faked by swapping the current thread object
+    fn with_thread_handle<F: FnOnce()>(handle: svc::Handle, f: F) {
+        let mut fake_thread = thread::Thread::new();
+        fake_thread.handle = handle;
+        unsafe {
+            let tls = thread::get_thread_local_storage();
+            let prev_thread = (*tls).thread_ref;
+            (*tls).thread_ref = &mut fake_thread;
+            f();
+            (*tls).thread_ref = prev_thread;
+        }
+    }
+
+    #[test]
+    fn services_are_opened_once() {
+        let _process = mock::initialize();
+        register_psm();
+        let first = get_service::<psm::PsmServer>().unwrap();
+        let second = get_service::<psm::PsmServer>().unwrap();
+        let handle = first.lock().get_session().handle;
+        assert_eq!(second.lock().get_session().handle, handle);
+        assert_eq!(second.lock().get_battery_charge_percentage().unwrap(), 87);
+        assert!(is_service_registered("psm"));
+        assert!(G_EXIT_HOOK_REGISTERED.load(Ordering::Acquire));
+
+        // The session stays open for whoever still holds it
+        close_service("psm");
+        assert!(!is_service_registered("psm"));
+        drop(first);
+        assert!(svc::mock::is_handle_open(handle));
+        drop(second);
+        assert!(!svc::mock::is_handle_open(handle));
+    }
+
+    #[test]
+    fn missing_services_arent_registered() {
+        let _process = mock::initialize();
+        assert!(get_service::<psm::PsmServer>().is_err());
+        assert!(!is_service_registered("psm"));
+    }
+
+    #[test]
+    fn pools_clone_sessions_for_other_threads() {
+        let _process = mock::initialize();
+        register_psm();
+        let mut pool = ServicePool::<psm::PsmServer>::new(2).unwrap();
+        let original_handle = pool.get_session().unwrap().handle;
+        assert_eq!(pool.get_session_count(), 1);
+
+        let mut clone_handle = 0;
+        with_thread_handle(0x100, || {
+            let mut psm = pool.get().unwrap();
+            assert_eq!(psm.get_battery_charge_percentage().unwrap(), 87);
+            clone_handle = psm.get_session().handle;
+        });
+        assert_ne!(clone_handle, original_handle);
+        // Pooled objects don't close the session
+        assert!(svc::mock::is_handle_open(clone_handle));
+        with_thread_handle(0x100, || assert_eq!(pool.get_session().unwrap().handle, clone_handle));
+
+        // Threads beyond the limit share the original session
+        with_thread_handle(0x200, || assert_eq!(pool.get_session().unwrap().handle, original_handle));
+        assert_eq!(pool.get_session_count(), 2);
+
+        pool.close();
+        assert!(!svc::mock::is_handle_open(original_handle));
+        assert!(!svc::mock::is_handle_open(clone_handle));
+        assert_eq!(pool.get_session_count(), 0);
+        assert!(pool.get_session().is_err());
+    }
+
+    #[test]
+    fn dropping_pools_closes_the_original_object() {
+        let _process = mock::initialize();
+        register_psm();
+        let pool = ServicePool::<psm::PsmServer>::new(4).unwrap();
+        let handle = pool.get_session().unwrap().handle;
+        drop(pool);
+        assert!(!svc::mock::is_handle_open(handle));
+    }
+
+    #[test]
+    fn pooled_domain_clones_leave_the_object_open() {
+        let _process = mock::initialize();
+        register_psm();
+        let mut psm = service::new_service_object::<psm::PsmServer>().unwrap();
+        psm.convert_current_object_to_domain().unwrap();
+        let mut pool = ServicePool::from(psm, 4);
+        let original_handle = pool.get_session().unwrap().handle;
+
+        let mut clone_session = ipc::Session::new();
+        with_thread_handle(0x100, || clone_session = pool.get_session().unwrap());
+        assert!(clone_session.is_domain());
+        assert_eq!(ipc::get_domain_reference_count(clone_session.handle), 1);
+
+        // Closing a clone like any other domain object would close the object shared with the original session
+        let mut closed_clone = clone_session;
+        closed_clone.close_clone();
+        assert!(!svc::mock::is_handle_open(clone_session.handle));
+        assert_eq!(pool.get().unwrap().get_battery_charge_percentage().unwrap(), 87);
+
+        // The clone was already closed above
+        pool.clones.lock().clear();
+        pool.close();
+        assert!(!svc::mock::is_handle_open(original_handle));
+    }
+}
\ No newline at end of file
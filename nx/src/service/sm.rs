@@ -1,7 +1,7 @@
 use crate::result::*;
 use crate::ipc;
+use crate::svc::handle::OwnedHandle;
 use crate::service;
-use crate::service::SessionObject;
 
 pub union ServiceName {
     name: [u8; 8],
@@ -15,6 +15,10 @@ impl ServiceName {
         Self { name: [*bytes.get(0).unwrap_or(&0), *bytes.get(1).unwrap_or(&0), *bytes.get(2).unwrap_or(&0), *bytes.get(3).unwrap_or(&0), *bytes.get(4).unwrap_or(&0), *bytes.get(5).unwrap_or(&0), *bytes.get(6).unwrap_or(&0), *bytes.get(7).unwrap_or(&0)] }
     }
 
+    pub const fn from(value: u64) -> Self {
+        Self { value: value }
+    }
+
     pub fn encode(&self) -> u64 {
         unsafe {
             self.value
@@ -25,6 +29,8 @@ impl ServiceName {
 pub trait IUserInterface {
     fn initialize(&mut self) -> Result<()>;
     fn get_service(&mut self, name: ServiceName) -> Result<ipc::Session>;
+    fn register_service(&mut self, name: ServiceName, is_light: bool, max_sessions: i32) -> Result<OwnedHandle>;
+    fn unregister_service(&mut self, name: ServiceName) -> Result<()>;
 }
 
 session_object_define!(UserInterface);
@@ -76,4 +82,43 @@ impl IUserInterface for UserInterface {
         });
         Ok(session)
     }
+
+    fn register_service(&mut self, name: ServiceName, is_light: bool, max_sessions: i32) -> Result<OwnedHandle> {
+        let port_handle: OwnedHandle;
+        ipc_client_session_send_request_command!([self.session; 2; false] => {
+            In {
+                service_name: u64 = name.encode(),
+                is_light: bool = is_light,
+                max_sessions: i32 = max_sessions
+            };
+            InHandles {};
+            InObjects {};
+            InSessions {};
+            Buffers {};
+            Out {};
+            OutHandles {
+                port_handle => ipc::HandleMode::Move
+            };
+            OutObjects {};
+            OutSessions {};
+        });
+        Ok(port_handle)
+    }
+
+    fn unregister_service(&mut self, name: ServiceName) -> Result<()> {
+        ipc_client_session_send_request_command!([self.session; 3; false] => {
+            In {
+                service_name: u64 = name.encode()
+            };
+            InHandles {};
+            InObjects {};
+            InSessions {};
+            Buffers {};
+            Out {};
+            OutHandles {};
+            OutObjects {};
+            OutSessions {};
+        });
+        Ok(())
+    }
 }
\ No newline at end of file
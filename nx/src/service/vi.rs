@@ -35,13 +35,13 @@ pub trait IApplicationDisplayService {
 
     fn close_display(&mut self, display_id: DisplayId) -> Result<()>;
 
-    fn open_layer(&mut self, name: DisplayName, layer_id: LayerId, aruid: u64, out_native_window_buf: *const u8, out_native_window_size: usize) -> Result<usize>;
+    fn open_layer(&mut self, name: DisplayName, layer_id: LayerId, aruid: u64, out_native_window: ipc::OutMapAlias<u8>) -> Result<usize>;
 
-    fn create_stray_layer(&mut self, flags: BitFlags<LayerFlags>, display_id: DisplayId, out_native_window_buf: *const u8, out_native_window_size: usize) -> Result<(LayerId, usize)>;
+    fn create_stray_layer(&mut self, flags: BitFlags<LayerFlags>, display_id: DisplayId, out_native_window: ipc::OutMapAlias<u8>) -> Result<(LayerId, usize)>;
 
     fn destroy_stray_layer(&mut self, layer_id: LayerId) -> Result<()>;
 
-    fn get_display_vsync_event(&mut self, display_id: DisplayId) -> Result<svc::Handle>;
+    fn get_display_vsync_event(&mut self, display_id: DisplayId) -> Result<svc::handle::ReadableEvent>;
 }
 
 session_object_define!(ApplicationDisplayService);
@@ -102,7 +102,7 @@ impl IApplicationDisplayService for ApplicationDisplayService {
         Ok(())
     }
 
-    fn open_layer(&mut self, name: DisplayName, layer_id: LayerId, aruid: u64, out_native_window_buf: *const u8, out_native_window_size: usize) -> Result<usize> {
+    fn open_layer(&mut self, name: DisplayName, layer_id: LayerId, aruid: u64, out_native_window: ipc::OutMapAlias<u8>) -> Result<usize> {
         let native_window_size: usize;
         ipc_client_session_send_request_command!([self.session; 2020; true] => {
             In {
@@ -114,7 +114,7 @@ impl IApplicationDisplayService for ApplicationDisplayService {
             InObjects {};
             InSessions {};
             Buffers {
-                (out_native_window_buf, out_native_window_size) => ipc::BufferAttribute::Out | ipc::BufferAttribute::MapAlias
+                out_native_window
             };
             Out {
                 native_window_size: usize => native_window_size
@@ -126,7 +126,7 @@ impl IApplicationDisplayService for ApplicationDisplayService {
         Ok(native_window_size)
     }
 
-    fn create_stray_layer(&mut self, flags: BitFlags<LayerFlags>, display_id: DisplayId, out_native_window_buf: *const u8, out_native_window_size: usize) -> Result<(LayerId, usize)> {
+    fn create_stray_layer(&mut self, flags: BitFlags<LayerFlags>, display_id: DisplayId, out_native_window: ipc::OutMapAlias<u8>) -> Result<(LayerId, usize)> {
         let layer_id: LayerId;
         let native_window_size: usize;
         ipc_client_session_send_request_command!([self.session; 2030; false] => {
@@ -139,7 +139,7 @@ impl IApplicationDisplayService for ApplicationDisplayService {
             InObjects {};
             InSessions {};
             Buffers {
-                (out_native_window_buf, out_native_window_size) => ipc::BufferAttribute::Out | ipc::BufferAttribute::MapAlias
+                out_native_window
             };
             Out {
                 layer_id: LayerId => layer_id,
@@ -169,8 +169,8 @@ impl IApplicationDisplayService for ApplicationDisplayService {
         Ok(())
     }
 
-    fn get_display_vsync_event(&mut self, display_id: DisplayId) -> Result<svc::Handle> {
-        let event_handle: svc::Handle;
+    fn get_display_vsync_event(&mut self, display_id: DisplayId) -> Result<svc::handle::ReadableEvent> {
+        let event_handle: svc::handle::ReadableEvent;
         ipc_client_session_send_request_command!([self.session; 5202; false] => {
             In {
                 display_id: DisplayId = display_id
@@ -0,0 +1,378 @@
+use crate::result::*;
+use enumflags2::BitFlags;
+use super::*;
+
+pub struct AsmBackend;
+
+impl Backend for AsmBackend {
+    fn set_heap_size(size: Size) -> Result<Address> {
+        let mut rc: ResultCode;
+        let address: *mut u8;
+        unsafe {
+            llvm_asm!("svc 0x1" : "={w0}"(rc), "={x1}"(address) : "{x1}"(size) :: "volatile");
+        }
+        wrap(rc, address)
+    }
+
+    fn set_memory_attribute(address: Address, size: Size, mask: u32, value: BitFlags<MemoryAttribute>) -> Result<()> {
+        let mut rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x3" : "={w0}"(rc) : "{x0}"(address), "{x1}"(size), "{w2}"(mask), "{w3}"(value) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn map_memory(address: Address, source_address: Address, size: Size) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x4" : "={w0}"(rc) : "{x0}"(address), "{x1}"(source_address), "{x2}"(size) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn unmap_memory(address: Address, source_address: Address, size: Size) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x5" : "={w0}"(rc) : "{x0}"(address), "{x1}"(source_address), "{x2}"(size) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    unsafe fn query_memory(out_info: *mut MemoryInfo, address: *const u8) -> Result<PageInfo> {
+        let rc: ResultCode;
+        let info: PageInfo;
+        llvm_asm!("svc 0x6" : "={w0}"(rc), "={w1}"(info) : "{x0}"(out_info), "{x2}"(address) :: "volatile");
+        wrap(rc, info)
+    }
+
+    fn exit_process() {
+        unsafe {
+            llvm_asm!("svc 0x7" :::: "volatile");
+        }
+    }
+
+    fn create_thread(entry: ThreadEntrypointFn, entry_arg: *mut u8, stack_top: Address, priority: i32, cpu_id: i32) -> Result<Handle> {
+        let rc: ResultCode;
+        let handle: Handle;
+        unsafe {
+            llvm_asm!("svc 0x8" : "={w0}"(rc), "={w1}"(handle) : "{x1}"(entry), "{x2}"(entry_arg), "{x3}"(stack_top), "{w4}"(priority), "{w5}"(cpu_id) :: "volatile");
+        }
+        wrap(rc, handle)
+    }
+
+    fn start_thread(handle: Handle) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x9" : "={w0}"(rc) : "{w0}"(handle) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn exit_thread() {
+        unsafe {
+            llvm_asm!("svc 0xA" :::: "volatile");
+        }
+    }
+
+    fn sleep_thread(timeout: i64) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0xB" : "={w0}"(rc) : "{x0}"(timeout) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn get_thread_priority(handle: Handle) -> Result<i32> {
+        let rc: ResultCode;
+        let priority: i32;
+        unsafe {
+            llvm_asm!("svc 0xC" : "={w0}"(rc), "={w1}"(priority) : "{w1}"(handle) :: "volatile");
+        }
+        wrap(rc, priority)
+    }
+
+    fn set_thread_priority(handle: Handle, priority: i32) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0xD" : "={w0}"(rc) : "{w0}"(handle), "{w1}"(priority) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn signal_event(handle: Handle) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x11" : "={w0}"(rc) : "{w0}"(handle) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn clear_event(handle: Handle) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x12" : "={w0}"(rc) : "{w0}"(handle) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn map_shared_memory(handle: Handle, address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x13" : "={w0}"(rc) : "{w0}"(handle), "{x1}"(address), "{x2}"(size), "{w3}"(permissions) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn unmap_shared_memory(handle: Handle, address: Address, size: Size) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x14" : "={w0}"(rc) : "{w0}"(handle), "{x1}"(address), "{x2}"(size) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn create_transfer_memory(address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<Handle> {
+        let rc: ResultCode;
+        let handle: Handle;
+        unsafe {
+            llvm_asm!("svc 0x15" : "={w0}"(rc), "={w1}"(handle) : "{x1}"(address), "{x2}"(size), "{w3}"(permissions) :: "volatile");
+        }
+        wrap(rc, handle)
+    }
+
+    fn close_handle(handle: Handle) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x16" : "={w0}"(rc) : "{w0}"(handle) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn reset_signal(handle: Handle) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x17" : "={w0}"(rc) : "{w0}"(handle) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    unsafe fn wait_synchronization(handles: *const Handle, handle_count: u32, timeout: i64) -> Result<i32> {
+        let rc: ResultCode;
+        let index: i32;
+        llvm_asm!("svc 0x18" : "={w0}"(rc), "={w1}"(index) : "{x1}"(handles), "{w2}"(handle_count), "{x3}"(timeout) :: "volatile");
+        wrap(rc, index)
+    }
+
+    fn cancel_synchronization(handle: Handle) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x19" : "={w0}"(rc) : "{w0}"(handle) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn arbitrate_lock(thread_handle: u32, address: Address, tag: u32) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x1A" : "={w0}"(rc) : "{w0}"(thread_handle), "{x1}"(address), "{w2}"(tag) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn arbitrate_unlock(address: Address) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x1B" : "={w0}"(rc) : "{x0}"(address) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn wait_process_wide_key_atomic(address: Address, cv_key: Address, tag: u32, timeout: i64) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x1C" : "={w0}"(rc) : "{x0}"(address), "{x1}"(cv_key), "{w2}"(tag), "{x3}"(timeout) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn signal_process_wide_key(cv_key: Address, count: i32) {
+        unsafe {
+            llvm_asm!("svc 0x1D" :: "{x0}"(cv_key), "{w1}"(count) :: "volatile");
+        }
+    }
+
+    fn get_system_tick() -> u64 {
+        let tick: u64;
+        unsafe {
+            llvm_asm!("svc 0x1E" : "={x0}"(tick) ::: "volatile");
+        }
+        tick
+    }
+
+    unsafe fn connect_to_named_port(name: *const u8) -> Result<Handle> {
+        let rc: ResultCode;
+        let handle: Handle;
+        llvm_asm!("svc 0x1F" : "={w0}"(rc), "={w1}"(handle) : "{x1}"(name) :: "volatile");
+        wrap(rc, handle)
+    }
+
+    fn send_sync_request(handle: Handle) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x21" : "={w0}"(rc) : "{w0}"(handle) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    unsafe fn send_sync_request_with_user_buffer(buffer: Address, size: Size, handle: Handle) -> Result<()> {
+        let rc: ResultCode;
+        llvm_asm!("svc 0x22" : "={w0}"(rc) : "{x0}"(buffer), "{x1}"(size), "{w2}"(handle) :: "volatile");
+        wrap(rc, ())
+    }
+
+    fn get_process_id(process_handle: Handle) -> Result<u64> {
+        let rc: ResultCode;
+        let process_id: u64;
+        unsafe {
+            llvm_asm!("svc 0x24" : "={w0}"(rc), "={x1}"(process_id) : "{w1}"(process_handle) :: "volatile");
+        }
+        wrap(rc, process_id)
+    }
+
+    fn get_thread_id(handle: Handle) -> Result<u64> {
+        let rc: ResultCode;
+        let thread_id: u64;
+        unsafe {
+            llvm_asm!("svc 0x25" : "={w0}"(rc), "={x1}"(thread_id) : "{w1}"(handle) :: "volatile");
+        }
+        wrap(rc, thread_id)
+    }
+
+    fn break_(reason: BreakReason, arg: Address, size: Size) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x26" : "={w0}"(rc) : "{x0}"(reason), "{x1}"(arg), "{x2}"(size) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    unsafe fn output_debug_string(msg: *const u8, len: Size) -> Result<()> {
+        let rc: ResultCode;
+        llvm_asm!("svc 0x27" : "={w0}"(rc) : "{x0}"(msg), "{x1}"(len) :: "volatile");
+        wrap(rc, ())
+    }
+
+    fn return_from_exception(rc: ResultCode) {
+        unsafe {
+            llvm_asm!("svc 0x28" :: "{w0}"(rc) :: "volatile");
+        }
+    }
+
+    fn get_info(id: InfoId, handle: Handle, sub_id: u64) -> Result<u64> {
+        let rc: ResultCode;
+        let info: u64;
+        unsafe {
+            llvm_asm!("svc 0x29" : "={w0}"(rc), "={x1}"(info) : "{w1}"(id), "{w2}"(handle), "{x3}"(sub_id) :: "volatile");
+        }
+        wrap(rc, info)
+    }
+
+    fn wait_for_address(address: Address, arbitration_type: ArbitrationType, value: i32, timeout: i64) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x34" : "={w0}"(rc) : "{x0}"(address), "{w1}"(arbitration_type), "{w2}"(value), "{x3}"(timeout) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn signal_to_address(address: Address, signal_type: SignalType, value: i32, count: i32) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x35" : "={w0}"(rc) : "{x0}"(address), "{w1}"(signal_type), "{w2}"(value), "{w3}"(count) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn create_session(is_light: bool, name: u64) -> Result<(Handle, Handle)> {
+        let rc: ResultCode;
+        let server_handle: Handle;
+        let client_handle: Handle;
+        unsafe {
+            llvm_asm!("svc 0x40" : "={w0}"(rc), "={w1}"(server_handle), "={w2}"(client_handle) : "{w2}"(is_light as u32), "{x3}"(name) :: "volatile");
+        }
+        wrap(rc, (server_handle, client_handle))
+    }
+
+    fn accept_session(port_handle: Handle) -> Result<Handle> {
+        let rc: ResultCode;
+        let session_handle: Handle;
+        unsafe {
+            llvm_asm!("svc 0x41" : "={w0}"(rc), "={w1}"(session_handle) : "{w1}"(port_handle) :: "volatile");
+        }
+        wrap(rc, session_handle)
+    }
+
+    unsafe fn reply_and_receive(handles: *const Handle, handle_count: u32, reply_target: Handle, timeout: i64) -> Result<i32> {
+        let rc: ResultCode;
+        let index: i32;
+        llvm_asm!("svc 0x43" : "={w0}"(rc), "={w1}"(index) : "{x1}"(handles), "{w2}"(handle_count), "{w3}"(reply_target), "{x4}"(timeout) :: "volatile");
+        wrap(rc, index)
+    }
+
+    fn create_event() -> Result<(Handle, Handle)> {
+        let rc: ResultCode;
+        let writable_handle: Handle;
+        let readable_handle: Handle;
+        unsafe {
+            llvm_asm!("svc 0x45" : "={w0}"(rc), "={w1}"(writable_handle), "={w2}"(readable_handle) ::: "volatile");
+        }
+        wrap(rc, (writable_handle, readable_handle))
+    }
+
+    fn map_transfer_memory(handle: Handle, address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x51" : "={w0}"(rc) : "{w0}"(handle), "{x1}"(address), "{x2}"(size), "{w3}"(permissions) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn unmap_transfer_memory(handle: Handle, address: Address, size: Size) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x52" : "={w0}"(rc) : "{w0}"(handle), "{x1}"(address), "{x2}"(size) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    unsafe fn manage_named_port(name: *const u8, max_sessions: i32) -> Result<Handle> {
+        let rc: ResultCode;
+        let handle: Handle;
+        llvm_asm!("svc 0x71" : "={w0}"(rc), "={w1}"(handle) : "{x1}"(name), "{w2}"(max_sessions) :: "volatile");
+        wrap(rc, handle)
+    }
+
+    fn set_process_memory_permission(process_handle: Handle, address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x73" : "={w0}"(rc) : "{w0}"(process_handle), "{x1}"(address), "{x2}"(size), "{w3}"(permissions) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn map_process_code_memory(process_handle: Handle, address: Address, source_address: Address, size: Size) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x77" : "={w0}"(rc) : "{w0}"(process_handle), "{x1}"(address), "{x2}"(source_address), "{x3}"(size) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+
+    fn unmap_process_code_memory(process_handle: Handle, address: Address, source_address: Address, size: Size) -> Result<()> {
+        let rc: ResultCode;
+        unsafe {
+            llvm_asm!("svc 0x78" : "={w0}"(rc) : "{w0}"(process_handle), "{x1}"(address), "{x2}"(source_address), "{x3}"(size) :: "volatile");
+        }
+        wrap(rc, ())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,394 @@
+use crate::result::*;
+use crate::svc;
+use enumflags2::BitFlags;
+use core::mem;
+use core::marker;
+
+// Closes the handle when dropped, unless ownership is given away with into_raw
+pub struct OwnedHandle {
+    handle: svc::Handle,
+}
+
+impl OwnedHandle {
+    pub const fn new(handle: svc::Handle) -> Self {
+        Self { handle: handle }
+    }
+
+    pub const fn invalid() -> Self {
+        Self::new(svc::INVALID_HANDLE)
+    }
+
+    pub const fn is_valid(&self) -> bool {
+        self.handle != svc::INVALID_HANDLE
+    }
+
+    pub const fn get_handle(&self) -> svc::Handle {
+        self.handle
+    }
+
+    pub fn borrow_handle(&self) -> BorrowedHandle<'_> {
+        BorrowedHandle::new(self.handle)
+    }
+
+    pub fn into_raw(self) -> svc::Handle {
+        let handle = self.handle;
+        mem::forget(self);
+        handle
+    }
+
+    pub fn close(&mut self) {
+        if self.is_valid() {
+            let _ = svc::close_handle(self.handle);
+            self.handle = svc::INVALID_HANDLE;
+        }
+    }
+}
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+// A handle someone else owns (or a pseudo-handle), never closed by us
+#[derive(Copy, Clone)]
+pub struct BorrowedHandle<'a> {
+    handle: svc::Handle,
+    owner: marker::PhantomData<&'a OwnedHandle>,
+}
+
+impl<'a> BorrowedHandle<'a> {
+    pub const fn new(handle: svc::Handle) -> Self {
+        Self { handle: handle, owner: marker::PhantomData }
+    }
+
+    pub const fn get_handle(&self) -> svc::Handle {
+        self.handle
+    }
+}
+
+pub const CURRENT_THREAD: BorrowedHandle<'static> = BorrowedHandle::new(svc::CURRENT_THREAD_PSEUDO_HANDLE);
+pub const CURRENT_PROCESS: BorrowedHandle<'static> = BorrowedHandle::new(svc::CURRENT_PROCESS_PSEUDO_HANDLE);
+
+// What the IPC macros accept for copy handles: the kernel copies them, so they stay ours and are just borrowed
+pub trait CopyHandle {
+    fn copy_handle(&self) -> svc::Handle;
+}
+
+// What the IPC macros accept for move handles: the kernel closes them once sent, so they must be given away (thus never borrowed)
+pub trait MoveHandle {
+    fn move_handle(self) -> svc::Handle;
+}
+
+// What the IPC macros can store out handles into
+pub trait FromHandle {
+    fn from_handle(handle: svc::Handle) -> Self;
+}
+
+impl CopyHandle for svc::Handle {
+    fn copy_handle(&self) -> svc::Handle {
+        *self
+    }
+}
+
+impl MoveHandle for svc::Handle {
+    fn move_handle(self) -> svc::Handle {
+        self
+    }
+}
+
+impl<'a, H: CopyHandle> CopyHandle for &'a H {
+    fn copy_handle(&self) -> svc::Handle {
+        (**self).copy_handle()
+    }
+}
+
+impl FromHandle for svc::Handle {
+    fn from_handle(handle: svc::Handle) -> Self {
+        handle
+    }
+}
+
+impl<'a> CopyHandle for BorrowedHandle<'a> {
+    fn copy_handle(&self) -> svc::Handle {
+        self.handle
+    }
+}
+
+impl CopyHandle for OwnedHandle {
+    fn copy_handle(&self) -> svc::Handle {
+        self.get_handle()
+    }
+}
+
+impl MoveHandle for OwnedHandle {
+    fn move_handle(self) -> svc::Handle {
+        self.into_raw()
+    }
+}
+
+impl FromHandle for OwnedHandle {
+    fn from_handle(handle: svc::Handle) -> Self {
+        Self::new(handle)
+    }
+}
+
+fn wait_one(handle: svc::Handle, timeout: i64) -> Result<()> {
+    unsafe {
+        svc::wait_synchronization(&handle, 1, timeout)?;
+    }
+    Ok(())
+}
+
+macro_rules! handle_object_define {
+    ($name:ident) => {
+        pub struct $name {
+            handle: OwnedHandle,
+        }
+
+        impl $name {
+            pub fn from(handle: OwnedHandle) -> Self {
+                Self { handle: handle }
+            }
+
+            pub fn from_raw(handle: svc::Handle) -> Self {
+                Self::from(OwnedHandle::new(handle))
+            }
+
+            pub fn is_valid(&self) -> bool {
+                self.handle.is_valid()
+            }
+
+            pub fn get_handle(&self) -> svc::Handle {
+                self.handle.get_handle()
+            }
+
+            pub fn borrow_handle(&self) -> BorrowedHandle<'_> {
+                self.handle.borrow_handle()
+            }
+
+            pub fn into_raw(self) -> svc::Handle {
+                self.handle.into_raw()
+            }
+
+            pub fn into_owned(self) -> OwnedHandle {
+                self.handle
+            }
+
+            pub fn close(&mut self) {
+                self.handle.close();
+            }
+        }
+
+        impl CopyHandle for $name {
+            fn copy_handle(&self) -> svc::Handle {
+                self.get_handle()
+            }
+        }
+
+        impl MoveHandle for $name {
+            fn move_handle(self) -> svc::Handle {
+                self.into_raw()
+            }
+        }
+
+        impl FromHandle for $name {
+            fn from_handle(handle: svc::Handle) -> Self {
+                Self::from_raw(handle)
+            }
+        }
+    };
+}
+
+// The writable side of an event
+handle_object_define!(Event);
+
+impl Event {
+    // Returns both sides of the new event
+    pub fn create() -> Result<(Event, ReadableEvent)> {
+        let (writable_handle, readable_handle) = svc::create_event()?;
+        Ok((Event::from_raw(writable_handle), ReadableEvent::from_raw(readable_handle)))
+    }
+
+    pub fn signal(&self) -> Result<()> {
+        svc::signal_event(self.get_handle())
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        svc::clear_event(self.get_handle())
+    }
+}
+
+handle_object_define!(ReadableEvent);
+
+impl ReadableEvent {
+    pub fn wait(&self, timeout: i64) -> Result<()> {
+        wait_one(self.get_handle(), timeout)
+    }
+
+    pub fn reset(&self) -> Result<()> {
+        svc::reset_signal(self.get_handle())
+    }
+
+    // Waits and resets it, for events meant to be waited on once per signal
+    pub fn wait_and_reset(&self, timeout: i64) -> Result<()> {
+        self.wait(timeout)?;
+        self.reset()
+    }
+}
+
+handle_object_define!(TransferMemory);
+
+impl TransferMemory {
+    pub fn create(address: svc::Address, size: svc::Size, permissions: BitFlags<svc::MemoryPermission>) -> Result<Self> {
+        let handle = svc::create_transfer_memory(address, size, permissions)?;
+        Ok(Self::from_raw(handle))
+    }
+
+    pub fn map(&self, address: svc::Address, size: svc::Size, permissions: BitFlags<svc::MemoryPermission>) -> Result<()> {
+        svc::map_transfer_memory(self.get_handle(), address, size, permissions)
+    }
+
+    pub fn unmap(&self, address: svc::Address, size: svc::Size) -> Result<()> {
+        svc::unmap_transfer_memory(self.get_handle(), address, size)
+    }
+}
+
+handle_object_define!(SharedMemory);
+
+impl SharedMemory {
+    pub fn map(&self, address: svc::Address, size: svc::Size, permissions: BitFlags<svc::MemoryPermission>) -> Result<()> {
+        svc::map_shared_memory(self.get_handle(), address, size, permissions)
+    }
+
+    pub fn unmap(&self, address: svc::Address, size: svc::Size) -> Result<()> {
+        svc::unmap_shared_memory(self.get_handle(), address, size)
+    }
+}
+
+handle_object_define!(Thread);
+
+impl Thread {
+    pub fn create(entry: svc::ThreadEntrypointFn, entry_arg: *mut u8, stack_top: svc::Address, priority: i32, cpu_id: i32) -> Result<Self> {
+        let handle = svc::create_thread(entry, entry_arg, stack_top, priority, cpu_id)?;
+        Ok(Self::from_raw(handle))
+    }
+
+    pub fn start(&self) -> Result<()> {
+        svc::start_thread(self.get_handle())
+    }
+
+    pub fn get_id(&self) -> Result<u64> {
+        svc::get_thread_id(self.get_handle())
+    }
+
+    pub fn get_priority(&self) -> Result<i32> {
+        svc::get_thread_priority(self.get_handle())
+    }
+
+    pub fn set_priority(&self, priority: i32) -> Result<()> {
+        svc::set_thread_priority(self.get_handle(), priority)
+    }
+
+    // Threads get signaled once they exit
+    pub fn wait_exit(&self, timeout: i64) -> Result<()> {
+        wait_one(self.get_handle(), timeout)
+    }
+}
+
+handle_object_define!(Process);
+
+impl Process {
+    pub fn get_id(&self) -> Result<u64> {
+        svc::get_process_id(self.get_handle())
+    }
+
+    pub fn wait(&self, timeout: i64) -> Result<()> {
+        wait_one(self.get_handle(), timeout)
+    }
+
+    pub fn reset_signal(&self) -> Result<()> {
+        svc::reset_signal(self.get_handle())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service;
+
+    fn create_handle() -> svc::Handle {
+        let (writable_handle, readable_handle) = svc::create_event().unwrap();
+        svc::close_handle(readable_handle).unwrap();
+        writable_handle
+    }
+
+    #[test]
+    fn owned_handles_are_closed_once() {
+        let _process = service::mock::initialize();
+        let raw_handle = create_handle();
+        let handle = OwnedHandle::new(raw_handle);
+        drop(handle);
+        assert!(!svc::mock::is_handle_open(raw_handle));
+
+        let raw_handle = create_handle();
+        let mut handle = OwnedHandle::new(raw_handle);
+        handle.close();
+        assert!(!handle.is_valid());
+        assert!(!svc::mock::is_handle_open(raw_handle));
+        // Neither closing again nor dropping it closes anything else
+        let other_handle = create_handle();
+        handle.close();
+        drop(handle);
+        assert!(svc::mock::is_handle_open(other_handle));
+        svc::close_handle(other_handle).unwrap();
+        assert_eq!(svc::mock::get_open_handle_count(), 0);
+    }
+
+    #[test]
+    fn raw_handles_are_given_away() {
+        let _process = service::mock::initialize();
+        let raw_handle = create_handle();
+        assert_eq!(OwnedHandle::new(raw_handle).into_raw(), raw_handle);
+        assert!(svc::mock::is_handle_open(raw_handle));
+        svc::close_handle(raw_handle).unwrap();
+
+        let (event, readable_event) = Event::create().unwrap();
+        let readable_handle = readable_event.get_handle();
+        assert_eq!(readable_event.into_raw(), readable_handle);
+        drop(event);
+        assert!(svc::mock::is_handle_open(readable_handle));
+        assert_eq!(svc::mock::get_open_handle_count(), 1);
+        drop(ReadableEvent::from_raw(readable_handle));
+        assert_eq!(svc::mock::get_open_handle_count(), 0);
+    }
+
+    #[test]
+    fn copy_handles_are_borrowed_and_move_handles_are_given_away() {
+        let _process = service::mock::initialize();
+        let raw_handle = create_handle();
+        let handle = OwnedHandle::new(raw_handle);
+        assert_eq!(CopyHandle::copy_handle(&&handle), raw_handle);
+        assert_eq!(handle.borrow_handle().copy_handle(), raw_handle);
+        assert!(svc::mock::is_handle_open(raw_handle));
+
+        assert_eq!(handle.move_handle(), raw_handle);
+        assert!(svc::mock::is_handle_open(raw_handle));
+        svc::close_handle(raw_handle).unwrap();
+    }
+
+    #[test]
+    fn wrappers_close_on_drop() {
+        let _process = service::mock::initialize();
+        let (mut event, readable_event) = Event::create().unwrap();
+        let handle = event.get_handle();
+        event.signal().unwrap();
+        readable_event.wait_and_reset(0).unwrap();
+        event.close();
+        assert!(!event.is_valid());
+        assert!(!svc::mock::is_handle_open(handle));
+        drop(event);
+        drop(readable_event);
+        assert_eq!(svc::mock::get_open_handle_count(), 0);
+    }
+}
\ No newline at end of file
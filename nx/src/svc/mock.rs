@@ -0,0 +1,832 @@
+extern crate alloc;
+
+use crate::result::*;
+use crate::thread;
+use crate::mem;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use alloc::collections::VecDeque;
+use enumflags2::BitFlags;
+use core::ptr;
+use core::cell::UnsafeCell;
+use super::*;
+
+// NOTE: this backend emulates a single-threaded process: there is only one fake TLS block, and every "kernel" object lives in-process
+// Threads are run to completion as soon as they're started, on the host thread starting them
+// Each host thread gets its own fake process though (kernel state, TLS and main thread are thread-local), so tests running in parallel don't see each other
+
+pub type RequestHandler = Box<dyn FnMut(Handle) -> Result<()>>;
+
+// Sessions from svc::create_session and svc::accept_session are served by the process itself, so their requests can't be answered within svc::send_sync_request
+// Instead, post_request leaves the request for svc::reply_and_receive to pick up, and take_reply gets the reply back
+struct MockSession {
+    request: Option<Vec<u8>>,
+    reply: Option<Vec<u8>>,
+    client_closed: bool,
+    server_closed: bool,
+}
+
+impl MockSession {
+    fn new() -> Self {
+        Self { request: None, reply: None, client_closed: false, server_closed: false }
+    }
+}
+
+// Sessions of the clients which connected, waiting to be accepted
+type MockPort = mem::SharedObject<VecDeque<mem::SharedObject<MockSession>>>;
+
+enum HandleObject {
+    Session(mem::SharedObject<RequestHandler>),
+    ClientSession(mem::SharedObject<MockSession>),
+    ServerSession(mem::SharedObject<MockSession>),
+    Port(MockPort),
+    TransferMemory(Address, Size),
+    // Both the writable and the readable handles share the signaled state
+    Event(mem::SharedObject<bool>),
+    // Signaled once the thread has exited
+    Thread(MockThread),
+}
+
+#[derive(Clone)]
+struct MockThread {
+    entry: ThreadEntrypointFn,
+    entry_arg: *mut u8,
+    started: bool,
+    signaled: mem::SharedObject<bool>,
+}
+
+struct MockKernel {
+    next_handle: Handle,
+    handles: BTreeMap<Handle, HandleObject>,
+    named_ports: BTreeMap<String, mem::SharedObject<RequestHandler>>,
+    managed_ports: BTreeMap<String, MockPort>,
+    // Like the real heap region, it's reserved once and the heap grows and shrinks in place
+    heap: Address,
+    heap_size: Size,
+    process_id: u64,
+    debug_output: Vec<String>,
+    break_reason: Option<BreakReason>,
+    exception_rc: Option<ResultCode>,
+    exited: bool,
+    tick: u64,
+    main_thread_priority: i32,
+    running_thread_count: usize,
+}
+
+impl MockKernel {
+    fn new() -> Self {
+        Self { next_handle: 0x100, handles: BTreeMap::new(), named_ports: BTreeMap::new(), managed_ports: BTreeMap::new(), heap: ptr::null_mut(), heap_size: 0, process_id: DEFAULT_PROCESS_ID, debug_output: Vec::new(), break_reason: None, exception_rc: None, exited: false, tick: 0, main_thread_priority: DEFAULT_THREAD_PRIORITY, running_thread_count: 0 }
+    }
+
+    fn add_handle(&mut self, object: HandleObject) -> Handle {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(handle, object);
+        handle
+    }
+
+    fn get_event(&self, handle: Handle) -> Result<mem::SharedObject<bool>> {
+        match self.handles.get(&handle) {
+            Some(HandleObject::Event(signaled)) => Ok(signaled.clone()),
+            _ => Err(ResultCode::from::<ResultInvalidHandle>()),
+        }
+    }
+
+    fn get_client_session(&self, handle: Handle) -> Result<mem::SharedObject<MockSession>> {
+        match self.handles.get(&handle) {
+            Some(HandleObject::ClientSession(session)) => Ok(session.clone()),
+            _ => Err(ResultCode::from::<ResultInvalidHandle>()),
+        }
+    }
+
+    fn get_server_session(&self, handle: Handle) -> Result<mem::SharedObject<MockSession>> {
+        match self.handles.get(&handle) {
+            Some(HandleObject::ServerSession(session)) => Ok(session.clone()),
+            _ => Err(ResultCode::from::<ResultInvalidHandle>()),
+        }
+    }
+
+    fn is_signaled(&self, handle: Handle) -> Result<bool> {
+        match self.handles.get(&handle) {
+            Some(HandleObject::Thread(thread)) => Ok(*thread.signaled.borrow()),
+            Some(HandleObject::Event(signaled)) => Ok(*signaled.borrow()),
+            // Server sessions get signaled by new requests, but also by the client going away
+            Some(HandleObject::ServerSession(session)) => {
+                let session_ref = session.borrow();
+                Ok(session_ref.request.is_some() || session_ref.client_closed)
+            },
+            Some(HandleObject::Port(port)) => Ok(!port.borrow().is_empty()),
+            _ => Err(ResultCode::from::<ResultInvalidHandle>()),
+        }
+    }
+
+    fn advance_tick(&mut self, timeout: i64) {
+        // The system tick runs at 19.2MHz
+        if timeout > 0 {
+            self.tick += ((timeout as u128) * 192 / 10000) as u64;
+        }
+    }
+
+    fn is_main_thread(&self, handle: Handle) -> bool {
+        (handle == CURRENT_THREAD_PSEUDO_HANDLE) || (handle == MAIN_THREAD_HANDLE)
+    }
+}
+
+const DEFAULT_PROCESS_ID: u64 = 0x51;
+const DEFAULT_THREAD_PRIORITY: i32 = 0x2C;
+const MAIN_THREAD_ID: u64 = 1;
+const TICKS_PER_CALL: u64 = 19;
+
+const MAIN_THREAD_HANDLE: Handle = 0xBEEF;
+
+const HEAP_REGION_SIZE: Size = 0x10000000;
+const HEAP_SIZE_ALIGNMENT: Size = 0x200000;
+
+fn get_heap_region_layout() -> alloc::alloc::Layout {
+    alloc::alloc::Layout::from_size_align(HEAP_REGION_SIZE, HEAP_SIZE_ALIGNMENT).unwrap()
+}
+
+std::thread_local! {
+    static G_KERNEL: UnsafeCell<MockKernel> = UnsafeCell::new(MockKernel::new());
+    static G_MAIN_THREAD: UnsafeCell<thread::Thread> = UnsafeCell::new(thread::Thread::new());
+    static G_TLS: UnsafeCell<thread::Tls> = UnsafeCell::new(thread::Tls { ipc_buffer: [0; 0x100], preemption_state: 0, unk: [0; 0xF4], thread_ref: ptr::null_mut() });
+}
+
+// The references never leave the host thread owning them, like the fake process they belong to
+fn get_kernel() -> &'static mut MockKernel {
+    G_KERNEL.with(|kernel| unsafe { &mut *kernel.get() })
+}
+
+fn read_c_str(name: *const u8) -> String {
+    let mut bytes: Vec<u8> = Vec::new();
+    unsafe {
+        let mut cur = name;
+        while *cur != 0 {
+            bytes.push(*cur);
+            cur = cur.offset(1);
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn read_ipc_buffer() -> Vec<u8> {
+    unsafe {
+        (*get_thread_local_storage()).ipc_buffer.to_vec()
+    }
+}
+
+fn write_ipc_buffer(message: &[u8]) {
+    unsafe {
+        (*get_thread_local_storage()).ipc_buffer.copy_from_slice(message);
+    }
+}
+
+pub fn reset() {
+    let kernel = get_kernel();
+    if !kernel.heap.is_null() {
+        unsafe {
+            alloc::alloc::dealloc(kernel.heap, get_heap_region_layout());
+        }
+    }
+    *kernel = MockKernel::new();
+}
+
+pub fn get_thread_local_storage() -> *mut thread::Tls {
+    let tls = G_TLS.with(|tls| tls.get());
+    unsafe {
+        if (*tls).thread_ref.is_null() {
+            let main_thread = G_MAIN_THREAD.with(|main_thread| main_thread.get());
+            (*main_thread).initialize_existing(MAIN_THREAD_HANDLE, "MainThread", ptr::null_mut(), 0, false).unwrap();
+            (*tls).thread_ref = main_thread;
+        }
+    }
+    tls
+}
+
+pub fn register_named_port<F: FnMut(Handle) -> Result<()> + 'static>(name: &str, handler: F) {
+    let boxed: RequestHandler = Box::new(handler);
+    get_kernel().named_ports.insert(String::from(name.trim_matches('\0')), mem::make_shared(boxed));
+}
+
+pub fn unregister_named_port(name: &str) {
+    get_kernel().named_ports.remove(name.trim_matches('\0'));
+}
+
+pub fn create_session<F: FnMut(Handle) -> Result<()> + 'static>(handler: F) -> Handle {
+    let boxed: RequestHandler = Box::new(handler);
+    get_kernel().add_handle(HandleObject::Session(mem::make_shared(boxed)))
+}
+
+// Leaves the request in the IPC buffer for the server end of the session
+pub fn post_request(client_handle: Handle) -> Result<()> {
+    let session = get_kernel().get_client_session(client_handle)?;
+    let mut session_ref = session.borrow_mut();
+    result_return_if!(session_ref.server_closed, ResultSessionClosed);
+    session_ref.request = Some(read_ipc_buffer());
+    Ok(())
+}
+
+// Copies the reply to the last posted request to the IPC buffer
+pub fn take_reply(client_handle: Handle) -> Result<()> {
+    let session = get_kernel().get_client_session(client_handle)?;
+    let mut session_ref = session.borrow_mut();
+    match session_ref.reply.take() {
+        Some(reply) => {
+            write_ipc_buffer(&reply);
+            Ok(())
+        },
+        None => {
+            result_return_if!(session_ref.server_closed, ResultSessionClosed);
+            Err(ResultCode::from::<ResultNotFound>())
+        }
+    }
+}
+
+pub fn is_handle_open(handle: Handle) -> bool {
+    get_kernel().handles.contains_key(&handle)
+}
+
+pub fn get_open_handle_count() -> usize {
+    get_kernel().handles.len()
+}
+
+pub fn get_transfer_memory(handle: Handle) -> Option<(Address, Size)> {
+    match get_kernel().handles.get(&handle) {
+        Some(HandleObject::TransferMemory(address, size)) => Some((*address, *size)),
+        _ => None,
+    }
+}
+
+pub fn set_process_id(process_id: u64) {
+    get_kernel().process_id = process_id;
+}
+
+pub fn take_debug_output() -> Vec<String> {
+    let kernel = get_kernel();
+    core::mem::replace(&mut kernel.debug_output, Vec::new())
+}
+
+pub fn get_break_reason() -> Option<BreakReason> {
+    get_kernel().break_reason
+}
+
+pub fn get_exception_result() -> Option<ResultCode> {
+    get_kernel().exception_rc
+}
+
+pub fn has_exited() -> bool {
+    get_kernel().exited
+}
+
+pub fn is_event_signaled(handle: Handle) -> Result<bool> {
+    let signaled = get_kernel().get_event(handle)?;
+    let is_signaled = *signaled.borrow();
+    Ok(is_signaled)
+}
+
+pub struct MockBackend;
+
+impl Backend for MockBackend {
+    fn set_heap_size(size: Size) -> Result<Address> {
+        result_return_unless!((size % HEAP_SIZE_ALIGNMENT) == 0, ResultInvalidSize);
+        result_return_if!(size > HEAP_REGION_SIZE, ResultOutOfMemory);
+        let kernel = get_kernel();
+        if kernel.heap.is_null() {
+            // Not zeroed here, since that would touch the whole region: only the memory the heap grows into is
+            let heap = unsafe { alloc::alloc::alloc(get_heap_region_layout()) };
+            result_return_if!(heap.is_null(), ResultOutOfMemory);
+            kernel.heap = heap;
+        }
+        if size > kernel.heap_size {
+            unsafe {
+                ptr::write_bytes(kernel.heap.offset(kernel.heap_size as isize), 0, size - kernel.heap_size);
+            }
+        }
+        kernel.heap_size = size;
+        Ok(kernel.heap)
+    }
+
+    fn set_memory_attribute(address: Address, _size: Size, _mask: u32, _value: BitFlags<MemoryAttribute>) -> Result<()> {
+        result_return_if!(address.is_null(), ResultInvalidAddress);
+        Ok(())
+    }
+
+    fn map_memory(_address: Address, _source_address: Address, _size: Size) -> Result<()> {
+        Err(ResultCode::from::<ResultNotImplemented>())
+    }
+
+    fn unmap_memory(_address: Address, _source_address: Address, _size: Size) -> Result<()> {
+        Err(ResultCode::from::<ResultNotImplemented>())
+    }
+
+    unsafe fn query_memory(out_info: *mut MemoryInfo, address: *const u8) -> Result<PageInfo> {
+        result_return_if!(out_info.is_null(), ResultInvalidAddress);
+        (*out_info).base_address = ((address as usize) & !0xFFF) as *mut u8;
+        (*out_info).size = 0x1000;
+        (*out_info).memory_state = MemoryState::Normal;
+        (*out_info).memory_permission = MemoryPermission::Read;
+        (*out_info).ipc_refcount = 0;
+        (*out_info).device_refcount = 0;
+        Ok(0)
+    }
+
+    fn exit_process() {
+        get_kernel().exited = true;
+    }
+
+    fn create_thread(entry: ThreadEntrypointFn, entry_arg: *mut u8, stack_top: Address, _priority: i32, _cpu_id: i32) -> Result<Handle> {
+        result_return_if!(stack_top.is_null(), ResultInvalidAddress);
+        Ok(get_kernel().add_handle(HandleObject::Thread(MockThread { entry: entry, entry_arg: entry_arg, started: false, signaled: mem::make_shared(false) })))
+    }
+
+    fn start_thread(handle: Handle) -> Result<()> {
+        let kernel = get_kernel();
+        let thread = match kernel.handles.get_mut(&handle) {
+            Some(HandleObject::Thread(thread)) => {
+                result_return_if!(thread.started, ResultInvalidState);
+                thread.started = true;
+                thread.clone()
+            },
+            _ => return Err(ResultCode::from::<ResultInvalidHandle>()),
+        };
+
+        // The kernel state must not be borrowed while the thread runs, and the thread leaves its own thread object in the TLS
+        let tls = get_thread_local_storage();
+        let prev_thread_ref = unsafe { (*tls).thread_ref };
+        get_kernel().running_thread_count += 1;
+        (thread.entry)(thread.entry_arg);
+        get_kernel().running_thread_count -= 1;
+        unsafe {
+            (*tls).thread_ref = prev_thread_ref;
+        }
+        *thread.signaled.borrow_mut() = true;
+        Ok(())
+    }
+
+    fn exit_thread() {
+        // Threads exit by returning to start_thread, only the main thread exiting ends the process
+        let kernel = get_kernel();
+        if kernel.running_thread_count == 0 {
+            kernel.exited = true;
+        }
+    }
+
+    fn sleep_thread(timeout: i64) -> Result<()> {
+        get_kernel().advance_tick(timeout);
+        Ok(())
+    }
+
+    fn get_thread_priority(handle: Handle) -> Result<i32> {
+        let kernel = get_kernel();
+        result_return_unless!(kernel.is_main_thread(handle), ResultInvalidHandle);
+        Ok(kernel.main_thread_priority)
+    }
+
+    fn set_thread_priority(handle: Handle, priority: i32) -> Result<()> {
+        let kernel = get_kernel();
+        result_return_unless!(kernel.is_main_thread(handle), ResultInvalidHandle);
+        kernel.main_thread_priority = priority;
+        Ok(())
+    }
+
+    fn signal_event(handle: Handle) -> Result<()> {
+        let signaled = get_kernel().get_event(handle)?;
+        *signaled.borrow_mut() = true;
+        Ok(())
+    }
+
+    fn clear_event(handle: Handle) -> Result<()> {
+        let signaled = get_kernel().get_event(handle)?;
+        *signaled.borrow_mut() = false;
+        Ok(())
+    }
+
+    fn map_shared_memory(_handle: Handle, _address: Address, _size: Size, _permissions: BitFlags<MemoryPermission>) -> Result<()> {
+        Err(ResultCode::from::<ResultNotImplemented>())
+    }
+
+    fn unmap_shared_memory(_handle: Handle, _address: Address, _size: Size) -> Result<()> {
+        Err(ResultCode::from::<ResultNotImplemented>())
+    }
+
+    fn create_transfer_memory(address: Address, size: Size, _permissions: BitFlags<MemoryPermission>) -> Result<Handle> {
+        result_return_if!(address.is_null(), ResultInvalidAddress);
+        result_return_if!(size == 0, ResultInvalidSize);
+        Ok(get_kernel().add_handle(HandleObject::TransferMemory(address, size)))
+    }
+
+    fn close_handle(handle: Handle) -> Result<()> {
+        let kernel = get_kernel();
+        match kernel.handles.remove(&handle) {
+            Some(HandleObject::ClientSession(session)) => session.borrow_mut().client_closed = true,
+            Some(HandleObject::ServerSession(session)) => session.borrow_mut().server_closed = true,
+            Some(HandleObject::Port(port)) => {
+                // Nobody could accept the sessions anymore
+                kernel.managed_ports.retain(|_, managed_port| !mem::SharedObject::ptr_eq(managed_port, &port));
+            },
+            Some(_) => {},
+            None => return Err(ResultCode::from::<ResultInvalidHandle>()),
+        };
+        Ok(())
+    }
+
+    fn reset_signal(handle: Handle) -> Result<()> {
+        let signaled = get_kernel().get_event(handle)?;
+        let mut signaled_ref = signaled.borrow_mut();
+        result_return_unless!(*signaled_ref, ResultInvalidState);
+        *signaled_ref = false;
+        Ok(())
+    }
+
+    unsafe fn wait_synchronization(handles: *const Handle, handle_count: u32, timeout: i64) -> Result<i32> {
+        result_return_if!(handle_count as usize > MAX_WAIT_HANDLE_COUNT, ResultOutOfRange);
+        let kernel = get_kernel();
+        for i in 0..handle_count {
+            let handle = *handles.offset(i as isize);
+            if kernel.is_signaled(handle)? {
+                return Ok(i as i32);
+            }
+        }
+        // Nothing else runs in the mock process, so nothing could ever signal them while waiting
+        kernel.advance_tick(timeout);
+        Err(ResultCode::from::<ResultTimedOut>())
+    }
+
+    fn cancel_synchronization(handle: Handle) -> Result<()> {
+        result_return_unless!(get_kernel().is_main_thread(handle), ResultInvalidHandle);
+        Ok(())
+    }
+
+    fn arbitrate_lock(_thread_handle: u32, address: Address, tag: u32) -> Result<()> {
+        // Being single-threaded, whoever owned the lock is gone: hand it straight to the waiter
+        result_return_if!(address.is_null(), ResultInvalidAddress);
+        unsafe {
+            *(address as *mut u32) = tag;
+        }
+        Ok(())
+    }
+
+    fn arbitrate_unlock(address: Address) -> Result<()> {
+        result_return_if!(address.is_null(), ResultInvalidAddress);
+        unsafe {
+            *(address as *mut u32) = 0;
+        }
+        Ok(())
+    }
+
+    fn wait_process_wide_key_atomic(address: Address, _cv_key: Address, _tag: u32, timeout: i64) -> Result<()> {
+        // Nobody can signal the key meanwhile, so this always times out, which (like the real kernel) leaves the mutex released
+        result_return_if!(address.is_null(), ResultInvalidAddress);
+        get_kernel().advance_tick(timeout);
+        unsafe {
+            *(address as *mut u32) = 0;
+        }
+        Err(ResultCode::from::<ResultTimedOut>())
+    }
+
+    fn signal_process_wide_key(_cv_key: Address, _count: i32) {
+    }
+
+    fn get_system_tick() -> u64 {
+        let kernel = get_kernel();
+        kernel.tick += TICKS_PER_CALL;
+        kernel.tick
+    }
+
+    unsafe fn connect_to_named_port(name: *const u8) -> Result<Handle> {
+        result_return_if!(name.is_null(), ResultInvalidAddress);
+        let kernel = get_kernel();
+        let name = read_c_str(name);
+        if let Some(port) = kernel.managed_ports.get(&name).cloned() {
+            let session = mem::make_shared(MockSession::new());
+            port.borrow_mut().push_back(session.clone());
+            return Ok(kernel.add_handle(HandleObject::ClientSession(session)));
+        }
+        let handler = match kernel.named_ports.get(&name) {
+            Some(handler) => handler.clone(),
+            None => return Err(ResultCode::from::<ResultNotFound>()),
+        };
+        Ok(kernel.add_handle(HandleObject::Session(handler)))
+    }
+
+    fn send_sync_request(handle: Handle) -> Result<()> {
+        let handler = match get_kernel().handles.get(&handle) {
+            Some(HandleObject::Session(handler)) => handler.clone(),
+            // Nothing else runs in the mock process to serve it meanwhile (see post_request)
+            Some(HandleObject::ClientSession(_)) => return Err(ResultCode::from::<ResultNotImplemented>()),
+            Some(_) => return Err(ResultCode::from::<ResultInvalidHandle>()),
+            None => return Err(ResultCode::from::<ResultSessionClosed>()),
+        };
+        // The kernel state must not be borrowed here, since handlers are free to create new sessions
+        let mut handler_ref = handler.borrow_mut();
+        (*handler_ref)(handle)
+    }
+
+    unsafe fn send_sync_request_with_user_buffer(buffer: Address, size: Size, handle: Handle) -> Result<()> {
+        result_return_if!(buffer.is_null(), ResultInvalidAddress);
+        // Handlers only know about the TLS IPC buffer, so route the message through it
+        let ipc_buf = &mut (*get_thread_local_storage()).ipc_buffer as *mut _ as *mut u8;
+        let copy_size = core::cmp::min(size, 0x100);
+        ptr::copy(buffer, ipc_buf, copy_size);
+        let rc = Self::send_sync_request(handle);
+        ptr::copy(ipc_buf, buffer, copy_size);
+        rc
+    }
+
+    fn get_process_id(process_handle: Handle) -> Result<u64> {
+        result_return_unless!(process_handle == CURRENT_PROCESS_PSEUDO_HANDLE, ResultInvalidHandle);
+        Ok(get_kernel().process_id)
+    }
+
+    fn get_thread_id(handle: Handle) -> Result<u64> {
+        result_return_unless!(get_kernel().is_main_thread(handle), ResultInvalidHandle);
+        Ok(MAIN_THREAD_ID)
+    }
+
+    fn break_(reason: BreakReason, _arg: Address, _size: Size) -> Result<()> {
+        get_kernel().break_reason = Some(reason);
+        Ok(())
+    }
+
+    unsafe fn output_debug_string(msg: *const u8, len: Size) -> Result<()> {
+        result_return_if!(msg.is_null(), ResultInvalidAddress);
+        // Negative sizes are used as a no-op by some loggers
+        util_return_if!((len as isize) < 0, Ok(()));
+        let msg_bytes = core::slice::from_raw_parts(msg, len);
+        get_kernel().debug_output.push(String::from_utf8_lossy(msg_bytes).into_owned());
+        Ok(())
+    }
+
+    fn return_from_exception(rc: ResultCode) {
+        get_kernel().exception_rc = Some(rc);
+    }
+
+    fn get_info(id: InfoId, _handle: Handle, _sub_id: u64) -> Result<u64> {
+        let kernel = get_kernel();
+        match id {
+            InfoId::HeapRegionAddress => Ok(kernel.heap as u64),
+            InfoId::HeapRegionSize => Ok(kernel.heap_size as u64),
+            InfoId::DebuggerAttached => Ok(0),
+            InfoId::RandomEntropy => {
+                kernel.tick += TICKS_PER_CALL;
+                Ok(kernel.tick.wrapping_mul(0x5851F42D4C957F2D))
+            },
+            InfoId::IsApplication => Ok(1),
+            _ => Err(ResultCode::from::<ResultNotImplemented>()),
+        }
+    }
+
+    fn wait_for_address(address: Address, arbitration_type: ArbitrationType, value: i32, _timeout: i64) -> Result<()> {
+        result_return_if!(address.is_null(), ResultInvalidAddress);
+        let cur_value = unsafe { *(address as *mut i32) };
+        let should_wait = match arbitration_type {
+            ArbitrationType::WaitIfLessThan => cur_value < value,
+            ArbitrationType::DecrementAndWaitIfLessThan => {
+                if cur_value < value {
+                    unsafe {
+                        *(address as *mut i32) = cur_value - 1;
+                    }
+                }
+                cur_value < value
+            },
+            ArbitrationType::WaitIfEqual => cur_value == value,
+        };
+        result_return_if!(should_wait, ResultTimedOut);
+        Err(ResultCode::from::<ResultInvalidState>())
+    }
+
+    fn signal_to_address(address: Address, signal_type: SignalType, value: i32, _count: i32) -> Result<()> {
+        result_return_if!(address.is_null(), ResultInvalidAddress);
+        let cur_value = unsafe { *(address as *mut i32) };
+        match signal_type {
+            SignalType::Signal => Ok(()),
+            _ => {
+                result_return_unless!(cur_value == value, ResultInvalidState);
+                // No waiters are ever present, so both modifying variants just increment
+                unsafe {
+                    *(address as *mut i32) = cur_value + 1;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn create_session(_is_light: bool, _name: u64) -> Result<(Handle, Handle)> {
+        let kernel = get_kernel();
+        let session = mem::make_shared(MockSession::new());
+        let server_handle = kernel.add_handle(HandleObject::ServerSession(session.clone()));
+        let client_handle = kernel.add_handle(HandleObject::ClientSession(session));
+        Ok((server_handle, client_handle))
+    }
+
+    fn accept_session(port_handle: Handle) -> Result<Handle> {
+        let kernel = get_kernel();
+        let port = match kernel.handles.get(&port_handle) {
+            Some(HandleObject::Port(port)) => port.clone(),
+            _ => return Err(ResultCode::from::<ResultInvalidHandle>()),
+        };
+        let session = match port.borrow_mut().pop_front() {
+            Some(session) => session,
+            None => return Err(ResultCode::from::<ResultNotFound>()),
+        };
+        Ok(kernel.add_handle(HandleObject::ServerSession(session)))
+    }
+
+    unsafe fn reply_and_receive(handles: *const Handle, handle_count: u32, reply_target: Handle, timeout: i64) -> Result<i32> {
+        let kernel = get_kernel();
+        if reply_target != INVALID_HANDLE {
+            let session = kernel.get_server_session(reply_target)?;
+            let mut session_ref = session.borrow_mut();
+            result_return_if!(session_ref.client_closed, ResultSessionClosed);
+            session_ref.reply = Some(read_ipc_buffer());
+        }
+
+        // Requests are received as they were sent: being the same process, their send statics are left pointing to the client's memory
+        for i in 0..handle_count {
+            let session = kernel.get_server_session(*handles.offset(i as isize))?;
+            let mut session_ref = session.borrow_mut();
+            if let Some(request) = session_ref.request.take() {
+                write_ipc_buffer(&request);
+                return Ok(i as i32);
+            }
+            result_return_if!(session_ref.client_closed, ResultSessionClosed);
+        }
+        kernel.advance_tick(timeout);
+        Err(ResultCode::from::<ResultTimedOut>())
+    }
+
+    fn create_event() -> Result<(Handle, Handle)> {
+        let kernel = get_kernel();
+        let signaled = mem::make_shared(false);
+        let writable_handle = kernel.add_handle(HandleObject::Event(signaled.clone()));
+        let readable_handle = kernel.add_handle(HandleObject::Event(signaled));
+        Ok((writable_handle, readable_handle))
+    }
+
+    fn map_transfer_memory(handle: Handle, _address: Address, _size: Size, _permissions: BitFlags<MemoryPermission>) -> Result<()> {
+        result_return_unless!(get_transfer_memory(handle).is_some(), ResultInvalidHandle);
+        Err(ResultCode::from::<ResultNotImplemented>())
+    }
+
+    fn unmap_transfer_memory(handle: Handle, _address: Address, _size: Size) -> Result<()> {
+        result_return_unless!(get_transfer_memory(handle).is_some(), ResultInvalidHandle);
+        Err(ResultCode::from::<ResultNotImplemented>())
+    }
+
+    unsafe fn manage_named_port(name: *const u8, _max_sessions: i32) -> Result<Handle> {
+        result_return_if!(name.is_null(), ResultInvalidAddress);
+        let kernel = get_kernel();
+        let port: MockPort = mem::make_shared(VecDeque::new());
+        kernel.managed_ports.insert(read_c_str(name), port.clone());
+        Ok(kernel.add_handle(HandleObject::Port(port)))
+    }
+
+    fn set_process_memory_permission(_process_handle: Handle, _address: Address, _size: Size, _permissions: BitFlags<MemoryPermission>) -> Result<()> {
+        Err(ResultCode::from::<ResultNotImplemented>())
+    }
+
+    fn map_process_code_memory(_process_handle: Handle, _address: Address, _source_address: Address, _size: Size) -> Result<()> {
+        Err(ResultCode::from::<ResultNotImplemented>())
+    }
+
+    fn unmap_process_code_memory(_process_handle: Handle, _address: Address, _source_address: Address, _size: Size) -> Result<()> {
+        Err(ResultCode::from::<ResultNotImplemented>())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::svc;
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    #[test]
+    fn events_are_signaled_and_reset() {
+        reset();
+        let (writable_handle, readable_handle) = svc::create_event().unwrap();
+        let handles = [writable_handle, readable_handle];
+
+        let rc = unsafe { svc::wait_synchronization(handles.as_ptr(), 2, 1000) }.unwrap_err();
+        assert!(rc.matches::<ResultTimedOut>());
+
+        svc::signal_event(writable_handle).unwrap();
+        assert_eq!(unsafe { svc::wait_synchronization(&readable_handle, 1, 0) }.unwrap(), 0);
+        assert!(is_event_signaled(readable_handle).unwrap());
+
+        svc::reset_signal(readable_handle).unwrap();
+        assert!(!is_event_signaled(writable_handle).unwrap());
+        assert!(svc::reset_signal(readable_handle).unwrap_err().matches::<ResultInvalidState>());
+    }
+
+    #[test]
+    fn named_port_sessions_dispatch_requests() {
+        reset();
+        let request_count = Rc::new(Cell::new(0));
+        let handler_request_count = request_count.clone();
+        register_named_port(nul!("test:"), move |_| {
+            handler_request_count.set(handler_request_count.get() + 1);
+            Ok(())
+        });
+
+        let handle = unsafe { svc::connect_to_named_port(nul!("test:").as_ptr()) }.unwrap();
+        svc::send_sync_request(handle).unwrap();
+        svc::send_sync_request(handle).unwrap();
+        assert_eq!(request_count.get(), 2);
+
+        svc::close_handle(handle).unwrap();
+        assert!(!is_handle_open(handle));
+        assert!(svc::send_sync_request(handle).unwrap_err().matches::<ResultSessionClosed>());
+        assert!(unsafe { svc::connect_to_named_port(nul!("unknown:").as_ptr()) }.unwrap_err().matches::<ResultNotFound>());
+    }
+
+    #[test]
+    fn server_sessions_receive_posted_requests() {
+        reset();
+        let port_handle = unsafe { svc::manage_named_port(nul!("srv:").as_ptr(), 1) }.unwrap();
+        let client_handle = unsafe { svc::connect_to_named_port(nul!("srv:").as_ptr()) }.unwrap();
+        assert_eq!(unsafe { svc::wait_synchronization(&port_handle, 1, 0) }.unwrap(), 0);
+        let server_handle = svc::accept_session(port_handle).unwrap();
+        assert!(svc::accept_session(port_handle).unwrap_err().matches::<ResultNotFound>());
+
+        let rc = unsafe { svc::reply_and_receive(&server_handle, 1, INVALID_HANDLE, 0) }.unwrap_err();
+        assert!(rc.matches::<ResultTimedOut>());
+
+        write_ipc_buffer(&[0xAB; 0x100]);
+        post_request(client_handle).unwrap();
+        write_ipc_buffer(&[0; 0x100]);
+        assert_eq!(unsafe { svc::reply_and_receive(&server_handle, 1, INVALID_HANDLE, 0) }.unwrap(), 0);
+        assert_eq!(read_ipc_buffer(), vec![0xAB; 0x100]);
+
+        write_ipc_buffer(&[0xCD; 0x100]);
+        let rc = unsafe { svc::reply_and_receive(ptr::null(), 0, server_handle, 0) }.unwrap_err();
+        assert!(rc.matches::<ResultTimedOut>());
+        write_ipc_buffer(&[0; 0x100]);
+        take_reply(client_handle).unwrap();
+        assert_eq!(read_ipc_buffer(), vec![0xCD; 0x100]);
+        assert!(take_reply(client_handle).unwrap_err().matches::<ResultNotFound>());
+
+        svc::close_handle(client_handle).unwrap();
+        let rc = unsafe { svc::reply_and_receive(&server_handle, 1, INVALID_HANDLE, 0) }.unwrap_err();
+        assert!(rc.matches::<ResultSessionClosed>());
+
+        svc::close_handle(port_handle).unwrap();
+        assert!(unsafe { svc::connect_to_named_port(nul!("srv:").as_ptr()) }.unwrap_err().matches::<ResultNotFound>());
+    }
+
+    #[test]
+    fn heap_is_reported_by_get_info() {
+        reset();
+        let heap = svc::set_heap_size(0x200000).unwrap();
+        assert!(!heap.is_null());
+        assert_eq!(svc::get_info(InfoId::HeapRegionAddress, INVALID_HANDLE, 0).unwrap(), heap as u64);
+        assert_eq!(svc::get_info(InfoId::HeapRegionSize, INVALID_HANDLE, 0).unwrap(), 0x200000);
+
+        svc::set_heap_size(0).unwrap();
+        assert_eq!(svc::get_info(InfoId::HeapRegionSize, INVALID_HANDLE, 0).unwrap(), 0);
+    }
+
+    #[test]
+    fn heap_grows_in_place() {
+        reset();
+        let heap = svc::set_heap_size(0x200000).unwrap();
+        unsafe {
+            *heap = 0xAB;
+        }
+        assert_eq!(svc::set_heap_size(0x600000).unwrap(), heap);
+        unsafe {
+            assert_eq!(*heap, 0xAB);
+            assert_eq!(*heap.offset(0x5FFFFF), 0);
+        }
+
+        assert!(svc::set_heap_size(0x1234).unwrap_err().matches::<ResultInvalidSize>());
+        assert!(svc::set_heap_size(HEAP_REGION_SIZE + HEAP_SIZE_ALIGNMENT).unwrap_err().matches::<ResultOutOfMemory>());
+    }
+
+    #[test]
+    fn debug_output_is_captured() {
+        reset();
+        let msg = "Hello from the mock";
+        unsafe {
+            svc::output_debug_string(msg.as_ptr(), msg.len()).unwrap();
+        }
+        assert_eq!(take_debug_output(), vec![String::from(msg)]);
+        assert!(take_debug_output().is_empty());
+    }
+
+    #[test]
+    fn host_threads_get_separate_processes() {
+        reset();
+        let (writable_handle, _) = svc::create_event().unwrap();
+        assert_eq!(get_open_handle_count(), 2);
+
+        let other_thread = std::thread::spawn(move || {
+            (get_open_handle_count(), is_handle_open(writable_handle))
+        });
+        assert_eq!(other_thread.join().unwrap(), (0, false));
+        assert_eq!(get_open_handle_count(), 2);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,406 @@
+use crate::result::*;
+use enumflags2::BitFlags;
+
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum BreakReason {
+    Panic = 0,
+    Assert = 1,
+    User = 2,
+    PreLoadDll = 3,
+    PostLoadDll = 4,
+    PreUnloadDll = 5,
+    PostUnloadDll = 6,
+    CppException = 7,
+    NotificationOnlyFlag = 0x80000000
+}
+
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum MemoryState {
+    Free = 0x0,
+    Io = 0x1,
+    Static = 0x2,
+    Code = 0x3,
+    CodeData = 0x4,
+    Normal = 0x5,
+    Shared = 0x6,
+    Alias = 0x7,
+    AliasCode = 0x8,
+    AliasCodeData = 0x9,
+    Ipc = 0xA,
+    Stack = 0xB,
+    ThreadLocal = 0xC,
+    Transfered = 0xD,
+    SharedTransfered = 0xE,
+    SharedCode = 0xF,
+    Inaccessible = 0x10,
+    NonSecureIpc = 0x11,
+    NonDeviceIpc = 0x12,
+    Kernel = 0x13,
+    GeneratedCode = 0x14,
+    CodeOut = 0x15
+}
+
+#[derive(BitFlags, Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum MemoryPermission {
+    Read = 0b1,
+    Write = 0b10,
+    Execute = 0b100,
+    DontCare = 0b10000000000000000000000000000,
+}
+
+#[derive(BitFlags, Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum MemoryAttribute {
+    Locked = 0b1,
+    IpcLocked = 0b10,
+    DeviceShared = 0b100,
+    Uncached = 0b1000,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct MemoryInfo {
+    pub base_address: *mut u8,
+    pub size: u64,
+    pub memory_state: MemoryState,
+    pub memory_attribute: MemoryAttribute,
+    pub memory_permission: MemoryPermission,
+    pub ipc_refcount: u32,
+    pub device_refcount: u32,
+    pub pad: u32,
+}
+
+pub type PageInfo = u32;
+pub type Address = *mut u8;
+pub type Size = usize;
+// The kernel jumps straight into it with the argument in x0, so it has to follow the C ABI
+pub type ThreadEntrypointFn = extern "C" fn(*mut u8);
+pub type Handle = u32;
+
+pub const INVALID_HANDLE: Handle = 0;
+
+pub const CURRENT_THREAD_PSEUDO_HANDLE: Handle = 0xFFFF8000;
+pub const CURRENT_PROCESS_PSEUDO_HANDLE: Handle = 0xFFFF8001;
+
+pub const INFINITE_TIMEOUT: i64 = -1;
+
+// wait_synchronization fails with ResultOutOfRange when given more handles than this
+pub const MAX_WAIT_HANDLE_COUNT: usize = 0x40;
+
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum InfoId {
+    CoreMask = 0,
+    PriorityMask = 1,
+    AliasRegionAddress = 2,
+    AliasRegionSize = 3,
+    HeapRegionAddress = 4,
+    HeapRegionSize = 5,
+    TotalMemorySize = 6,
+    UsedMemorySize = 7,
+    DebuggerAttached = 8,
+    ResourceLimit = 9,
+    IdleTickCount = 10,
+    RandomEntropy = 11,
+    AslrRegionAddress = 12,
+    AslrRegionSize = 13,
+    StackRegionAddress = 14,
+    StackRegionSize = 15,
+    SystemResourceSizeTotal = 16,
+    SystemResourceSizeUsed = 17,
+    ProgramId = 18,
+    InitialProcessIdRange = 19,
+    UserExceptionContextAddress = 20,
+    TotalNonSystemMemorySize = 21,
+    UsedNonSystemMemorySize = 22,
+    IsApplication = 23,
+    FreeThreadCount = 24,
+    ThreadTickCount = 25
+}
+
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum ArbitrationType {
+    WaitIfLessThan = 0,
+    DecrementAndWaitIfLessThan = 1,
+    WaitIfEqual = 2
+}
+
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum SignalType {
+    Signal = 0,
+    SignalAndIncrementIfEqual = 1,
+    SignalAndModifyByWaitingCountIfEqual = 2
+}
+
+pub trait Backend {
+    fn set_heap_size(size: Size) -> Result<Address>;
+    fn set_memory_attribute(address: Address, size: Size, mask: u32, value: BitFlags<MemoryAttribute>) -> Result<()>;
+    fn map_memory(address: Address, source_address: Address, size: Size) -> Result<()>;
+    fn unmap_memory(address: Address, source_address: Address, size: Size) -> Result<()>;
+    unsafe fn query_memory(out_info: *mut MemoryInfo, address: *const u8) -> Result<PageInfo>;
+    fn exit_process();
+    fn create_thread(entry: ThreadEntrypointFn, entry_arg: *mut u8, stack_top: Address, priority: i32, cpu_id: i32) -> Result<Handle>;
+    fn start_thread(handle: Handle) -> Result<()>;
+    fn exit_thread();
+    fn sleep_thread(timeout: i64) -> Result<()>;
+    fn get_thread_priority(handle: Handle) -> Result<i32>;
+    fn set_thread_priority(handle: Handle, priority: i32) -> Result<()>;
+    fn signal_event(handle: Handle) -> Result<()>;
+    fn clear_event(handle: Handle) -> Result<()>;
+    fn map_shared_memory(handle: Handle, address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<()>;
+    fn unmap_shared_memory(handle: Handle, address: Address, size: Size) -> Result<()>;
+    fn create_transfer_memory(address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<Handle>;
+    fn close_handle(handle: Handle) -> Result<()>;
+    fn reset_signal(handle: Handle) -> Result<()>;
+    unsafe fn wait_synchronization(handles: *const Handle, handle_count: u32, timeout: i64) -> Result<i32>;
+    fn cancel_synchronization(handle: Handle) -> Result<()>;
+    fn arbitrate_lock(thread_handle: u32, address: Address, tag: u32) -> Result<()>;
+    fn arbitrate_unlock(address: Address) -> Result<()>;
+    fn wait_process_wide_key_atomic(address: Address, cv_key: Address, tag: u32, timeout: i64) -> Result<()>;
+    fn signal_process_wide_key(cv_key: Address, count: i32);
+    fn get_system_tick() -> u64;
+    unsafe fn connect_to_named_port(name: *const u8) -> Result<Handle>;
+    fn send_sync_request(handle: Handle) -> Result<()>;
+    unsafe fn send_sync_request_with_user_buffer(buffer: Address, size: Size, handle: Handle) -> Result<()>;
+    fn get_process_id(process_handle: Handle) -> Result<u64>;
+    fn get_thread_id(handle: Handle) -> Result<u64>;
+    fn break_(reason: BreakReason, arg: Address, size: Size) -> Result<()>;
+    unsafe fn output_debug_string(msg: *const u8, len: Size) -> Result<()>;
+    fn return_from_exception(rc: ResultCode);
+    fn get_info(id: InfoId, handle: Handle, sub_id: u64) -> Result<u64>;
+    fn wait_for_address(address: Address, arbitration_type: ArbitrationType, value: i32, timeout: i64) -> Result<()>;
+    fn signal_to_address(address: Address, signal_type: SignalType, value: i32, count: i32) -> Result<()>;
+    fn create_session(is_light: bool, name: u64) -> Result<(Handle, Handle)>;
+    fn accept_session(port_handle: Handle) -> Result<Handle>;
+    unsafe fn reply_and_receive(handles: *const Handle, handle_count: u32, reply_target: Handle, timeout: i64) -> Result<i32>;
+    fn create_event() -> Result<(Handle, Handle)>;
+    fn map_transfer_memory(handle: Handle, address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<()>;
+    fn unmap_transfer_memory(handle: Handle, address: Address, size: Size) -> Result<()>;
+    unsafe fn manage_named_port(name: *const u8, max_sessions: i32) -> Result<Handle>;
+    fn set_process_memory_permission(process_handle: Handle, address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<()>;
+    fn map_process_code_memory(process_handle: Handle, address: Address, source_address: Address, size: Size) -> Result<()>;
+    fn unmap_process_code_memory(process_handle: Handle, address: Address, source_address: Address, size: Size) -> Result<()>;
+}
+
+pub mod handle;
+
+#[cfg(not(feature = "mock-svc"))]
+pub mod asm;
+
+#[cfg(not(feature = "mock-svc"))]
+pub type CurrentBackend = asm::AsmBackend;
+
+#[cfg(feature = "mock-svc")]
+pub mod mock;
+
+#[cfg(feature = "mock-svc")]
+pub type CurrentBackend = mock::MockBackend;
+
+pub fn set_heap_size(size: Size) -> Result<Address> {
+    CurrentBackend::set_heap_size(size)
+}
+
+pub fn set_memory_attribute(address: Address, size: Size, mask: u32, value: BitFlags<MemoryAttribute>) -> Result<()> {
+    CurrentBackend::set_memory_attribute(address, size, mask, value)
+}
+
+pub fn map_memory(address: Address, source_address: Address, size: Size) -> Result<()> {
+    CurrentBackend::map_memory(address, source_address, size)
+}
+
+pub fn unmap_memory(address: Address, source_address: Address, size: Size) -> Result<()> {
+    CurrentBackend::unmap_memory(address, source_address, size)
+}
+
+pub unsafe fn query_memory(out_info: *mut MemoryInfo, address: *const u8) -> Result<PageInfo> {
+    CurrentBackend::query_memory(out_info, address)
+}
+
+pub fn exit_process() {
+    CurrentBackend::exit_process()
+}
+
+pub fn create_thread(entry: ThreadEntrypointFn, entry_arg: *mut u8, stack_top: Address, priority: i32, cpu_id: i32) -> Result<Handle> {
+    CurrentBackend::create_thread(entry, entry_arg, stack_top, priority, cpu_id)
+}
+
+pub fn start_thread(handle: Handle) -> Result<()> {
+    CurrentBackend::start_thread(handle)
+}
+
+pub fn exit_thread() {
+    CurrentBackend::exit_thread()
+}
+
+pub fn sleep_thread(timeout: i64) -> Result<()> {
+    CurrentBackend::sleep_thread(timeout)
+}
+
+pub fn get_thread_priority(handle: Handle) -> Result<i32> {
+    CurrentBackend::get_thread_priority(handle)
+}
+
+pub fn set_thread_priority(handle: Handle, priority: i32) -> Result<()> {
+    CurrentBackend::set_thread_priority(handle, priority)
+}
+
+pub fn signal_event(handle: Handle) -> Result<()> {
+    CurrentBackend::signal_event(handle)
+}
+
+pub fn clear_event(handle: Handle) -> Result<()> {
+    CurrentBackend::clear_event(handle)
+}
+
+pub fn map_shared_memory(handle: Handle, address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<()> {
+    CurrentBackend::map_shared_memory(handle, address, size, permissions)
+}
+
+pub fn unmap_shared_memory(handle: Handle, address: Address, size: Size) -> Result<()> {
+    CurrentBackend::unmap_shared_memory(handle, address, size)
+}
+
+pub fn create_transfer_memory(address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<Handle> {
+    CurrentBackend::create_transfer_memory(address, size, permissions)
+}
+
+pub fn close_handle(handle: Handle) -> Result<()> {
+    CurrentBackend::close_handle(handle)
+}
+
+pub fn reset_signal(handle: Handle) -> Result<()> {
+    CurrentBackend::reset_signal(handle)
+}
+
+pub unsafe fn wait_synchronization(handles: *const Handle, handle_count: u32, timeout: i64) -> Result<i32> {
+    CurrentBackend::wait_synchronization(handles, handle_count, timeout)
+}
+
+pub fn cancel_synchronization(handle: Handle) -> Result<()> {
+    CurrentBackend::cancel_synchronization(handle)
+}
+
+pub fn arbitrate_lock(thread_handle: u32, address: Address, tag: u32) -> Result<()> {
+    CurrentBackend::arbitrate_lock(thread_handle, address, tag)
+}
+
+pub fn arbitrate_unlock(address: Address) -> Result<()> {
+    CurrentBackend::arbitrate_unlock(address)
+}
+
+pub fn wait_process_wide_key_atomic(address: Address, cv_key: Address, tag: u32, timeout: i64) -> Result<()> {
+    CurrentBackend::wait_process_wide_key_atomic(address, cv_key, tag, timeout)
+}
+
+pub fn signal_process_wide_key(cv_key: Address, count: i32) {
+    CurrentBackend::signal_process_wide_key(cv_key, count)
+}
+
+pub fn get_system_tick() -> u64 {
+    CurrentBackend::get_system_tick()
+}
+
+pub unsafe fn connect_to_named_port(name: *const u8) -> Result<Handle> {
+    CurrentBackend::connect_to_named_port(name)
+}
+
+pub fn send_sync_request(handle: Handle) -> Result<()> {
+    CurrentBackend::send_sync_request(handle)
+}
+
+pub unsafe fn send_sync_request_with_user_buffer(buffer: Address, size: Size, handle: Handle) -> Result<()> {
+    CurrentBackend::send_sync_request_with_user_buffer(buffer, size, handle)
+}
+
+pub fn get_process_id(process_handle: Handle) -> Result<u64> {
+    CurrentBackend::get_process_id(process_handle)
+}
+
+pub fn get_thread_id(handle: Handle) -> Result<u64> {
+    CurrentBackend::get_thread_id(handle)
+}
+
+pub fn break_(reason: BreakReason, arg: Address, size: Size) -> Result<()> {
+    CurrentBackend::break_(reason, arg, size)
+}
+
+pub unsafe fn output_debug_string(msg: *const u8, len: Size) -> Result<()> {
+    CurrentBackend::output_debug_string(msg, len)
+}
+
+pub fn return_from_exception(rc: ResultCode) {
+    CurrentBackend::return_from_exception(rc)
+}
+
+pub fn get_info(id: InfoId, handle: Handle, sub_id: u64) -> Result<u64> {
+    CurrentBackend::get_info(id, handle, sub_id)
+}
+
+pub fn wait_for_address(address: Address, arbitration_type: ArbitrationType, value: i32, timeout: i64) -> Result<()> {
+    CurrentBackend::wait_for_address(address, arbitration_type, value, timeout)
+}
+
+pub fn signal_to_address(address: Address, signal_type: SignalType, value: i32, count: i32) -> Result<()> {
+    CurrentBackend::signal_to_address(address, signal_type, value, count)
+}
+
+pub fn create_session(is_light: bool, name: u64) -> Result<(Handle, Handle)> {
+    CurrentBackend::create_session(is_light, name)
+}
+
+pub fn accept_session(port_handle: Handle) -> Result<Handle> {
+    CurrentBackend::accept_session(port_handle)
+}
+
+pub unsafe fn reply_and_receive(handles: *const Handle, handle_count: u32, reply_target: Handle, timeout: i64) -> Result<i32> {
+    CurrentBackend::reply_and_receive(handles, handle_count, reply_target, timeout)
+}
+
+pub fn create_event() -> Result<(Handle, Handle)> {
+    CurrentBackend::create_event()
+}
+
+pub fn map_transfer_memory(handle: Handle, address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<()> {
+    CurrentBackend::map_transfer_memory(handle, address, size, permissions)
+}
+
+pub fn unmap_transfer_memory(handle: Handle, address: Address, size: Size) -> Result<()> {
+    CurrentBackend::unmap_transfer_memory(handle, address, size)
+}
+
+pub unsafe fn manage_named_port(name: *const u8, max_sessions: i32) -> Result<Handle> {
+    CurrentBackend::manage_named_port(name, max_sessions)
+}
+
+pub fn set_process_memory_permission(process_handle: Handle, address: Address, size: Size, permissions: BitFlags<MemoryPermission>) -> Result<()> {
+    CurrentBackend::set_process_memory_permission(process_handle, address, size, permissions)
+}
+
+pub fn map_process_code_memory(process_handle: Handle, address: Address, source_address: Address, size: Size) -> Result<()> {
+    CurrentBackend::map_process_code_memory(process_handle, address, source_address, size)
+}
+
+pub fn unmap_process_code_memory(process_handle: Handle, address: Address, source_address: Address, size: Size) -> Result<()> {
+    CurrentBackend::unmap_process_code_memory(process_handle, address, source_address, size)
+}
+
+result_define_group!(1 => {
+    ResultNotImplemented: 33,
+    ResultInvalidSize: 101,
+    ResultInvalidAddress: 102,
+    ResultOutOfMemory: 104,
+    ResultInvalidHandle: 114,
+    ResultTimedOut: 117,
+    ResultOutOfRange: 119,
+    ResultCancelled: 118,
+    ResultNotFound: 121,
+    ResultSessionClosed: 123,
+    ResultUnhandledException: 124,
+    ResultInvalidState: 125,
+    ResultFatalException: 128
+});
\ No newline at end of file
@@ -1,19 +1,33 @@
+use crate::result::*;
+use crate::diag::assert;
 use crate::svc;
 use crate::thread;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
 
-pub struct Mutex {
-    value: u32,
+pub struct RawMutex {
+    value: UnsafeCell<u32>,
     is_recursive: bool,
-    counter: u32,
-    thread_handle: u32,
+    counter: UnsafeCell<u32>,
+    thread_handle: UnsafeCell<u32>,
 }
 
+unsafe impl Sync for RawMutex {}
+unsafe impl Send for RawMutex {}
+
 const HANDLE_WAIT_MASK: u32 = 0x40000000;
 
 fn get_current_thread_handle() -> u32 {
-    thread::get_current_thread().get_handle()
+    // The kernel needs the owner's real handle, which threads without a thread object have no way to get
+    match thread::get_current_thread_handle() {
+        Ok(handle) => handle,
+        Err(rc) => assert::assert(assert::AssertMode::SvcBreak, rc),
+    }
 }
 
+#[cfg(not(feature = "mock-svc"))]
 fn load_exclusive(ptr: *mut u32) -> u32 {
     let value: u32;
     unsafe {
@@ -22,6 +36,7 @@ fn load_exclusive(ptr: *mut u32) -> u32 {
     value
 }
 
+#[cfg(not(feature = "mock-svc"))]
 fn store_exclusive(ptr: *mut u32, value: u32) -> i32 {
     let res: i32;
     unsafe {
@@ -30,12 +45,34 @@ fn store_exclusive(ptr: *mut u32, value: u32) -> i32 {
     res
 }
 
+#[cfg(not(feature = "mock-svc"))]
 fn clear_exclusive() {
     unsafe {
         llvm_asm!("clrex" ::: "memory" : "volatile");
     }
 }
 
+// The mock backend only emulates a single thread, so plain volatile accesses are enough there
+
+#[cfg(feature = "mock-svc")]
+fn load_exclusive(ptr: *mut u32) -> u32 {
+    unsafe {
+        core::ptr::read_volatile(ptr)
+    }
+}
+
+#[cfg(feature = "mock-svc")]
+fn store_exclusive(ptr: *mut u32, value: u32) -> i32 {
+    unsafe {
+        core::ptr::write_volatile(ptr, value);
+    }
+    0
+}
+
+#[cfg(feature = "mock-svc")]
+fn clear_exclusive() {
+}
+
 fn lock_impl(handle_ref: *mut u32) {
     let thr_handle = get_current_thread_handle();
     
@@ -104,103 +141,600 @@ fn try_lock_impl(handle_ref: *mut u32) -> bool {
     false
 }
 
-impl Mutex {
+impl RawMutex {
     pub const fn new(recursive: bool) -> Self {
-        Self { value: 0, is_recursive: recursive, counter: 0, thread_handle: 0 }
+        Self { value: UnsafeCell::new(0), is_recursive: recursive, counter: UnsafeCell::new(0), thread_handle: UnsafeCell::new(0) }
     }
 
-    pub fn lock(&mut self) {
+    // The counter and owner fields are only ever accessed by the thread holding the lock
+
+    pub fn lock(&self) {
         let mut do_lock = true;
         if self.is_recursive {
             do_lock = false;
             let thr_handle = get_current_thread_handle();
-            if self.thread_handle != thr_handle {
-                do_lock = true;
-                self.thread_handle = thr_handle;
+            unsafe {
+                if *self.thread_handle.get() != thr_handle {
+                    lock_impl(self.value.get());
+                    *self.thread_handle.get() = thr_handle;
+                }
+                *self.counter.get() += 1;
             }
-            self.counter += 1;
         }
 
         if do_lock {
-            lock_impl(&mut self.value);
+            lock_impl(self.value.get());
         }
     }
 
-    pub fn unlock(&mut self) {
+    pub fn unlock(&self) {
         let mut do_unlock = true;
         if self.is_recursive {
             do_unlock = false;
-            self.counter -= 1;
-            if self.counter == 0 {
-                self.thread_handle = 0;
-                do_unlock = true;
+            unsafe {
+                *self.counter.get() -= 1;
+                if *self.counter.get() == 0 {
+                    *self.thread_handle.get() = 0;
+                    do_unlock = true;
+                }
             }
         }
 
         if do_unlock {
-            unlock_impl(&mut self.value);
+            unlock_impl(self.value.get());
         }
     }
 
-    pub fn try_lock(&mut self) -> bool {
+    pub fn try_lock(&self) -> bool {
         if self.is_recursive {
             let thr_handle = get_current_thread_handle();
-            if self.thread_handle != thr_handle {
-                if !try_lock_impl(&mut self.value) {
-                    return false;
+            unsafe {
+                if *self.thread_handle.get() != thr_handle {
+                    if !try_lock_impl(self.value.get()) {
+                        return false;
+                    }
+                    *self.thread_handle.get() = thr_handle;
                 }
-                self.thread_handle = thr_handle;
+                *self.counter.get() += 1;
             }
-            self.counter += 1;
             true
         }
         else {
-            try_lock_impl(&mut self.value)
+            try_lock_impl(self.value.get())
         }
     }
+
+    pub fn is_recursive(&self) -> bool {
+        self.is_recursive
+    }
 }
 
-pub struct ScopedLock {
-    lock: &'static mut Mutex,
+pub struct Mutex<T: ?Sized> {
+    raw: RawMutex,
+    object: UnsafeCell<T>,
 }
 
-impl ScopedLock {
-    pub fn new(lock: &'static mut Mutex) -> Self {
-        lock.lock();
-        Self { lock: lock }
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(t: T) -> Self {
+        Self { raw: RawMutex::new(false), object: UnsafeCell::new(t) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.object.into_inner()
     }
 }
 
-impl Drop for ScopedLock {
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<T> {
+        self.raw.lock();
+        MutexGuard { mutex: self, _no_send: PhantomData }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        match self.raw.try_lock() {
+            true => Some(MutexGuard { mutex: self, _no_send: PhantomData }),
+            false => None,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe {
+            &mut *self.object.get()
+        }
+    }
+}
+
+// Guards can't be sent to other threads, since the kernel only lets the owning thread unlock the mutex
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    _no_send: PhantomData<*const ()>,
+}
+
+unsafe impl<'a, T: ?Sized + Sync> Sync for MutexGuard<'a, T> {}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {
+            &*self.mutex.object.get()
+        }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {
+            &mut *self.mutex.object.get()
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.unlock();
+        self.mutex.raw.unlock();
     }
 }
 
-pub struct Locked<T> {
-    lock: Mutex,
+// Since the same thread can hold several guards at once, only shared access is given (use cells for mutation)
+pub struct RecursiveMutex<T: ?Sized> {
+    raw: RawMutex,
     object: T,
 }
 
-impl<T> Locked<T> {
-    pub const fn new(recursive: bool, t: T) -> Self {
-        Self { lock: Mutex::new(recursive), object: t }
+unsafe impl<T: ?Sized + Send> Sync for RecursiveMutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for RecursiveMutex<T> {}
+
+impl<T> RecursiveMutex<T> {
+    pub const fn new(t: T) -> Self {
+        Self { raw: RawMutex::new(true), object: t }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.object
+    }
+}
+
+impl<T: ?Sized> RecursiveMutex<T> {
+    pub fn lock(&self) -> RecursiveMutexGuard<T> {
+        self.raw.lock();
+        RecursiveMutexGuard { mutex: self, _no_send: PhantomData }
     }
 
-    pub fn get(&mut self) -> &T {
-        self.lock.lock();
-        let obj_ref = &self.object;
-        self.lock.unlock();
-        obj_ref
+    pub fn try_lock(&self) -> Option<RecursiveMutexGuard<T>> {
+        match self.raw.try_lock() {
+            true => Some(RecursiveMutexGuard { mutex: self, _no_send: PhantomData }),
+            false => None,
+        }
+    }
+}
+
+pub struct RecursiveMutexGuard<'a, T: ?Sized> {
+    mutex: &'a RecursiveMutex<T>,
+    _no_send: PhantomData<*const ()>,
+}
+
+unsafe impl<'a, T: ?Sized + Sync> Sync for RecursiveMutexGuard<'a, T> {}
+
+impl<'a, T: ?Sized> Deref for RecursiveMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.mutex.object
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RecursiveMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.raw.unlock();
+    }
+}
+
+pub struct Condvar {
+    value: UnsafeCell<u32>,
+}
+
+unsafe impl Sync for Condvar {}
+unsafe impl Send for Condvar {}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self { value: UnsafeCell::new(0) }
+    }
+
+    pub fn wait_timeout<T>(&self, guard: &mut MutexGuard<T>, timeout: i64) -> Result<()> {
+        let mutex_value = guard.mutex.raw.value.get();
+        match svc::wait_process_wide_key_atomic(mutex_value as *mut u8, self.value.get() as *mut u8, get_current_thread_handle(), timeout) {
+            Err(rc) if rc.matches::<svc::ResultTimedOut>() => {
+                // The kernel doesn't reacquire the mutex on timeout
+                lock_impl(mutex_value);
+                Err(rc)
+            },
+            rc => rc,
+        }
+    }
+
+    pub fn wait<T>(&self, guard: &mut MutexGuard<T>) -> Result<()> {
+        self.wait_timeout(guard, svc::INFINITE_TIMEOUT)
+    }
+
+    pub fn wake(&self, count: i32) {
+        svc::signal_process_wide_key(self.value.get() as *mut u8, count);
+    }
+
+    pub fn wake_one(&self) {
+        self.wake(1);
+    }
+
+    pub fn wake_all(&self) {
+        self.wake(-1);
+    }
+}
+
+pub struct Semaphore {
+    count: Mutex<u64>,
+    condvar: Condvar,
+}
+
+impl Semaphore {
+    pub const fn new(count: u64) -> Self {
+        Self { count: Mutex::new(count), condvar: Condvar::new() }
+    }
+
+    pub fn signal(&self) {
+        let mut count = self.count.lock();
+        *count += 1;
+        self.condvar.wake_one();
+    }
+
+    pub fn wait(&self) {
+        let mut count = self.count.lock();
+        while *count == 0 {
+            let _ = self.condvar.wait(&mut count);
+        }
+        *count -= 1;
+    }
+
+    pub fn try_wait(&self) -> bool {
+        let mut count = self.count.lock();
+        let acquired = *count > 0;
+        if acquired {
+            *count -= 1;
+        }
+        acquired
+    }
+}
+
+struct RwLockState {
+    reader_count: u32,
+    writer_waiting_count: u32,
+    writer_active: bool,
+}
+
+pub struct RwLock<T: ?Sized> {
+    state: Mutex<RwLockState>,
+    reader_condvar: Condvar,
+    writer_condvar: Condvar,
+    object: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(t: T) -> Self {
+        Self { state: Mutex::new(RwLockState { reader_count: 0, writer_waiting_count: 0, writer_active: false }), reader_condvar: Condvar::new(), writer_condvar: Condvar::new(), object: UnsafeCell::new(t) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.object.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        let mut state = self.state.lock();
+        // Waiting writers go first, so that they don't get starved by readers
+        while state.writer_active || (state.writer_waiting_count > 0) {
+            let _ = self.reader_condvar.wait(&mut state);
+        }
+        state.reader_count += 1;
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        let mut state = self.state.lock();
+        match !state.writer_active && (state.writer_waiting_count == 0) {
+            true => {
+                state.reader_count += 1;
+                Some(RwLockReadGuard { lock: self })
+            },
+            false => None,
+        }
+    }
+
+    fn read_unlock(&self) {
+        let mut state = self.state.lock();
+        state.reader_count -= 1;
+        if (state.reader_count == 0) && (state.writer_waiting_count > 0) {
+            self.writer_condvar.wake_one();
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let mut state = self.state.lock();
+        state.writer_waiting_count += 1;
+        while state.writer_active || (state.reader_count > 0) {
+            let _ = self.writer_condvar.wait(&mut state);
+        }
+        state.writer_waiting_count -= 1;
+        state.writer_active = true;
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        let mut state = self.state.lock();
+        match !state.writer_active && (state.reader_count == 0) {
+            true => {
+                state.writer_active = true;
+                Some(RwLockWriteGuard { lock: self })
+            },
+            false => None,
+        }
+    }
+
+    fn write_unlock(&self) {
+        let mut state = self.state.lock();
+        state.writer_active = false;
+        if state.writer_waiting_count > 0 {
+            self.writer_condvar.wake_one();
+        }
+        else {
+            self.reader_condvar.wake_all();
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe {
+            &mut *self.object.get()
+        }
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {
+            &*self.lock.object.get()
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {
+            &*self.lock.object.get()
+        }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {
+            &mut *self.lock.object.get()
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
+
+struct BarrierState {
+    count: u32,
+    generation: u32,
+}
+
+pub struct Barrier {
+    state: Mutex<BarrierState>,
+    thread_count: u32,
+    condvar: Condvar,
+}
+
+impl Barrier {
+    pub const fn new(thread_count: u32) -> Self {
+        Self { state: Mutex::new(BarrierState { count: 0, generation: 0 }), thread_count: thread_count, condvar: Condvar::new() }
+    }
+
+    // Returns true for the thread which released the barrier
+    pub fn wait(&self) -> bool {
+        let mut state = self.state.lock();
+        state.count += 1;
+        let is_leader = state.count >= self.thread_count;
+        if is_leader {
+            state.count = 0;
+            state.generation = state.generation.wrapping_add(1);
+            self.condvar.wake_all();
+        }
+        else {
+            let generation = state.generation;
+            while generation == state.generation {
+                let _ = self.condvar.wait(&mut state);
+            }
+        }
+        is_leader
+    }
+}
+
+pub struct Once {
+    done: AtomicBool,
+    mutex: Mutex<()>,
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Self { done: AtomicBool::new(false), mutex: Mutex::new(()) }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        if self.is_completed() {
+            return;
+        }
+
+        let _guard = self.mutex.lock();
+        if !self.is_completed() {
+            f();
+            self.done.store(true, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_raw_value(raw: &RawMutex) -> u32 {
+        unsafe {
+            *raw.value.get()
+        }
+    }
+
+    fn get_recursion_depth(raw: &RawMutex) -> u32 {
+        unsafe {
+            *raw.counter.get()
+        }
+    }
+
+    #[test]
+    fn mutex_lock_and_try_lock() {
+        let mutex = Mutex::new(1);
+        {
+            let mut guard = mutex.lock();
+            *guard += 1;
+            assert_eq!(get_raw_value(&mutex.raw), get_current_thread_handle());
+            assert!(mutex.try_lock().is_none());
+        }
+        assert_eq!(get_raw_value(&mutex.raw), 0);
+
+        let guard = mutex.try_lock().unwrap();
+        assert_eq!(*guard, 2);
+        drop(guard);
+        assert_eq!(mutex.into_inner(), 2);
+    }
+
+    #[test]
+    fn recursive_mutex_tracks_depth() {
+        let mutex = RecursiveMutex::new(5);
+        let first = mutex.lock();
+        let second = mutex.lock();
+        let third = mutex.try_lock().unwrap();
+        assert_eq!(get_recursion_depth(&mutex.raw), 3);
+        assert_eq!(*first + *second + *third, 15);
+
+        drop(third);
+        drop(first);
+        assert_eq!(get_recursion_depth(&mutex.raw), 1);
+        assert_eq!(get_raw_value(&mutex.raw), get_current_thread_handle());
+        drop(second);
+        assert_eq!(get_recursion_depth(&mutex.raw), 0);
+        assert_eq!(get_raw_value(&mutex.raw), 0);
+        assert!(mutex.try_lock().is_some());
+    }
+
+    #[test]
+    fn unsized_mutexes() {
+        let mutex: &Mutex<[u32]> = &Mutex::new([1, 2, 3]);
+        mutex.lock()[1] = 4;
+        assert_eq!(&*mutex.lock(), &[1, 4, 3]);
+    }
+
+    #[test]
+    fn condvar_wait_relocks_on_timeout() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+        let mut guard = mutex.lock();
+        condvar.wake_all();
+        assert!(condvar.wait_timeout(&mut guard, 1000).unwrap_err().matches::<svc::ResultTimedOut>());
+        assert_eq!(get_raw_value(&mutex.raw), get_current_thread_handle());
+        drop(guard);
+        assert_eq!(get_raw_value(&mutex.raw), 0);
+    }
+
+    #[test]
+    fn semaphore_counts() {
+        let semaphore = Semaphore::new(1);
+        assert!(semaphore.try_wait());
+        assert!(!semaphore.try_wait());
+        semaphore.signal();
+        semaphore.signal();
+        semaphore.wait();
+        assert!(semaphore.try_wait());
+        assert!(!semaphore.try_wait());
+    }
+
+    #[test]
+    fn rwlock_readers_and_writers_exclude_each_other() {
+        let lock = RwLock::new(1);
+        {
+            let first = lock.read();
+            let second = lock.try_read().unwrap();
+            assert_eq!(*first + *second, 2);
+            assert!(lock.try_write().is_none());
+        }
+        {
+            let mut writer = lock.write();
+            *writer = 3;
+            assert!(lock.try_read().is_none());
+            assert!(lock.try_write().is_none());
+        }
+        assert_eq!(*lock.try_read().unwrap(), 3);
+        *lock.try_write().unwrap() += 1;
+        assert_eq!(lock.into_inner(), 4);
     }
 
-    pub fn set(&mut self, t: T) {
-        self.lock.lock();
-        self.object = t;
-        self.lock.unlock();
+    #[test]
+    fn barrier_releases_with_the_last_thread() {
+        let barrier = Barrier::new(1);
+        assert!(barrier.wait());
+        assert!(barrier.wait());
+        assert_eq!(barrier.state.lock().generation, 2);
+        assert_eq!(barrier.state.lock().count, 0);
     }
 
-    pub fn get_lock(&self) -> &Mutex {
-        &self.lock
+    #[test]
+    fn once_only_runs_once() {
+        let once = Once::new();
+        let mut call_count = 0;
+        assert!(!once.is_completed());
+        once.call_once(|| call_count += 1);
+        once.call_once(|| call_count += 1);
+        assert!(once.is_completed());
+        assert_eq!(call_count, 1);
     }
 }
\ No newline at end of file
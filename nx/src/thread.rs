@@ -8,13 +8,52 @@ pub enum ThreadState {
     Terminated = 4
 }
 
+extern crate alloc;
+
 use crate::result::*;
 use crate::svc;
 use crate::util;
+use crate::sync;
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
 use core::ptr;
 
 pub type ThreadName = [u8; 0x20];
 
+pub const RESULT_SUBMODULE: u32 = 11;
+
+result_lib_define_group!(RESULT_SUBMODULE => {
+    ResultInvalidState: 1,
+    ResultInvalidStack: 2,
+    ResultStackAllocationFailed: 3,
+    ResultNoResult: 4,
+    ResultNoFreeTlsSlots: 5,
+    ResultNoThreadObject: 6
+});
+
+pub const DEFAULT_STACK_SIZE: usize = 0x10000;
+pub const DEFAULT_PRIORITY: i32 = 0x2C;
+// Makes the kernel use the process's default core
+pub const DEFAULT_CPU_ID: i32 = -2;
+
+const STACK_ALIGNMENT: usize = 0x1000;
+
+extern "C" fn thread_entry_impl(thread_ref: *mut u8) {
+    let thread = thread_ref as *mut Thread;
+    unsafe {
+        set_current_thread(thread);
+        let entry: svc::ThreadEntrypointFn = core::mem::transmute((*thread).entry);
+        entry((*thread).entry_arg);
+        run_tls_destructors(&mut *thread);
+        unregister_thread(thread);
+        (*thread).state = ThreadState::Terminated;
+    }
+    svc::exit_thread();
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct Thread {
@@ -28,7 +67,9 @@ pub struct Thread {
     pub entry: *mut u8,
     pub entry_arg: *mut u8,
     pub tls_slots: [*mut u8; 0x20],
-    pub reserved: [u8; 0x54],
+    pub next: *mut Thread,
+    pub prev_next: *mut *mut Thread,
+    pub reserved: [u8; 0x44],
     pub name_len: u32,
     pub name: ThreadName,
     pub name_addr: *mut u8,
@@ -48,7 +89,9 @@ impl Thread {
             entry: ptr::null_mut(),
             entry_arg: ptr::null_mut(),
             tls_slots: [ptr::null_mut(); 0x20],
-            reserved: [0; 0x54],
+            next: ptr::null_mut(),
+            prev_next: ptr::null_mut(),
+            reserved: [0; 0x44],
             name_len: 0,
             name: [0; 0x20],
             name_addr: ptr::null_mut(),
@@ -56,31 +99,106 @@ impl Thread {
         }
     }
 
-    pub fn existing(handle: svc::Handle, name: &str, stack: *mut u8, stack_size: usize, owns_stack: bool) -> Result<Self> {
-        let mut thread = Self {
-            self_ref: ptr::null_mut(),
-            state: ThreadState::Started,
-            owns_stack: owns_stack,
-            pad: [0; 2],
-            handle: handle,
-            stack: stack,
-            stack_size: stack_size,
-            entry: ptr::null_mut(),
-            entry_arg: ptr::null_mut(),
-            tls_slots: [ptr::null_mut(); 0x20],
-            reserved: [0; 0x54],
-            name_len: 0,
-            name: [0; 0x20],
-            name_addr: ptr::null_mut(),
-            reserved_2: [0; 0x20],
-        };
-        thread.self_ref = &mut thread;
+    // Initialized in place, since the thread object refers to itself (it must not be moved afterwards)
+    pub fn initialize_existing(&mut self, handle: svc::Handle, name: &str, stack: *mut u8, stack_size: usize, owns_stack: bool) -> Result<()> {
+        *self = Self::new();
+        self.self_ref = self;
+        self.state = ThreadState::Started;
+        self.owns_stack = owns_stack;
+        self.handle = handle;
+        self.stack = stack;
+        self.stack_size = stack_size;
+        self.name_addr = &mut self.name as *mut ThreadName as *mut u8;
+        self.set_name(name)
+    }
+
+    // The thread object is boxed since the new thread keeps a reference to it, thus it can't be moved
+    pub fn create(entry: svc::ThreadEntrypointFn, entry_arg: *mut u8, stack_size: usize, priority: i32, cpu_id: i32, name: &str) -> Result<Box<Self>> {
+        result_return_if!(stack_size == 0, ResultInvalidStack);
+        reap_detached_threads();
+        let stack_size = (stack_size + STACK_ALIGNMENT - 1) & !(STACK_ALIGNMENT - 1);
+        let stack_layout = Layout::from_size_align(stack_size, STACK_ALIGNMENT).map_err(|_| ResultCode::from::<ResultInvalidStack>())?;
+        let stack = unsafe { alloc::alloc::alloc(stack_layout) };
+        result_return_if!(stack.is_null(), ResultStackAllocationFailed);
+
+        let mut thread = Box::new(Self::new());
+        thread.self_ref = &mut *thread;
         thread.name_addr = &mut thread.name as *mut ThreadName as *mut u8;
-        thread.set_name(name)?;
-        Ok(thread)
+        thread.owns_stack = true;
+        thread.stack = stack;
+        thread.stack_size = stack_size;
+        thread.entry = entry as *mut u8;
+        thread.entry_arg = entry_arg;
+        if let Err(rc) = thread.set_name(name) {
+            thread.free_stack();
+            return Err(rc);
+        }
+
+        let stack_top = unsafe { stack.offset(stack_size as isize) };
+        match svc::create_thread(thread_entry_impl, thread.self_ref as *mut u8, stack_top, priority, cpu_id) {
+            Ok(handle) => {
+                thread.handle = handle;
+                thread.state = ThreadState::Initialized;
+                Ok(thread)
+            },
+            Err(rc) => {
+                thread.free_stack();
+                Err(rc)
+            }
+        }
+    }
+
+    fn free_stack(&mut self) {
+        if self.owns_stack && !self.stack.is_null() {
+            unsafe {
+                alloc::alloc::dealloc(self.stack, Layout::from_size_align_unchecked(self.stack_size, STACK_ALIGNMENT));
+            }
+            self.stack = ptr::null_mut();
+            self.stack_size = 0;
+        }
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        result_return_unless!(self.state == ThreadState::Initialized, ResultInvalidState);
+        svc::start_thread(self.handle)?;
+        self.state = ThreadState::Started;
+        Ok(())
+    }
+
+    pub fn join(&mut self) -> Result<()> {
+        match self.state {
+            ThreadState::Started | ThreadState::Terminated => {
+                unsafe {
+                    svc::wait_synchronization(&self.handle, 1, svc::INFINITE_TIMEOUT)?;
+                }
+                self.state = ThreadState::Terminated;
+            },
+            // A thread which was never started is just destroyed
+            ThreadState::Initialized => self.state = ThreadState::DestroyedBeforeStarted,
+            _ => return Err(ResultCode::from::<ResultInvalidState>()),
+        };
+        svc::close_handle(self.handle)?;
+        self.handle = 0;
+        self.free_stack();
+        Ok(())
+    }
+
+    // A thread can't free its own stack, so detached ones are freed by the next thread creation (or detach) after they exit
+    pub fn detach(self: Box<Self>) -> Result<()> {
+        result_return_unless!(self.state == ThreadState::Started, ResultInvalidState);
+        reap_detached_threads();
+        G_DETACHED_THREADS.lock().push(ThreadRef(Box::into_raw(self)));
+        Ok(())
     }
 
     pub fn set_name(&mut self, name: &str) -> Result<()> {
+        // Names are at most 0x20 bytes, and a shorter name mustn't keep the end of the previous one
+        result_return_if!(name.len() > 0x20, util::ResultInvalidSize);
+        if !self.name_addr.is_null() {
+            unsafe {
+                ptr::write_bytes(self.name_addr, 0, 0x20);
+            }
+        }
         util::copy_str_to_pointer(name, self.name_addr)
     }
 
@@ -91,17 +209,119 @@ impl Thread {
     pub fn get_handle(&self) -> svc::Handle {
         self.handle
     }
+
+    pub fn get_state(&self) -> ThreadState {
+        self.state
+    }
+}
+
+struct ThreadRef(*mut Thread);
+
+unsafe impl Send for ThreadRef {}
+
+// Detached threads which might still be running, see reap_detached_threads
+static G_DETACHED_THREADS: sync::Mutex<Vec<ThreadRef>> = sync::Mutex::new(Vec::new());
+
+fn reap_detached_threads() {
+    G_DETACHED_THREADS.lock().retain(|thread_ref| {
+        // Threads get signaled once they have exited, which is when they are done with their stack
+        let thread = thread_ref.0;
+        let exited = unsafe { svc::wait_synchronization(&(*thread).handle, 1, 0).is_ok() };
+        if exited {
+            let mut thread = unsafe { Box::from_raw(thread) };
+            let _ = svc::close_handle(thread.handle);
+            thread.free_stack();
+        }
+        !exited
+    });
+}
+
+// The result is shared, so that whichever of the thread and the JoinHandle is done last frees it
+type SpawnResult<T> = Arc<UnsafeCell<Option<T>>>;
+
+struct SpawnContext<F, T> {
+    f: F,
+    result: SpawnResult<T>,
+}
+
+extern "C" fn spawn_entry_impl<F: FnOnce() -> T, T>(context_ref: *mut u8) {
+    unsafe {
+        let context = Box::from_raw(context_ref as *mut SpawnContext<F, T>);
+        let result = context.result;
+        *result.get() = Some((context.f)());
+    }
+}
+
+pub struct JoinHandle<T> {
+    thread: Option<Box<Thread>>,
+    result: SpawnResult<T>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn get_thread(&self) -> &Thread {
+        self.thread.as_ref().unwrap()
+    }
+
+    pub fn join(mut self) -> Result<T> {
+        let mut thread = self.thread.take().unwrap();
+        thread.join()?;
+        match unsafe { (*self.result.get()).take() } {
+            Some(t) => Ok(t),
+            None => Err(ResultCode::from::<ResultNoResult>()),
+        }
+    }
+
+    pub fn detach(mut self) -> Result<()> {
+        self.thread.take().unwrap().detach()
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.detach();
+        }
+    }
 }
 
+pub fn spawn_with<F: FnOnce() -> T + Send + 'static, T: Send + 'static>(f: F, stack_size: usize, priority: i32, cpu_id: i32, name: &str) -> Result<JoinHandle<T>> {
+    let result: SpawnResult<T> = Arc::new(UnsafeCell::new(None));
+    let context = Box::into_raw(Box::new(SpawnContext { f: f, result: result.clone() })) as *mut u8;
+    let free_context = || unsafe {
+        drop(Box::from_raw(context as *mut SpawnContext<F, T>));
+    };
+
+    let mut thread = match Thread::create(spawn_entry_impl::<F, T>, context, stack_size, priority, cpu_id, name) {
+        Ok(thread) => thread,
+        Err(rc) => {
+            free_context();
+            return Err(rc);
+        }
+    };
+    if let Err(rc) = thread.start() {
+        let _ = thread.join();
+        free_context();
+        return Err(rc);
+    }
+    Ok(JoinHandle { thread: Some(thread), result: result })
+}
+
+pub fn spawn<F: FnOnce() -> T + Send + 'static, T: Send + 'static>(f: F) -> Result<JoinHandle<T>> {
+    spawn_with(f, DEFAULT_STACK_SIZE, DEFAULT_PRIORITY, DEFAULT_CPU_ID, "WorkerThread")
+}
+
+pub const IPC_BUFFER_SIZE: usize = 0x100;
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct Tls {
-    pub ipc_buffer: [u8; 0x100],
+    pub ipc_buffer: [u8; IPC_BUFFER_SIZE],
     pub preemption_state: u32,
     pub unk: [u8; 0xF4],
     pub thread_ref: *mut Thread,
 }
 
+#[cfg(not(feature = "mock-svc"))]
 pub fn get_thread_local_storage() -> *mut Tls {
     let tls: *mut Tls;
     unsafe {
@@ -110,8 +330,364 @@ pub fn get_thread_local_storage() -> *mut Tls {
     tls
 }
 
-pub fn get_current_thread() -> &'static Thread {
+#[cfg(feature = "mock-svc")]
+pub fn get_thread_local_storage() -> *mut Tls {
+    svc::mock::get_thread_local_storage()
+}
+
+// Every running thread, so that deleted TLS keys can be cleared on all of them
+// The list is linked through the thread objects themselves, since the main thread gets registered before the heap is set up
+struct ThreadList {
+    head: *mut Thread,
+}
+
+unsafe impl Send for ThreadList {}
+
+static G_THREADS: sync::Mutex<ThreadList> = sync::Mutex::new(ThreadList { head: ptr::null_mut() });
+
+// The thread object must stay where it is until the thread exits
+pub unsafe fn set_current_thread(thread: *mut Thread) {
+    (*get_thread_local_storage()).thread_ref = thread;
+    let mut threads = G_THREADS.lock();
+    (*thread).next = threads.head;
+    (*thread).prev_next = &mut threads.head;
+    if !threads.head.is_null() {
+        (*threads.head).prev_next = &mut (*thread).next;
+    }
+    threads.head = thread;
+}
+
+unsafe fn unregister_thread(thread: *mut Thread) {
+    let _threads = G_THREADS.lock();
+    if (*thread).prev_next.is_null() {
+        return;
+    }
+    *(*thread).prev_next = (*thread).next;
+    if !(*thread).next.is_null() {
+        (*(*thread).next).prev_next = (*thread).prev_next;
+    }
+    (*thread).next = ptr::null_mut();
+    (*thread).prev_next = ptr::null_mut();
+}
+
+// Threads which weren't created through nx::thread (other than the main thread) have no thread object
+pub fn get_current_thread() -> Option<&'static Thread> {
+    unsafe {
+        (*get_thread_local_storage()).thread_ref.as_ref()
+    }
+}
+
+pub fn get_current_thread_handle() -> Result<svc::Handle> {
+    match get_current_thread() {
+        Some(thread) => Ok(thread.get_handle()),
+        None => Err(ResultCode::from::<ResultNoThreadObject>()),
+    }
+}
+
+pub const TLS_SLOT_COUNT: usize = 0x20;
+
+pub type TlsDestructorFn = fn(*mut u8);
+
+struct TlsSlots {
+    // Each bit tells whether the slot with that index is in use
+    mask: u32,
+    destructors: [Option<TlsDestructorFn>; TLS_SLOT_COUNT],
+}
+
+static G_TLS_SLOTS: sync::Mutex<TlsSlots> = sync::Mutex::new(TlsSlots { mask: 0, destructors: [None; TLS_SLOT_COUNT] });
+
+fn get_current_thread_mut() -> Option<&'static mut Thread> {
     unsafe {
-        &*(*get_thread_local_storage()).thread_ref
+        (*get_thread_local_storage()).thread_ref.as_mut()
+    }
+}
+
+fn run_tls_destructors(thread: &mut Thread) {
+    // Destructors might set values again, so keep iterating until no more are left (with a limit, like pthreads does)
+    for _ in 0..4 {
+        let mut any_ran = false;
+        for i in 0..TLS_SLOT_COUNT {
+            let value = thread.tls_slots[i];
+            if value.is_null() {
+                continue;
+            }
+            thread.tls_slots[i] = ptr::null_mut();
+            // Copied out, since destructors might create or delete keys themselves
+            let destructor = G_TLS_SLOTS.lock().destructors[i];
+            if let Some(destructor) = destructor {
+                destructor(value);
+                any_ran = true;
+            }
+        }
+        if !any_ran {
+            break;
+        }
+    }
+}
+
+// The main thread never goes through thread_entry_impl, so crt0 runs its destructors on exit
+pub fn run_current_thread_tls_destructors() {
+    if let Some(thread) = get_current_thread_mut() {
+        run_tls_destructors(thread);
+    }
+}
+
+pub struct TlsKey {
+    slot: usize,
+}
+
+impl TlsKey {
+    pub fn new(destructor: Option<TlsDestructorFn>) -> Result<Self> {
+        let mut slots = G_TLS_SLOTS.lock();
+        result_return_if!(slots.mask == u32::MAX, ResultNoFreeTlsSlots);
+        let slot = (!slots.mask).trailing_zeros() as usize;
+        slots.mask |= 1 << slot;
+        slots.destructors[slot] = destructor;
+        // The slot was already cleared on every thread when its previous key was deleted
+        Ok(Self { slot: slot })
+    }
+
+    pub fn get_slot(&self) -> usize {
+        self.slot
+    }
+
+    // Threads without a thread object have nowhere to keep values, so nothing is ever set on them
+    pub fn get(&self) -> *mut u8 {
+        match get_current_thread() {
+            Some(thread) => thread.tls_slots[self.slot],
+            None => ptr::null_mut(),
+        }
+    }
+
+    pub fn set(&self, value: *mut u8) -> Result<()> {
+        let thread = get_current_thread_mut().ok_or(ResultCode::from::<ResultNoThreadObject>())?;
+        thread.tls_slots[self.slot] = value;
+        Ok(())
+    }
+}
+
+impl Drop for TlsKey {
+    fn drop(&mut self) {
+        // Like pthread_key_delete, values still set are not destroyed, but (like libnx does) they are cleared on every thread so that a new key reusing the slot starts out empty
+        let threads = G_THREADS.lock();
+        let mut thread = threads.head;
+        while !thread.is_null() {
+            unsafe {
+                (*thread).tls_slots[self.slot] = ptr::null_mut();
+                thread = (*thread).next;
+            }
+        }
+        drop(threads);
+
+        let mut slots = G_TLS_SLOTS.lock();
+        slots.destructors[self.slot] = None;
+        slots.mask &= !(1 << self.slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::alloc::{GlobalAlloc, System};
+    use std::cell::Cell;
+
+    // Counts the allocations made by each host thread, so that tests can check that something doesn't allocate
+    struct CountingAllocator;
+
+    std::thread_local! {
+        static G_ALLOCATION_COUNT: Cell<usize> = Cell::new(0);
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = G_ALLOCATION_COUNT.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static G_ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn get_allocation_count() -> usize {
+        G_ALLOCATION_COUNT.with(|count| count.get())
+    }
+
+    // Makes a thread object on the caller's stack the current thread, like crt0 does with the main thread
+    fn with_current_thread<F: FnOnce(&mut Thread)>(f: F) {
+        let mut thread = Thread::new();
+        unsafe {
+            set_current_thread(&mut thread);
+        }
+        f(&mut thread);
+        unsafe {
+            unregister_thread(&mut thread);
+            (*get_thread_local_storage()).thread_ref = ptr::null_mut();
+        }
+    }
+
+    #[test]
+    fn registering_threads_doesnt_allocate() {
+        let _guard = service::mock::initialize();
+        let mut first = Thread::new();
+        let mut second = Thread::new();
+        let allocation_count = get_allocation_count();
+        unsafe {
+            set_current_thread(&mut first);
+            set_current_thread(&mut second);
+            unregister_thread(&mut first);
+            unregister_thread(&mut second);
+            (*get_thread_local_storage()).thread_ref = ptr::null_mut();
+        }
+        assert_eq!(get_allocation_count(), allocation_count);
+        assert!(G_THREADS.lock().head.is_null());
+    }
+
+    #[test]
+    fn existing_threads_are_initialized_in_place() {
+        // Like crt0's main thread, which lives in a static
+        let mut thread = Box::new(Thread::new());
+        thread.initialize_existing(0x1234, "MainThread", ptr::null_mut(), 0, false).unwrap();
+        let thread_ptr = &mut *thread as *mut Thread;
+        let name_ptr = thread.name.as_mut_ptr();
+        assert_eq!(thread.self_ref, thread_ptr);
+        assert_eq!(thread.name_addr, name_ptr);
+        assert_eq!(thread.get_name().unwrap(), "MainThread");
+
+        thread.set_name("Main").unwrap();
+        assert_eq!(thread.get_name().unwrap(), "Main");
+        assert_eq!(thread.get_handle(), 0x1234);
+        assert!(thread.get_state() == ThreadState::Started);
+    }
+
+    #[test]
+    fn tls_keys_reuse_cleared_slots() {
+        let _guard = service::mock::initialize();
+        with_current_thread(|thread| {
+            let first = TlsKey::new(None).unwrap();
+            let second = TlsKey::new(None).unwrap();
+            assert_ne!(first.get_slot(), second.get_slot());
+
+            let first_slot = first.get_slot();
+            first.set(1 as *mut u8).unwrap();
+            second.set(2 as *mut u8).unwrap();
+            assert_eq!(thread.tls_slots[first_slot], 1 as *mut u8);
+            drop(first);
+            assert!(thread.tls_slots[first_slot].is_null());
+
+            let third = TlsKey::new(None).unwrap();
+            assert_eq!(third.get_slot(), first_slot);
+            assert!(third.get().is_null());
+            assert_eq!(second.get(), 2 as *mut u8);
+        });
+    }
+
+    #[test]
+    fn tls_slots_run_out() {
+        let _guard = service::mock::initialize();
+        let keys: Vec<TlsKey> = (0..TLS_SLOT_COUNT).map(|_| TlsKey::new(None).unwrap()).collect();
+        assert!(TlsKey::new(None).err().unwrap().matches::<ResultNoFreeTlsSlots>());
+        drop(keys);
+        assert!(TlsKey::new(None).is_ok());
+    }
+
+    static G_DESTROYED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_destroyed(value: *mut u8) {
+        assert_eq!(value, 0x10 as *mut u8);
+        G_DESTROYED_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn tls_destructors_run_for_set_values() {
+        let _guard = service::mock::initialize();
+        G_DESTROYED_COUNT.store(0, Ordering::SeqCst);
+        with_current_thread(|_| {
+            let set_key = TlsKey::new(Some(count_destroyed)).unwrap();
+            let unset_key = TlsKey::new(Some(count_destroyed)).unwrap();
+            set_key.set(0x10 as *mut u8).unwrap();
+            run_current_thread_tls_destructors();
+            assert_eq!(G_DESTROYED_COUNT.load(Ordering::SeqCst), 1);
+            assert!(set_key.get().is_null());
+            assert!(unset_key.get().is_null());
+        });
+    }
+    #[test]
+    fn spawned_threads_return_their_result() {
+        let _guard = service::mock::initialize();
+        let handle = spawn(|| 5).unwrap();
+        assert!(handle.get_thread().get_state() == ThreadState::Started);
+        let thread_handle = handle.get_thread().get_handle();
+        assert_eq!(handle.join().unwrap(), 5);
+        assert!(!svc::mock::is_handle_open(thread_handle));
+        assert!(G_THREADS.lock().head.is_null());
+    }
+
+    #[test]
+    fn tls_destructors_run_for_spawned_threads() {
+        let _guard = service::mock::initialize();
+        G_DESTROYED_COUNT.store(0, Ordering::SeqCst);
+        let key = Arc::new(TlsKey::new(Some(count_destroyed)).unwrap());
+        let thread_key = key.clone();
+        spawn(move || thread_key.set(0x10 as *mut u8)).unwrap().join().unwrap().unwrap();
+        assert_eq!(G_DESTROYED_COUNT.load(Ordering::SeqCst), 1);
+    }
+
+    extern "C" fn do_nothing(_arg: *mut u8) {}
+
+    #[test]
+    fn thread_state_errors() {
+        let _guard = service::mock::initialize();
+        assert!(Thread::create(do_nothing, ptr::null_mut(), 0, DEFAULT_PRIORITY, DEFAULT_CPU_ID, "Test").err().unwrap().matches::<ResultInvalidStack>());
+        assert!(Thread::create(do_nothing, ptr::null_mut(), DEFAULT_STACK_SIZE, DEFAULT_PRIORITY, DEFAULT_CPU_ID, "").err().unwrap().matches::<util::ResultInvalidSize>());
+        assert!(Thread::create(do_nothing, ptr::null_mut(), DEFAULT_STACK_SIZE, DEFAULT_PRIORITY, DEFAULT_CPU_ID, "ThisNameIsLongerThanThirtyTwoBytes").err().unwrap().matches::<util::ResultInvalidSize>());
+
+        let mut thread = Thread::create(do_nothing, ptr::null_mut(), DEFAULT_STACK_SIZE, DEFAULT_PRIORITY, DEFAULT_CPU_ID, "Test").unwrap();
+        thread.start().unwrap();
+        assert!(thread.start().err().unwrap().matches::<ResultInvalidState>());
+        thread.join().unwrap();
+        assert!(thread.get_state() == ThreadState::Terminated);
+        assert!(thread.stack.is_null());
+
+        // Never started threads are just destroyed
+        let mut thread = Thread::create(do_nothing, ptr::null_mut(), DEFAULT_STACK_SIZE, DEFAULT_PRIORITY, DEFAULT_CPU_ID, "Test").unwrap();
+        let handle = thread.get_handle();
+        thread.join().unwrap();
+        assert!(thread.get_state() == ThreadState::DestroyedBeforeStarted);
+        assert!(!svc::mock::is_handle_open(handle));
+        assert!(thread.join().err().unwrap().matches::<ResultInvalidState>());
+        assert!(thread.detach().err().unwrap().matches::<ResultInvalidState>());
+
+        let mut thread = Thread::new();
+        assert!(thread.join().err().unwrap().matches::<ResultInvalidState>());
+    }
+
+    #[test]
+    fn thread_names_are_replaced() {
+        let _guard = service::mock::initialize();
+        let mut thread = Thread::create(do_nothing, ptr::null_mut(), DEFAULT_STACK_SIZE, DEFAULT_PRIORITY, DEFAULT_CPU_ID, "LongThreadName").unwrap();
+        thread.set_name("Short").unwrap();
+        assert_eq!(thread.get_name().unwrap(), "Short");
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn detached_threads_are_reaped() {
+        let _guard = service::mock::initialize();
+        let handle = spawn(|| ()).unwrap();
+        let thread_ref = handle.get_thread() as *const Thread as *mut Thread;
+        let thread_handle = handle.get_thread().get_handle();
+        handle.detach().unwrap();
+        assert!(G_DETACHED_THREADS.lock().iter().any(|detached| detached.0 == thread_ref));
+        assert!(svc::mock::is_handle_open(thread_handle));
+
+        reap_detached_threads();
+        assert!(!G_DETACHED_THREADS.lock().iter().any(|detached| detached.0 == thread_ref));
+        assert!(!svc::mock::is_handle_open(thread_handle));
     }
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 use crate::result::*;
 use crate::thread;
 use crate::diag::assert;
+use crate::diag::backtrace;
 use crate::diag::log::Logger;
 use core::str;
 use core::ptr;
@@ -54,10 +55,11 @@ pub fn copy_str_to_pointer(string: &str, ptr: *mut u8) -> Result<()> {
 }
 
 pub fn on_panic_handler<L: Logger>(info: &panic::PanicInfo, assert_mode: assert::AssertMode, rc: ResultCode) -> ! {
-    let thread_name = match thread::get_current_thread().get_name() {
-        Ok(name) => name,
+    let thread_name = match thread::get_current_thread().map(|thread| thread.get_name()) {
+        Some(Ok(name)) => name,
         _ => "<unknown>",
     };
     diag_log!(L { crate::diag::log::LogSeverity::Fatal, true } => "Panic! at thread '{}' -> {}", thread_name, info);
+    backtrace::dump_backtrace::<L>(&backtrace::Backtrace::capture(), crate::diag::log::LogSeverity::Fatal);
     assert::assert(assert_mode, rc)
 }
\ No newline at end of file
@@ -0,0 +1,281 @@
+extern crate alloc;
+
+use crate::result::*;
+use crate::svc;
+use crate::svc::handle::BorrowedHandle;
+use crate::svc::handle::ReadableEvent;
+use crate::sync;
+use crate::thread;
+use alloc::vec::Vec;
+use core::ptr;
+
+pub const RESULT_SUBMODULE: u32 = 16;
+
+result_lib_define_group!(RESULT_SUBMODULE => {
+    ResultNoWaitables: 2
+});
+
+// All timeouts here are in nanoseconds, like the kernel's (negative ones never time out)
+pub const INFINITE_TIMEOUT: i64 = svc::INFINITE_TIMEOUT;
+
+// The system tick runs at 19.2MHz (12 ticks every 625ns), split up so that long timeouts don't overflow
+pub const fn ns_to_ticks(ns: u64) -> u64 {
+    (ns / 625) * 12 + ((ns % 625) * 12) / 625
+}
+
+pub const fn ticks_to_ns(ticks: u64) -> u64 {
+    (ticks / 12) * 625 + ((ticks % 12) * 625) / 12
+}
+
+pub const fn ms_to_ns(ms: u64) -> i64 {
+    (ms * 1_000_000) as i64
+}
+
+struct UserEventState {
+    signaled: bool,
+    // Threads currently waiting on the event, woken up with svc::CancelSynchronization
+    waiters: Vec<svc::Handle>,
+}
+
+// An event signaled by the process itself, which costs no kernel object
+pub struct UserEvent {
+    auto_clear: bool,
+    state: sync::Mutex<UserEventState>,
+}
+
+impl UserEvent {
+    pub const fn new(auto_clear: bool) -> Self {
+        Self { auto_clear: auto_clear, state: sync::Mutex::new(UserEventState { signaled: false, waiters: Vec::new() }) }
+    }
+
+    pub fn signal(&self) {
+        let mut state = self.state.lock();
+        state.signaled = true;
+        // Woken up waiters are removed here, so that they can tell they were woken up (see remove_waiter)
+        for waiter in state.waiters.drain(..) {
+            let _ = svc::cancel_synchronization(waiter);
+        }
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().signaled = false;
+    }
+
+    pub fn is_signaled(&self) -> bool {
+        self.state.lock().signaled
+    }
+
+    // Either consumes the signal or registers the thread to be woken up by the next one, in a single step so that no signal gets lost
+    fn consume_or_add_waiter(&self, thread_handle: svc::Handle) -> bool {
+        let mut state = self.state.lock();
+        if state.signaled {
+            if self.auto_clear {
+                state.signaled = false;
+            }
+            return true;
+        }
+        state.waiters.push(thread_handle);
+        false
+    }
+
+    // Returns whether the thread was still waiting, since otherwise a signal already cancelled its synchronization
+    fn remove_waiter(&self, thread_handle: svc::Handle) -> bool {
+        let mut state = self.state.lock();
+        match state.waiters.iter().position(|waiter| *waiter == thread_handle) {
+            Some(index) => {
+                state.waiters.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum Waitable<'a> {
+    // Any kernel synchronization object: readable events, threads (signaled when they exit), processes, sessions, ports...
+    Handle(BorrowedHandle<'a>),
+    UserEvent(&'a UserEvent),
+}
+
+impl<'a> Waitable<'a> {
+    pub fn from_handle(handle: BorrowedHandle<'a>) -> Self {
+        Waitable::Handle(handle)
+    }
+
+    pub fn from_event(event: &'a ReadableEvent) -> Self {
+        Waitable::Handle(event.borrow_handle())
+    }
+
+    pub fn from_user_event(event: &'a UserEvent) -> Self {
+        Waitable::UserEvent(event)
+    }
+
+    // Waiting on a thread waits for it to exit, like joining it
+    pub fn from_thread(thread: &'a thread::Thread) -> Self {
+        Waitable::Handle(BorrowedHandle::new(thread.get_handle()))
+    }
+
+    pub fn from_thread_handle(thread: &'a svc::handle::Thread) -> Self {
+        Waitable::Handle(thread.borrow_handle())
+    }
+}
+
+// Returns how many of the user events woke the thread up
+fn remove_user_event_waiters(waitables: &[Waitable], thread_handle: svc::Handle) -> usize {
+    let mut wake_count = 0;
+    for waitable in waitables {
+        if let Waitable::UserEvent(event) = waitable {
+            if !event.remove_waiter(thread_handle) {
+                wake_count += 1;
+            }
+        }
+    }
+    wake_count
+}
+
+// A signal that came in after the wait was over leaves the thread's synchronization cancelled, which would make its next wait fail right away
+fn absorb_late_cancel() {
+    // With nothing to wait on, this just clears the cancel (ResultCancelled)
+    let _ = unsafe { svc::wait_synchronization(ptr::null(), 0, 0) };
+}
+
+// Returns the index of the waitable that was signaled
+pub fn wait_any(waitables: &[Waitable], timeout: i64) -> Result<usize> {
+    result_return_if!(waitables.is_empty(), ResultNoWaitables);
+
+    let deadline = match timeout >= 0 {
+        true => Some(svc::get_system_tick() + ns_to_ticks(timeout as u64)),
+        false => None,
+    };
+    // User events wake their waiters by handle, which threads without a thread object don't have
+    let thread_handle = match waitables.iter().any(|waitable| matches!(waitable, Waitable::UserEvent(_))) {
+        true => thread::get_current_thread_handle()?,
+        false => svc::INVALID_HANDLE,
+    };
+
+    loop {
+        let mut handles: Vec<svc::Handle> = Vec::new();
+        let mut handle_indices: Vec<usize> = Vec::new();
+        for (i, waitable) in waitables.iter().enumerate() {
+            match waitable {
+                Waitable::Handle(handle) => {
+                    handles.push(handle.get_handle());
+                    handle_indices.push(i);
+                },
+                Waitable::UserEvent(event) => {
+                    if event.consume_or_add_waiter(thread_handle) {
+                        if remove_user_event_waiters(&waitables[..i], thread_handle) > 0 {
+                            absorb_late_cancel();
+                        }
+                        return Ok(i);
+                    }
+                }
+            }
+        }
+
+        if handles.len() > svc::MAX_WAIT_HANDLE_COUNT {
+            if remove_user_event_waiters(waitables, thread_handle) > 0 {
+                absorb_late_cancel();
+            }
+            return Err(ResultCode::from::<svc::ResultOutOfRange>());
+        }
+
+        let cur_timeout = match deadline {
+            Some(deadline_tick) => ticks_to_ns(deadline_tick.saturating_sub(svc::get_system_tick())) as i64,
+            None => INFINITE_TIMEOUT,
+        };
+        let wait_rc = unsafe { svc::wait_synchronization(handles.as_ptr(), handles.len() as u32, cur_timeout) };
+        // The waiters are removed before acting on the result, and a wait that was cancelled already took care of one of the wake-ups
+        let wake_count = remove_user_event_waiters(waitables, thread_handle);
+        let cancelled = match wait_rc {
+            Err(rc) => rc.matches::<svc::ResultCancelled>(),
+            Ok(_) => false,
+        };
+        if wake_count > (cancelled as usize) {
+            absorb_late_cancel();
+        }
+
+        match wait_rc {
+            Ok(index) => return Ok(handle_indices[index as usize]),
+            // A user event was signaled, so check them again
+            Err(rc) if rc.matches::<svc::ResultCancelled>() => continue,
+            Err(rc) => return Err(rc),
+        }
+    }
+}
+
+pub fn wait_one(waitable: Waitable, timeout: i64) -> Result<()> {
+    wait_any(&[waitable], timeout)?;
+    Ok(())
+}
+
+// Keeps a set of waitables around, for loops that wait on the same things over and over
+pub struct Waiter<'a> {
+    waitables: Vec<Waitable<'a>>,
+}
+
+impl<'a> Waiter<'a> {
+    pub fn new() -> Self {
+        Self { waitables: Vec::new() }
+    }
+
+    // Returns the index wait_any will return when this waitable is signaled
+    pub fn add(&mut self, waitable: Waitable<'a>) -> usize {
+        self.waitables.push(waitable);
+        self.waitables.len() - 1
+    }
+
+    pub fn add_event(&mut self, event: &'a ReadableEvent) -> usize {
+        self.add(Waitable::from_event(event))
+    }
+
+    pub fn add_user_event(&mut self, event: &'a UserEvent) -> usize {
+        self.add(Waitable::from_user_event(event))
+    }
+
+    pub fn add_thread(&mut self, thread: &'a thread::Thread) -> usize {
+        self.add(Waitable::from_thread(thread))
+    }
+
+    pub fn clear(&mut self) {
+        self.waitables.clear();
+    }
+
+    pub fn wait_any(&self, timeout: i64) -> Result<usize> {
+        wait_any(&self.waitables, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tick_conversions_dont_overflow() {
+        assert_eq!(ns_to_ticks(1_000_000_000), 19_200_000);
+        assert_eq!(ticks_to_ns(19_200_000), 1_000_000_000);
+        // The longest possible timeout, way past where ns * 12 overflows
+        assert_eq!(ns_to_ticks(i64::max_value() as u64), 177_088_743_107_611_695);
+        assert_eq!(ticks_to_ns(177_088_743_107_611_695), 9_223_372_036_854_775_781);
+    }
+
+    #[test]
+    fn user_events_are_consumed_by_waits() {
+        svc::mock::reset();
+        let auto_event = UserEvent::new(true);
+        let manual_event = UserEvent::new(false);
+        let waitables = [Waitable::from_user_event(&auto_event), Waitable::from_user_event(&manual_event)];
+        assert!(wait_any(&waitables, 0).unwrap_err().matches::<svc::ResultTimedOut>());
+
+        manual_event.signal();
+        assert_eq!(wait_any(&waitables, 0).unwrap(), 1);
+        assert_eq!(wait_any(&waitables, 0).unwrap(), 1);
+        manual_event.clear();
+
+        auto_event.signal();
+        assert_eq!(wait_any(&waitables, INFINITE_TIMEOUT).unwrap(), 0);
+        assert!(!auto_event.is_signaled());
+        assert!(wait_one(waitables[0], 0).unwrap_err().matches::<svc::ResultTimedOut>());
+    }
+}
\ No newline at end of file
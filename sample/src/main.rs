@@ -71,7 +71,8 @@ pub fn gpu_test() -> Result<()> {
     Ok(())
 }
 
-#[no_mangle]
+nx_main!(main);
+
 pub fn main() -> Result<()> {
     diag_log!(log::LmLogger { log::LogSeverity::Info, false } => "Hello from {} and {} logging!", "Rust", "lm");
 